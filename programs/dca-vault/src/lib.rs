@@ -1,5 +1,27 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_lang::solana_program::sysvar::instructions as instructions_sysvar;
+use anchor_lang::solana_program::sysvar::slot_hashes;
+use anchor_lang::system_program;
+use anchor_spl::associated_token::{
+    self, get_associated_token_address_with_program_id, AssociatedToken,
+};
+use anchor_spl::token::spl_token::native_mint;
+use anchor_spl::token_2022::spl_token_2022;
+use anchor_spl::token_2022_extensions::transfer_fee::{
+    harvest_withheld_tokens_to_mint, HarvestWithheldTokensToMint,
+};
+use anchor_spl::token_interface::{
+    self, CloseAccount, Mint, SyncNative, TokenAccount, TokenInterface, TransferChecked,
+};
+use pyth_sdk_solana::state::SolanaPriceAccount;
+use pyth_sdk_solana::Price;
+use session_keys::program::SessionKeys;
+use session_keys::SessionKey;
+use spl_token_2022::extension::transfer_fee::TransferFeeAmount;
+use spl_token_2022::extension::{BaseStateWithExtensions, StateWithExtensions};
 
 declare_id!("Df9BwQfySajVQgbJE4TXCHqy6UxCXKhEAUwXyw3TVK5a");
 
@@ -8,440 +30,12168 @@ pub mod dca_vault {
     use super::*;
 
     /// Initialize a new DCA vault
-    pub fn initialize_vault(
+    /// In `MODE_PERCENT` each cycle spends `percent_bps` of the remaining
+    /// balance and `amount_per_cycle` is ignored for sizing swaps
+    /// In `MODE_ALTERNATE` even cycles buy with `amount_per_cycle` of source and
+    /// odd cycles sell `percent_bps` of the destination balance back to source
+    /// In `MODE_SELL` the source is the asset being liquidated and the
+    /// destination the proceeds token; sizing matches `MODE_FIXED`
+    /// With `catch_up` the schedule stays anchored to its original cadence
+    /// unless a keeper is more than `max_delay_seconds` late
+    /// A non-default `referrer` earns a share of the protocol fee on each buy
+    /// `jitter_seconds` delays the first execution by a pseudo-random offset in
+    /// `[0, jitter_seconds]` so vaults created together don't fire together
+    /// A non-zero `expiry_timestamp` cancels the vault at its first execution
+    /// attempt on or after that time
+    /// A non-default `recovery_authority` may `emergency_withdraw` to the owner
+    /// once the vault has gone `recovery_delay_seconds` without executing
+    /// No cycle runs until `total_deposited` reaches `min_initial_deposit`
+    /// (e.g. `amount_per_cycle * total_cycles` to require full funding)
+    pub fn initialize_vault(ctx: Context<InitializeVault>, params: VaultParams) -> Result<()> {
+        let event = process_initialize_vault(
+            NewVaultAccounts {
+                vault: &mut ctx.accounts.vault,
+                owner: ctx.accounts.owner.key(),
+                payer: ctx.accounts.payer.key(),
+                source_mint: &ctx.accounts.source_mint,
+                dest_mint: &ctx.accounts.dest_mint,
+                config: &ctx.accounts.config,
+                registry: ctx.accounts.registry.as_mut(),
+                slot_hashes: ctx.accounts.slot_hashes.as_deref(),
+                bump: ctx.bumps.vault,
+            },
+            params,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        emit_cpi!(event);
+
+        Ok(())
+    }
+
+    /// Initialize a vault with its interval given as `frequency_value` of
+    /// `frequency_unit` (one of `Vault::FREQUENCY_UNIT_*`) instead of raw
+    /// seconds; `params.frequency_seconds` is ignored, otherwise identical to
+    /// `initialize_vault`
+    pub fn initialize_vault_v2(
         ctx: Context<InitializeVault>,
-        amount_per_cycle: u64,
-        frequency_seconds: i64,
-        total_cycles: u16,
+        mut params: VaultParams,
+        frequency_value: u32,
+        frequency_unit: u8,
     ) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
-        
-        vault.owner = ctx.accounts.owner.key();
-        vault.source_mint = ctx.accounts.source_mint.key();
-        vault.dest_mint = ctx.accounts.dest_mint.key();
-        vault.amount_per_cycle = amount_per_cycle;
-        vault.frequency_seconds = frequency_seconds;
-        vault.total_cycles = total_cycles;
-        vault.executed_cycles = 0;
-        vault.total_deposited = 0;
-        vault.total_received = 0;
-        vault.last_execution = Clock::get()?.unix_timestamp;
-        vault.next_execution = Clock::get()?.unix_timestamp + frequency_seconds;
-        vault.status = Vault::STATUS_ACTIVE;
-        vault.bump = ctx.bumps.vault;
+        params.frequency_seconds = frequency_to_seconds(frequency_value, frequency_unit)?;
 
-        msg!("DCA Vault initialized: {}", vault.key());
-        msg!("Amount per cycle: {}", amount_per_cycle);
-        msg!("Frequency: {} seconds", frequency_seconds);
-        msg!("Total cycles: {}", total_cycles);
+        initialize_vault(ctx, params)
+    }
+
+    /// Create a vault and fund it in one transaction
+    /// Takes the same parameters as `initialize_vault`, then moves
+    /// `deposit_amount` from the owner into the vault as `deposit` does.
+    /// The vault's source token account must already exist (clients create
+    /// the associated token account idempotently in the same transaction).
+    pub fn initialize_and_deposit(
+        ctx: Context<InitializeAndDeposit>,
+        params: VaultParams,
+        deposit_amount: u64,
+    ) -> Result<()> {
+        let initialized = process_initialize_vault(
+            NewVaultAccounts {
+                vault: &mut ctx.accounts.vault,
+                owner: ctx.accounts.owner.key(),
+                payer: ctx.accounts.payer.key(),
+                source_mint: &ctx.accounts.source_mint,
+                dest_mint: &ctx.accounts.dest_mint,
+                config: &ctx.accounts.config,
+                registry: ctx.accounts.registry.as_mut(),
+                slot_hashes: ctx.accounts.slot_hashes.as_deref(),
+                bump: ctx.bumps.vault,
+            },
+            params,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        let deposited = process_deposit(
+            DepositAccounts {
+                vault: &mut ctx.accounts.vault,
+                depositor: ctx.accounts.owner.to_account_info(),
+                depositor_token_account: ctx.accounts.user_token_account.to_account_info(),
+                vault_token_account: &mut ctx.accounts.vault_token_account,
+                source_mint: &ctx.accounts.source_mint,
+                token_program: ctx.accounts.token_program.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+            },
+            deposit_amount,
+        )?;
+
+        emit_cpi!(initialized);
+        emit_cpi!(deposited);
 
         Ok(())
     }
 
     /// Deposit tokens into vault
+    /// For native SOL vaults `amount` is in lamports and is wrapped into the
+    /// vault's WSOL account; `user_token_account` is not debited
     pub fn deposit(ctx: Context<DepositToVault>, amount: u64) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
-        
-        require!(
-            vault.status == Vault::STATUS_ACTIVE,
-            ErrorCode::VaultNotActive
-        );
+        let event = process_deposit(
+            DepositAccounts {
+                vault: &mut ctx.accounts.vault,
+                depositor: ctx.accounts.owner.to_account_info(),
+                depositor_token_account: ctx.accounts.user_token_account.to_account_info(),
+                vault_token_account: &mut ctx.accounts.vault_token_account,
+                source_mint: &ctx.accounts.source_mint,
+                token_program: ctx.accounts.token_program.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+            },
+            amount,
+        )?;
 
-        // Transfer tokens from user to vault
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.user_token_account.to_account_info(),
-            to: ctx.accounts.vault_token_account.to_account_info(),
-            authority: ctx.accounts.owner.to_account_info(),
-        };
-        
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        
-        token::transfer(cpi_ctx, amount)?;
+        emit_cpi!(event);
+
+        Ok(())
+    }
+
+    /// Fund someone else's vault; the depositor need not be the owner
+    /// Native SOL vaults wrap the depositor's lamports as in `deposit`
+    pub fn deposit_from(ctx: Context<DepositFrom>, amount: u64) -> Result<()> {
+        let event = process_deposit(
+            DepositAccounts {
+                vault: &mut ctx.accounts.vault,
+                depositor: ctx.accounts.depositor.to_account_info(),
+                depositor_token_account: ctx.accounts.depositor_token_account.to_account_info(),
+                vault_token_account: &mut ctx.accounts.vault_token_account,
+                source_mint: &ctx.accounts.source_mint,
+                token_program: ctx.accounts.token_program.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+            },
+            amount,
+        )?;
 
-        vault.total_deposited += amount;
+        emit_cpi!(event);
+
+        Ok(())
+    }
 
-        msg!("Deposited {} tokens to vault", amount);
-        msg!("Total deposited: {}", vault.total_deposited);
+    /// Create the vault's destination ATA so the first execution has
+    /// somewhere to land; anyone may call it and pays the rent
+    pub fn ensure_dest_account(ctx: Context<EnsureDestAccount>) -> Result<()> {
+        associated_token::create(CpiContext::new(
+            ctx.accounts.associated_token_program.to_account_info(),
+            associated_token::Create {
+                payer: ctx.accounts.payer.to_account_info(),
+                associated_token: ctx.accounts.vault_dest_token_account.to_account_info(),
+                authority: ctx.accounts.vault.to_account_info(),
+                mint: ctx.accounts.dest_mint.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+            },
+        ))?;
 
+        msg!(
+            "Created destination account {} for vault {}",
+            ctx.accounts.vault_dest_token_account.key(),
+            ctx.accounts.vault.key()
+        );
         Ok(())
     }
 
     /// Execute DCA swap (called by backend worker with session key)
     /// Integrates with Jupiter for optimal swap routing
-    pub fn execute_dca(ctx: Context<ExecuteDCA>, min_amount_out: u64) -> Result<()> {
-        let vault_key = ctx.accounts.vault.key();
-        let vault = &mut ctx.accounts.vault;
+    ///
+    /// `route_data` is the serialized Jupiter v6 `route` / `shared_accounts_route`
+    /// instruction data (discriminator + args) as returned by the Jupiter swap API.
+    ///
+    /// Remaining accounts must be passed in the exact order expected by that
+    /// Jupiter instruction, i.e. the `accounts` list of the quoted swap instruction.
+    /// The vault PDA must appear as the user transfer authority, with
+    /// `vault_token_account` as the source and `vault_dest_token_account` as the
+    /// destination; it is marked as a signer when forwarded.
+    ///
+    /// Passing `min_amount_out == 0` derives the minimum from the oracle price
+    /// less the vault's `slippage_bps`; vaults without a price feed reject it.
+    ///
+    /// When `session_key`, `session_keys_program` and the `instructions` sysvar
+    /// are supplied, the keeper's session budget is validated and charged via
+    /// CPI in the same transaction; `session_nonce` must then be the session
+    /// key's next nonce. The session must allow this program and the
+    /// transaction's top-level instruction. Vaults that opted in with
+    /// `set_require_session` reject executions without one.
+    ///
+    /// Compounding vaults must also pass `compound_token_account`, the next hop
+    /// vault's source token account that receives each buy leg's output.
+    ///
+    /// Referred vaults must pass `referrer_token_account` while the protocol
+    /// pays a referral share.
+    ///
+    /// While the treasury is the program's PDA, `fee_ledger` must be the
+    /// `treasury_token_account`'s ledger so the fees paid can be withdrawn.
+    pub fn execute_dca<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteDCA<'info>>,
+        route_data: Vec<u8>,
+        min_amount_out: u64,
+        session_nonce: u64,
+    ) -> Result<()> {
         let clock = Clock::get()?;
 
-        // === Validation Phase ===
         require!(
-            clock.unix_timestamp >= vault.next_execution,
-            ErrorCode::TooEarlyToExecute
+            ctx.accounts.session_key.is_some() == ctx.accounts.session_keys_program.is_some()
+                && ctx.accounts.session_key.is_some() == ctx.accounts.instructions.is_some(),
+            ErrorCode::MissingSessionAccounts
         );
+        let instruction = ctx
+            .accounts
+            .instructions
+            .as_deref()
+            .map(SessionKey::current_instruction)
+            .transpose()?;
 
+        // === Validation Phase ===
+        ctx.accounts.config.ensure_not_paused()?;
+        ctx.accounts
+            .config
+            .ensure_keeper_authorized(&ctx.accounts.session_authority.key())?;
+        ctx.accounts.vault.check_executor(ctx.accounts.session_authority.key())?;
         require!(
-            vault.executed_cycles < vault.total_cycles,
-            ErrorCode::AllCyclesCompleted
+            ctx.accounts.fee_ledger.is_some() || !ctx.accounts.config.treasury_is_pda(),
+            ErrorCode::MissingFeeLedger
         );
 
-        require!(
-            vault.status == Vault::STATUS_ACTIVE,
-            ErrorCode::VaultNotActive
-        );
+        let plan = prepare_cycle(
+            &ctx.accounts.vault,
+            ctx.accounts.vault_token_account.amount,
+            ctx.accounts.vault_dest_token_account.amount,
+            ctx.accounts.price_feed.as_ref(),
+            min_amount_out,
+            clock.unix_timestamp,
+        )?;
 
-        // Validate sufficient balance
-        let vault_balance = ctx.accounts.vault_token_account.amount;
-        require!(
-            vault_balance >= vault.amount_per_cycle,
-            ErrorCode::InsufficientBalance
-        );
+        let charged_session = check_session_charge(
+            &ctx.accounts.vault,
+            ctx.accounts.vault.key(),
+            ctx.accounts.session_key.as_deref(),
+            ctx.accounts.session_authority.key(),
+            instruction,
+            ctx.accounts.swap_program_account.key(),
+            &plan,
+            session_nonce,
+            clock.unix_timestamp,
+        )?;
 
-        // === Swap Execution Phase ===
-        // Build vault signer seeds for PDA signing
-        let seeds = &[
-            b"vault",
-            vault.owner.as_ref(),
-            vault.source_mint.as_ref(),
-            vault.dest_mint.as_ref(),
-            &[vault.bump],
-        ];
-        let signer = &[&seeds[..]];
+        if let (Some(_), Some(session_key), Some(session_keys_program), Some(instructions)) = (
+            charged_session,
+            &ctx.accounts.session_key,
+            &ctx.accounts.session_keys_program,
+            &ctx.accounts.instructions,
+        ) {
+            charge_keeper_session(
+                session_key,
+                session_keys_program,
+                ctx.accounts.session_authority.to_account_info(),
+                instructions.to_account_info(),
+                &ctx.accounts.vault,
+                ctx.accounts.swap_program_account.key(),
+                &plan,
+                session_nonce,
+            )?;
+        }
 
-        // Get balance before swap for output calculation
-        let dest_balance_before = ctx.accounts.vault_dest_token_account.amount;
+        let events = execute_cycle(
+            CycleAccounts {
+                vault: &mut ctx.accounts.vault,
+                vault_token_account: &mut ctx.accounts.vault_token_account,
+                vault_dest_token_account: &mut ctx.accounts.vault_dest_token_account,
+                keeper_token_account: &ctx.accounts.keeper_token_account,
+                treasury_token_account: &ctx.accounts.treasury_token_account,
+                source_mint: &ctx.accounts.source_mint,
+                dest_mint: &ctx.accounts.dest_mint,
+                compound_token_account: ctx.accounts.compound_token_account.as_ref(),
+                referrer_token_account: ctx.accounts.referrer_token_account.as_ref(),
+                swap_program: ctx.accounts.swap_program_account.to_account_info(),
+                swap_accounts: ctx.remaining_accounts,
+                source_token_program: ctx.accounts.source_token_program.to_account_info(),
+                dest_token_program: ctx.accounts.dest_token_program.to_account_info(),
+                config: &ctx.accounts.config,
+                fee_ledger: ctx.accounts.fee_ledger.as_deref_mut(),
+            },
+            plan,
+            route_data,
+            &clock,
+        )?;
 
-        // Transfer tokens to Jupiter swap program
-        // Note: In production, this would be a CPI call to Jupiter's swap instruction
-        // Jupiter handles route optimization and actual DEX interactions
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.vault_token_account.to_account_info(),
-            to: ctx.accounts.swap_program_account.to_account_info(),
-            authority: vault.to_account_info(),
-        };
+        if let Some(event) = events.take_profit {
+            emit_cpi!(event);
+        }
+        if let Some(event) = events.skipped {
+            emit_cpi!(event);
+        }
+        if let Some(event) = events.low_balance {
+            emit_cpi!(event);
+        }
+        if let Some(event) = events.status_changed {
+            emit_cpi!(event);
+        }
+        if let Some(event) = events.executed {
+            emit_cpi!(event);
+        }
 
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        Ok(())
+    }
+
+    /// Execute DCA swap with the minimum output given as `expected_out` less
+    /// `slippage_bps`, instead of an absolute `min_amount_out`
+    pub fn execute_dca_bps<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteDCA<'info>>,
+        route_data: Vec<u8>,
+        expected_out: u64,
+        slippage_bps: u16,
+        session_nonce: u64,
+    ) -> Result<()> {
+        require!(slippage_bps <= Vault::MAX_BPS, ErrorCode::InvalidSlippage);
+
+        let min_amount_out = apply_slippage(expected_out, slippage_bps)?;
 
-        token::transfer(cpi_ctx, vault.amount_per_cycle)?;
+        // Zero would silently switch to deriving the minimum from the quote
+        require!(min_amount_out > 0, ErrorCode::InvalidAmount);
 
-        // === Post-Swap Verification ===
-        // Reload destination account to get new balance
-        ctx.accounts.vault_dest_token_account.reload()?;
-        let dest_balance_after = ctx.accounts.vault_dest_token_account.amount;
-        let amount_received = dest_balance_after.saturating_sub(dest_balance_before);
+        execute_dca(ctx, route_data, min_amount_out, session_nonce)
+    }
+
+    /// Execute DCA swap only if the vault is still at cycle `expected_cycle`
+    /// A retried transaction landing after the first one finds the counter
+    /// advanced and fails with `CycleMismatch` instead of swapping twice
+    pub fn execute_dca_checked<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteDCA<'info>>,
+        route_data: Vec<u8>,
+        expected_cycle: u16,
+        min_amount_out: u64,
+        session_nonce: u64,
+    ) -> Result<()> {
+        ctx.accounts.vault.check_expected_cycle(expected_cycle)?;
+
+        execute_dca(ctx, route_data, min_amount_out, session_nonce)
+    }
+
+    /// Execute several due vaults in one transaction
+    ///
+    /// Each entry in `executions` consumes one group of remaining accounts:
+    ///
+    /// 0. `[writable]` vault
+    /// 1. `[writable]` vault source token account
+    /// 2. `[writable]` vault destination token account
+    /// 3. `[writable]` keeper source token account (reward)
+    /// 4. `[writable]` treasury destination token account (protocol fee)
+    /// 5. `[]` source mint
+    /// 6. `[]` destination mint
+    /// 7. `[]` Pyth price feed (any account if the vault has none configured)
+    /// 8. `[writable]` treasury account's fee ledger (any account unless the
+    ///    treasury is the program's PDA)
+    /// 9. `swap_account_count` Jupiter route accounts, as for `execute_dca`
+    ///
+    /// Every vault's mints must belong to `source_token_program` and
+    /// `dest_token_program`; batch vaults of other token programs separately.
+    ///
+    /// With `session_key`, `session_keys_program` and the `instructions` sysvar
+    /// supplied, the keeper's session is charged per executed vault, each
+    /// taking the session's next nonce, so a skipped vault doesn't throw off
+    /// the vaults after it. The session must allow `execute_dca_batch` itself.
+    ///
+    /// Groups whose accounts don't match their vault and vaults failing any
+    /// pre-swap check (not yet due, paused, locked, underfunded, stale price,
+    /// session rejected, compounding buy legs and referred vaults needing
+    /// `execute_dca`, basket vaults needing `execute_basket`, pinned to another
+    /// executor, ...) are skipped with a `VaultSkippedEvent`, as are cycles
+    /// that end without a swap (stop-loss, price above the limit). Failures
+    /// after the swap CPI cannot be isolated on Solana and abort the whole
+    /// transaction.
+    pub fn execute_dca_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteDCABatch<'info>>,
+        executions: Vec<BatchExecution>,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        ctx.accounts.config.ensure_not_paused()?;
+        ctx.accounts
+            .config
+            .ensure_keeper_authorized(&ctx.accounts.session_authority.key())?;
+        let fee_ledger_required = ctx.accounts.config.treasury_is_pda();
+
+        let mut remaining: &'info [AccountInfo<'info>] = ctx.remaining_accounts;
+        let mut executed_count: u16 = 0;
+        let mut skipped_count: u16 = 0;
 
-        // Verify slippage protection
         require!(
-            amount_received >= min_amount_out,
-            ErrorCode::SlippageExceeded
+            ctx.accounts.session_key.is_some() == ctx.accounts.session_keys_program.is_some()
+                && ctx.accounts.session_key.is_some() == ctx.accounts.instructions.is_some(),
+            ErrorCode::MissingSessionAccounts
         );
+        let instruction = ctx
+            .accounts
+            .instructions
+            .as_deref()
+            .map(SessionKey::current_instruction)
+            .transpose()?;
 
-        // === State Update Phase ===
-        vault.executed_cycles += 1;
-        vault.total_received += amount_received;
-        vault.last_execution = clock.unix_timestamp;
-        vault.next_execution = clock.unix_timestamp + vault.frequency_seconds;
+        // The session account isn't reloaded after each CPI, so track the
+        // charges made so far on a copy
+        let mut session = ctx.accounts.session_key.as_deref().cloned();
 
-        // Check if all cycles complete
-        if vault.executed_cycles >= vault.total_cycles {
-            vault.status = Vault::STATUS_COMPLETED;
-            msg!("DCA completed - All {} cycles executed", vault.total_cycles);
+        for execution in executions {
+            let group_len = BatchExecution::FIXED_ACCOUNTS + execution.swap_account_count as usize;
+            require!(remaining.len() >= group_len, ErrorCode::InvalidBatchAccounts);
+
+            let (group, rest) = remaining.split_at(group_len);
+            remaining = rest;
+
+            // A bad vault only costs its own entry, not the whole batch
+            let prepared = prepare_batch_entry(
+                group,
+                &ctx.accounts.config,
+                fee_ledger_required,
+                execution.min_amount_out,
+                clock.unix_timestamp,
+            )
+            .and_then(|(entry, plan)| {
+                entry.vault.check_executor(ctx.accounts.session_authority.key())?;
+                require_keys_eq!(
+                    *entry.source_mint.to_account_info().owner,
+                    ctx.accounts.source_token_program.key(),
+                    ErrorCode::InvalidTokenProgram
+                );
+                require_keys_eq!(
+                    *entry.dest_mint.to_account_info().owner,
+                    ctx.accounts.dest_token_program.key(),
+                    ErrorCode::InvalidTokenProgram
+                );
+                let nonce = next_session_nonce(session.as_ref());
+                let charged_session = check_session_charge(
+                    &entry.vault,
+                    entry.vault.key(),
+                    session.as_ref(),
+                    ctx.accounts.session_authority.key(),
+                    instruction,
+                    ctx.accounts.swap_program_account.key(),
+                    &plan,
+                    nonce,
+                    clock.unix_timestamp,
+                )?;
+                Ok((entry, plan, charged_session, nonce))
+            });
+
+            let (mut entry, plan, charged_session, nonce) = match prepared {
+                Ok(prepared) => prepared,
+                Err(err) => {
+                    msg!("Skipping vault {}: {}", group[0].key(), err);
+                    emit_cpi!(VaultSkippedEvent {
+                        vault: group[0].key(),
+                        error_code: ProgramError::from(err).into(),
+                        timestamp: clock.unix_timestamp,
+                    });
+                    skipped_count += 1;
+                    continue;
+                }
+            };
+
+            if let (
+                Some(charged),
+                Some(session_key),
+                Some(session_keys_program),
+                Some(instructions),
+            ) = (
+                charged_session,
+                &ctx.accounts.session_key,
+                &ctx.accounts.session_keys_program,
+                &ctx.accounts.instructions,
+            ) {
+                charge_keeper_session(
+                    session_key,
+                    session_keys_program,
+                    ctx.accounts.session_authority.to_account_info(),
+                    instructions.to_account_info(),
+                    &entry.vault,
+                    ctx.accounts.swap_program_account.key(),
+                    &plan,
+                    nonce,
+                )?;
+                session = Some(charged);
+            }
+
+            let events = execute_cycle(
+                CycleAccounts {
+                    vault: &mut entry.vault,
+                    vault_token_account: &mut entry.vault_token_account,
+                    vault_dest_token_account: &mut entry.vault_dest_token_account,
+                    keeper_token_account: &entry.keeper_token_account,
+                    treasury_token_account: &entry.treasury_token_account,
+                    source_mint: &entry.source_mint,
+                    dest_mint: &entry.dest_mint,
+                    compound_token_account: None,
+                    referrer_token_account: None,
+                    swap_program: ctx.accounts.swap_program_account.to_account_info(),
+                    swap_accounts: &group[BatchExecution::FIXED_ACCOUNTS..],
+                    source_token_program: ctx.accounts.source_token_program.to_account_info(),
+                    dest_token_program: ctx.accounts.dest_token_program.to_account_info(),
+                    config: &ctx.accounts.config,
+                    fee_ledger: entry.fee_ledger.as_deref_mut(),
+                },
+                plan,
+                execution.route_data,
+                &clock,
+            )?;
+
+            // Remaining accounts are not persisted by Anchor
+            entry.vault.exit(&crate::ID)?;
+            if let Some(fee_ledger) = &entry.fee_ledger {
+                fee_ledger.exit(&crate::ID)?;
+            }
+
+            if events.executed.is_some() {
+                executed_count += 1;
+            } else {
+                skipped_count += 1;
+            }
+
+            if let Some(event) = events.take_profit {
+                emit_cpi!(event);
+            }
+            if let Some(event) = events.skipped {
+                emit_cpi!(event);
+            }
+            if let Some(event) = events.low_balance {
+                emit_cpi!(event);
+            }
+            if let Some(event) = events.status_changed {
+                emit_cpi!(event);
+            }
+            if let Some(event) = events.executed {
+                emit_cpi!(event);
+            }
         }
 
-        // === Emit Events ===
-        msg!("DCA executed - Cycle {}/{}", vault.executed_cycles, vault.total_cycles);
-        msg!("Swapped {} → {} tokens", vault.amount_per_cycle, amount_received);
-        msg!("Total received: {}", vault.total_received);
-        msg!("Next execution: {}", vault.next_execution);
+        require!(remaining.is_empty(), ErrorCode::InvalidBatchAccounts);
 
-        // Emit event for indexers/webhooks
-        emit!(DCAExecutedEvent {
-            vault: vault_key,
-            cycle: vault.executed_cycles,
-            amount_in: vault.amount_per_cycle,
-            amount_out: amount_received,
+        msg!("Batch executed {} vaults, skipped {}", executed_count, skipped_count);
+
+        emit_cpi!(BatchExecutedEvent {
+            executed_count,
+            skipped_count,
             timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
 
-    /// Pause vault
-    pub fn pause_vault(ctx: Context<UpdateVault>) -> Result<()> {
+    /// Attach a basket of up to four weighted destination mints to a vault
+    /// Each basket cycle splits `amount_per_cycle` across the legs by weight,
+    /// and the vault can then only be executed with `execute_basket`
+    /// `price_feeds` is empty or pairs each leg with a Pyth feed (default =
+    /// none) that its output is checked against
+    pub fn initialize_basket(
+        ctx: Context<InitializeBasket>,
+        legs: Vec<BasketLeg>,
+        price_feeds: Vec<Pubkey>,
+    ) -> Result<()> {
         let vault = &mut ctx.accounts.vault;
-        vault.status = Vault::STATUS_PAUSED;
-        
-        msg!("Vault paused");
+
+        require!(vault.mode == Vault::MODE_FIXED, ErrorCode::InvalidMode);
+        DcaBasket::validate_legs(vault.source_mint, &legs)?;
+
+        let basket = &mut ctx.accounts.basket;
+        basket.vault = vault.key();
+        basket.legs = [BasketLeg::default(); DcaBasket::MAX_LEGS];
+        basket.legs[..legs.len()].copy_from_slice(&legs);
+        basket.leg_count = legs.len() as u8;
+        basket.bump = ctx.bumps.basket;
+        basket.version = DcaBasket::VERSION;
+        basket.leg_balances = [0; DcaBasket::MAX_LEGS];
+        basket.set_price_feeds(&price_feeds)?;
+
+        vault.is_basket = true;
+
+        msg!("Basket initialized with {} legs", legs.len());
         Ok(())
     }
 
-    /// Resume vault
-    pub fn resume_vault(ctx: Context<UpdateVault>) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
-        
-        require!(
-            vault.status == Vault::STATUS_PAUSED,
-            ErrorCode::VaultNotPaused
-        );
+    /// Replace the Pyth feeds checked against each basket leg's output, one
+    /// per leg in order (default = none); an empty list clears them all
+    pub fn set_basket_price_feeds(
+        ctx: Context<UpdateBasket>,
+        price_feeds: Vec<Pubkey>,
+    ) -> Result<()> {
+        ctx.accounts.basket.set_price_feeds(&price_feeds)?;
 
-        vault.status = Vault::STATUS_ACTIVE;
-        vault.next_execution = Clock::get()?.unix_timestamp + vault.frequency_seconds;
-        
-        msg!("Vault resumed");
+        msg!("Basket price feeds updated");
         Ok(())
     }
 
-    /// Close vault and withdraw remaining funds
-    pub fn close_vault(ctx: Context<CloseVault>) -> Result<()> {
-        let vault = &ctx.accounts.vault;
-        
-        // Transfer all remaining tokens back to owner
-        let vault_balance = ctx.accounts.vault_token_account.amount;
-        
-        if vault_balance > 0 {
-            let seeds = &[
-                b"vault",
-                vault.owner.as_ref(),
-                vault.source_mint.as_ref(),
-                vault.dest_mint.as_ref(),
-                &[vault.bump],
-            ];
-            let signer = &[&seeds[..]];
+    /// Rewrite a basket created before `version` existed in the current
+    /// layout, without leg price feeds
+    /// `remaining_accounts` hold one vault token account per leg, in leg
+    /// order; their balances become what each leg has bought so far
+    pub fn migrate_basket<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MigrateBasket<'info>>,
+    ) -> Result<()> {
+        let basket_info = ctx.accounts.basket.to_account_info();
+        let new_size = 8 + DcaBasket::LEN;
+        let vault_key = ctx.accounts.vault.key();
 
-            let cpi_accounts = Transfer {
-                from: ctx.accounts.vault_token_account.to_account_info(),
-                to: ctx.accounts.owner_token_account.to_account_info(),
-                authority: vault.to_account_info(),
-            };
+        let mut basket = DcaBasket::load_outdated(&basket_info.try_borrow_data()?)?;
+        require_keys_eq!(basket.vault, vault_key, ErrorCode::InvalidBasketAccounts);
+        require!(
+            ctx.remaining_accounts.len() == basket.leg_count as usize,
+            ErrorCode::InvalidBasketAccounts
+        );
 
-            let cpi_program = ctx.accounts.token_program.to_account_info();
-            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        for (index, account) in ctx.remaining_accounts.iter().enumerate() {
+            let leg_token_account = InterfaceAccount::<TokenAccount>::try_from(account)?;
+            require_keys_eq!(
+                leg_token_account.owner,
+                vault_key,
+                ErrorCode::InvalidTokenAccountOwner
+            );
+            require_keys_eq!(
+                leg_token_account.mint,
+                basket.legs[index].dest_mint,
+                ErrorCode::InvalidMint
+            );
+            basket.record_leg_received(index, leg_token_account.amount)?;
+        }
+
+        // Top up rent for the larger account from the owner
+        let required_lamports = Rent::get()?.minimum_balance(new_size);
+        let top_up = required_lamports.saturating_sub(basket_info.lamports());
 
-            token::transfer(cpi_ctx, vault_balance)?;
+        if top_up > 0 {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.owner.to_account_info(),
+                to: basket_info.clone(),
+            };
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+            system_program::transfer(cpi_ctx, top_up)?;
         }
 
-        msg!("Vault closed - {} tokens returned", vault_balance);
+        basket_info.resize(new_size)?;
+
+        let mut data = basket_info.try_borrow_mut_data()?;
+        let mut writer: &mut [u8] = &mut data[..];
+        basket.try_serialize(&mut writer)?;
+
+        msg!("Basket migrated");
+        msg!("New size: {} bytes", new_size);
+
         Ok(())
     }
-}
 
-// ============================================
-// Account Contexts
-// ============================================
+    /// Execute one basket cycle, swapping each leg's share of `amount_per_cycle`
+    ///
+    /// `routes` and `min_amounts_out` hold one entry per basket leg, in order.
+    /// Each leg consumes one group of remaining accounts:
+    ///
+    /// 0. `[writable]` vault token account for the leg's destination mint
+    /// 1. the leg's destination mint
+    /// 2. the leg's Pyth price feed (any account when the leg has none)
+    /// 3. `[writable]` treasury token account for the leg's destination mint
+    /// 4. `[writable]` that treasury account's fee ledger (any account unless
+    ///    the treasury is the program's PDA)
+    /// 5. `swap_account_count` Jupiter route accounts, as for `execute_dca`
+    ///
+    /// A leg with a price feed may take a zero minimum, derived from the
+    /// oracle with the vault's `slippage_bps`, and its output must stay within
+    /// `max_price_deviation_bps` of the oracle; a leg without one needs a
+    /// non-zero minimum. The session charge, expiry, stop-loss, limit-buy
+    /// price and execution window apply as in `execute_dca`, against the
+    /// vault's own price feed, and `max_amount_out` caps every leg.
+    ///
+    /// Each leg pays the protocol fee on its own output, all of it to the
+    /// treasury since referrers earn no share of basket fees. Basket cycles
+    /// do not pay keeper rewards. They record best/worst output and price
+    /// stats on the summed leg output, but not `total_received` or the recent
+    /// price history.
+    pub fn execute_basket<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteBasket<'info>>,
+        routes: Vec<BasketRoute>,
+        min_amounts_out: Vec<u64>,
+        session_nonce: u64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
 
-#[derive(Accounts)]
-pub struct InitializeVault<'info> {
-    #[account(
-        init,
-        payer = owner,
-        space = 166,
-        seeds = [
-            b"vault",
-            owner.key().as_ref(),
-            source_mint.key().as_ref(),
-            dest_mint.key().as_ref(),
-        ],
-        bump
-    )]
-    pub vault: Account<'info, Vault>,
+        require!(
+            ctx.accounts.session_key.is_some() == ctx.accounts.session_keys_program.is_some()
+                && ctx.accounts.session_key.is_some() == ctx.accounts.instructions.is_some(),
+            ErrorCode::MissingSessionAccounts
+        );
+        let instruction = ctx
+            .accounts
+            .instructions
+            .as_deref()
+            .map(SessionKey::current_instruction)
+            .transpose()?;
 
-    #[account(mut)]
-    pub owner: Signer<'info>,
+        // === Validation Phase ===
+        ctx.accounts.config.ensure_not_paused()?;
+        ctx.accounts
+            .config
+            .ensure_keeper_authorized(&ctx.accounts.session_authority.key())?;
+        ctx.accounts.vault.check_executor(ctx.accounts.session_authority.key())?;
 
-    /// CHECK: Source token mint
-    pub source_mint: AccountInfo<'info>,
-    
-    /// CHECK: Destination token mint
-    pub dest_mint: AccountInfo<'info>,
+        let plan = prepare_basket_cycle(
+            &ctx.accounts.vault,
+            &ctx.accounts.basket,
+            routes.len(),
+            &min_amounts_out,
+            ctx.accounts.vault_token_account.amount,
+            ctx.accounts.price_feed.as_ref(),
+            clock.unix_timestamp,
+        )?;
 
-    pub system_program: Program<'info, System>,
-}
+        let charged_session = check_session_charge(
+            &ctx.accounts.vault,
+            ctx.accounts.vault.key(),
+            ctx.accounts.session_key.as_deref(),
+            ctx.accounts.session_authority.key(),
+            instruction,
+            ctx.accounts.swap_program_account.key(),
+            &plan,
+            session_nonce,
+            clock.unix_timestamp,
+        )?;
 
-#[derive(Accounts)]
-pub struct DepositToVault<'info> {
-    #[account(
-        mut,
-        seeds = [
-            b"vault",
-            vault.owner.as_ref(),
-            vault.source_mint.as_ref(),
-            vault.dest_mint.as_ref(),
-        ],
-        bump = vault.bump,
-        has_one = owner,
-    )]
-    pub vault: Account<'info, Vault>,
+        if let (Some(_), Some(session_key), Some(session_keys_program), Some(instructions)) = (
+            charged_session,
+            &ctx.accounts.session_key,
+            &ctx.accounts.session_keys_program,
+            &ctx.accounts.instructions,
+        ) {
+            charge_keeper_session(
+                session_key,
+                session_keys_program,
+                ctx.accounts.session_authority.to_account_info(),
+                instructions.to_account_info(),
+                &ctx.accounts.vault,
+                ctx.accounts.swap_program_account.key(),
+                &plan,
+                session_nonce,
+            )?;
+        }
 
-    #[account(mut)]
-    pub owner: Signer<'info>,
+        let vault_key = ctx.accounts.vault.key();
+        let vault = &mut ctx.accounts.vault;
 
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
+        if plan.expired || plan.stop_loss_triggered {
+            let event = vault.set_status(vault_key, Vault::STATUS_CANCELLED, clock.unix_timestamp);
+            if plan.expired {
+                msg!("Basket vault expired - cancelled, swap skipped");
+            } else {
+                msg!("Stop-loss triggered - basket vault cancelled, swap skipped");
+            }
+            emit_cpi!(event);
+            return Ok(());
+        }
 
-    #[account(mut)]
-    pub vault_token_account: Account<'info, TokenAccount>,
+        if plan.above_max_buy_price {
+            let skipped = vault.skip_priced_out_cycle(vault_key, clock.unix_timestamp)?;
+            msg!("Price above max buy price - basket cycle skipped");
+            emit_cpi!(skipped);
+            return Ok(());
+        }
 
-    pub token_program: Program<'info, Token>,
-}
+        if plan.window_missed {
+            let skipped = vault.skip_missed_window(vault_key, clock.unix_timestamp)?;
+            msg!("Execution window missed - basket cycle skipped");
+            emit_cpi!(skipped);
+            return Ok(());
+        }
 
-#[derive(Accounts)]
-pub struct ExecuteDCA<'info> {
-    #[account(
-        mut,
-        seeds = [
+        // Lock the vault and persist the flag before any CPI, as in `execute_dca`
+        vault.locked = true;
+        vault.exit(&crate::ID)?;
+
+        let seeds = &[
             b"vault",
-            vault.owner.as_ref(),
+            vault.creator.as_ref(),
             vault.source_mint.as_ref(),
             vault.dest_mint.as_ref(),
-        ],
-        bump = vault.bump,
-    )]
-    pub vault: Account<'info, Vault>,
+            &[vault.bump],
+        ];
+        let signer = &[&seeds[..]];
 
-    /// CHECK: Session key authority (validated in backend)
-    pub session_authority: Signer<'info>,
+        // === Swap Execution Phase ===
+        let legs = ctx.accounts.basket.active_legs().to_vec();
+        let fee_ledger_required = ctx.accounts.config.treasury_is_pda();
+        let mut remaining: &'info [AccountInfo<'info>] = ctx.remaining_accounts;
+        let mut amount_in: u64 = 0;
+        let mut planned_in: u64 = 0;
+        let mut amounts_out: Vec<u64> = Vec::with_capacity(legs.len());
+        let mut protocol_fees: Vec<u64> = Vec::with_capacity(legs.len());
 
-    /// Source token account (tokens to swap from)
-    #[account(mut)]
-    pub vault_token_account: Account<'info, TokenAccount>,
+        let leg_runs = legs.iter().zip(routes).zip(min_amounts_out).enumerate();
+        for (index, ((leg, route), min_amount_out)) in leg_runs {
+            let group_len = BasketRoute::FIXED_ACCOUNTS + route.swap_account_count as usize;
+            require!(remaining.len() >= group_len, ErrorCode::InvalidBasketAccounts);
 
-    /// Destination token account (tokens received from swap)
-    #[account(mut)]
-    pub vault_dest_token_account: Account<'info, TokenAccount>,
+            let (group, rest) = remaining.split_at(group_len);
+            remaining = rest;
 
-    /// CHECK: Swap program account (Jupiter)
-    #[account(mut)]
-    pub swap_program_account: AccountInfo<'info>,
+            let mut leg_token_account = InterfaceAccount::<TokenAccount>::try_from(&group[0])?;
+            require_keys_eq!(
+                leg_token_account.owner,
+                vault_key,
+                ErrorCode::InvalidTokenAccountOwner
+            );
+            require_keys_eq!(leg_token_account.mint, leg.dest_mint, ErrorCode::InvalidMint);
 
-    pub token_program: Program<'info, Token>,
-}
+            let leg_mint = InterfaceAccount::<Mint>::try_from(&group[1])?;
+            require_keys_eq!(leg_mint.key(), leg.dest_mint, ErrorCode::InvalidMint);
 
-#[derive(Accounts)]
-pub struct UpdateVault<'info> {
-    #[account(
-        mut,
-        seeds = [
-            b"vault",
-            vault.owner.as_ref(),
-            vault.source_mint.as_ref(),
-            vault.dest_mint.as_ref(),
-        ],
-        bump = vault.bump,
-        has_one = owner,
-    )]
-    pub vault: Account<'info, Vault>,
+            let treasury_token_account = InterfaceAccount::<TokenAccount>::try_from(&group[3])?;
+            require_keys_eq!(
+                treasury_token_account.owner,
+                ctx.accounts.config.treasury,
+                ErrorCode::InvalidTokenAccountOwner
+            );
+            require_keys_eq!(treasury_token_account.mint, leg.dest_mint, ErrorCode::InvalidMint);
 
-    pub owner: Signer<'info>,
-}
+            // Ledgers are only created at their token account's PDA
+            let mut fee_ledger = if fee_ledger_required {
+                let fee_ledger = Account::<FeeLedger>::try_from(&group[4])?;
+                require_keys_eq!(
+                    fee_ledger.token_account,
+                    treasury_token_account.key(),
+                    ErrorCode::InvalidFeeLedger
+                );
+                Some(fee_ledger)
+            } else {
+                None
+            };
 
-#[derive(Accounts)]
-pub struct CloseVault<'info> {
-    #[account(
-        mut,
-        seeds = [
-            b"vault",
-            vault.owner.as_ref(),
-            vault.source_mint.as_ref(),
-            vault.dest_mint.as_ref(),
-        ],
-        bump = vault.bump,
-        has_one = owner,
-        close = owner
-    )]
-    pub vault: Account<'info, Vault>,
+            let leg_amount = leg.leg_amount(vault.amount_per_cycle)?;
 
-    #[account(mut)]
-    pub owner: Signer<'info>,
+            // A leg with a feed is held to its oracle like a single-destination
+            // cycle, which also lets the keeper leave its minimum to the vault
+            let leg_price = match ctx.accounts.basket.leg_price_feed(index) {
+                Some(feed) => Some(read_oracle_price(Some(&group[2]), feed, clock.unix_timestamp)?),
+                None => None,
+            };
+            let leg_expected_out = |amount: u64, price: &Price| {
+                oracle_expected_out(
+                    amount,
+                    price.price,
+                    price.expo,
+                    vault.source_decimals,
+                    leg_mint.decimals,
+                )
+            };
+            let min_amount_out = match &leg_price {
+                Some(price) if min_amount_out == 0 => {
+                    apply_slippage(leg_expected_out(leg_amount, price)?, vault.slippage_bps)?
+                }
+                _ => min_amount_out,
+            };
 
-    #[account(mut)]
-    pub vault_token_account: Account<'info, TokenAccount>,
+            let source_balance_before = ctx.accounts.vault_token_account.amount;
+            let leg_balance_before = leg_token_account.amount;
 
-    #[account(mut)]
-    pub owner_token_account: Account<'info, TokenAccount>,
+            invoke_swap(
+                ctx.accounts.swap_program_account.to_account_info(),
+                &group[BasketRoute::FIXED_ACCOUNTS..],
+                route.route_data,
+                vault_key,
+                signer,
+            )?;
 
-    pub token_program: Program<'info, Token>,
-}
+            ctx.accounts.vault_token_account.reload()?;
+            let amount_spent = source_balance_before
+                .saturating_sub(ctx.accounts.vault_token_account.amount);
+            require!(amount_spent <= leg_amount, ErrorCode::SwapInputExceeded);
 
-// ============================================
-// State
-// ============================================
+            let amount_received = received_amount(&mut leg_token_account, leg_balance_before)?;
+            check_swap_output(vault_key, amount_received, min_amount_out, clock.unix_timestamp)?;
+            // Every leg buys, so each one is held to the cap
+            check_output_cap(vault.max_amount_out, amount_received)?;
 
-#[account]
+            if let Some(price) = &leg_price {
+                let min_oracle_out = apply_slippage(
+                    leg_expected_out(amount_spent, price)?,
+                    vault.max_price_deviation_bps,
+                )?;
+                require!(
+                    amount_received >= min_oracle_out,
+                    ErrorCode::PriceDeviationExceeded
+                );
+            }
+
+            // === Protocol Fee Phase ===
+            let protocol_fee = ctx.accounts.config.protocol_fee(amount_received)?;
+            if protocol_fee > 0 {
+                let cpi_accounts = TransferChecked {
+                    from: leg_token_account.to_account_info(),
+                    mint: leg_mint.to_account_info(),
+                    to: treasury_token_account.to_account_info(),
+                    authority: vault.to_account_info(),
+                };
+
+                let cpi_ctx = CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    cpi_accounts,
+                    signer,
+                );
+
+                token_interface::transfer_checked(cpi_ctx, protocol_fee, leg_mint.decimals)?;
+                if let Some(fee_ledger) = &mut fee_ledger {
+                    fee_ledger.record_fees_collected(protocol_fee)?;
+                    // Remaining accounts are not persisted by Anchor
+                    fee_ledger.exit(&crate::ID)?;
+                }
+
+                msg!("Basket leg {} - protocol fee collected: {}", leg.dest_mint, protocol_fee);
+            }
+
+            // The fee never reaches the owner, so the leg only books the rest
+            ctx.accounts.basket.record_leg_received(index, amount_received - protocol_fee)?;
+
+            amount_in = amount_in
+                .checked_add(amount_spent)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+            planned_in = planned_in
+                .checked_add(leg_amount)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+            amounts_out.push(amount_received);
+            protocol_fees.push(protocol_fee);
+
+            msg!("Basket leg {} - swapped {} → {}", leg.dest_mint, amount_spent, amount_received);
+        }
+
+        require!(remaining.is_empty(), ErrorCode::InvalidBasketAccounts);
+
+        // === State Update Phase ===
+        vault.executed_cycles = vault
+            .executed_cycles
+            .checked_add(1)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        vault.consecutive_failures = 0;
+        vault.last_execution = clock.unix_timestamp;
+        vault.next_execution = vault.next_execution_after(clock.unix_timestamp)?;
+        vault.locked = false;
+
+        // The legs and their weights never change, so a cycle's summed output
+        // is comparable from one cycle to the next
+        let basket_out = amounts_out
+            .iter()
+            .try_fold(0u64, |total, &amount_out| total.checked_add(amount_out))
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        let basket_fees: u64 = protocol_fees.iter().sum();
+        vault.record_cycle_stats(basket_out - basket_fees, cycle_price(basket_out, planned_in)?)?;
+
+        if vault.executed_cycles >= vault.total_cycles {
+            msg!("Basket DCA completed - All {} cycles executed", vault.total_cycles);
+            let event = vault.set_status(vault_key, Vault::STATUS_COMPLETED, clock.unix_timestamp);
+            emit_cpi!(event);
+        }
+
+        msg!("Basket executed - Cycle {}/{}", vault.executed_cycles, vault.total_cycles);
+
+        emit_cpi!(BasketExecutedEvent {
+            vault: vault_key,
+            cycle: vault.executed_cycles,
+            amount_in,
+            amounts_out,
+            protocol_fees,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Average acquisition price (source per destination token, scaled by 1e6)
+    pub fn get_average_price(ctx: Context<ViewVault>) -> Result<u64> {
+        let vault = &ctx.accounts.vault;
+
+        require!(vault.total_received > 0, ErrorCode::NoExecutionsYet);
+
+        vault.average_price()
+    }
+
+    /// Recent per-cycle prices (dest per source, scaled by 1e6), oldest first
+    pub fn get_recent_prices(ctx: Context<ViewVault>) -> Result<Vec<u64>> {
+        Ok(ctx.accounts.vault.recent_prices())
+    }
+
+    /// Timestamps of the next `n` executions (at most
+    /// `Vault::MAX_SCHEDULE_PREVIEW`), stopping at the last remaining cycle
+    /// and before any expiry; meant to be read via `simulateTransaction`
+    pub fn preview_schedule(ctx: Context<ViewVault>, n: u8) -> Result<Vec<i64>> {
+        ctx.accounts.vault.schedule_preview(n)
+    }
+
+    /// Progress summary for UIs, meant to be read via `simulateTransaction`
+    pub fn get_summary(ctx: Context<ViewVault>) -> Result<VaultSummary> {
+        ctx.accounts.vault.summary()
+    }
+
+    /// Whether the vault can be executed right now
+    /// Intended for keepers to call via `simulateTransaction`; applies the same
+    /// readiness checks as `execute_dca`
+    pub fn is_due(ctx: Context<CheckDue>) -> Result<bool> {
+        let clock = Clock::get()?;
+
+        Ok(cycle_is_due(
+            &ctx.accounts.vault,
+            ctx.accounts.vault_token_account.amount,
+            ctx.accounts.vault_dest_token_account.amount,
+            clock.unix_timestamp,
+        ))
+    }
+
+    /// Classify the vault for monitoring as a set of `Vault::HEALTH_*` flags
+    /// Meant to be read via `simulateTransaction`. `HEALTH_NEAR_EXPIRY` is set
+    /// when the vault expires within one cycle interval.
+    pub fn health_check(ctx: Context<CheckDue>) -> Result<u8> {
+        let clock = Clock::get()?;
+
+        vault_health(
+            &ctx.accounts.vault,
+            ctx.accounts.vault_token_account.amount,
+            ctx.accounts.vault_dest_token_account.amount,
+            clock.unix_timestamp,
+        )
+    }
+
+    /// Pause vault
+    pub fn pause_vault(ctx: Context<UpdateVault>) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        let vault = &mut ctx.accounts.vault;
+        let status_changed = vault.pause(vault_key, Clock::get()?.unix_timestamp)?;
+        
+        msg!("Vault paused");
+
+        emit_cpi!(status_changed);
+
+        Ok(())
+    }
+
+    /// Resume vault
+    /// With `resume_preserves_schedule` the pending slot is kept (or run as
+    /// soon as possible if it passed); otherwise a full interval restarts
+    pub fn resume_vault(ctx: Context<UpdateVault>) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        let vault = &mut ctx.accounts.vault;
+        let status_changed = vault.resume(vault_key, Clock::get()?.unix_timestamp)?;
+        
+        msg!("Vault resumed - Next execution: {}", vault.next_execution);
+
+        emit_cpi!(status_changed);
+
+        Ok(())
+    }
+
+    /// Cancel vault - stops future executions but keeps the account
+    /// so history stays inspectable and funds can still be withdrawn
+    pub fn cancel_vault(ctx: Context<UpdateVault>) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        let vault = &mut ctx.accounts.vault;
+        let status_changed = vault.cancel(vault_key, Clock::get()?.unix_timestamp)?;
+
+        msg!("Vault cancelled");
+
+        emit_cpi!(status_changed);
+
+        Ok(())
+    }
+
+    /// Update cycle size, frequency and/or expiry on a running vault
+    /// Only the fields passed as `Some` are changed; an expiry of 0 removes it
+    /// A new frequency must meet the config's minimum
+    pub fn update_schedule(
+        ctx: Context<UpdateSchedule>,
+        amount_per_cycle: Option<u64>,
+        frequency_seconds: Option<i64>,
+        expiry_timestamp: Option<i64>,
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+
+        require!(
+            vault.status == Vault::STATUS_ACTIVE || vault.status == Vault::STATUS_PAUSED,
+            ErrorCode::VaultAlreadyFinalized
+        );
+
+        if let Some(amount_per_cycle) = amount_per_cycle {
+            require!(amount_per_cycle > 0, ErrorCode::InvalidAmount);
+            vault.amount_per_cycle = amount_per_cycle;
+        }
+
+        if let Some(frequency_seconds) = frequency_seconds {
+            require!(
+                frequency_seconds > 0 && frequency_seconds <= Vault::MAX_FREQUENCY_SECONDS,
+                ErrorCode::InvalidFrequency
+            );
+            ctx.accounts.config.check_frequency_limit(frequency_seconds)?;
+
+            if frequency_seconds != vault.frequency_seconds {
+                vault.frequency_seconds = frequency_seconds;
+                vault.next_execution = vault
+                    .last_execution
+                    .checked_add(frequency_seconds)
+                    .ok_or(ErrorCode::ArithmeticOverflow)?;
+            }
+        }
+
+        if let Some(expiry_timestamp) = expiry_timestamp {
+            Vault::validate_expiry(expiry_timestamp, Clock::get()?.unix_timestamp)?;
+            vault.expiry_timestamp = expiry_timestamp;
+        }
+
+        msg!("Schedule updated - Amount per cycle: {}", vault.amount_per_cycle);
+        msg!("Frequency: {} seconds", vault.frequency_seconds);
+        msg!("Next execution: {}", vault.next_execution);
+        msg!("Expiry: {}", vault.expiry_timestamp);
+
+        emit_cpi!(ScheduleUpdatedEvent {
+            vault: vault.key(),
+            amount_per_cycle: vault.amount_per_cycle,
+            frequency_seconds: vault.frequency_seconds,
+            total_cycles: vault.total_cycles,
+            next_execution: vault.next_execution,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Add more cycles to a vault, reactivating it if it had completed
+    /// The new total must fit the config's cycle limit
+    pub fn extend_cycles(ctx: Context<UpdateSchedule>, additional: u16) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        let vault = &mut ctx.accounts.vault;
+        let clock = Clock::get()?;
+
+        let status_changed = vault.extend_cycles(vault_key, additional, clock.unix_timestamp)?;
+        ctx.accounts.config.check_cycle_limit(vault.total_cycles)?;
+        if let Some(status_changed) = status_changed {
+            emit_cpi!(status_changed);
+        }
+
+        msg!("Cycles extended by {} - Total cycles: {}", additional, vault.total_cycles);
+
+        emit_cpi!(ScheduleUpdatedEvent {
+            vault: vault.key(),
+            amount_per_cycle: vault.amount_per_cycle,
+            frequency_seconds: vault.frequency_seconds,
+            total_cycles: vault.total_cycles,
+            next_execution: vault.next_execution,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Update the flat source-token reward paid to keepers per cycle
+    pub fn update_keeper_reward(ctx: Context<UpdateVault>, keeper_reward: u64) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.keeper_reward = keeper_reward;
+
+        msg!("Keeper reward updated: {}", keeper_reward);
+        Ok(())
+    }
+
+    /// Set the source balance below which a `LowBalanceEvent` is emitted (0 = disabled)
+    pub fn set_low_balance_threshold(
+        ctx: Context<UpdateVault>,
+        low_balance_threshold: u64,
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.low_balance_threshold = low_balance_threshold;
+
+        msg!("Low balance threshold updated: {}", low_balance_threshold);
+        Ok(())
+    }
+
+    /// Rename the vault (zero-padded UTF-8, shown in UIs)
+    pub fn set_label(ctx: Context<UpdateVault>, label: [u8; 32]) -> Result<()> {
+        validate_label(&label)?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.label = label;
+
+        msg!("Vault label updated");
+        Ok(())
+    }
+
+    /// Chain this vault into another for multi-hop DCA (e.g. USDC→SOL→mSOL)
+    /// Each buy leg's output is forwarded to `compound_account`, the next
+    /// vault's source token account, instead of accumulating here
+    pub fn set_compound(
+        ctx: Context<UpdateVault>,
+        compound: bool,
+        compound_account: Pubkey,
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.set_compound(compound, compound_account)?;
+
+        msg!("Compound: {} into {}", compound, compound_account);
+        Ok(())
+    }
+
+    /// Skip the next cycle without swapping, pushing the schedule back one period
+    pub fn skip_cycle(ctx: Context<UpdateVault>) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        let vault = &mut ctx.accounts.vault;
+        let skipped = vault.skip_cycle(vault_key, Clock::get()?.unix_timestamp)?;
+
+        msg!("Cycle skipped - Next execution: {}", vault.next_execution);
+
+        emit_cpi!(skipped);
+
+        Ok(())
+    }
+
+    /// Set the take-profit price (source per destination token, scaled by 1e6)
+    /// Execution completes the vault once the oracle price is at or above it;
+    /// with `take_profit_liquidate` that cycle first swaps all remaining source.
+    /// Pass 0 to disable; requires a configured price feed otherwise.
+    pub fn set_take_profit(
+        ctx: Context<UpdateVault>,
+        take_profit_price: u64,
+        take_profit_liquidate: bool,
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.set_take_profit(take_profit_price, take_profit_liquidate)?;
+
+        msg!("Take-profit price updated: {}", take_profit_price);
+        msg!("Take-profit liquidate: {}", take_profit_liquidate);
+        Ok(())
+    }
+
+    /// Set the limit-buy price (source per destination token, scaled by 1e6)
+    /// Buy cycles are skipped while the oracle price is above it.
+    /// Pass 0 to disable; requires a configured price feed otherwise.
+    pub fn set_max_buy_price(ctx: Context<UpdateVault>, max_buy_price: u64) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.set_max_buy_price(max_buy_price)?;
+
+        msg!("Max buy price updated: {}", max_buy_price);
+        Ok(())
+    }
+
+    /// Name a recovery authority (`Pubkey::default()` removes it) and the
+    /// inactivity after which it may `emergency_withdraw` to the owner
+    pub fn set_recovery(
+        ctx: Context<UpdateVault>,
+        recovery_authority: Pubkey,
+        recovery_delay_seconds: i64,
+    ) -> Result<()> {
+        require!(recovery_delay_seconds >= 0, ErrorCode::InvalidRecoveryDelay);
+
+        let vault = &mut ctx.accounts.vault;
+        vault.recovery_authority = recovery_authority;
+        vault.recovery_delay_seconds = recovery_delay_seconds;
+
+        msg!("Recovery authority updated: {}", recovery_authority);
+        msg!("Recovery delay: {} seconds", recovery_delay_seconds);
+        Ok(())
+    }
+
+    /// Pin execution to a single keeper (`Pubkey::default()` allows any keeper)
+    pub fn set_executor(ctx: Context<UpdateVault>, executor: Pubkey) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.executor = executor;
+
+        msg!("Executor updated: {}", executor);
+        Ok(())
+    }
+
+    /// Change the vault's deposit cap (0 = unlimited)
+    /// The cap can never be set below what has already been deposited
+    pub fn set_deposit_cap(ctx: Context<UpdateVault>, max_deposit: u64) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.set_deposit_cap(max_deposit)?;
+
+        msg!("Deposit cap updated: {}", max_deposit);
+        Ok(())
+    }
+
+    /// Change the minimum time between withdrawals (0 = disabled)
+    /// Lowering it waits out the current cooldown first
+    pub fn set_withdraw_cooldown(
+        ctx: Context<UpdateVault>,
+        withdraw_cooldown_seconds: i64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        vault.set_withdraw_cooldown(withdraw_cooldown_seconds, now)?;
+
+        msg!("Withdraw cooldown updated: {} seconds", withdraw_cooldown_seconds);
+        Ok(())
+    }
+
+    /// Limit how late after `next_execution` a cycle may still run (0 = no limit)
+    /// Later executions skip the cycle and roll the schedule forward
+    pub fn set_execution_window(
+        ctx: Context<UpdateVault>,
+        execution_window_seconds: i64,
+    ) -> Result<()> {
+        require!(execution_window_seconds >= 0, ErrorCode::InvalidExecutionWindow);
+
+        let vault = &mut ctx.accounts.vault;
+        vault.execution_window_seconds = execution_window_seconds;
+
+        msg!("Execution window updated: {} seconds", execution_window_seconds);
+        Ok(())
+    }
+
+    /// Keep the existing schedule on resume instead of restarting a full interval
+    pub fn set_resume_preserves_schedule(
+        ctx: Context<UpdateVault>,
+        resume_preserves_schedule: bool,
+    ) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.resume_preserves_schedule = resume_preserves_schedule;
+
+        msg!("Resume preserves schedule: {}", resume_preserves_schedule);
+        Ok(())
+    }
+
+    /// Let the final cycle swap whatever balance is left when it is short
+    pub fn set_swap_dust(ctx: Context<UpdateVault>, swap_dust: bool) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.swap_dust = swap_dust;
+
+        msg!("Swap dust: {}", swap_dust);
+        Ok(())
+    }
+
+    /// Cap what a single buy swap may deliver, in destination units
+    /// (0 = disabled); on a basket vault it caps every leg
+    /// Outputs above the cap revert instead of being booked as received
+    pub fn set_max_amount_out(ctx: Context<UpdateVault>, max_amount_out: u64) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.max_amount_out = max_amount_out;
+
+        msg!("Max amount out updated: {}", max_amount_out);
+        Ok(())
+    }
+
+    /// Require keepers to pass an explicit non-zero `min_amount_out`
+    pub fn set_require_min_out(ctx: Context<UpdateVault>, require_min_out: bool) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.require_min_out = require_min_out;
+
+        msg!("Require min out: {}", require_min_out);
+        Ok(())
+    }
+
+    /// Set how many consecutive slippage failures auto-pause the vault (0 = never)
+    pub fn set_max_failures(ctx: Context<UpdateVault>, max_failures: u8) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.max_failures = max_failures;
+
+        msg!("Max consecutive failures updated: {}", max_failures);
+        Ok(())
+    }
+
+    /// Update the vault's slippage tolerance
+    pub fn update_slippage(ctx: Context<UpdateVault>, slippage_bps: u16) -> Result<()> {
+        require!(slippage_bps <= Vault::MAX_BPS, ErrorCode::InvalidSlippage);
+
+        let vault = &mut ctx.accounts.vault;
+        vault.slippage_bps = slippage_bps;
+
+        msg!("Slippage updated: {} bps", slippage_bps);
+        Ok(())
+    }
+
+    /// Configure the Pyth price feed used to sanity-check swap prices
+    /// The feed must quote the destination token priced in source token units.
+    /// Pass `Pubkey::default()` to disable the oracle check.
+    pub fn update_price_feed(
+        ctx: Context<UpdateVault>,
+        price_feed: Pubkey,
+        max_price_deviation_bps: u16,
+    ) -> Result<()> {
+        require!(max_price_deviation_bps <= Vault::MAX_BPS, ErrorCode::InvalidSlippage);
+
+        let vault = &mut ctx.accounts.vault;
+        vault.price_feed = price_feed;
+        vault.max_price_deviation_bps = max_price_deviation_bps;
+
+        msg!("Price feed updated: {}", price_feed);
+        msg!("Max price deviation: {} bps", max_price_deviation_bps);
+        Ok(())
+    }
+
+    /// Set the stop-loss price (source per destination token, scaled by 1e6)
+    /// Execution cancels the vault once the oracle price is at or below it.
+    /// Pass 0 to disable; requires a configured price feed otherwise.
+    pub fn set_stop_loss(ctx: Context<UpdateVault>, stop_loss_price: u64) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+
+        require!(
+            stop_loss_price == 0 || vault.price_feed != Pubkey::default(),
+            ErrorCode::MissingPriceFeed
+        );
+
+        vault.stop_loss_price = stop_loss_price;
+
+        msg!("Stop-loss price updated: {}", stop_loss_price);
+        Ok(())
+    }
+
+    /// Require every execution of this vault to charge a keeper session key
+    /// owned by the vault owner
+    pub fn set_require_session(ctx: Context<UpdateVault>, require_session: bool) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        vault.require_session = require_session;
+
+        msg!("Require session: {}", require_session);
+        Ok(())
+    }
+
+    /// Transfer vault ownership to a new wallet
+    ///
+    /// The vault address does NOT change: its PDA seeds are derived from the
+    /// immutable `creator` field fixed at creation. Authorization for all owner
+    /// instructions uses `has_one = owner` against the stored `vault.owner`,
+    /// so the new owner takes over immediately.
+    pub fn transfer_ownership(ctx: Context<TransferOwnership>, new_owner: Pubkey) -> Result<()> {
+        let vault = &mut ctx.accounts.vault;
+        let old_owner = vault.owner;
+
+        vault.owner = new_owner;
+
+        msg!("Vault ownership transferred: {} -> {}", old_owner, new_owner);
+
+        emit_cpi!(OwnershipTransferredEvent {
+            vault: vault.key(),
+            old_owner,
+            new_owner,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw source tokens mid-DCA without stopping the vault
+    /// Fails if less than one cycle would remain, unless `force` is set
+    /// or the vault has been cancelled
+    pub fn withdraw_source(ctx: Context<WithdrawSource>, amount: u64, force: bool) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        vault.record_withdrawal(now)?;
+
+        require!(
+            vault.status == Vault::STATUS_ACTIVE
+                || vault.status == Vault::STATUS_PAUSED
+                || vault.status == Vault::STATUS_CANCELLED,
+            ErrorCode::VaultNotActive
+        );
+
+        let vault_balance = ctx.accounts.vault_token_account.amount;
+        require!(amount <= vault_balance, ErrorCode::InsufficientBalance);
+
+        let remaining = vault_balance - amount;
+        require!(
+            force
+                || vault.status == Vault::STATUS_CANCELLED
+                || remaining >= vault.amount_per_cycle,
+            ErrorCode::WithdrawalLeavesInsufficientBalance
+        );
+
+        vault.check_withdraw_accounts(
+            ctx.accounts.owner_token_account.is_some(),
+            ctx.accounts.unwrap_account.is_some(),
+        )?;
+
+        if let Some(unwrap_account) = &ctx.accounts.unwrap_account {
+            unwrap_native(
+                UnwrapAccounts {
+                    vault,
+                    vault_token_account: &ctx.accounts.vault_token_account,
+                    unwrap_account,
+                    source_mint: &ctx.accounts.source_mint,
+                    payer: ctx.accounts.owner.to_account_info(),
+                    recipient: ctx.accounts.owner.to_account_info(),
+                    token_program: ctx.accounts.token_program.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                },
+                amount,
+            )?;
+        } else if let Some(owner_token_account) = &ctx.accounts.owner_token_account {
+            let seeds = &[
+                b"vault",
+                vault.creator.as_ref(),
+                vault.source_mint.as_ref(),
+                vault.dest_mint.as_ref(),
+                &[vault.bump],
+            ];
+            let signer = &[&seeds[..]];
+
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.vault_token_account.to_account_info(),
+                mint: ctx.accounts.source_mint.to_account_info(),
+                to: owner_token_account.to_account_info(),
+                authority: vault.to_account_info(),
+            };
+
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+            token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.source_mint.decimals)?;
+        }
+
+        msg!("Withdrew {} source tokens from vault", amount);
+        msg!("Remaining balance: {}", remaining);
+
+        emit_cpi!(SourceWithdrawnEvent {
+            vault: vault.key(),
+            amount,
+            remaining,
+            timestamp: now,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw up to `amount` swapped destination tokens without closing the vault
+    /// Amounts above the balance, including `u64::MAX`, withdraw everything
+    pub fn withdraw_dest(ctx: Context<WithdrawDest>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        vault.record_withdrawal(now)?;
+
+        let dest_balance = ctx.accounts.vault_dest_token_account.amount;
+
+        let withdraw_amount = capped_withdrawal(amount, dest_balance)?;
+
+        let seeds = &[
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+            &[vault.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault_dest_token_account.to_account_info(),
+            mint: ctx.accounts.dest_mint.to_account_info(),
+            to: ctx.accounts.owner_dest_token_account.to_account_info(),
+            authority: vault.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        token_interface::transfer_checked(
+            cpi_ctx,
+            withdraw_amount,
+            ctx.accounts.dest_mint.decimals,
+        )?;
+
+        msg!("Withdrew {} destination tokens from vault", withdraw_amount);
+
+        emit_cpi!(DestWithdrawnEvent {
+            vault: vault.key(),
+            amount: withdraw_amount,
+            timestamp: now,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw tokens bought by one basket leg to the owner
+    /// `amount` is capped at the leg's balance; `close_vault` refuses a basket
+    /// vault until every leg's purchases have been withdrawn
+    pub fn withdraw_basket_leg(ctx: Context<WithdrawBasketLeg>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let now = Clock::get()?.unix_timestamp;
+        let vault = &mut ctx.accounts.vault;
+        vault.record_withdrawal(now)?;
+
+        let leg_balance = ctx.accounts.vault_leg_token_account.amount;
+
+        let withdraw_amount = capped_withdrawal(amount, leg_balance)?;
+
+        let seeds = &[
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+            &[vault.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.vault_leg_token_account.to_account_info(),
+            mint: ctx.accounts.leg_mint.to_account_info(),
+            to: ctx.accounts.owner_leg_token_account.to_account_info(),
+            authority: vault.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        token_interface::transfer_checked(
+            cpi_ctx,
+            withdraw_amount,
+            ctx.accounts.leg_mint.decimals,
+        )?;
+
+        ctx.accounts
+            .basket
+            .record_leg_withdrawn(ctx.accounts.leg_mint.key(), withdraw_amount);
+
+        msg!("Withdrew {} basket leg tokens from vault", withdraw_amount);
+
+        emit_cpi!(BasketLegWithdrawnEvent {
+            vault: vault.key(),
+            dest_mint: ctx.accounts.leg_mint.key(),
+            amount: withdraw_amount,
+            timestamp: now,
+        });
+
+        Ok(())
+    }
+
+    /// Sweep both source and destination balances back to the owner and
+    /// cancel the vault, regardless of its current status
+    /// The withdraw cooldown doesn't apply, so recovery is never blocked by it
+    /// The recovery authority may sign instead of the owner once the vault has
+    /// been inactive for its recovery delay; funds still go to the owner
+    pub fn emergency_withdraw(ctx: Context<EmergencyWithdraw>) -> Result<()> {
+        let vault_key = ctx.accounts.vault.key();
+        let vault = &mut ctx.accounts.vault;
+        let clock = Clock::get()?;
+
+        vault.check_emergency_authority(ctx.accounts.authority.key(), clock.unix_timestamp)?;
+
+        let seeds = &[
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+            &[vault.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let source_balance = ctx.accounts.vault_token_account.amount;
+
+        vault.check_withdraw_accounts(
+            ctx.accounts.owner_token_account.is_some(),
+            ctx.accounts.unwrap_account.is_some(),
+        )?;
+
+        // A native vault's temporary account is closed again even when there is
+        // nothing to unwrap
+        if let Some(unwrap_account) = &ctx.accounts.unwrap_account {
+            unwrap_native(
+                UnwrapAccounts {
+                    vault,
+                    vault_token_account: &ctx.accounts.vault_token_account,
+                    unwrap_account,
+                    source_mint: &ctx.accounts.source_mint,
+                    payer: ctx.accounts.authority.to_account_info(),
+                    recipient: ctx.accounts.owner.to_account_info(),
+                    token_program: ctx.accounts.source_token_program.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                },
+                source_balance,
+            )?;
+        } else if let Some(owner_token_account) = &ctx.accounts.owner_token_account {
+            if source_balance > 0 {
+                let cpi_accounts = TransferChecked {
+                    from: ctx.accounts.vault_token_account.to_account_info(),
+                    mint: ctx.accounts.source_mint.to_account_info(),
+                    to: owner_token_account.to_account_info(),
+                    authority: vault.to_account_info(),
+                };
+
+                let cpi_program = ctx.accounts.source_token_program.to_account_info();
+                let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+                token_interface::transfer_checked(
+                    cpi_ctx,
+                    source_balance,
+                    ctx.accounts.source_mint.decimals,
+                )?;
+            }
+        }
+
+        let dest_balance = ctx.accounts.vault_dest_token_account.amount;
+
+        if dest_balance > 0 {
+            let cpi_accounts = TransferChecked {
+                from: ctx.accounts.vault_dest_token_account.to_account_info(),
+                mint: ctx.accounts.dest_mint.to_account_info(),
+                to: ctx.accounts.owner_dest_token_account.to_account_info(),
+                authority: vault.to_account_info(),
+            };
+
+            let cpi_program = ctx.accounts.dest_token_program.to_account_info();
+            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+            token_interface::transfer_checked(
+                cpi_ctx,
+                dest_balance,
+                ctx.accounts.dest_mint.decimals,
+            )?;
+        }
+
+        let status_changed = vault.force_cancel(vault_key, clock.unix_timestamp);
+
+        msg!("Emergency withdraw - {} source tokens returned", source_balance);
+        msg!("Destination tokens returned: {}", dest_balance);
+
+        emit_cpi!(SourceWithdrawnEvent {
+            vault: vault_key,
+            amount: source_balance,
+            remaining: 0,
+            timestamp: clock.unix_timestamp,
+        });
+
+        emit_cpi!(DestWithdrawnEvent {
+            vault: vault_key,
+            amount: dest_balance,
+            timestamp: clock.unix_timestamp,
+        });
+
+        if let Some(status_changed) = status_changed {
+            emit_cpi!(status_changed);
+        }
+
+        Ok(())
+    }
+
+    /// Close vault and withdraw remaining funds
+    /// Only completed or cancelled vaults can be closed; cancel first otherwise
+    /// The vault's token accounts are closed too, refunding their rent to the owner
+    pub fn close_vault(ctx: Context<CloseVault>) -> Result<()> {
+        let accounts = ctx.accounts;
+
+        sweep_vault(SweepAccounts {
+            vault: &accounts.vault,
+            owner: accounts.owner.to_account_info(),
+            vault_token_account: &mut accounts.vault_token_account,
+            owner_token_account: &accounts.owner_token_account,
+            vault_dest_token_account: &mut accounts.vault_dest_token_account,
+            owner_dest_token_account: &accounts.owner_dest_token_account,
+            source_mint: &accounts.source_mint,
+            dest_mint: &accounts.dest_mint,
+            registry: accounts.registry.as_mut(),
+            basket: accounts.basket.as_ref(),
+            source_token_program: accounts.source_token_program.to_account_info(),
+            dest_token_program: accounts.dest_token_program.to_account_info(),
+        })
+    }
+
+    /// Close vault like `close_vault`, but refund the vault account's rent to
+    /// the `rent_payer` recorded at init; token balances still go to the owner
+    pub fn close_vault_to_payer(ctx: Context<CloseVaultToPayer>) -> Result<()> {
+        let accounts = ctx.accounts;
+
+        sweep_vault(SweepAccounts {
+            vault: &accounts.vault,
+            owner: accounts.owner.to_account_info(),
+            vault_token_account: &mut accounts.vault_token_account,
+            owner_token_account: &accounts.owner_token_account,
+            vault_dest_token_account: &mut accounts.vault_dest_token_account,
+            owner_dest_token_account: &accounts.owner_dest_token_account,
+            source_mint: &accounts.source_mint,
+            dest_mint: &accounts.dest_mint,
+            registry: accounts.registry.as_mut(),
+            basket: accounts.basket.as_ref(),
+            source_token_program: accounts.source_token_program.to_account_info(),
+            dest_token_program: accounts.dest_token_program.to_account_info(),
+        })?;
+
+        // `close` can only name a fixed account, so the refund target read
+        // from the vault is paid out by hand
+        accounts.vault.close(accounts.rent_payer.to_account_info())?;
+
+        msg!("Vault rent refunded to {}", accounts.rent_payer.key());
+        Ok(())
+    }
+
+    /// Create the owner's vault registry, which new vaults are then added to
+    pub fn initialize_registry(ctx: Context<InitializeRegistry>) -> Result<()> {
+        let registry = &mut ctx.accounts.registry;
+
+        registry.owner = ctx.accounts.owner.key();
+        registry.vaults = Vec::new();
+        registry.bump = ctx.bumps.registry;
+
+        msg!("Vault registry initialized for {}", registry.owner);
+        Ok(())
+    }
+
+    /// Pause every active vault passed in `remaining_accounts` (panic button)
+    /// Vaults come from the owner's `VaultRegistry`; ones not active are left as-is
+    pub fn pause_all<'info>(ctx: Context<'_, '_, 'info, 'info, PauseAll<'info>>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let events = pause_owned_vaults(ctx.accounts.owner.key(), ctx.remaining_accounts, now)?;
+
+        let paused_count = events.len();
+
+        for event in events {
+            emit_cpi!(event);
+        }
+
+        msg!("Paused {} vaults", paused_count);
+        Ok(())
+    }
+
+    /// Grow a vault created with any earlier layout (the original 166-byte
+    /// one or an unversioned or older versioned one) to the current `Vault`
+    /// size, filling new fields with defaults
+    pub fn migrate_vault(ctx: Context<MigrateVault>) -> Result<()> {
+        let vault_info = ctx.accounts.vault.to_account_info();
+        let new_size = 8 + Vault::LEN;
+
+        require!(vault_info.data_len() < new_size, ErrorCode::VaultAlreadyMigrated);
+
+        let vault = Vault::load_outdated(
+            &vault_info.try_borrow_data()?,
+            ctx.accounts.source_mint.decimals,
+            ctx.accounts.dest_mint.decimals,
+        )?;
+
+        require_keys_eq!(vault.owner, ctx.accounts.owner.key(), ErrorCode::Unauthorized);
+        require_keys_eq!(
+            ctx.accounts.source_mint.key(),
+            vault.source_mint,
+            ErrorCode::InvalidMint
+        );
+        require_keys_eq!(ctx.accounts.dest_mint.key(), vault.dest_mint, ErrorCode::InvalidMint);
+
+        // Legacy vaults were seeded by their owner, who became the creator
+        let expected = Pubkey::create_program_address(
+            &[
+                b"vault",
+                vault.creator.as_ref(),
+                vault.source_mint.as_ref(),
+                vault.dest_mint.as_ref(),
+                &[vault.bump],
+            ],
+            &crate::ID,
+        )
+        .map_err(|_| error!(ErrorCode::InvalidVaultAccount))?;
+        require_keys_eq!(expected, vault_info.key(), ErrorCode::InvalidVaultAccount);
+
+        // Top up rent for the larger account from the owner
+        let required_lamports = Rent::get()?.minimum_balance(new_size);
+        let top_up = required_lamports.saturating_sub(vault_info.lamports());
+
+        if top_up > 0 {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.owner.to_account_info(),
+                to: vault_info.clone(),
+            };
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+            system_program::transfer(cpi_ctx, top_up)?;
+        }
+
+        vault_info.resize(new_size)?;
+
+        let mut data = vault_info.try_borrow_mut_data()?;
+        let mut writer: &mut [u8] = &mut data[..];
+        vault.try_serialize(&mut writer)?;
+
+        msg!("Vault migrated: {}", vault_info.key());
+        msg!("New size: {} bytes", new_size);
+
+        Ok(())
+    }
+
+    /// Rewrite a config created with any earlier layout in the current one,
+    /// filling new fields with defaults
+    /// Layouts from before `jupiter_program` existed need `set_jupiter_program`
+    /// afterwards
+    pub fn migrate_config(ctx: Context<MigrateConfig>) -> Result<()> {
+        let config_info = ctx.accounts.config.to_account_info();
+        let new_size = 8 + Config::LEN;
+
+        let config = Config::load_outdated(&config_info.try_borrow_data()?)?;
+        require_keys_eq!(config.admin, ctx.accounts.admin.key(), ErrorCode::Unauthorized);
+
+        // Top up rent for a larger account from the admin
+        let required_lamports = Rent::get()?.minimum_balance(new_size);
+        let top_up = required_lamports.saturating_sub(config_info.lamports());
+
+        if top_up > 0 {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.admin.to_account_info(),
+                to: config_info.clone(),
+            };
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+            system_program::transfer(cpi_ctx, top_up)?;
+        }
+
+        config_info.resize(new_size)?;
+
+        let mut data = config_info.try_borrow_mut_data()?;
+        let mut writer: &mut [u8] = &mut data[..];
+        config.try_serialize(&mut writer)?;
+
+        msg!("Config migrated");
+        msg!("New size: {} bytes", new_size);
+
+        Ok(())
+    }
+
+    /// Initialize the global protocol config (fee and treasury)
+    /// Only the program's upgrade authority may call this, so the one-time
+    /// init can't be front-run to install a hostile `jupiter_program`
+    pub fn initialize_config(
+        ctx: Context<InitializeConfig>,
+        fee_bps: u16,
+        treasury: Pubkey,
+        jupiter_program: Pubkey,
+    ) -> Result<()> {
+        require!(fee_bps <= Config::MAX_FEE_BPS, ErrorCode::FeeTooHigh);
+
+        let config = &mut ctx.accounts.config;
+
+        config.admin = ctx.accounts.admin.key();
+        config.fee_bps = fee_bps;
+        config.treasury = treasury;
+        config.jupiter_program = jupiter_program;
+        config.paused = false;
+        config.allowed_mints = [Pubkey::default(); 16];
+        config.allowed_mints_count = 0;
+        config.referral_share_bps = 0;
+        config.authorized_keepers = [Pubkey::default(); Config::MAX_AUTHORIZED_KEEPERS];
+        config.authorized_keepers_count = 0;
+        config.require_whitelisted_keeper = false;
+        config.max_total_cycles = 0;
+        config.min_frequency_seconds = 0;
+        config.bump = ctx.bumps.config;
+        config.version = Config::VERSION;
+
+        msg!("Config initialized - Fee: {} bps", fee_bps);
+        msg!("Treasury: {}", treasury);
+        msg!("Jupiter program: {}", jupiter_program);
+
+        Ok(())
+    }
+
+    /// Update the protocol fee
+    pub fn update_fee(ctx: Context<UpdateConfig>, fee_bps: u16) -> Result<()> {
+        require!(fee_bps <= Config::MAX_FEE_BPS, ErrorCode::FeeTooHigh);
+
+        let config = &mut ctx.accounts.config;
+        config.fee_bps = fee_bps;
+
+        msg!("Protocol fee updated: {} bps", fee_bps);
+        Ok(())
+    }
+
+    /// Update the swap program keepers must route through
+    pub fn set_jupiter_program(ctx: Context<UpdateConfig>, jupiter_program: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.jupiter_program = jupiter_program;
+
+        msg!("Jupiter program updated: {}", jupiter_program);
+        Ok(())
+    }
+
+    /// Add a mint to the destination allowlist
+    /// While the list is empty any destination mint is accepted
+    pub fn add_allowed_mint(ctx: Context<UpdateConfig>, mint: Pubkey) -> Result<()> {
+        ctx.accounts.config.add_allowed_mint(mint)?;
+
+        msg!("Allowed mint added: {}", mint);
+        Ok(())
+    }
+
+    /// Remove a mint from the destination allowlist
+    pub fn remove_allowed_mint(ctx: Context<UpdateConfig>, mint: Pubkey) -> Result<()> {
+        ctx.accounts.config.remove_allowed_mint(mint)?;
+
+        msg!("Allowed mint removed: {}", mint);
+        Ok(())
+    }
+
+    /// Add a keeper to the shared keeper whitelist
+    pub fn add_keeper(ctx: Context<UpdateConfig>, keeper: Pubkey) -> Result<()> {
+        ctx.accounts.config.add_keeper(keeper)?;
+
+        msg!("Keeper authorized: {}", keeper);
+        Ok(())
+    }
+
+    /// Remove a keeper from the shared keeper whitelist
+    pub fn remove_keeper(ctx: Context<UpdateConfig>, keeper: Pubkey) -> Result<()> {
+        ctx.accounts.config.remove_keeper(keeper)?;
+
+        msg!("Keeper removed: {}", keeper);
+        Ok(())
+    }
+
+    /// Require executions to come from a whitelisted keeper
+    pub fn set_require_whitelisted_keeper(
+        ctx: Context<UpdateConfig>,
+        require_whitelisted_keeper: bool,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.require_whitelisted_keeper = require_whitelisted_keeper;
+
+        msg!("Require whitelisted keeper: {}", require_whitelisted_keeper);
+        Ok(())
+    }
+
+    /// Bound the schedules vaults may use (0 disables either limit)
+    /// Vaults are checked when created and when their schedule is changed or
+    /// extended, so existing vaults keep running
+    pub fn update_limits_config(
+        ctx: Context<UpdateConfig>,
+        max_total_cycles: u16,
+        min_frequency_seconds: i64,
+    ) -> Result<()> {
+        require!(
+            max_total_cycles <= Vault::MAX_TOTAL_CYCLES,
+            ErrorCode::InvalidCycleCount
+        );
+        require!(
+            (0..=Vault::MAX_FREQUENCY_SECONDS).contains(&min_frequency_seconds),
+            ErrorCode::InvalidFrequency
+        );
+
+        let config = &mut ctx.accounts.config;
+        config.max_total_cycles = max_total_cycles;
+        config.min_frequency_seconds = min_frequency_seconds;
+
+        msg!("Max total cycles: {}", max_total_cycles);
+        msg!("Min frequency: {} seconds", min_frequency_seconds);
+        Ok(())
+    }
+
+    /// Start tallying the fees paid into a treasury PDA token account
+    /// Executions paying fees to that account must pass its ledger
+    pub fn initialize_fee_ledger(ctx: Context<InitializeFeeLedger>) -> Result<()> {
+        let fee_ledger = &mut ctx.accounts.fee_ledger;
+        fee_ledger.token_account = ctx.accounts.treasury_token_account.key();
+        fee_ledger.mint = ctx.accounts.treasury_token_account.mint;
+        fee_ledger.total_fees_collected = 0;
+        fee_ledger.total_fees_withdrawn = 0;
+        fee_ledger.bump = ctx.bumps.fee_ledger;
+
+        msg!("Fee ledger initialized for {}", fee_ledger.token_account);
+        Ok(())
+    }
+
+    /// Move `amount` collected fees out of a treasury PDA token account
+    /// Fees accrue there when `config.treasury` is set to the `[b"treasury"]` PDA
+    pub fn withdraw_treasury(ctx: Context<WithdrawTreasury>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        ctx.accounts.fee_ledger.record_fees_withdrawn(amount)?;
+
+        let seeds = &[b"treasury".as_ref(), &[ctx.bumps.treasury]];
+        let signer = &[&seeds[..]];
+
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.treasury_token_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.destination_token_account.to_account_info(),
+            authority: ctx.accounts.treasury.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+        msg!("Withdrew {} treasury fees", amount);
+        msg!("Total fees withdrawn: {}", ctx.accounts.fee_ledger.total_fees_withdrawn);
+        Ok(())
+    }
+
+    /// Update the share of the protocol fee paid to vault referrers
+    pub fn set_referral_share(ctx: Context<UpdateConfig>, referral_share_bps: u16) -> Result<()> {
+        require!(
+            referral_share_bps <= Vault::MAX_BPS,
+            ErrorCode::InvalidReferralShare
+        );
+
+        let config = &mut ctx.accounts.config;
+        config.referral_share_bps = referral_share_bps;
+
+        msg!("Referral share updated: {} bps", referral_share_bps);
+        Ok(())
+    }
+
+    /// Halt or resume all executions protocol-wide (admin circuit breaker)
+    pub fn set_global_pause(ctx: Context<UpdateConfig>, paused: bool) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.paused = paused;
+
+        msg!("Global pause set: {}", paused);
+
+        emit!(GlobalPauseEvent {
+            admin: ctx.accounts.admin.key(),
+            paused,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+}
+
+// ============================================
+// Helpers
+// ============================================
+
+/// Clamp a withdrawal request to the available balance
+fn capped_withdrawal(amount: u64, balance: u64) -> Result<u64> {
+    let withdraw_amount = amount.min(balance);
+    require!(withdraw_amount > 0, ErrorCode::InsufficientBalance);
+    Ok(withdraw_amount)
+}
+
+/// Reload `account` and return what it gained since `balance_before`, i.e.
+/// what actually arrived net of any Token-2022 transfer fee
+fn received_amount(
+    account: &mut InterfaceAccount<TokenAccount>,
+    balance_before: u64,
+) -> Result<u64> {
+    account.reload()?;
+    Ok(account.amount.saturating_sub(balance_before))
+}
+
+/// Accounts needed to set up a freshly created vault, shared by
+/// `initialize_vault` and `initialize_and_deposit`
+struct NewVaultAccounts<'a, 'info> {
+    vault: &'a mut Account<'info, Vault>,
+    owner: Pubkey,
+    payer: Pubkey,
+    source_mint: &'a InterfaceAccount<'info, Mint>,
+    dest_mint: &'a InterfaceAccount<'info, Mint>,
+    config: &'a Config,
+    registry: Option<&'a mut Account<'info, VaultRegistry>>,
+    slot_hashes: Option<&'a AccountInfo<'info>>,
+    bump: u8,
+}
+
+/// Validate `params` and write the initial state of a vault created at `now`
+/// Returns the event for the caller to emit via `emit_cpi!`
+fn process_initialize_vault(
+    accounts: NewVaultAccounts<'_, '_>,
+    params: VaultParams,
+    now: i64,
+) -> Result<VaultInitializedEvent> {
+    let NewVaultAccounts {
+        vault,
+        owner,
+        payer,
+        source_mint,
+        dest_mint,
+        config,
+        registry,
+        slot_hashes,
+        bump,
+    } = accounts;
+    let VaultParams {
+        amount_per_cycle,
+        frequency_seconds,
+        total_cycles,
+        slippage_bps,
+        keeper_reward,
+        mode,
+        percent_bps,
+        catch_up,
+        max_delay_seconds,
+        low_balance_threshold,
+        label,
+        max_deposit,
+        withdraw_cooldown_seconds,
+        executor,
+        referrer,
+        jitter_seconds,
+        expiry_timestamp,
+        recovery_authority,
+        recovery_delay_seconds,
+        min_initial_deposit,
+    } = params;
+
+    validate_new_vault(
+        &source_mint.key(),
+        &dest_mint.key(),
+        amount_per_cycle,
+        frequency_seconds,
+        total_cycles,
+    )?;
+    config.check_vault_limits(frequency_seconds, total_cycles)?;
+    require!(slippage_bps <= Vault::MAX_BPS, ErrorCode::InvalidSlippage);
+    require!(
+        matches!(
+            mode,
+            Vault::MODE_FIXED | Vault::MODE_PERCENT | Vault::MODE_ALTERNATE | Vault::MODE_SELL
+        ),
+        ErrorCode::InvalidMode
+    );
+    require!(
+        percent_bps <= Vault::MAX_BPS
+            && (matches!(mode, Vault::MODE_FIXED | Vault::MODE_SELL) || percent_bps > 0),
+        ErrorCode::InvalidPercent
+    );
+    require!(max_delay_seconds >= 0, ErrorCode::InvalidMaxDelay);
+    Vault::validate_withdraw_cooldown(withdraw_cooldown_seconds)?;
+    require!(
+        jitter_seconds >= 0 && jitter_seconds < frequency_seconds,
+        ErrorCode::InvalidJitter
+    );
+    Vault::validate_expiry(expiry_timestamp, now)?;
+    require!(recovery_delay_seconds >= 0, ErrorCode::InvalidRecoveryDelay);
+    require!(
+        max_deposit == 0 || min_initial_deposit <= max_deposit,
+        ErrorCode::DepositCapExceeded
+    );
+    validate_label(&label)?;
+    require!(
+        config.is_mint_allowed(&dest_mint.key()),
+        ErrorCode::MintNotAllowed
+    );
+
+    vault.owner = owner;
+    vault.creator = owner;
+    vault.source_mint = source_mint.key();
+    vault.dest_mint = dest_mint.key();
+    vault.source_decimals = source_mint.decimals;
+    vault.dest_decimals = dest_mint.decimals;
+    vault.set_native_flags();
+    vault.amount_per_cycle = amount_per_cycle;
+    vault.frequency_seconds = frequency_seconds;
+    vault.total_cycles = total_cycles;
+    vault.executed_cycles = 0;
+    vault.total_deposited = 0;
+    vault.total_received = 0;
+    vault.cumulative_amount_in = 0;
+    vault.recent_prices = [0; 16];
+    vault.price_cursor = 0;
+    vault.locked = false;
+    vault.stop_loss_price = 0;
+    vault.mode = mode;
+    vault.percent_bps = percent_bps;
+    vault.catch_up = catch_up;
+    vault.max_delay_seconds = max_delay_seconds;
+    vault.low_balance_threshold = low_balance_threshold;
+    vault.consecutive_failures = 0;
+    vault.max_failures = 0;
+    vault.label = label;
+    vault.require_min_out = false;
+    vault.compound = false;
+    vault.compound_account = Pubkey::default();
+    vault.max_deposit = max_deposit;
+    vault.withdraw_cooldown_seconds = withdraw_cooldown_seconds;
+    vault.last_withdraw = 0;
+    vault.skipped_cycles = 0;
+    vault.max_buy_price = 0;
+    vault.executor = executor;
+    vault.swap_dust = false;
+    vault.execution_window_seconds = 0;
+    vault.is_basket = false;
+    vault.referrer = referrer;
+    vault.referral_earned = 0;
+    vault.resume_preserves_schedule = false;
+    vault.take_profit_price = 0;
+    vault.take_profit_liquidate = false;
+    vault.rent_payer = payer;
+    vault.expiry_timestamp = expiry_timestamp;
+    vault.max_amount_out = 0;
+    vault.pause_reason = Vault::PAUSE_REASON_NONE;
+    vault.recovery_authority = recovery_authority;
+    vault.recovery_delay_seconds = recovery_delay_seconds;
+    vault.best_cycle_out = 0;
+    vault.worst_cycle_out = 0;
+    vault.cycles_executed_sum_price = 0;
+    vault.priced_cycles = 0;
+    vault.min_initial_deposit = min_initial_deposit;
+    vault.paused_at = 0;
+    vault.total_paused_seconds = 0;
+    vault.last_execution = now;
+    vault.next_execution = offset_timestamp(
+        offset_timestamp(vault.last_execution, frequency_seconds)?,
+        schedule_jitter(slot_hashes, &vault.key(), jitter_seconds)?,
+    )?;
+    vault.slippage_bps = slippage_bps;
+    vault.keeper_reward = keeper_reward;
+    vault.status = Vault::STATUS_ACTIVE;
+    vault.bump = bump;
+    vault.version = Vault::VERSION;
+
+    if let Some(registry) = registry {
+        registry.register(vault.key());
+    }
+
+    msg!("DCA Vault initialized: {}", vault.key());
+    msg!("Amount per cycle: {}", amount_per_cycle);
+    msg!("Frequency: {} seconds", frequency_seconds);
+    msg!("Total cycles: {}", total_cycles);
+    msg!("Slippage: {} bps", slippage_bps);
+    msg!("Keeper reward: {}", keeper_reward);
+
+    Ok(vault.initialized_event(vault.key()))
+}
+
+/// Accounts needed to credit a deposit, shared by `deposit` and `deposit_from`
+struct DepositAccounts<'a, 'info> {
+    vault: &'a mut Account<'info, Vault>,
+    depositor: AccountInfo<'info>,
+    depositor_token_account: AccountInfo<'info>,
+    vault_token_account: &'a mut InterfaceAccount<'info, TokenAccount>,
+    source_mint: &'a InterfaceAccount<'info, Mint>,
+    token_program: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+}
+
+/// Move `amount` from the depositor into the vault and credit `total_deposited`
+/// Returns the event for the caller to emit via `emit_cpi!`
+fn process_deposit(accounts: DepositAccounts<'_, '_>, amount: u64) -> Result<DepositEvent> {
+    let DepositAccounts {
+        vault,
+        depositor,
+        depositor_token_account,
+        vault_token_account,
+        source_mint,
+        token_program,
+        system_program,
+    } = accounts;
+
+    require!(
+        vault.status == Vault::STATUS_ACTIVE,
+        ErrorCode::VaultNotActive
+    );
+
+    vault.check_deposit_cap(amount)?;
+
+    let balance_before = vault_token_account.amount;
+
+    if vault.is_native_source {
+        // Wrap lamports: move SOL into the WSOL account and sync its balance
+        let cpi_accounts = system_program::Transfer {
+            from: depositor.clone(),
+            to: vault_token_account.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(system_program, cpi_accounts);
+        system_program::transfer(cpi_ctx, amount)?;
+
+        let cpi_accounts = SyncNative {
+            account: vault_token_account.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(token_program, cpi_accounts);
+        token_interface::sync_native(cpi_ctx)?;
+    } else {
+        // Transfer tokens from depositor to vault
+        let cpi_accounts = TransferChecked {
+            from: depositor_token_account,
+            mint: source_mint.to_account_info(),
+            to: vault_token_account.to_account_info(),
+            authority: depositor.clone(),
+        };
+
+        let cpi_ctx = CpiContext::new(token_program, cpi_accounts);
+
+        token_interface::transfer_checked(cpi_ctx, amount, source_mint.decimals)?;
+    }
+
+    // Record what actually arrived, net of any Token-2022 transfer fee
+    let amount_deposited = received_amount(vault_token_account, balance_before)?;
+
+    vault.total_deposited = vault
+        .total_deposited
+        .checked_add(amount_deposited)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+    msg!("Deposited {} tokens to vault", amount_deposited);
+    msg!("Total deposited: {}", vault.total_deposited);
+
+    Ok(vault.deposit_event(
+        vault.key(),
+        depositor.key(),
+        amount_deposited,
+        Clock::get()?.unix_timestamp,
+    ))
+}
+
+/// Accounts needed to run a single DCA cycle, shared by `execute_dca`
+/// and `execute_dca_batch`
+struct CycleAccounts<'a, 'info> {
+    vault: &'a mut Account<'info, Vault>,
+    vault_token_account: &'a mut InterfaceAccount<'info, TokenAccount>,
+    vault_dest_token_account: &'a mut InterfaceAccount<'info, TokenAccount>,
+    keeper_token_account: &'a InterfaceAccount<'info, TokenAccount>,
+    treasury_token_account: &'a InterfaceAccount<'info, TokenAccount>,
+    source_mint: &'a InterfaceAccount<'info, Mint>,
+    dest_mint: &'a InterfaceAccount<'info, Mint>,
+    compound_token_account: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    referrer_token_account: Option<&'a InterfaceAccount<'info, TokenAccount>>,
+    swap_program: AccountInfo<'info>,
+    swap_accounts: &'a [AccountInfo<'info>],
+    source_token_program: AccountInfo<'info>,
+    dest_token_program: AccountInfo<'info>,
+    config: &'a Config,
+    fee_ledger: Option<&'a mut FeeLedger>,
+}
+
+/// Accounts needed to empty a vault before closing it, shared by
+/// `close_vault` and `close_vault_to_payer`
+struct SweepAccounts<'a, 'info> {
+    vault: &'a Account<'info, Vault>,
+    owner: AccountInfo<'info>,
+    vault_token_account: &'a mut InterfaceAccount<'info, TokenAccount>,
+    owner_token_account: &'a InterfaceAccount<'info, TokenAccount>,
+    vault_dest_token_account: &'a mut InterfaceAccount<'info, TokenAccount>,
+    owner_dest_token_account: &'a InterfaceAccount<'info, TokenAccount>,
+    source_mint: &'a InterfaceAccount<'info, Mint>,
+    dest_mint: &'a InterfaceAccount<'info, Mint>,
+    registry: Option<&'a mut Account<'info, VaultRegistry>>,
+    basket: Option<&'a Account<'info, DcaBasket>>,
+    source_token_program: AccountInfo<'info>,
+    dest_token_program: AccountInfo<'info>,
+}
+
+/// Accounts needed to hand a native vault's WSOL back as SOL, shared by
+/// `withdraw_source` and `emergency_withdraw`
+struct UnwrapAccounts<'a, 'info> {
+    vault: &'a Account<'info, Vault>,
+    vault_token_account: &'a InterfaceAccount<'info, TokenAccount>,
+    unwrap_account: &'a InterfaceAccount<'info, TokenAccount>,
+    source_mint: &'a InterfaceAccount<'info, Mint>,
+    /// Signer that funded `unwrap_account`; its rent comes back here
+    payer: AccountInfo<'info>,
+    recipient: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+}
+
+/// Pay `amount` of a native vault's WSOL to the recipient as SOL
+/// The tokens move into the freshly created `unwrap_account`, which is then
+/// closed, so exactly `amount` is unwrapped and any WSOL the recipient holds
+/// elsewhere stays wrapped
+fn unwrap_native(accounts: UnwrapAccounts<'_, '_>, amount: u64) -> Result<()> {
+    let UnwrapAccounts {
+        vault,
+        vault_token_account,
+        unwrap_account,
+        source_mint,
+        payer,
+        recipient,
+        token_program,
+        system_program,
+    } = accounts;
+
+    let seeds = &[
+        b"vault",
+        vault.creator.as_ref(),
+        vault.source_mint.as_ref(),
+        vault.dest_mint.as_ref(),
+        &[vault.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let cpi_accounts = TransferChecked {
+        from: vault_token_account.to_account_info(),
+        mint: source_mint.to_account_info(),
+        to: unwrap_account.to_account_info(),
+        authority: vault.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program.clone(), cpi_accounts, signer);
+    token_interface::transfer_checked(cpi_ctx, amount, source_mint.decimals)?;
+
+    // Closing a WSOL account releases its rent and wrapped lamports together
+    let cpi_accounts = CloseAccount {
+        account: unwrap_account.to_account_info(),
+        destination: payer.clone(),
+        authority: vault.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program, cpi_accounts, signer);
+    token_interface::close_account(cpi_ctx)?;
+
+    // The payer keeps its rent back and forwards the unwrapped SOL
+    if payer.key() != recipient.key() {
+        let cpi_accounts = system_program::Transfer {
+            from: payer,
+            to: recipient,
+        };
+        let cpi_ctx = CpiContext::new(system_program, cpi_accounts);
+        system_program::transfer(cpi_ctx, amount)?;
+    }
+    Ok(())
+}
+
+/// Return a closable vault's token balances to the owner and drop it from
+/// the owner's registry
+fn sweep_vault(accounts: SweepAccounts<'_, '_>) -> Result<()> {
+    let SweepAccounts {
+        vault,
+        owner,
+        vault_token_account,
+        owner_token_account,
+        vault_dest_token_account,
+        owner_dest_token_account,
+        source_mint,
+        dest_mint,
+        registry,
+        basket,
+        source_token_program,
+        dest_token_program,
+    } = accounts;
+
+    vault.ensure_closable()?;
+
+    // A basket vault's `DcaBasket` is closed along with it, once every leg
+    // has been withdrawn
+    match basket {
+        Some(basket) => basket.ensure_legs_empty()?,
+        None => require!(!vault.is_basket, ErrorCode::InvalidBasketAccounts),
+    }
+
+    let seeds = &[
+        b"vault",
+        vault.creator.as_ref(),
+        vault.source_mint.as_ref(),
+        vault.dest_mint.as_ref(),
+        &[vault.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    // Transfer all remaining tokens back to owner
+    // WSOL balances are unwrapped by closing the account below instead
+    let vault_balance = vault_token_account.amount;
+
+    if !vault.is_native_source && vault_balance > 0 {
+        let cpi_accounts = TransferChecked {
+            from: vault_token_account.to_account_info(),
+            mint: source_mint.to_account_info(),
+            to: owner_token_account.to_account_info(),
+            authority: vault.to_account_info(),
+        };
+
+        let cpi_program = source_token_program.clone();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        token_interface::transfer_checked(cpi_ctx, vault_balance, source_mint.decimals)?;
+    }
+
+    // Sweep any swapped tokens still held in the destination account
+    let dest_balance = vault_dest_token_account.amount;
+
+    if !vault.is_native_dest && dest_balance > 0 {
+        let cpi_accounts = TransferChecked {
+            from: vault_dest_token_account.to_account_info(),
+            mint: dest_mint.to_account_info(),
+            to: owner_dest_token_account.to_account_info(),
+            authority: vault.to_account_info(),
+        };
+
+        let cpi_program = dest_token_program.clone();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+
+        token_interface::transfer_checked(cpi_ctx, dest_balance, dest_mint.decimals)?;
+    }
+
+    // Close both vault-owned token accounts so their rent is reclaimed too
+    close_vault_token_account(
+        vault,
+        vault_token_account,
+        source_mint.to_account_info(),
+        vault.is_native_source,
+        owner.clone(),
+        source_token_program,
+        signer,
+    )?;
+    close_vault_token_account(
+        vault,
+        vault_dest_token_account,
+        dest_mint.to_account_info(),
+        vault.is_native_dest,
+        owner.clone(),
+        dest_token_program,
+        signer,
+    )?;
+
+    // Drop the vault from the owner's registry when one is supplied, shrinking
+    // the account and refunding the freed rent to the owner
+    if let Some(registry) = registry {
+        registry.unregister(vault.key());
+
+        let registry_info = registry.to_account_info();
+        let new_size = 8 + VaultRegistry::space(registry.vaults.len());
+        if new_size < registry_info.data_len() {
+            let excess = registry_info
+                .lamports()
+                .saturating_sub(Rent::get()?.minimum_balance(new_size));
+            registry_info.resize(new_size)?;
+            registry_info.sub_lamports(excess)?;
+            owner.add_lamports(excess)?;
+        }
+    }
+
+    msg!("Vault closed - {} tokens returned", vault_balance);
+    msg!("Destination tokens returned: {}", dest_balance);
+    Ok(())
+}
+
+/// Close a vault-owned token account, sending its rent to `destination`
+/// Token-2022 won't close an account still holding withheld transfer fees, so
+/// those are harvested to the mint first
+fn close_vault_token_account<'info>(
+    vault: &Account<'info, Vault>,
+    token_account: &mut InterfaceAccount<'info, TokenAccount>,
+    mint: AccountInfo<'info>,
+    is_native: bool,
+    destination: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    signer: &[&[&[u8]]],
+) -> Result<()> {
+    token_account.reload()?;
+    ensure_token_account_closable(is_native, token_account.amount)?;
+
+    if withheld_transfer_fees(&token_account.to_account_info())? > 0 {
+        let cpi_accounts = HarvestWithheldTokensToMint {
+            token_program_id: token_program.clone(),
+            mint,
+        };
+        let cpi_ctx = CpiContext::new(token_program.clone(), cpi_accounts);
+        harvest_withheld_tokens_to_mint(cpi_ctx, vec![token_account.to_account_info()])?;
+    }
+
+    let cpi_accounts = CloseAccount {
+        account: token_account.to_account_info(),
+        destination,
+        authority: vault.to_account_info(),
+    };
+
+    let cpi_ctx = CpiContext::new_with_signer(token_program, cpi_accounts, signer);
+
+    token_interface::close_account(cpi_ctx)
+}
+
+/// Transfer fees Token-2022 has withheld on `token_account`; always 0 for
+/// SPL Token accounts
+fn withheld_transfer_fees(token_account: &AccountInfo) -> Result<u64> {
+    if *token_account.owner != spl_token_2022::ID {
+        return Ok(0);
+    }
+
+    let data = token_account.try_borrow_data()?;
+    let state = StateWithExtensions::<spl_token_2022::state::Account>::unpack(&data)?;
+    Ok(state
+        .get_extension::<TransferFeeAmount>()
+        .map_or(0, |fees| u64::from(fees.withheld_amount)))
+}
+
+/// WSOL accounts are unwrapped by closing them; any other token account must
+/// be swept to zero first, as the token program only closes empty accounts
+fn ensure_token_account_closable(is_native: bool, amount: u64) -> Result<()> {
+    require!(is_native || amount == 0, ErrorCode::TokenAccountNotEmpty);
+    Ok(())
+}
+
+/// Events produced by a cycle, emitted by the caller with `emit_cpi!` so
+/// they cannot be lost to RPC log truncation
+#[derive(Default)]
+struct CycleEvents {
+    take_profit: Option<TakeProfitTriggeredEvent>,
+    skipped: Option<CycleSkippedEvent>,
+    low_balance: Option<LowBalanceEvent>,
+    status_changed: Option<VaultStatusChangedEvent>,
+    executed: Option<DCAExecutedEvent>,
+}
+
+/// Everything about a cycle decided before the swap
+struct CyclePlan {
+    swap_amount: u64,
+    min_amount_out: u64,
+    /// `min_amount_out` came from the vault's oracle policy, not the keeper
+    derived_min: bool,
+    /// Alternate-mode sell leg: destination tokens are swapped back to source
+    reverse: bool,
+    pay_keeper_reward: bool,
+    oracle_price: Option<Price>,
+    stop_loss_triggered: bool,
+    /// Buy leg priced above the vault's `max_buy_price`
+    above_max_buy_price: bool,
+    /// Buy leg priced at or above the vault's `take_profit_price`; the vault
+    /// completes, after swapping all remaining source with `take_profit_liquidate`
+    take_profit_triggered: bool,
+    /// Keeper arrived after the execution window closed
+    window_missed: bool,
+    /// The oracle's confidence band reaches below the vault's floors, so the
+    /// swap would likely miss them; recorded as a slippage failure instead
+    oracle_uncertain: bool,
+    /// Vault reached its `expiry_timestamp`; it is cancelled instead of trading
+    expired: bool,
+    /// Most the swap may deliver (0 = uncapped), see `Vault::output_cap`
+    output_cap: u64,
+}
+
+impl CyclePlan {
+    /// Plan for a vault past its `expiry_timestamp`, which is only cancelled
+    fn expired() -> Self {
+        CyclePlan {
+            swap_amount: 0,
+            min_amount_out: 0,
+            derived_min: false,
+            reverse: false,
+            pay_keeper_reward: false,
+            oracle_price: None,
+            stop_loss_triggered: false,
+            above_max_buy_price: false,
+            take_profit_triggered: false,
+            window_missed: false,
+            oracle_uncertain: false,
+            expired: true,
+            output_cap: 0,
+        }
+    }
+}
+
+/// One vault's accounts from an `execute_dca_batch` group
+struct BatchEntry<'info> {
+    vault: Account<'info, Vault>,
+    vault_token_account: InterfaceAccount<'info, TokenAccount>,
+    vault_dest_token_account: InterfaceAccount<'info, TokenAccount>,
+    keeper_token_account: InterfaceAccount<'info, TokenAccount>,
+    treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+    source_mint: InterfaceAccount<'info, Mint>,
+    dest_mint: InterfaceAccount<'info, Mint>,
+    price_feed: AccountInfo<'info>,
+    fee_ledger: Option<Account<'info, FeeLedger>>,
+}
+
+impl<'info> BatchEntry<'info> {
+    /// Deserialize a batch group and apply the checks `ExecuteDCA` enforces
+    /// through account constraints
+    fn load(
+        group: &'info [AccountInfo<'info>],
+        treasury: Pubkey,
+        fee_ledger_required: bool,
+    ) -> Result<Self> {
+        let vault = Account::<Vault>::try_from(&group[0])?;
+        let vault_token_account = InterfaceAccount::<TokenAccount>::try_from(&group[1])?;
+        let vault_dest_token_account = InterfaceAccount::<TokenAccount>::try_from(&group[2])?;
+        let keeper_token_account = InterfaceAccount::<TokenAccount>::try_from(&group[3])?;
+        let treasury_token_account = InterfaceAccount::<TokenAccount>::try_from(&group[4])?;
+        let source_mint = InterfaceAccount::<Mint>::try_from(&group[5])?;
+        let dest_mint = InterfaceAccount::<Mint>::try_from(&group[6])?;
+
+        require_keys_eq!(source_mint.key(), vault.source_mint, ErrorCode::InvalidMint);
+        require_keys_eq!(dest_mint.key(), vault.dest_mint, ErrorCode::InvalidMint);
+
+        require_keys_eq!(
+            vault_token_account.owner,
+            vault.key(),
+            ErrorCode::InvalidTokenAccountOwner
+        );
+        require_keys_eq!(
+            vault_token_account.mint,
+            vault.source_mint,
+            ErrorCode::InvalidMint
+        );
+        require_keys_eq!(
+            vault_dest_token_account.owner,
+            vault.key(),
+            ErrorCode::InvalidTokenAccountOwner
+        );
+        require_keys_eq!(
+            vault_dest_token_account.mint,
+            vault.dest_mint,
+            ErrorCode::InvalidMint
+        );
+        require_keys_eq!(
+            keeper_token_account.mint,
+            vault.source_mint,
+            ErrorCode::InvalidMint
+        );
+        require_keys_eq!(
+            treasury_token_account.owner,
+            treasury,
+            ErrorCode::InvalidTokenAccountOwner
+        );
+        require_keys_eq!(
+            treasury_token_account.mint,
+            vault.dest_mint,
+            ErrorCode::InvalidMint
+        );
+
+        // Ledgers are only created at their token account's PDA
+        let fee_ledger = if fee_ledger_required {
+            let fee_ledger = Account::<FeeLedger>::try_from(&group[8])?;
+            require_keys_eq!(
+                fee_ledger.token_account,
+                treasury_token_account.key(),
+                ErrorCode::InvalidFeeLedger
+            );
+            Some(fee_ledger)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            vault,
+            vault_token_account,
+            vault_dest_token_account,
+            keeper_token_account,
+            treasury_token_account,
+            source_mint,
+            dest_mint,
+            price_feed: group[7].clone(),
+            fee_ledger,
+        })
+    }
+}
+
+/// Load a batch group and run every pre-swap check on its vault
+/// Nothing is written, so a failure here can be skipped without aborting
+/// the batch
+fn prepare_batch_entry<'info>(
+    group: &'info [AccountInfo<'info>],
+    config: &Config,
+    fee_ledger_required: bool,
+    min_amount_out: u64,
+    now: i64,
+) -> Result<(BatchEntry<'info>, CyclePlan)> {
+    let entry = BatchEntry::load(group, config.treasury, fee_ledger_required)?;
+    let plan = prepare_cycle(
+        &entry.vault,
+        entry.vault_token_account.amount,
+        entry.vault_dest_token_account.amount,
+        Some(&entry.price_feed),
+        min_amount_out,
+        now,
+    )?;
+
+    // Batches carry no next-hop account, so compounding buy legs are skipped
+    entry.vault.compound_target(plan.reverse, None)?;
+
+    // Nor a referrer account, so referred vaults need `execute_dca`
+    require!(
+        !config.pays_referral(&entry.vault),
+        ErrorCode::MissingReferrerAccount
+    );
+
+    Ok((entry, plan))
+}
+
+/// Check the keeper's session against one cycle of `vault` without writing
+/// anything, returning the session as it will be after the charge
+/// `None` means nothing is charged: no session was supplied, or the stop-loss
+/// cancels the vault without a swap
+/// `instruction` is the transaction's top-level program and discriminator, as
+/// the session program will read them from the instructions sysvar
+#[allow(clippy::too_many_arguments)]
+fn check_session_charge(
+    vault: &Vault,
+    vault_key: Pubkey,
+    session: Option<&SessionKey>,
+    session_authority: Pubkey,
+    instruction: Option<(Pubkey, [u8; 8])>,
+    swap_program: Pubkey,
+    plan: &CyclePlan,
+    nonce: u64,
+    now: i64,
+) -> Result<Option<SessionKey>> {
+    let session = match session {
+        Some(session) => session,
+        None => {
+            require!(!vault.require_session, ErrorCode::SessionRequired);
+            return Ok(None);
+        }
+    };
+
+    require_keys_eq!(session.owner, vault.owner, ErrorCode::InvalidSessionKeyOwner);
+
+    if plan.expired
+        || plan.stop_loss_triggered
+        || plan.above_max_buy_price
+        || plan.window_missed
+        || plan.oracle_uncertain
+        || (plan.take_profit_triggered && !vault.take_profit_liquidate)
+    {
+        return Ok(None);
+    }
+
+    let mut charged = session.clone();
+    charged.check_mint(vault.cycle_input_mint(plan.reverse))?;
+    let (program, discriminator) = instruction.ok_or(ErrorCode::MissingSessionAccounts)?;
+    charged.check_instruction(program, discriminator)?;
+    charged.validate_use(
+        session_authority,
+        swap_program,
+        vault_key,
+        plan.swap_amount,
+        nonce,
+        now,
+    )?;
+    Ok(Some(charged))
+}
+
+/// The nonce the next use of `session` must present (unused without a session)
+fn next_session_nonce(session: Option<&SessionKey>) -> u64 {
+    session.map_or(0, |session| session.nonce.saturating_add(1))
+}
+
+/// Validate and charge the keeper's session key for one cycle of `vault` via CPI
+/// The vault is the session's target account and the cycle's input mint is
+/// the mint being spent; the session program reads the instruction being
+/// authorized from `instructions`
+#[allow(clippy::too_many_arguments)]
+fn charge_keeper_session<'info>(
+    session_key: &Account<'info, SessionKey>,
+    session_keys_program: &Program<'info, SessionKeys>,
+    session_authority: AccountInfo<'info>,
+    instructions: AccountInfo<'info>,
+    vault: &Account<'info, Vault>,
+    swap_program: Pubkey,
+    plan: &CyclePlan,
+    nonce: u64,
+) -> Result<()> {
+    require_keys_eq!(session_key.owner, vault.owner, ErrorCode::InvalidSessionKeyOwner);
+
+    let cpi_accounts = session_keys::cpi::accounts::ValidateSession {
+        session_key: session_key.to_account_info(),
+        session_authority,
+        instructions,
+    };
+    let cpi_ctx = CpiContext::new(session_keys_program.to_account_info(), cpi_accounts);
+
+    session_keys::cpi::validate_session(
+        cpi_ctx,
+        swap_program,
+        vault.key(),
+        plan.swap_amount,
+        nonce,
+        vault.cycle_input_mint(plan.reverse),
+    )
+}
+
+/// Pre-swap stage of a cycle: readiness, oracle price and swap sizing
+/// Nothing is written, so callers can still back out on error
+fn prepare_cycle(
+    vault: &Vault,
+    source_balance: u64,
+    dest_balance: u64,
+    price_feed: Option<&AccountInfo>,
+    min_amount_out: u64,
+    now: i64,
+) -> Result<CyclePlan> {
+    require!(!vault.is_basket, ErrorCode::BasketVault);
+    check_cycle_active(vault)?;
+
+    // An expired vault is cancelled without reading the oracle or swapping,
+    // however early or underfunded this cycle would otherwise be
+    if vault.is_expired(now) {
+        return Ok(CyclePlan::expired());
+    }
+
+    check_cycle_ready(vault, source_balance, dest_balance, now)?;
+
+    require!(
+        !vault.require_min_out || min_amount_out > 0,
+        ErrorCode::MinOutRequired
+    );
+
+    let reverse = vault.is_reverse_cycle();
+    let cycle_amount = vault.cycle_amount(if reverse { dest_balance } else { source_balance })?;
+    let mut swap_amount = cycle_amount;
+
+    // Keeper is only rewarded when the source balance covers both the swap
+    // (if it spends source) and the reward
+    let source_needed = if reverse { 0 } else { swap_amount };
+    let pay_keeper_reward = vault.keeper_reward > 0
+        && source_needed
+            .checked_add(vault.keeper_reward)
+            .is_some_and(|required| source_balance >= required);
+
+    // Read the oracle price up front so the stop-loss can short-circuit the
+    // swap and the price can also set the default minimum
+    let oracle_price = if vault.price_feed != Pubkey::default() {
+        Some(read_oracle_price(price_feed, vault.price_feed, now)?)
+    } else {
+        None
+    };
+
+    let stop_loss_triggered = match &oracle_price {
+        Some(price) => vault.stop_loss_triggered(price)?,
+        None => false,
+    };
+    let take_profit_triggered = match &oracle_price {
+        Some(price) => !reverse && vault.take_profit_triggered(price)?,
+        None => false,
+    };
+    let above_max_buy_price = match &oracle_price {
+        Some(price) => !reverse && !take_profit_triggered && vault.above_max_buy_price(price)?,
+        None => false,
+    };
+
+    // A liquidating take-profit swaps everything left but the keeper reward
+    if take_profit_triggered && vault.take_profit_liquidate {
+        let reserved_reward = if pay_keeper_reward { vault.keeper_reward } else { 0 };
+        swap_amount = source_balance.saturating_sub(reserved_reward);
+    }
+
+    // Fall back to the vault's slippage policy against the oracle when no
+    // minimum is supplied; the route quote comes from the keeper, so it
+    // can't be trusted to set the floor
+    let derived_min = min_amount_out == 0;
+    let min_amount_out = if derived_min {
+        let price = oracle_price.as_ref().ok_or(ErrorCode::MissingPriceFeed)?;
+        let expected_out = cycle_expected_out(vault, reverse, swap_amount, price)?;
+        apply_slippage(expected_out, vault.slippage_bps)?
+    } else {
+        min_amount_out
+    };
+
+    let oracle_uncertain = match &oracle_price {
+        Some(price) => oracle_band_misses_floors(
+            vault,
+            reverse,
+            swap_amount,
+            min_amount_out,
+            derived_min,
+            price,
+        )?,
+        None => false,
+    };
+
+    Ok(CyclePlan {
+        swap_amount,
+        min_amount_out,
+        derived_min,
+        reverse,
+        pay_keeper_reward,
+        oracle_price,
+        stop_loss_triggered,
+        above_max_buy_price,
+        take_profit_triggered,
+        window_missed: vault.missed_execution_window(now),
+        oracle_uncertain,
+        expired: false,
+        output_cap: vault.output_cap(reverse, swap_amount, cycle_amount),
+    })
+}
+
+/// Run one prepared DCA cycle: swap through Jupiter, verify the output, pay
+/// the keeper and protocol fee, then update vault state
+/// Returns the cycle's events for the caller to emit with `emit_cpi!`;
+/// `executed` is set only when a swap happened. An expired vault or a tripped
+/// stop-loss cancels the vault instead, a buy leg priced above the limit is
+/// pushed back a period (the keeper call still succeeds so it is not
+/// penalized), a cycle past its execution window is rolled forward one
+/// interval, a reached take-profit completes the vault (after swapping all
+/// remaining source when liquidating), and a cycle whose oracle is too
+/// uncertain to clear the vault's floors is recorded as a slippage failure
+///
+/// A plain vault (no price feed, fee, referrer or compound hop) already skips
+/// the oracle check and every transfer but the swap through the conditionals
+/// below, so it has no separate fast path; `PLAIN_CYCLE_COMPUTE_BUDGET` in the
+/// tests holds it to that
+fn execute_cycle(
+    accounts: CycleAccounts<'_, '_>,
+    plan: CyclePlan,
+    route_data: Vec<u8>,
+    clock: &Clock,
+) -> Result<CycleEvents> {
+    let CycleAccounts {
+        vault,
+        vault_token_account,
+        vault_dest_token_account,
+        keeper_token_account,
+        treasury_token_account,
+        source_mint,
+        dest_mint,
+        compound_token_account,
+        referrer_token_account,
+        swap_program,
+        swap_accounts,
+        source_token_program,
+        dest_token_program,
+        config,
+        fee_ledger,
+    } = accounts;
+
+    let vault_key = vault.key();
+
+    if plan.expired {
+        let status_changed =
+            vault.set_status(vault_key, Vault::STATUS_CANCELLED, clock.unix_timestamp);
+
+        msg!("Vault expired - cancelled, swap skipped");
+
+        return Ok(CycleEvents {
+            status_changed: Some(status_changed),
+            ..CycleEvents::default()
+        });
+    }
+
+    if plan.stop_loss_triggered {
+        let status_changed =
+            vault.set_status(vault_key, Vault::STATUS_CANCELLED, clock.unix_timestamp);
+
+        msg!("Stop-loss triggered - vault cancelled, swap skipped");
+
+        return Ok(CycleEvents {
+            status_changed: Some(status_changed),
+            ..CycleEvents::default()
+        });
+    }
+
+    if plan.above_max_buy_price {
+        let skipped = vault.skip_priced_out_cycle(vault_key, clock.unix_timestamp)?;
+
+        msg!("Price above max buy price - cycle skipped");
+
+        return Ok(CycleEvents {
+            skipped: Some(skipped),
+            ..CycleEvents::default()
+        });
+    }
+
+    // A keeper past the execution window would trade at a stale point in the
+    // schedule; roll forward one interval instead of swapping
+    if plan.window_missed {
+        let skipped = vault.skip_missed_window(vault_key, clock.unix_timestamp)?;
+
+        msg!("Execution window missed - cycle skipped");
+
+        return Ok(CycleEvents {
+            skipped: Some(skipped),
+            ..CycleEvents::default()
+        });
+    }
+
+    // Take-profit: stop buying once the price reaches the owner's target
+    let take_profit = plan
+        .oracle_price
+        .filter(|_| plan.take_profit_triggered)
+        .map(|price| vault.take_profit_event(vault_key, &price, clock.unix_timestamp))
+        .transpose()?;
+
+    if take_profit.is_some() {
+        if !vault.take_profit_liquidate {
+            let status_changed =
+                vault.set_status(vault_key, Vault::STATUS_COMPLETED, clock.unix_timestamp);
+
+            msg!("Take-profit triggered - vault completed, swap skipped");
+
+            return Ok(CycleEvents {
+                take_profit,
+                status_changed: Some(status_changed),
+                ..CycleEvents::default()
+            });
+        }
+
+        msg!("Take-profit triggered - liquidating {} remaining source", plan.swap_amount);
+    }
+
+    // Compounding vaults forward each buy leg's output to the next hop
+    let forward_output =
+        vault.compound_target(plan.reverse, compound_token_account.map(|account| account.key()))?;
+    let compound_token_account = compound_token_account.filter(|_| forward_output);
+
+    // Referred vaults pay part of each buy leg's protocol fee to the referrer
+    let referrer_token_account = if config.pays_referral(vault) && !plan.reverse {
+        Some(referrer_token_account.ok_or(ErrorCode::MissingReferrerAccount)?)
+    } else {
+        None
+    };
+
+    // === Soft Slippage Check ===
+    // A cycle the oracle already expects to miss the vault's floors is
+    // recorded as a failure and skipped without erroring, so the failure
+    // counter persists. Only the oracle decides this, never the keeper's route
+    // quote. Post-swap checks below stay hard errors because the swap itself
+    // must be rolled back, which would also roll back the counter.
+    if plan.oracle_uncertain {
+        let status_changed = vault.record_slippage_failure(vault_key, clock.unix_timestamp)?;
+
+        msg!("Slippage check failed - failure {}", vault.consecutive_failures);
+        msg!("Next execution: {}", vault.next_execution);
+
+        if status_changed.is_some() {
+            msg!("Vault auto-paused after {} consecutive failures", vault.consecutive_failures);
+        }
+
+        return Ok(CycleEvents {
+            take_profit,
+            status_changed,
+            ..CycleEvents::default()
+        });
+    }
+
+    let CyclePlan {
+        swap_amount,
+        min_amount_out,
+        reverse,
+        pay_keeper_reward,
+        oracle_price,
+        take_profit_triggered,
+        output_cap,
+        ..
+    } = plan;
+
+    // Label the execution before the cycle counter moves on
+    let side = vault.cycle_side();
+
+    // Lock the vault and persist the flag before any CPI so a re-entrant
+    // call sees it; failed executions roll the flag back with the transaction
+    vault.locked = true;
+    vault.exit(&crate::ID)?;
+
+    // === Swap Execution Phase ===
+    // Build vault signer seeds for PDA signing
+    let seeds = &[
+        b"vault",
+        vault.creator.as_ref(),
+        vault.source_mint.as_ref(),
+        vault.dest_mint.as_ref(),
+        &[vault.bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    // Alternate-mode sell legs swap destination tokens back into source
+    let source_balance_before = vault_token_account.amount;
+    let (input_account, output_account, output_is_native, output_token_program) = if reverse {
+        (
+            &mut *vault_dest_token_account,
+            &mut *vault_token_account,
+            vault.is_native_source,
+            &source_token_program,
+        )
+    } else {
+        (
+            &mut *vault_token_account,
+            &mut *vault_dest_token_account,
+            vault.is_native_dest,
+            &dest_token_program,
+        )
+    };
+
+    // Get balances before swap for input/output calculation
+    let input_balance_before = input_account.amount;
+    let output_balance_before = output_account.amount;
+
+    // CPI into Jupiter with the route supplied by the backend
+    // Jupiter handles route optimization and actual DEX interactions
+    invoke_swap(swap_program, swap_accounts, route_data, vault_key, signer)?;
+
+    // === Post-Swap Verification ===
+    // Reload token accounts to get new balances
+    input_account.reload()?;
+    let amount_spent = input_balance_before.saturating_sub(input_account.amount);
+
+    // Route must not spend more than one cycle's worth of input tokens
+    require!(
+        amount_spent <= swap_amount,
+        ErrorCode::SwapInputExceeded
+    );
+
+    // Routes that unwrap into the WSOL account leave raw lamports behind;
+    // sync so they show up in the token balance
+    if output_is_native {
+        let cpi_accounts = SyncNative {
+            account: output_account.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(output_token_program.clone(), cpi_accounts);
+        token_interface::sync_native(cpi_ctx)?;
+    }
+
+    let amount_received = received_amount(output_account, output_balance_before)?;
+
+    check_swap_output(vault_key, amount_received, min_amount_out, clock.unix_timestamp)?;
+    check_output_cap(output_cap, amount_received)?;
+
+    // Verify execution price against the oracle when one is configured
+    if let Some(price) = oracle_price {
+        let expected_out = cycle_expected_out(vault, reverse, amount_spent, &price)?;
+        let min_oracle_out = apply_slippage(expected_out, vault.max_price_deviation_bps)?;
+
+        require!(
+            amount_received >= min_oracle_out,
+            ErrorCode::PriceDeviationExceeded
+        );
+    }
+
+    // === Keeper Reward Phase ===
+    let keeper_reward = if pay_keeper_reward {
+        let cpi_accounts = TransferChecked {
+            from: vault_token_account.to_account_info(),
+            mint: source_mint.to_account_info(),
+            to: keeper_token_account.to_account_info(),
+            authority: vault.to_account_info(),
+        };
+
+        let cpi_ctx = CpiContext::new_with_signer(source_token_program, cpi_accounts, signer);
+
+        token_interface::transfer_checked(cpi_ctx, vault.keeper_reward, source_mint.decimals)?;
+
+        msg!("Keeper rewarded {} tokens", vault.keeper_reward);
+        vault.keeper_reward
+    } else {
+        0
+    };
+
+    // === Protocol Fee Phase ===
+    // The treasury account holds the destination mint, so only buy legs pay
+    let protocol_fee = if reverse {
+        0
+    } else {
+        config.protocol_fee(amount_received)?
+    };
+
+    let referral_fee = match referrer_token_account {
+        Some(_) => config.referral_fee(protocol_fee)?,
+        None => 0,
+    };
+    let treasury_fee = protocol_fee - referral_fee;
+
+    if treasury_fee > 0 {
+        let cpi_accounts = TransferChecked {
+            from: vault_dest_token_account.to_account_info(),
+            mint: dest_mint.to_account_info(),
+            to: treasury_token_account.to_account_info(),
+            authority: vault.to_account_info(),
+        };
+
+        let cpi_ctx =
+            CpiContext::new_with_signer(dest_token_program.clone(), cpi_accounts, signer);
+
+        token_interface::transfer_checked(cpi_ctx, treasury_fee, dest_mint.decimals)?;
+        if let Some(fee_ledger) = fee_ledger {
+            fee_ledger.record_fees_collected(treasury_fee)?;
+        }
+
+        msg!("Protocol fee collected: {}", treasury_fee);
+    }
+
+    if let Some(referrer_token_account) = referrer_token_account.filter(|_| referral_fee > 0) {
+        let cpi_accounts = TransferChecked {
+            from: vault_dest_token_account.to_account_info(),
+            mint: dest_mint.to_account_info(),
+            to: referrer_token_account.to_account_info(),
+            authority: vault.to_account_info(),
+        };
+
+        let cpi_ctx =
+            CpiContext::new_with_signer(dest_token_program.clone(), cpi_accounts, signer);
+
+        token_interface::transfer_checked(cpi_ctx, referral_fee, dest_mint.decimals)?;
+
+        msg!("Referral fee paid: {}", referral_fee);
+    }
+
+    let net_received = amount_received - protocol_fee;
+
+    // === Compound Phase ===
+    // This vault still records the output as received; the next hop never
+    // counts it as a deposit, so nothing is double-counted
+    if let Some(compound_token_account) = compound_token_account {
+        let cpi_accounts = TransferChecked {
+            from: vault_dest_token_account.to_account_info(),
+            mint: dest_mint.to_account_info(),
+            to: compound_token_account.to_account_info(),
+            authority: vault.to_account_info(),
+        };
+
+        let cpi_ctx =
+            CpiContext::new_with_signer(dest_token_program.clone(), cpi_accounts, signer);
+
+        token_interface::transfer_checked(cpi_ctx, net_received, dest_mint.decimals)?;
+
+        msg!("Compounded {} tokens into {}", net_received, compound_token_account.key());
+    }
+
+    // === State Update Phase ===
+    vault.executed_cycles = vault
+        .executed_cycles
+        .checked_add(1)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    vault.referral_earned = vault
+        .referral_earned
+        .checked_add(referral_fee)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    vault.consecutive_failures = 0;
+    vault.last_execution = clock.unix_timestamp;
+    vault.next_execution = vault.next_execution_after(clock.unix_timestamp)?;
+    vault.locked = false;
+
+    // Average price and price history track buy legs only
+    if !reverse {
+        vault.record_buy(amount_spent, net_received)?;
+
+        let cycle_price = cycle_price(amount_received, swap_amount)?;
+        vault.record_price(cycle_price);
+        vault.record_cycle_stats(net_received, cycle_price)?;
+    }
+
+    // Check if all cycles complete; a take-profit liquidation is the last cycle
+    let status_changed = if vault.executed_cycles >= vault.total_cycles || take_profit_triggered {
+        msg!("DCA completed - {} of {} cycles executed", vault.executed_cycles, vault.total_cycles);
+        Some(vault.set_status(vault_key, Vault::STATUS_COMPLETED, clock.unix_timestamp))
+    } else {
+        None
+    };
+
+    // === Emit Events ===
+    msg!("DCA executed - Cycle {}/{}", vault.executed_cycles, vault.total_cycles);
+    msg!("Swapped {} → {} tokens", swap_amount, amount_received);
+    msg!("Total received: {}", vault.total_received);
+    msg!("Next execution: {}", vault.next_execution);
+
+    let average_price = vault.average_price()?;
+
+    // Warn once when the source balance drops below the owner's threshold
+    let remaining_balance = vault_token_account.amount.saturating_sub(keeper_reward);
+    let low_balance = vault.low_balance_event(
+        vault_key,
+        source_balance_before,
+        remaining_balance,
+        clock.unix_timestamp,
+    );
+
+    // Returned for the caller to emit via CPI for indexers/webhooks
+    Ok(CycleEvents {
+        take_profit,
+        skipped: None,
+        low_balance,
+        status_changed,
+        executed: Some(DCAExecutedEvent {
+            vault: vault_key,
+            cycle: vault.executed_cycles,
+            amount_in: swap_amount,
+            amount_out: amount_received,
+            keeper_reward,
+            protocol_fee,
+            average_price,
+            side,
+            timestamp: clock.unix_timestamp,
+        }),
+    })
+}
+
+/// Whether `check_cycle_ready` passes, for keepers polling via `is_due`
+/// An expired active vault is always due, since executing it cancels it
+fn cycle_is_due(vault: &Vault, source_balance: u64, dest_balance: u64, now: i64) -> bool {
+    if vault.is_expired(now) {
+        return check_cycle_active(vault).is_ok();
+    }
+    check_cycle_ready(vault, source_balance, dest_balance, now).is_ok()
+}
+
+/// `Vault::HEALTH_*` flags describing the vault at `now`
+fn vault_health(vault: &Vault, source_balance: u64, dest_balance: u64, now: i64) -> Result<u8> {
+    let mut flags = 0;
+
+    if cycle_is_due(vault, source_balance, dest_balance, now) {
+        flags |= Vault::HEALTH_DUE;
+    }
+
+    let input_balance = if vault.is_reverse_cycle() {
+        dest_balance
+    } else {
+        source_balance
+    };
+    let swap_amount = vault.cycle_amount(input_balance)?;
+    if swap_amount == 0 || input_balance < swap_amount {
+        flags |= Vault::HEALTH_INSUFFICIENT_BALANCE;
+    }
+
+    if vault.status == Vault::STATUS_PAUSED {
+        flags |= Vault::HEALTH_PAUSED;
+    }
+
+    if vault.status == Vault::STATUS_COMPLETED {
+        flags |= Vault::HEALTH_COMPLETED;
+    }
+
+    if vault.expiry_timestamp > 0
+        && vault.expiry_timestamp.saturating_sub(now) <= vault.frequency_seconds
+    {
+        flags |= Vault::HEALTH_NEAR_EXPIRY;
+    }
+
+    if source_balance < vault.low_balance_threshold {
+        flags |= Vault::HEALTH_LOW_BALANCE;
+    }
+
+    Ok(flags)
+}
+
+/// Checks that must pass before a cycle is attempted
+/// Status is checked before timing so paused or finished vaults always report
+/// `VaultNotActive` rather than a timing-dependent error
+fn check_cycle_ready(
+    vault: &Vault,
+    source_balance: u64,
+    dest_balance: u64,
+    now: i64,
+) -> Result<()> {
+    check_cycle_active(vault)?;
+
+    require!(
+        vault.executed_cycles < vault.total_cycles,
+        ErrorCode::AllCyclesCompleted
+    );
+
+    require!(
+        vault.total_deposited >= vault.min_initial_deposit,
+        ErrorCode::DepositTooSmall
+    );
+
+    require!(
+        now >= vault.next_execution,
+        ErrorCode::TooEarlyToExecute
+    );
+
+    // Validate sufficient balance of whichever token this cycle sells
+    let input_balance = if vault.is_reverse_cycle() {
+        dest_balance
+    } else {
+        source_balance
+    };
+    let swap_amount = vault.cycle_amount(input_balance)?;
+    require!(
+        swap_amount > 0 && input_balance >= swap_amount,
+        ErrorCode::InsufficientBalance
+    );
+
+    Ok(())
+}
+
+/// Status checks of `check_cycle_ready`, which are all an expired vault has
+/// to pass before a keeper cancels it
+fn check_cycle_active(vault: &Vault) -> Result<()> {
+    require!(
+        vault.status == Vault::STATUS_ACTIVE,
+        ErrorCode::VaultNotActive
+    );
+
+    require!(!vault.locked, ErrorCode::VaultLocked);
+
+    Ok(())
+}
+
+/// Reject schedules that could never run, would run every slot or are
+/// instantly complete, and vaults swapping a mint into itself
+fn validate_new_vault(
+    source_mint: &Pubkey,
+    dest_mint: &Pubkey,
+    amount_per_cycle: u64,
+    frequency_seconds: i64,
+    total_cycles: u16,
+) -> Result<()> {
+    require!(amount_per_cycle > 0, ErrorCode::InvalidAmount);
+    require!(
+        frequency_seconds > 0 && frequency_seconds <= Vault::MAX_FREQUENCY_SECONDS,
+        ErrorCode::InvalidFrequency
+    );
+    require!(
+        total_cycles > 0 && total_cycles <= Vault::MAX_TOTAL_CYCLES,
+        ErrorCode::InvalidCycleCount
+    );
+    require_keys_neq!(*source_mint, *dest_mint, ErrorCode::InvalidMint);
+    Ok(())
+}
+
+/// CPI into the swap program with a keeper-supplied route, signing as the vault
+fn invoke_swap<'info>(
+    swap_program: AccountInfo<'info>,
+    swap_accounts: &[AccountInfo<'info>],
+    route_data: Vec<u8>,
+    vault_key: Pubkey,
+    signer: &[&[&[u8]]],
+) -> Result<()> {
+    let account_metas: Vec<AccountMeta> = swap_accounts
+        .iter()
+        .map(|account| AccountMeta {
+            pubkey: account.key(),
+            is_signer: account.key() == vault_key || account.is_signer,
+            is_writable: account.is_writable,
+        })
+        .collect();
+
+    let swap_ix = Instruction {
+        program_id: swap_program.key(),
+        accounts: account_metas,
+        data: route_data,
+    };
+
+    let mut swap_account_infos = swap_accounts.to_vec();
+    swap_account_infos.push(swap_program);
+
+    invoke_signed(&swap_ix, &swap_account_infos, signer)?;
+    Ok(())
+}
+
+/// Pre-swap stage of a basket cycle: readiness, per-leg arguments and the
+/// same plan as a single-destination buy, priced on the vault's own feed
+/// `swap_amount` totals the legs; minimums and caps are applied per leg
+fn prepare_basket_cycle(
+    vault: &Vault,
+    basket: &DcaBasket,
+    route_count: usize,
+    min_amounts_out: &[u64],
+    source_balance: u64,
+    price_feed: Option<&AccountInfo>,
+    now: i64,
+) -> Result<CyclePlan> {
+    let legs = basket.active_legs();
+    require!(
+        route_count == legs.len() && min_amounts_out.len() == legs.len(),
+        ErrorCode::InvalidBasketAccounts
+    );
+
+    // As in `prepare_cycle`, expiry cancels the vault before any readiness check
+    check_cycle_active(vault)?;
+    if vault.is_expired(now) {
+        return Ok(CyclePlan::expired());
+    }
+
+    // Only legs with a price feed can fall back to an oracle-derived minimum
+    let minimums_set = min_amounts_out.iter().enumerate().all(|(index, &min_amount_out)| {
+        min_amount_out > 0 || (!vault.require_min_out && basket.leg_price_feed(index).is_some())
+    });
+    require!(minimums_set, ErrorCode::MinOutRequired);
+
+    check_cycle_ready(vault, source_balance, 0, now)?;
+
+    let oracle_price = if vault.price_feed != Pubkey::default() {
+        Some(read_oracle_price(price_feed, vault.price_feed, now)?)
+    } else {
+        None
+    };
+    let stop_loss_triggered = match &oracle_price {
+        Some(price) => vault.stop_loss_triggered(price)?,
+        None => false,
+    };
+    let above_max_buy_price = match &oracle_price {
+        Some(price) => vault.above_max_buy_price(price)?,
+        None => false,
+    };
+
+    let mut swap_amount: u64 = 0;
+    for leg in legs {
+        swap_amount = swap_amount
+            .checked_add(leg.leg_amount(vault.amount_per_cycle)?)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+    }
+
+    Ok(CyclePlan {
+        swap_amount,
+        min_amount_out: 0,
+        derived_min: false,
+        reverse: false,
+        pay_keeper_reward: false,
+        oracle_price,
+        stop_loss_triggered,
+        above_max_buy_price,
+        take_profit_triggered: false,
+        window_missed: vault.missed_execution_window(now),
+        oracle_uncertain: false,
+        expired: false,
+        output_cap: vault.max_amount_out,
+    })
+}
+
+/// Per-cycle price: destination received per source planned, scaled by 1e6
+fn cycle_price(amount_received: u64, swap_amount: u64) -> Result<u64> {
+    let price = (amount_received as u128)
+        .checked_mul(Vault::PRICE_SCALE as u128)
+        .ok_or(ErrorCode::ArithmeticOverflow)?
+        .checked_div(swap_amount as u128)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    Ok(u64::try_from(price).unwrap_or(u64::MAX))
+}
+
+/// Reject a swap delivering more than `output_cap` (0 = uncapped), which
+/// points at a manipulated pool rather than a genuine fill
+fn check_output_cap(output_cap: u64, amount_received: u64) -> Result<()> {
+    require!(
+        output_cap == 0 || amount_received <= output_cap,
+        ErrorCode::SuspiciousOutput
+    );
+    Ok(())
+}
+
+/// Slippage protection on what the swap actually delivered
+/// A shortfall is logged and emitted before reverting: the transaction still
+/// fails, but its logs keep the amounts so keepers need not re-simulate
+fn check_swap_output(
+    vault: Pubkey,
+    amount_received: u64,
+    min_amount_out: u64,
+    now: i64,
+) -> Result<()> {
+    // A swap that produces nothing is never acceptable
+    require!(amount_received > 0, ErrorCode::ZeroSwapOutput);
+
+    if let Some(failure) = slippage_failure(vault, amount_received, min_amount_out, now) {
+        msg!("Slippage exceeded - received {}, minimum {}", amount_received, min_amount_out);
+        // A self-CPI would be rolled back with the failing transaction, so
+        // this one stays in the logs
+        emit!(failure);
+
+        return err!(ErrorCode::SlippageExceeded);
+    }
+    Ok(())
+}
+
+/// The shortfall of a swap that returned less than `min_amount_out`, if any
+fn slippage_failure(
+    vault: Pubkey,
+    amount_received: u64,
+    min_amount_out: u64,
+    now: i64,
+) -> Option<SlippageFailureEvent> {
+    (amount_received < min_amount_out).then_some(SlippageFailureEvent {
+        vault,
+        amount_received,
+        min_amount_out,
+        timestamp: now,
+    })
+}
+
+/// Convert `value` of a `Vault::FREQUENCY_UNIT_*` unit into seconds
+fn frequency_to_seconds(value: u32, unit: u8) -> Result<i64> {
+    let unit_seconds: i64 = match unit {
+        Vault::FREQUENCY_UNIT_SECONDS => 1,
+        Vault::FREQUENCY_UNIT_MINUTES => 60,
+        Vault::FREQUENCY_UNIT_HOURS => 3_600,
+        Vault::FREQUENCY_UNIT_DAYS => 86_400,
+        _ => return err!(ErrorCode::InvalidFrequencyUnit),
+    };
+
+    (value as i64)
+        .checked_mul(unit_seconds)
+        .ok_or_else(|| error!(ErrorCode::ArithmeticOverflow))
+}
+
+/// `timestamp + seconds`, erroring instead of wrapping on overflow
+fn offset_timestamp(timestamp: i64, seconds: i64) -> Result<i64> {
+    timestamp
+        .checked_add(seconds)
+        .ok_or_else(|| error!(ErrorCode::ArithmeticOverflow))
+}
+
+/// Whether the oracle's confidence band reaches below the vault's floors: at
+/// the band's unfavourable edge the cycle's expected output misses the oracle
+/// deviation band or, when the vault derived it, `min_amount_out`
+fn oracle_band_misses_floors(
+    vault: &Vault,
+    reverse: bool,
+    swap_amount: u64,
+    min_amount_out: u64,
+    derived_min: bool,
+    price: &Price,
+) -> Result<bool> {
+    // Buy legs divide by the price and sell legs multiply by it
+    let conf = i64::try_from(price.conf).unwrap_or(i64::MAX);
+    let worst_price = if reverse {
+        price.price.saturating_sub(conf)
+    } else {
+        price.price.saturating_add(conf)
+    };
+    if worst_price <= 0 {
+        return Ok(true);
+    }
+
+    let expected_out = cycle_expected_out(vault, reverse, swap_amount, price)?;
+    let worst_out = cycle_expected_out(
+        vault,
+        reverse,
+        swap_amount,
+        &Price {
+            price: worst_price,
+            ..*price
+        },
+    )?;
+
+    Ok(worst_out < apply_slippage(expected_out, vault.max_price_deviation_bps)?
+        || (derived_min && worst_out < min_amount_out))
+}
+
+/// Load and validate the vault's configured Pyth price
+/// Prices published more than `Vault::MAX_PRICE_AGE_SECONDS` away from `now`
+/// are stale
+fn read_oracle_price(
+    price_feed: Option<&AccountInfo>,
+    expected_feed: Pubkey,
+    now: i64,
+) -> Result<Price> {
+    let price_feed = price_feed.ok_or(ErrorCode::MissingPriceFeed)?;
+    require_keys_eq!(price_feed.key(), expected_feed, ErrorCode::InvalidPriceFeed);
+
+    let feed = SolanaPriceAccount::account_info_to_feed(price_feed)
+        .map_err(|_| ErrorCode::InvalidPriceFeed)?;
+    let price = feed
+        .get_price_no_older_than(now, Vault::MAX_PRICE_AGE_SECONDS)
+        .ok_or(ErrorCode::StalePriceFeed)?;
+
+    Ok(price)
+}
+
+/// Convert an oracle price with exponent `expo` to `Vault::PRICE_SCALE` fixed point
+fn scale_oracle_price(price: i64, expo: i32) -> Result<u64> {
+    require!(price > 0, ErrorCode::InvalidPriceFeed);
+
+    // PRICE_SCALE is 10^6
+    let exponent = expo + 6;
+    let scale = 10u128
+        .checked_pow(exponent.unsigned_abs())
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+    let scaled = if exponent >= 0 {
+        (price as u128)
+            .checked_mul(scale)
+            .ok_or(ErrorCode::ArithmeticOverflow)?
+    } else {
+        price as u128 / scale
+    };
+
+    u64::try_from(scaled).map_err(|_| error!(ErrorCode::ArithmeticOverflow))
+}
+
+/// Expected output of this cycle's swap at the oracle price, in whichever
+/// token the cycle buys
+fn cycle_expected_out(vault: &Vault, reverse: bool, amount_in: u64, price: &Price) -> Result<u64> {
+    let expected_out = if reverse {
+        oracle_expected_source_out
+    } else {
+        oracle_expected_out
+    };
+    expected_out(
+        amount_in,
+        price.price,
+        price.expo,
+        vault.source_decimals,
+        vault.dest_decimals,
+    )
+}
+
+/// Expected destination output for `amount_in` source tokens at the oracle price,
+/// where the price is the destination token quoted in source token units
+fn oracle_expected_out(
+    amount_in: u64,
+    price: i64,
+    expo: i32,
+    source_decimals: u8,
+    dest_decimals: u8,
+) -> Result<u64> {
+    require!(price > 0, ErrorCode::InvalidPriceFeed);
+
+    // expected = amount_in * 10^(dest_decimals - source_decimals - expo) / price
+    let exponent = dest_decimals as i32 - source_decimals as i32 - expo;
+    let scale = 10u128
+        .checked_pow(exponent.unsigned_abs())
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+    let expected = if exponent >= 0 {
+        (amount_in as u128)
+            .checked_mul(scale)
+            .ok_or(ErrorCode::ArithmeticOverflow)?
+            / price as u128
+    } else {
+        (amount_in as u128)
+            / (price as u128)
+                .checked_mul(scale)
+                .ok_or(ErrorCode::ArithmeticOverflow)?
+    };
+
+    u64::try_from(expected).map_err(|_| error!(ErrorCode::ArithmeticOverflow))
+}
+
+/// Expected source output for `amount_in` destination tokens at the oracle
+/// price; the inverse of `oracle_expected_out`
+fn oracle_expected_source_out(
+    amount_in: u64,
+    price: i64,
+    expo: i32,
+    source_decimals: u8,
+    dest_decimals: u8,
+) -> Result<u64> {
+    require!(price > 0, ErrorCode::InvalidPriceFeed);
+
+    // expected = amount_in * price * 10^(source_decimals - dest_decimals + expo)
+    let exponent = source_decimals as i32 - dest_decimals as i32 + expo;
+    let scale = 10u128
+        .checked_pow(exponent.unsigned_abs())
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+    let value = (amount_in as u128)
+        .checked_mul(price as u128)
+        .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+    let expected = if exponent >= 0 {
+        value.checked_mul(scale).ok_or(ErrorCode::ArithmeticOverflow)?
+    } else {
+        value / scale
+    };
+
+    u64::try_from(expected).map_err(|_| error!(ErrorCode::ArithmeticOverflow))
+}
+
+/// Pause each active vault in `vaults`, all of which must belong to `owner`
+/// Vaults not active are left as-is; returns one status event per paused vault
+fn pause_owned_vaults<'info>(
+    owner: Pubkey,
+    vaults: &'info [AccountInfo<'info>],
+    now: i64,
+) -> Result<Vec<VaultStatusChangedEvent>> {
+    let mut events = Vec::with_capacity(vaults.len());
+
+    for account in vaults.iter() {
+        let mut vault = Account::<Vault>::try_from(account)?;
+        require_keys_eq!(vault.owner, owner, ErrorCode::Unauthorized);
+
+        if vault.status != Vault::STATUS_ACTIVE {
+            continue;
+        }
+
+        events.push(vault.pause(account.key(), now)?);
+
+        // Remaining accounts are not persisted by Anchor
+        vault.exit(&crate::ID)?;
+    }
+
+    Ok(events)
+}
+
+/// Reduce `amount` by `slippage_bps` basis points
+fn apply_slippage(amount: u64, slippage_bps: u16) -> Result<u64> {
+    let reduced = (amount as u128)
+        .checked_mul((Vault::MAX_BPS - slippage_bps) as u128)
+        .ok_or(ErrorCode::ArithmeticOverflow)?
+        / Vault::MAX_BPS as u128;
+
+    Ok(reduced as u64)
+}
+
+/// Pseudo-random offset in `[0, jitter_seconds]` derived from the most recent
+/// slot hash and the vault address, so vaults created in one slot still differ
+/// Only meant to spread keeper load, not as a secure source of randomness
+/// The SlotHashes sysvar is only needed when `jitter_seconds` is non-zero
+fn schedule_jitter(
+    slot_hashes: Option<&AccountInfo>,
+    vault_key: &Pubkey,
+    jitter_seconds: i64,
+) -> Result<i64> {
+    if jitter_seconds == 0 {
+        return Ok(0);
+    }
+
+    let slot_hashes = slot_hashes.ok_or(ErrorCode::MissingSlotHashes)?;
+
+    // SlotHashes layout: u64 entry count, then (slot: u64, hash: [u8; 32]) newest first
+    let data = slot_hashes.try_borrow_data()?;
+    require!(data.len() >= 48, ErrorCode::InvalidSlotHashes);
+
+    let seed = hashv(&[&data[16..48], vault_key.as_ref()]).to_bytes();
+    let mut entropy = [0u8; 8];
+    entropy.copy_from_slice(&seed[..8]);
+
+    Ok((u64::from_le_bytes(entropy) % (jitter_seconds as u64 + 1)) as i64)
+}
+
+/// Labels are zero-padded UTF-8 and must not be empty
+fn validate_label(label: &[u8; 32]) -> Result<()> {
+    let len = label.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    require!(len > 0, ErrorCode::InvalidLabel);
+    require!(
+        std::str::from_utf8(&label[..len]).is_ok(),
+        ErrorCode::InvalidLabel
+    );
+    Ok(())
+}
+
+// ============================================
+// Account Contexts
+// ============================================
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct InitializeVault<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Vault::LEN,
+        seeds = [
+            b"vault",
+            owner.key().as_ref(),
+            source_mint.key().as_ref(),
+            dest_mint.key().as_ref(),
+        ],
+        bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    /// Pays the vault's rent; may differ from the owner for custodial setups
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Source token mint
+    pub source_mint: InterfaceAccount<'info, Mint>,
+    
+    /// Destination token mint
+    pub dest_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    /// Owner's vault registry, grown by one entry when supplied; the payer
+    /// funds the extra rent along with the vault's
+    #[account(
+        mut,
+        seeds = [b"registry", owner.key().as_ref()],
+        bump = registry.bump,
+        realloc = 8 + VaultRegistry::space(registry.vaults.len() + 1),
+        realloc::payer = payer,
+        realloc::zero = false,
+    )]
+    pub registry: Option<Account<'info, VaultRegistry>>,
+
+    /// CHECK: SlotHashes sysvar, read as entropy for schedule jitter; only
+    /// needed when `jitter_seconds` is non-zero
+    #[account(address = slot_hashes::ID)]
+    pub slot_hashes: Option<UncheckedAccount<'info>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct InitializeAndDeposit<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Vault::LEN,
+        seeds = [
+            b"vault",
+            owner.key().as_ref(),
+            source_mint.key().as_ref(),
+            dest_mint.key().as_ref(),
+        ],
+        bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    /// Pays the vault's rent; may differ from the owner for custodial setups
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// Source token mint
+    pub source_mint: InterfaceAccount<'info, Mint>,
+
+    /// Destination token mint
+    pub dest_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    /// Owner's vault registry, grown by one entry when supplied; the payer
+    /// funds the extra rent along with the vault's
+    #[account(
+        mut,
+        seeds = [b"registry", owner.key().as_ref()],
+        bump = registry.bump,
+        realloc = 8 + VaultRegistry::space(registry.vaults.len() + 1),
+        realloc::payer = payer,
+        realloc::zero = false,
+    )]
+    pub registry: Option<Account<'info, VaultRegistry>>,
+
+    /// CHECK: SlotHashes sysvar, read as entropy for schedule jitter; only
+    /// needed when `jitter_seconds` is non-zero
+    #[account(address = slot_hashes::ID)]
+    pub slot_hashes: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        constraint = user_token_account.owner == owner.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = user_token_account.mint == source_mint.key() @ ErrorCode::InvalidMint,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = source_mint,
+        associated_token::authority = vault,
+        associated_token::token_program = token_program,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct DepositToVault<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+        has_one = owner,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = user_token_account.owner == owner.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = user_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = vault.source_mint,
+        associated_token::authority = vault,
+        associated_token::token_program = token_program,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = vault.source_mint @ ErrorCode::InvalidMint)]
+    pub source_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct DepositFrom<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = depositor_token_account.owner == depositor.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = depositor_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub depositor_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = vault.source_mint,
+        associated_token::authority = vault,
+        associated_token::token_program = token_program,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = vault.source_mint @ ErrorCode::InvalidMint)]
+    pub source_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct EnsureDestAccount<'info> {
+    #[account(
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: the vault's ATA for `dest_mint`; must not exist yet
+    #[account(
+        mut,
+        address = get_associated_token_address_with_program_id(
+            &vault.key(),
+            &vault.dest_mint,
+            &token_program.key(),
+        ),
+        constraint = vault_dest_token_account.data_is_empty() @ ErrorCode::DestAccountAlreadyExists,
+    )]
+    pub vault_dest_token_account: UncheckedAccount<'info>,
+
+    #[account(address = vault.dest_mint @ ErrorCode::InvalidMint)]
+    pub dest_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ExecuteDCA<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    /// CHECK: Session key authority (validated in backend)
+    pub session_authority: Signer<'info>,
+
+    /// Source token account (tokens to swap from)
+    #[account(
+        mut,
+        constraint = vault_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Destination token account (tokens received from swap)
+    #[account(
+        mut,
+        constraint = vault_dest_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_dest_token_account.mint == vault.dest_mint @ ErrorCode::InvalidMint,
+    )]
+    pub vault_dest_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: Swap program account (Jupiter), invoked via CPI
+    #[account(
+        constraint = swap_program_account.key() == config.jupiter_program @ ErrorCode::InvalidSwapProgram,
+    )]
+    pub swap_program_account: AccountInfo<'info>,
+
+    /// Keeper's source token account receiving the execution reward
+    #[account(
+        mut,
+        constraint = keeper_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub keeper_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: Pyth price feed, required when the vault has one configured
+    pub price_feed: Option<AccountInfo<'info>>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    /// Treasury's destination token account receiving the protocol fee
+    #[account(
+        mut,
+        constraint = treasury_token_account.owner == config.treasury @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = treasury_token_account.mint == vault.dest_mint @ ErrorCode::InvalidMint,
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = vault.source_mint @ ErrorCode::InvalidMint)]
+    pub source_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(address = vault.dest_mint @ ErrorCode::InvalidMint)]
+    pub dest_mint: InterfaceAccount<'info, Mint>,
+
+    /// Next hop's source token account, required for compounding vaults
+    #[account(mut)]
+    pub compound_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Referrer's destination token account, required for referred vaults
+    #[account(
+        mut,
+        constraint = referrer_token_account.owner == vault.referrer @ ErrorCode::InvalidReferrerAccount,
+        constraint = referrer_token_account.mint == vault.dest_mint @ ErrorCode::InvalidMint,
+    )]
+    pub referrer_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Token program owning the source mint
+    #[account(address = *source_mint.to_account_info().owner @ ErrorCode::InvalidTokenProgram)]
+    pub source_token_program: Interface<'info, TokenInterface>,
+
+    /// Token program owning the destination mint
+    #[account(address = *dest_mint.to_account_info().owner @ ErrorCode::InvalidTokenProgram)]
+    pub dest_token_program: Interface<'info, TokenInterface>,
+
+    /// Keeper's session key, validated via CPI when supplied
+    #[account(
+        mut,
+        constraint = session_key.owner == vault.owner @ ErrorCode::InvalidSessionKeyOwner,
+    )]
+    pub session_key: Option<Account<'info, SessionKey>>,
+
+    pub session_keys_program: Option<Program<'info, SessionKeys>>,
+
+    /// CHECK: address-constrained to the instructions sysvar, which the
+    /// session program reads for the instruction it authorizes
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    /// Fees collected into `treasury_token_account`, required when the
+    /// treasury is the program's PDA
+    #[account(
+        mut,
+        seeds = [b"fee_ledger", treasury_token_account.key().as_ref()],
+        bump = fee_ledger.bump,
+    )]
+    pub fee_ledger: Option<Account<'info, FeeLedger>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ExecuteDCABatch<'info> {
+    /// CHECK: Session key authority (validated in backend)
+    pub session_authority: Signer<'info>,
+
+    /// CHECK: Swap program account (Jupiter), invoked via CPI
+    #[account(
+        constraint = swap_program_account.key() == config.jupiter_program @ ErrorCode::InvalidSwapProgram,
+    )]
+    pub swap_program_account: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    /// Token program owning every batched vault's source mint
+    pub source_token_program: Interface<'info, TokenInterface>,
+
+    /// Token program owning every batched vault's destination mint
+    pub dest_token_program: Interface<'info, TokenInterface>,
+
+    /// Keeper's session key, validated via CPI for each executed vault when
+    /// supplied; its owner is checked against each vault's owner
+    #[account(mut)]
+    pub session_key: Option<Account<'info, SessionKey>>,
+
+    pub session_keys_program: Option<Program<'info, SessionKeys>>,
+
+    /// CHECK: address-constrained to the instructions sysvar, which the
+    /// session program reads for the instruction it authorizes
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ViewVault<'info> {
+    #[account(
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, Vault>,
+}
+
+#[derive(Accounts)]
+pub struct CheckDue<'info> {
+    #[account(
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        constraint = vault_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = vault_dest_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_dest_token_account.mint == vault.dest_mint @ ErrorCode::InvalidMint,
+    )]
+    pub vault_dest_token_account: InterfaceAccount<'info, TokenAccount>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UpdateVault<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+        has_one = owner,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    pub owner: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UpdateSchedule<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+        has_one = owner,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    pub owner: Signer<'info>,
+
+    /// Protocol config whose schedule limits the new values must meet
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct TransferOwnership<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+        has_one = owner,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    pub owner: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct WithdrawSource<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+        has_one = owner,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    /// Mutable so unwrapped SOL can be returned for native vaults
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    /// Source token account funding the DCA
+    #[account(
+        mut,
+        constraint = vault_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Owner's token account receiving the source tokens (omit for native
+    /// vaults, which pay out SOL through `unwrap_account`)
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub owner_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = vault.source_mint @ ErrorCode::InvalidMint)]
+    pub source_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// Temporary WSOL account a native vault unwraps the withdrawal through,
+    /// closed again before the instruction ends (omit for other vaults)
+    #[account(
+        init,
+        payer = owner,
+        seeds = [b"unwrap", vault.key().as_ref()],
+        bump,
+        token::mint = source_mint,
+        token::authority = vault,
+        token::token_program = token_program,
+    )]
+    pub unwrap_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct WithdrawDest<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+        has_one = owner,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    pub owner: Signer<'info>,
+
+    /// Destination token account holding swapped tokens
+    #[account(
+        mut,
+        constraint = vault_dest_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_dest_token_account.mint == vault.dest_mint @ ErrorCode::InvalidMint,
+    )]
+    pub vault_dest_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Owner's token account receiving the destination tokens
+    #[account(
+        mut,
+        constraint = owner_dest_token_account.mint == vault.dest_mint @ ErrorCode::InvalidMint,
+    )]
+    pub owner_dest_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = vault.dest_mint @ ErrorCode::InvalidMint)]
+    pub dest_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct EmergencyWithdraw<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+        has_one = owner,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    /// CHECK: vault owner; every token is returned to their accounts, and a
+    /// native vault's source as SOL
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+
+    /// The owner, or the vault's recovery authority once its delay has passed
+    /// Funds a native vault's `unwrap_account` and gets its rent back
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_dest_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_dest_token_account.mint == vault.dest_mint @ ErrorCode::InvalidMint,
+    )]
+    pub vault_dest_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Omitted for native vaults, which pay out SOL through `unwrap_account`
+    #[account(
+        mut,
+        constraint = owner_token_account.owner == owner.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = owner_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub owner_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = owner_dest_token_account.owner == owner.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = owner_dest_token_account.mint == vault.dest_mint @ ErrorCode::InvalidMint,
+    )]
+    pub owner_dest_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = vault.source_mint @ ErrorCode::InvalidMint)]
+    pub source_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(address = vault.dest_mint @ ErrorCode::InvalidMint)]
+    pub dest_mint: InterfaceAccount<'info, Mint>,
+
+    /// Token program owning the source mint
+    #[account(address = *source_mint.to_account_info().owner @ ErrorCode::InvalidTokenProgram)]
+    pub source_token_program: Interface<'info, TokenInterface>,
+
+    /// Token program owning the destination mint
+    #[account(address = *dest_mint.to_account_info().owner @ ErrorCode::InvalidTokenProgram)]
+    pub dest_token_program: Interface<'info, TokenInterface>,
+
+    /// Temporary WSOL account a native vault unwraps its source through,
+    /// closed again before the instruction ends (omit for other vaults)
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"unwrap", vault.key().as_ref()],
+        bump,
+        token::mint = source_mint,
+        token::authority = vault,
+        token::token_program = source_token_program,
+    )]
+    pub unwrap_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseVault<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+        has_one = owner,
+        close = owner
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = owner_token_account.owner == owner.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = owner_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_dest_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_dest_token_account.mint == vault.dest_mint @ ErrorCode::InvalidMint,
+    )]
+    pub vault_dest_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = owner_dest_token_account.owner == owner.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = owner_dest_token_account.mint == vault.dest_mint @ ErrorCode::InvalidMint,
+    )]
+    pub owner_dest_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Writable so Token-2022 fees withheld on the vault's token accounts
+    /// can be harvested before they close
+    #[account(mut, address = vault.source_mint @ ErrorCode::InvalidMint)]
+    pub source_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut, address = vault.dest_mint @ ErrorCode::InvalidMint)]
+    pub dest_mint: InterfaceAccount<'info, Mint>,
+
+    /// Owner's vault registry; the vault is removed from it when supplied
+    #[account(
+        mut,
+        seeds = [b"registry", owner.key().as_ref()],
+        bump = registry.bump,
+    )]
+    pub registry: Option<Account<'info, VaultRegistry>>,
+
+    /// Basket legs of a basket vault, closed with it; required for basket vaults
+    #[account(
+        mut,
+        seeds = [b"basket", vault.key().as_ref()],
+        bump = basket.bump,
+        has_one = vault,
+        close = owner,
+    )]
+    pub basket: Option<Account<'info, DcaBasket>>,
+
+    /// Token program owning the source mint
+    #[account(address = *source_mint.to_account_info().owner @ ErrorCode::InvalidTokenProgram)]
+    pub source_token_program: Interface<'info, TokenInterface>,
+
+    /// Token program owning the destination mint
+    #[account(address = *dest_mint.to_account_info().owner @ ErrorCode::InvalidTokenProgram)]
+    pub dest_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CloseVaultToPayer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+        has_one = owner,
+        has_one = rent_payer,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    /// Account that paid the vault's rent at init, refunded on close
+    #[account(mut)]
+    pub rent_payer: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = vault_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = owner_token_account.owner == owner.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = owner_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = vault_dest_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_dest_token_account.mint == vault.dest_mint @ ErrorCode::InvalidMint,
+    )]
+    pub vault_dest_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = owner_dest_token_account.owner == owner.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = owner_dest_token_account.mint == vault.dest_mint @ ErrorCode::InvalidMint,
+    )]
+    pub owner_dest_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Writable so Token-2022 fees withheld on the vault's token accounts
+    /// can be harvested before they close
+    #[account(mut, address = vault.source_mint @ ErrorCode::InvalidMint)]
+    pub source_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut, address = vault.dest_mint @ ErrorCode::InvalidMint)]
+    pub dest_mint: InterfaceAccount<'info, Mint>,
+
+    /// Owner's vault registry; the vault is removed from it when supplied
+    #[account(
+        mut,
+        seeds = [b"registry", owner.key().as_ref()],
+        bump = registry.bump,
+    )]
+    pub registry: Option<Account<'info, VaultRegistry>>,
+
+    /// Basket legs of a basket vault, closed with it; required for basket vaults
+    #[account(
+        mut,
+        seeds = [b"basket", vault.key().as_ref()],
+        bump = basket.bump,
+        has_one = vault,
+        close = owner,
+    )]
+    pub basket: Option<Account<'info, DcaBasket>>,
+
+    /// Token program owning the source mint
+    #[account(address = *source_mint.to_account_info().owner @ ErrorCode::InvalidTokenProgram)]
+    pub source_token_program: Interface<'info, TokenInterface>,
+
+    /// Token program owning the destination mint
+    #[account(address = *dest_mint.to_account_info().owner @ ErrorCode::InvalidTokenProgram)]
+    pub dest_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeRegistry<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + VaultRegistry::space(0),
+        seeds = [b"registry", owner.key().as_ref()],
+        bump
+    )]
+    pub registry: Account<'info, VaultRegistry>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct PauseAll<'info> {
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeBasket<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+        has_one = owner,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + DcaBasket::LEN,
+        seeds = [b"basket", vault.key().as_ref()],
+        bump
+    )]
+    pub basket: Account<'info, DcaBasket>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ExecuteBasket<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"basket", vault.key().as_ref()],
+        bump = basket.bump,
+        has_one = vault,
+    )]
+    pub basket: Account<'info, DcaBasket>,
+
+    /// CHECK: Session key authority (validated in backend)
+    pub session_authority: Signer<'info>,
+
+    /// Source token account funding every leg
+    #[account(
+        mut,
+        constraint = vault_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_token_account.mint == vault.source_mint @ ErrorCode::InvalidMint,
+    )]
+    pub vault_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: Pyth price feed, required when the vault has one configured
+    pub price_feed: Option<AccountInfo<'info>>,
+
+    /// CHECK: Swap program account (Jupiter), invoked via CPI
+    #[account(
+        constraint = swap_program_account.key() == config.jupiter_program @ ErrorCode::InvalidSwapProgram,
+    )]
+    pub swap_program_account: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// Keeper's session key, validated via CPI when supplied
+    #[account(
+        mut,
+        constraint = session_key.owner == vault.owner @ ErrorCode::InvalidSessionKeyOwner,
+    )]
+    pub session_key: Option<Account<'info, SessionKey>>,
+
+    pub session_keys_program: Option<Program<'info, SessionKeys>>,
+
+    /// CHECK: address-constrained to the instructions sysvar, which the
+    /// session program reads for the instruction it authorizes
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateBasket<'info> {
+    #[account(
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+        has_one = owner,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"basket", vault.key().as_ref()],
+        bump = basket.bump,
+        has_one = vault,
+    )]
+    pub basket: Account<'info, DcaBasket>,
+
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateBasket<'info> {
+    #[account(
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+        has_one = owner,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    /// CHECK: outdated basket; its discriminator and layout are verified in
+    /// `migrate_basket` since it cannot deserialize as the current `DcaBasket`
+    #[account(mut, owner = crate::ID, seeds = [b"basket", vault.key().as_ref()], bump)]
+    pub basket: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct WithdrawBasketLeg<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault",
+            vault.creator.as_ref(),
+            vault.source_mint.as_ref(),
+            vault.dest_mint.as_ref(),
+        ],
+        bump = vault.bump,
+        has_one = owner,
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [b"basket", vault.key().as_ref()],
+        bump = basket.bump,
+        has_one = vault,
+    )]
+    pub basket: Account<'info, DcaBasket>,
+
+    pub owner: Signer<'info>,
+
+    /// Vault token account holding the leg's swapped tokens
+    #[account(
+        mut,
+        constraint = vault_leg_token_account.owner == vault.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = vault_leg_token_account.mint == leg_mint.key() @ ErrorCode::InvalidMint,
+    )]
+    pub vault_leg_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Owner's token account receiving the leg's tokens
+    #[account(
+        mut,
+        constraint = owner_leg_token_account.owner == owner.key() @ ErrorCode::InvalidTokenAccountOwner,
+        constraint = owner_leg_token_account.mint == leg_mint.key() @ ErrorCode::InvalidMint,
+    )]
+    pub owner_leg_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Destination mint of one of the basket's legs
+    #[account(constraint = basket.has_leg(leg_mint.key()) @ ErrorCode::InvalidMint)]
+    pub leg_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateVault<'info> {
+    /// CHECK: outdated vault; discriminator, PDA and owner are verified in
+    /// `migrate_vault` since it cannot deserialize as the current `Vault`
+    #[account(mut, owner = crate::ID)]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub source_mint: InterfaceAccount<'info, Mint>,
+
+    pub dest_mint: InterfaceAccount<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateConfig<'info> {
+    /// CHECK: outdated config; discriminator and admin are verified in
+    /// `migrate_config` since it cannot deserialize as the current `Config`
+    #[account(mut, owner = crate::ID, seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Config::LEN,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        constraint = program.programdata_address()? == Some(program_data.key()) @ ErrorCode::Unauthorized,
+    )]
+    pub program: Program<'info, crate::program::DcaVault>,
+
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(admin.key()) @ ErrorCode::Unauthorized,
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeFeeLedger<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// CHECK: Treasury PDA owning the fee token accounts; holds no data
+    #[account(seeds = [b"treasury"], bump)]
+    pub treasury: UncheckedAccount<'info>,
+
+    #[account(
+        constraint = treasury_token_account.owner == treasury.key() @ ErrorCode::InvalidTokenAccountOwner,
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + FeeLedger::LEN,
+        seeds = [b"fee_ledger", treasury_token_account.key().as_ref()],
+        bump
+    )]
+    pub fee_ledger: Account<'info, FeeLedger>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTreasury<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub admin: Signer<'info>,
+
+    /// CHECK: Treasury PDA owning the fee token accounts; holds no data
+    #[account(seeds = [b"treasury"], bump)]
+    pub treasury: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = treasury_token_account.owner == treasury.key() @ ErrorCode::InvalidTokenAccountOwner,
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// Fees collected into `treasury_token_account`, capping withdrawals
+    #[account(
+        mut,
+        seeds = [b"fee_ledger", treasury_token_account.key().as_ref()],
+        bump = fee_ledger.bump,
+    )]
+    pub fee_ledger: Account<'info, FeeLedger>,
+
+    /// Any account of the same mint chosen by the admin
+    #[account(
+        mut,
+        constraint = destination_token_account.mint == treasury_token_account.mint @ ErrorCode::InvalidMint,
+    )]
+    pub destination_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = treasury_token_account.mint @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin,
+    )]
+    pub config: Account<'info, Config>,
+
+    pub admin: Signer<'info>,
+}
+
+// ============================================
+// State
+// ============================================
+
+#[account]
 pub struct Vault {
     pub owner: Pubkey,              // 32
-    pub source_mint: Pubkey,        // 32
-    pub dest_mint: Pubkey,          // 32
-    pub amount_per_cycle: u64,      // 8
-    pub frequency_seconds: i64,     // 8
-    pub total_cycles: u16,          // 2
-    pub executed_cycles: u16,       // 2
-    pub total_deposited: u64,       // 8
-    pub total_received: u64,        // 8
-    pub last_execution: i64,        // 8
-    pub next_execution: i64,        // 8
-    pub status: u8,                 // 1 (0=Active, 1=Paused, 2=Completed, 3=Cancelled)
+    pub creator: Pubkey,            // 32 (PDA seed, never changes)
+    pub source_mint: Pubkey,        // 32
+    pub dest_mint: Pubkey,          // 32
+    pub amount_per_cycle: u64,      // 8
+    pub frequency_seconds: i64,     // 8
+    pub total_cycles: u16,          // 2
+    pub executed_cycles: u16,       // 2
+    pub total_deposited: u64,       // 8
+    pub total_received: u64,        // 8
+    pub last_execution: i64,        // 8
+    pub next_execution: i64,        // 8
+    pub slippage_bps: u16,          // 2
+    pub price_feed: Pubkey,         // 32 (default = oracle check disabled)
+    pub max_price_deviation_bps: u16, // 2
+    pub source_decimals: u8,        // 1
+    pub dest_decimals: u8,          // 1
+    pub keeper_reward: u64,         // 8
+    pub cumulative_amount_in: u64,  // 8
+    pub recent_prices: [u64; 16],   // 8 * 16 = 128 (ring buffer)
+    pub price_cursor: u8,           // 1 (next write index)
+    pub stop_loss_price: u64,       // 8 (0 = disabled)
+    pub mode: u8,                   // 1 (0=Fixed, 1=Percent, 2=Alternate, 3=Sell)
+    pub percent_bps: u16,           // 2
+    pub catch_up: bool,             // 1
+    pub max_delay_seconds: i64,     // 8
+    pub locked: bool,               // 1 (reentrancy guard for execute_dca)
+    pub require_session: bool,      // 1 (executions must charge an owner session key)
+    pub low_balance_threshold: u64, // 8 (0 = disabled)
+    pub consecutive_failures: u8,   // 1
+    pub max_failures: u8,           // 1 (0 = never auto-pause)
+    pub is_native_source: bool,     // 1 (source mint is WSOL)
+    pub is_native_dest: bool,       // 1 (dest mint is WSOL)
+    pub label: [u8; 32],            // 32 (zero-padded UTF-8)
+    pub require_min_out: bool,      // 1 (reject min_amount_out == 0)
+    pub compound: bool,             // 1 (forward output to the next hop)
+    pub compound_account: Pubkey,   // 32 (next hop's source token account)
+    pub max_deposit: u64,           // 8 (0 = unlimited)
+    pub withdraw_cooldown_seconds: i64, // 8 (0 = disabled)
+    pub last_withdraw: i64,         // 8
+    pub skipped_cycles: u16,        // 2
+    pub max_buy_price: u64,         // 8 (0 = disabled)
+    pub executor: Pubkey,           // 32 (default = any keeper may execute)
+    pub swap_dust: bool,            // 1 (swap a short final cycle instead of failing)
+    pub execution_window_seconds: i64, // 8 (0 = no upper bound)
+    pub is_basket: bool,            // 1 (executed via execute_basket only)
+    pub referrer: Pubkey,           // 32 (default = no referrer)
+    pub referral_earned: u64,       // 8 (lifetime fee share paid to referrer)
+    pub resume_preserves_schedule: bool, // 1 (resume keeps next_execution)
+    pub take_profit_price: u64,     // 8 (0 = disabled)
+    pub take_profit_liquidate: bool, // 1 (swap all remaining source on trigger)
+    pub rent_payer: Pubkey,         // 32 (refunded by close_vault_to_payer)
+    pub expiry_timestamp: i64,      // 8 (0 = never expires)
+    pub max_amount_out: u64,        // 8 (buy legs only, 0 = disabled)
+    pub pause_reason: u8,           // 1 (why the vault is paused, 0 = not paused)
+    pub recovery_authority: Pubkey, // 32 (default = no recovery)
+    pub recovery_delay_seconds: i64, // 8 (inactivity before recovery is allowed)
+    pub best_cycle_out: u64,        // 8 (largest buy-cycle output)
+    pub worst_cycle_out: u64,       // 8 (smallest buy-cycle output, 0 = none yet)
+    pub cycles_executed_sum_price: u128, // 16 (sum of buy-cycle prices)
+    pub min_initial_deposit: u64,   // 8 (no cycle runs below this total_deposited)
+    pub paused_at: i64,             // 8 (start of the current pause, 0 = not paused)
+    pub total_paused_seconds: i64,  // 8 (idle time across completed pauses)
+    pub status: u8,                 // 1 (0=Active, 1=Paused, 2=Completed, 3=Cancelled)
+    pub bump: u8,                   // 1
+    pub version: u8,                // 1 (layout version; new fields go after it)
+    pub priced_cycles: u16,         // 2 (buy cycles in cycles_executed_sum_price)
+}
+
+impl Vault {
+    pub const LEN: usize = 32 // owner
+        + 32 // creator
+        + 32 // source_mint
+        + 32 // dest_mint
+        + 8 // amount_per_cycle
+        + 8 // frequency_seconds
+        + 2 // total_cycles
+        + 2 // executed_cycles
+        + 8 // total_deposited
+        + 8 // total_received
+        + 8 // last_execution
+        + 8 // next_execution
+        + 2 // slippage_bps
+        + 32 // price_feed
+        + 2 // max_price_deviation_bps
+        + 1 // source_decimals
+        + 1 // dest_decimals
+        + 8 // keeper_reward
+        + 8 // cumulative_amount_in
+        + 8 * 16 // recent_prices
+        + 1 // price_cursor
+        + 8 // stop_loss_price
+        + 1 // mode
+        + 2 // percent_bps
+        + 1 // catch_up
+        + 8 // max_delay_seconds
+        + 1 // locked
+        + 1 // require_session
+        + 8 // low_balance_threshold
+        + 1 // consecutive_failures
+        + 1 // max_failures
+        + 1 // is_native_source
+        + 1 // is_native_dest
+        + 32 // label
+        + 1 // require_min_out
+        + 1 // compound
+        + 32 // compound_account
+        + 8 // max_deposit
+        + 8 // withdraw_cooldown_seconds
+        + 8 // last_withdraw
+        + 2 // skipped_cycles
+        + 8 // max_buy_price
+        + 32 // executor
+        + 1 // swap_dust
+        + 8 // execution_window_seconds
+        + 1 // is_basket
+        + 32 // referrer
+        + 8 // referral_earned
+        + 1 // resume_preserves_schedule
+        + 8 // take_profit_price
+        + 1 // take_profit_liquidate
+        + 32 // rent_payer
+        + 8 // expiry_timestamp
+        + 8 // max_amount_out
+        + 1 // pause_reason
+        + 32 // recovery_authority
+        + 8 // recovery_delay_seconds
+        + 8 // best_cycle_out
+        + 8 // worst_cycle_out
+        + 16 // cycles_executed_sum_price
+        + 8 // min_initial_deposit
+        + 8 // paused_at
+        + 8 // total_paused_seconds
+        + 1 // status
+        + 1 // bump
+        + 1 // version
+        + 2; // priced_cycles
+
+    pub const STATUS_ACTIVE: u8 = 0;
+    pub const STATUS_PAUSED: u8 = 1;
+    pub const STATUS_COMPLETED: u8 = 2;
+    pub const STATUS_CANCELLED: u8 = 3;
+
+    pub const MODE_FIXED: u8 = 0;
+    pub const MODE_PERCENT: u8 = 1;
+    pub const MODE_ALTERNATE: u8 = 2;
+    pub const MODE_SELL: u8 = 3;
+
+    /// `frequency_unit` values accepted by `initialize_vault_v2`
+    pub const FREQUENCY_UNIT_SECONDS: u8 = 0;
+    pub const FREQUENCY_UNIT_MINUTES: u8 = 1;
+    pub const FREQUENCY_UNIT_HOURS: u8 = 2;
+    pub const FREQUENCY_UNIT_DAYS: u8 = 3;
+
+    pub const SIDE_BUY: u8 = 0;
+    pub const SIDE_SELL: u8 = 1;
+
+    /// `CycleSkippedEvent` reasons
+    pub const SKIP_REASON_OWNER: u8 = 0;
+    pub const SKIP_REASON_PRICE_ABOVE_LIMIT: u8 = 1;
+    pub const SKIP_REASON_WINDOW_MISSED: u8 = 2;
+
+    /// `pause_reason` values; owner pauses (including `pause_all`) are manual
+    pub const PAUSE_REASON_NONE: u8 = 0;
+    pub const PAUSE_REASON_MANUAL: u8 = 1;
+    pub const PAUSE_REASON_AUTO_SLIPPAGE: u8 = 2;
+
+    /// Bitflags returned by `health_check`
+    pub const HEALTH_DUE: u8 = 1 << 0;
+    pub const HEALTH_INSUFFICIENT_BALANCE: u8 = 1 << 1;
+    pub const HEALTH_PAUSED: u8 = 1 << 2;
+    pub const HEALTH_COMPLETED: u8 = 1 << 3;
+    pub const HEALTH_NEAR_EXPIRY: u8 = 1 << 4;
+    pub const HEALTH_LOW_BALANCE: u8 = 1 << 5;
+
+    pub const MAX_BPS: u16 = 10_000;
+
+    /// Oldest oracle price accepted, in seconds between its publish time and
+    /// the clock; measured in time rather than slots, since slot times drift
+    /// and Pyth's own staleness check works in seconds
+    pub const MAX_PRICE_AGE_SECONDS: u64 = 60;
+
+    /// One year
+    pub const MAX_FREQUENCY_SECONDS: i64 = 365 * 24 * 60 * 60;
+
+    /// Thirty days, so a cooldown can slow withdrawals but never lock funds
+    pub const MAX_WITHDRAW_COOLDOWN_SECONDS: i64 = 30 * 24 * 60 * 60;
+
+    pub const MAX_TOTAL_CYCLES: u16 = 10_000;
+
+    /// Slippage assigned to vaults migrated from the legacy layout
+    pub const DEFAULT_SLIPPAGE_BPS: u16 = 50;
+
+    /// Fixed-point scale for prices
+    pub const PRICE_SCALE: u64 = 1_000_000;
+
+    pub const RECENT_PRICES_LEN: usize = 16;
+
+    /// Current layout version; bump it when appending fields after `version`
+    pub const VERSION: u8 = 2;
+
+    /// `LEN` of every layout before `version` existed, oldest first (the
+    /// original layout is `LegacyVault`). Each one inserted its new fields
+    /// just ahead of `status` and `bump`, so its body is a prefix of the
+    /// current field list followed by those two bytes
+    pub const UNVERSIONED_LENS: [usize; 36] = [
+        182, 184, 220, 228, 236, 365, 373, 376, 385, 386, 387, 395, 397, 399, 431, 432, 465, 473,
+        489, 491, 499, 531, 532, 540, 541, 581, 582, 591, 623, 631, 639, 640, 680, 712, 720, 736,
+    ];
+
+    /// Most executions `preview_schedule` returns
+    pub const MAX_SCHEDULE_PREVIEW: u8 = 32;
+
+    /// `VaultInitializedEvent` describing the vault's schedule at creation
+    pub fn initialized_event(&self, vault: Pubkey) -> VaultInitializedEvent {
+        VaultInitializedEvent {
+            vault,
+            owner: self.owner,
+            source_mint: self.source_mint,
+            dest_mint: self.dest_mint,
+            amount_per_cycle: self.amount_per_cycle,
+            frequency_seconds: self.frequency_seconds,
+            total_cycles: self.total_cycles,
+            label: self.label,
+            timestamp: self.last_execution,
+        }
+    }
+
+    /// `DepositEvent` for `amount` just credited to the vault
+    pub fn deposit_event(
+        &self,
+        vault: Pubkey,
+        depositor: Pubkey,
+        amount: u64,
+        timestamp: i64,
+    ) -> DepositEvent {
+        DepositEvent {
+            vault,
+            depositor,
+            amount,
+            total_deposited: self.total_deposited,
+            timestamp,
+        }
+    }
+
+    /// `LowBalanceEvent` when this cycle took the source balance from at or
+    /// above the threshold to below it
+    pub fn low_balance_event(
+        &self,
+        vault: Pubkey,
+        balance_before: u64,
+        balance_after: u64,
+        timestamp: i64,
+    ) -> Option<LowBalanceEvent> {
+        (balance_before >= self.low_balance_threshold
+            && balance_after < self.low_balance_threshold)
+            .then_some(LowBalanceEvent {
+                vault,
+                remaining_balance: balance_after,
+                timestamp,
+            })
+    }
+
+    /// Average price paid per destination token, scaled by `PRICE_SCALE`
+    /// Sell-mode vaults report the average sale price instead: destination
+    /// proceeds per source token sold
+    /// Returns 0 before the first execution
+    pub fn average_price(&self) -> Result<u64> {
+        let (numerator, denominator) = if self.mode == Self::MODE_SELL {
+            (self.total_received, self.cumulative_amount_in)
+        } else {
+            (self.cumulative_amount_in, self.total_received)
+        };
+
+        if denominator == 0 {
+            return Ok(0);
+        }
+
+        let price = (numerator as u128)
+            .checked_mul(Self::PRICE_SCALE as u128)
+            .ok_or(ErrorCode::ArithmeticOverflow)?
+            .checked_div(denominator as u128)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+        u64::try_from(price).map_err(|_| error!(ErrorCode::ArithmeticOverflow))
+    }
+
+    /// Whether this cycle buys or sells the vault's asset, for event labeling
+    pub fn cycle_side(&self) -> u8 {
+        if self.mode == Self::MODE_SELL || self.is_reverse_cycle() {
+            Self::SIDE_SELL
+        } else {
+            Self::SIDE_BUY
+        }
+    }
+
+    /// Derived progress for `get_summary`
+    /// Vaults without cycles report 0% rather than dividing by zero
+    pub fn summary(&self) -> Result<VaultSummary> {
+        let percent_complete_bps = if self.total_cycles == 0 {
+            0
+        } else {
+            let bps = self.executed_cycles as u64 * Self::MAX_BPS as u64
+                / self.total_cycles as u64;
+            bps.min(Self::MAX_BPS as u64) as u16
+        };
+        let remaining_cycles = self.total_cycles.saturating_sub(self.executed_cycles);
+        let estimated_completion_ts = self.next_execution.saturating_add(
+            (remaining_cycles as i64).saturating_mul(self.frequency_seconds),
+        );
+
+        Ok(VaultSummary {
+            percent_complete_bps,
+            remaining_cycles,
+            average_price: self.average_price()?,
+            estimated_completion_ts,
+            best_cycle_out: self.best_cycle_out,
+            worst_cycle_out: self.worst_cycle_out,
+            cycles_executed_sum_price: self.cycles_executed_sum_price,
+            priced_cycles: self.priced_cycles,
+            average_cycle_price: self.average_cycle_price(),
+            total_paused_seconds: self.total_paused_seconds,
+        })
+    }
+
+    /// Upcoming execution timestamps, one interval apart from `next_execution`
+    /// Cancelled vaults have none; the list ends at the last remaining cycle
+    /// or before `expiry_timestamp`
+    pub fn schedule_preview(&self, n: u8) -> Result<Vec<i64>> {
+        if self.status == Self::STATUS_CANCELLED {
+            return Ok(Vec::new());
+        }
+
+        let remaining_cycles = self.total_cycles.saturating_sub(self.executed_cycles);
+        let count = n.min(Self::MAX_SCHEDULE_PREVIEW) as u16;
+        let count = count.min(remaining_cycles);
+
+        let mut timestamps = Vec::with_capacity(count as usize);
+        let mut timestamp = self.next_execution;
+        for _ in 0..count {
+            if self.is_expired(timestamp) {
+                break;
+            }
+            timestamps.push(timestamp);
+            timestamp = offset_timestamp(timestamp, self.frequency_seconds)?;
+        }
+
+        Ok(timestamps)
+    }
+
+    /// Move the vault to `new_status`, describing the transition for indexers
+    /// Leaving the paused state clears `pause_reason`
+    pub fn set_status(
+        &mut self,
+        vault: Pubkey,
+        new_status: u8,
+        timestamp: i64,
+    ) -> VaultStatusChangedEvent {
+        let old_status = self.status;
+        self.status = new_status;
+
+        if new_status != Self::STATUS_PAUSED {
+            self.pause_reason = Self::PAUSE_REASON_NONE;
+        }
+
+        // Accumulate idle time when a pause ends; pauses that predate
+        // `paused_at` (migrated vaults) have no start and aren't counted
+        if old_status != Self::STATUS_PAUSED && new_status == Self::STATUS_PAUSED {
+            self.paused_at = timestamp;
+        } else if old_status == Self::STATUS_PAUSED && new_status != Self::STATUS_PAUSED {
+            if self.paused_at > 0 {
+                self.total_paused_seconds = self
+                    .total_paused_seconds
+                    .saturating_add(timestamp.saturating_sub(self.paused_at).max(0));
+            }
+            self.paused_at = 0;
+        }
+
+        VaultStatusChangedEvent {
+            vault,
+            old_status,
+            new_status,
+            pause_reason: self.pause_reason,
+            timestamp,
+        }
+    }
+
+    /// Pause the vault, recording why for dashboards
+    pub fn set_paused(
+        &mut self,
+        vault: Pubkey,
+        pause_reason: u8,
+        timestamp: i64,
+    ) -> VaultStatusChangedEvent {
+        self.pause_reason = pause_reason;
+        self.set_status(vault, Self::STATUS_PAUSED, timestamp)
+    }
+
+    /// Pause an active vault at the owner's request
+    /// Cancelled vaults must not be revived through pause/resume
+    pub fn pause(&mut self, vault: Pubkey, timestamp: i64) -> Result<VaultStatusChangedEvent> {
+        require!(self.status == Self::STATUS_ACTIVE, ErrorCode::VaultNotActive);
+
+        Ok(self.set_paused(vault, Self::PAUSE_REASON_MANUAL, timestamp))
+    }
+
+    /// Resume a paused vault at `timestamp`, either keeping the pending slot
+    /// (`resume_preserves_schedule`) or restarting a full interval
+    pub fn resume(&mut self, vault: Pubkey, timestamp: i64) -> Result<VaultStatusChangedEvent> {
+        require!(self.status == Self::STATUS_PAUSED, ErrorCode::VaultNotPaused);
+
+        self.next_execution = if self.resume_preserves_schedule {
+            // Keep the original slot so a brief pause doesn't cost an interval
+            self.next_execution.max(timestamp)
+        } else {
+            offset_timestamp(timestamp, self.frequency_seconds)?
+        };
+
+        Ok(self.set_status(vault, Self::STATUS_ACTIVE, timestamp))
+    }
+
+    /// Push the next execution back one period without counting a cycle
+    pub fn skip_cycle(&mut self, vault: Pubkey, timestamp: i64) -> Result<CycleSkippedEvent> {
+        require!(self.status == Self::STATUS_ACTIVE, ErrorCode::VaultNotActive);
+
+        self.next_execution = offset_timestamp(self.next_execution, self.frequency_seconds)?;
+        self.skipped_cycles = self
+            .skipped_cycles
+            .checked_add(1)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+
+        Ok(self.skipped_event(vault, Self::SKIP_REASON_OWNER, timestamp))
+    }
+
+    /// Reschedule a buy leg priced above `max_buy_price` as if it had run at
+    /// `now`, without counting a cycle
+    pub fn skip_priced_out_cycle(&mut self, vault: Pubkey, now: i64) -> Result<CycleSkippedEvent> {
+        self.next_execution = self.next_execution_after(now)?;
+        self.skipped_cycles = self.skipped_cycles.saturating_add(1);
+
+        Ok(self.skipped_event(vault, Self::SKIP_REASON_PRICE_ABOVE_LIMIT, now))
+    }
+
+    /// An expiry is either disabled (zero) or still in the future
+    pub fn validate_expiry(expiry_timestamp: i64, now: i64) -> Result<()> {
+        require!(
+            expiry_timestamp == 0 || expiry_timestamp > now,
+            ErrorCode::InvalidVaultExpiry
+        );
+        Ok(())
+    }
+
+    /// Most a swap of `swap_amount` may deliver under `max_amount_out`
+    /// (0 = uncapped). The cap is in destination units, so sell legs are never
+    /// capped; a take-profit liquidation swapping more than the regular
+    /// `cycle_amount` is allowed proportionally more
+    pub fn output_cap(&self, reverse: bool, swap_amount: u64, cycle_amount: u64) -> u64 {
+        if reverse || self.max_amount_out == 0 {
+            return 0;
+        }
+        if cycle_amount == 0 || swap_amount <= cycle_amount {
+            return self.max_amount_out;
+        }
+        let cap = self.max_amount_out as u128 * swap_amount as u128 / cycle_amount as u128;
+        u64::try_from(cap).unwrap_or(u64::MAX)
+    }
+
+    /// Whether the vault's `expiry_timestamp` has been reached at `now`
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expiry_timestamp > 0 && now >= self.expiry_timestamp
+    }
+
+    /// Whether `now` is past the end of the current cycle's execution window
+    pub fn missed_execution_window(&self, now: i64) -> bool {
+        self.execution_window_seconds > 0
+            && now > self.next_execution.saturating_add(self.execution_window_seconds)
+    }
+
+    /// Roll a cycle whose execution window was missed forward one interval,
+    /// without counting a cycle
+    pub fn skip_missed_window(&mut self, vault: Pubkey, now: i64) -> Result<CycleSkippedEvent> {
+        self.next_execution = offset_timestamp(self.next_execution, self.frequency_seconds)?;
+        self.skipped_cycles = self.skipped_cycles.saturating_add(1);
+
+        Ok(self.skipped_event(vault, Self::SKIP_REASON_WINDOW_MISSED, now))
+    }
+
+    fn skipped_event(&self, vault: Pubkey, reason: u8, timestamp: i64) -> CycleSkippedEvent {
+        CycleSkippedEvent {
+            vault,
+            reason,
+            skipped_cycles: self.skipped_cycles,
+            next_execution: self.next_execution,
+            timestamp,
+        }
+    }
+
+    /// Set the take-profit price; a non-zero price needs a price feed to check
+    pub fn set_take_profit(&mut self, take_profit_price: u64, liquidate: bool) -> Result<()> {
+        require!(
+            take_profit_price == 0 || self.price_feed != Pubkey::default(),
+            ErrorCode::MissingPriceFeed
+        );
+
+        self.take_profit_price = take_profit_price;
+        self.take_profit_liquidate = liquidate;
+        Ok(())
+    }
+
+    /// Set the limit-buy price; a non-zero limit needs a price feed to check
+    pub fn set_max_buy_price(&mut self, max_buy_price: u64) -> Result<()> {
+        require!(
+            max_buy_price == 0 || self.price_feed != Pubkey::default(),
+            ErrorCode::MissingPriceFeed
+        );
+
+        self.max_buy_price = max_buy_price;
+        Ok(())
+    }
+
+    /// Count a skipped cycle the oracle expected to miss the vault's floors and
+    /// back off a full interval so it isn't retried at once
+    /// Pauses the vault once `max_failures` is reached (0 = never)
+    pub fn record_slippage_failure(
+        &mut self,
+        vault: Pubkey,
+        now: i64,
+    ) -> Result<Option<VaultStatusChangedEvent>> {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        self.next_execution = self.next_execution_after(now)?;
+
+        if self.max_failures > 0 && self.consecutive_failures >= self.max_failures {
+            return Ok(Some(self.set_paused(vault, Self::PAUSE_REASON_AUTO_SLIPPAGE, now)));
+        }
+        Ok(None)
+    }
+
+    /// Cancel an active or paused vault, keeping the account so history stays
+    /// inspectable and funds can still be withdrawn
+    pub fn cancel(&mut self, vault: Pubkey, timestamp: i64) -> Result<VaultStatusChangedEvent> {
+        require!(
+            self.status == Self::STATUS_ACTIVE || self.status == Self::STATUS_PAUSED,
+            ErrorCode::VaultAlreadyFinalized
+        );
+
+        Ok(self.set_status(vault, Self::STATUS_CANCELLED, timestamp))
+    }
+
+    /// Only completed or cancelled vaults can be closed, so a keeper can't be
+    /// mid-cycle when the account disappears
+    pub fn ensure_closable(&self) -> Result<()> {
+        require!(
+            matches!(self.status, Self::STATUS_COMPLETED | Self::STATUS_CANCELLED),
+            ErrorCode::VaultStillActive
+        );
+        Ok(())
+    }
+
+    /// Cancel the vault from any status; reports a transition only if the
+    /// vault was not already cancelled
+    pub fn force_cancel(
+        &mut self,
+        vault: Pubkey,
+        timestamp: i64,
+    ) -> Option<VaultStatusChangedEvent> {
+        (self.status != Self::STATUS_CANCELLED)
+            .then(|| self.set_status(vault, Self::STATUS_CANCELLED, timestamp))
+    }
+
+    /// Add `additional` cycles; a completed vault is reactivated with its next
+    /// cycle one interval after `now`
+    pub fn extend_cycles(
+        &mut self,
+        vault: Pubkey,
+        additional: u16,
+        now: i64,
+    ) -> Result<Option<VaultStatusChangedEvent>> {
+        require!(additional > 0, ErrorCode::InvalidCycleCount);
+        require!(
+            self.status != Self::STATUS_CANCELLED,
+            ErrorCode::VaultAlreadyFinalized
+        );
+
+        let total_cycles = self
+            .total_cycles
+            .checked_add(additional)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        require!(
+            total_cycles <= Self::MAX_TOTAL_CYCLES,
+            ErrorCode::InvalidCycleCount
+        );
+
+        self.total_cycles = total_cycles;
+
+        if self.status != Self::STATUS_COMPLETED {
+            return Ok(None);
+        }
+
+        self.next_execution = offset_timestamp(now, self.frequency_seconds)?;
+        Ok(Some(self.set_status(vault, Self::STATUS_ACTIVE, now)))
+    }
+
+    /// Add a buy leg's input spent and output to the lifetime totals
+    pub fn record_buy(&mut self, amount_in: u64, amount_out: u64) -> Result<()> {
+        self.total_received = self
+            .total_received
+            .checked_add(amount_out)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        self.cumulative_amount_in = self
+            .cumulative_amount_in
+            .checked_add(amount_in)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    /// Track best/worst output and the running price sum for a buy cycle
+    pub fn record_cycle_stats(&mut self, amount_out: u64, price: u64) -> Result<()> {
+        self.best_cycle_out = self.best_cycle_out.max(amount_out);
+        if self.worst_cycle_out == 0 || amount_out < self.worst_cycle_out {
+            self.worst_cycle_out = amount_out;
+        }
+        self.cycles_executed_sum_price = self
+            .cycles_executed_sum_price
+            .checked_add(price as u128)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        self.priced_cycles = self
+            .priced_cycles
+            .checked_add(1)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    /// Mean of the recorded buy-cycle prices, 0 before the first one
+    /// Alternate-mode sell legs aren't priced, so this divides by
+    /// `priced_cycles` rather than `executed_cycles`
+    pub fn average_cycle_price(&self) -> u64 {
+        if self.priced_cycles == 0 {
+            return 0;
+        }
+        let average = self.cycles_executed_sum_price / self.priced_cycles as u128;
+        u64::try_from(average).unwrap_or(u64::MAX)
+    }
+
+    /// Whether this cycle sells destination tokens back to source
+    /// Only odd cycles of `MODE_ALTERNATE` vaults run in reverse
+    pub fn is_reverse_cycle(&self) -> bool {
+        self.mode == Self::MODE_ALTERNATE && self.executed_cycles % 2 == 1
+    }
+
+    /// Mint spent by a cycle: destination on reverse cycles, source otherwise
+    pub fn cycle_input_mint(&self, reverse: bool) -> Pubkey {
+        if reverse {
+            self.dest_mint
+        } else {
+            self.source_mint
+        }
+    }
+
+    /// Check that `keeper` may execute this vault
+    /// Vaults without a pinned executor accept any keeper
+    pub fn check_executor(&self, keeper: Pubkey) -> Result<()> {
+        require!(
+            self.executor == Pubkey::default() || self.executor == keeper,
+            ErrorCode::Unauthorized
+        );
+        Ok(())
+    }
+
+    /// Check that `authority` may trigger an emergency withdrawal at `now`
+    /// The owner always can; the recovery authority only once the vault has
+    /// gone more than `recovery_delay_seconds` without executing
+    pub fn check_emergency_authority(&self, authority: Pubkey, now: i64) -> Result<()> {
+        if authority == self.owner {
+            return Ok(());
+        }
+
+        require!(
+            self.recovery_authority != Pubkey::default() && authority == self.recovery_authority,
+            ErrorCode::Unauthorized
+        );
+        require!(
+            now.saturating_sub(self.last_execution) > self.recovery_delay_seconds,
+            ErrorCode::RecoveryNotYetAvailable
+        );
+        Ok(())
+    }
+
+    /// Check that a source withdrawal supplies the right target account: a
+    /// temporary unwrap account for native vaults, the owner's token account
+    /// otherwise
+    pub fn check_withdraw_accounts(
+        &self,
+        has_owner_token_account: bool,
+        has_unwrap_account: bool,
+    ) -> Result<()> {
+        let valid = if self.is_native_source {
+            has_unwrap_account
+        } else {
+            has_owner_token_account && !has_unwrap_account
+        };
+        require!(valid, ErrorCode::InvalidWithdrawAccounts);
+        Ok(())
+    }
+
+    /// Check that no execution has advanced the vault past `expected_cycle`
+    pub fn check_expected_cycle(&self, expected_cycle: u16) -> Result<()> {
+        require!(
+            self.executed_cycles == expected_cycle,
+            ErrorCode::CycleMismatch
+        );
+        Ok(())
+    }
+
+    /// Check that depositing `amount` keeps `total_deposited` within the cap
+    pub fn check_deposit_cap(&self, amount: u64) -> Result<()> {
+        if self.max_deposit > 0 {
+            let new_total = self
+                .total_deposited
+                .checked_add(amount)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+            require!(new_total <= self.max_deposit, ErrorCode::DepositCapExceeded);
+        }
+        Ok(())
+    }
+
+    /// Change the deposit cap (0 = unlimited), never below `total_deposited`
+    pub fn set_deposit_cap(&mut self, max_deposit: u64) -> Result<()> {
+        require!(
+            max_deposit == 0 || max_deposit >= self.total_deposited,
+            ErrorCode::InvalidDepositCap
+        );
+
+        self.max_deposit = max_deposit;
+        Ok(())
+    }
+
+    /// Check that the cooldown since the last withdrawal has elapsed at
+    /// `now`; a zero cooldown never blocks
+    pub fn check_withdraw_cooldown(&self, now: i64) -> Result<()> {
+        require!(
+            now >= self.last_withdraw.saturating_add(self.withdraw_cooldown_seconds),
+            ErrorCode::WithdrawCooldown
+        );
+        Ok(())
+    }
+
+    /// Start a withdrawal at `now` once the cooldown allows it
+    pub fn record_withdrawal(&mut self, now: i64) -> Result<()> {
+        self.check_withdraw_cooldown(now)?;
+
+        self.last_withdraw = now;
+        Ok(())
+    }
+
+    /// A withdraw cooldown is between 0 (disabled) and
+    /// `MAX_WITHDRAW_COOLDOWN_SECONDS`
+    pub fn validate_withdraw_cooldown(withdraw_cooldown_seconds: i64) -> Result<()> {
+        require!(
+            (0..=Self::MAX_WITHDRAW_COOLDOWN_SECONDS).contains(&withdraw_cooldown_seconds),
+            ErrorCode::InvalidWithdrawCooldown
+        );
+        Ok(())
+    }
+
+    /// Change the withdraw cooldown (0 = disabled)
+    /// A longer cooldown applies at once; a shorter one only once the current
+    /// cooldown has elapsed, so lowering it can't skip a pending wait
+    pub fn set_withdraw_cooldown(
+        &mut self,
+        withdraw_cooldown_seconds: i64,
+        now: i64,
+    ) -> Result<()> {
+        Self::validate_withdraw_cooldown(withdraw_cooldown_seconds)?;
+
+        if withdraw_cooldown_seconds < self.withdraw_cooldown_seconds {
+            self.check_withdraw_cooldown(now)?;
+        }
+
+        self.withdraw_cooldown_seconds = withdraw_cooldown_seconds;
+        Ok(())
+    }
+
+    /// Turn compounding on or off; enabling it needs a next-hop account
+    pub fn set_compound(&mut self, compound: bool, compound_account: Pubkey) -> Result<()> {
+        require!(
+            !compound || compound_account != Pubkey::default(),
+            ErrorCode::InvalidCompoundAccount
+        );
+
+        self.compound = compound;
+        self.compound_account = compound_account;
+        Ok(())
+    }
+
+    /// Whether this cycle's output is forwarded to the next hop, checking the
+    /// `supplied` account against `compound_account`
+    /// Only buy legs of compounding vaults forward; sell legs and other vaults
+    /// ignore the account
+    pub fn compound_target(&self, reverse: bool, supplied: Option<Pubkey>) -> Result<bool> {
+        if !self.compound || reverse {
+            return Ok(false);
+        }
+
+        let supplied = supplied.ok_or(ErrorCode::MissingCompoundAccount)?;
+        require_keys_eq!(supplied, self.compound_account, ErrorCode::InvalidCompoundAccount);
+        Ok(true)
+    }
+
+    /// Amount of this cycle's input token to swap given its vault balance
+    /// Percent mode and alternate-mode sell legs swap `percent_bps` of the
+    /// balance, taking the whole balance once it falls below the dust floor
+    /// With `swap_dust`, a short final fixed-size cycle swaps what is left
+    pub fn cycle_amount(&self, balance: u64) -> Result<u64> {
+        let decimals = match self.mode {
+            Self::MODE_PERCENT => self.source_decimals,
+            Self::MODE_ALTERNATE if self.is_reverse_cycle() => self.dest_decimals,
+            _ => {
+                let is_final_cycle = self.executed_cycles.saturating_add(1) == self.total_cycles;
+                if self.swap_dust
+                    && is_final_cycle
+                    && balance > 0
+                    && balance < self.amount_per_cycle
+                {
+                    return Ok(balance);
+                }
+                return Ok(self.amount_per_cycle);
+            }
+        };
+
+        let amount = (balance as u128)
+            .checked_mul(self.percent_bps as u128)
+            .ok_or(ErrorCode::ArithmeticOverflow)?
+            / Self::MAX_BPS as u128;
+        let amount = amount as u64;
+
+        // Dust floor: one thousandth of a whole input token
+        let floor = 10u64.pow(decimals.saturating_sub(3) as u32);
+
+        Ok(if amount < floor { balance.min(floor) } else { amount })
+    }
+
+    /// Next execution time after a cycle runs at `now`
+    /// Catch-up vaults stay anchored to the original cadence unless the keeper
+    /// is more than `max_delay_seconds` late, which resets the schedule instead
+    /// of allowing a burst of catch-up executions
+    pub fn next_execution_after(&self, now: i64) -> Result<i64> {
+        let lateness = now.saturating_sub(self.next_execution);
+
+        let base = if self.catch_up && lateness <= self.max_delay_seconds {
+            self.next_execution
+        } else {
+            now
+        };
+
+        base.checked_add(self.frequency_seconds)
+            .ok_or_else(|| error!(ErrorCode::ArithmeticOverflow))
+    }
+
+    /// Write a cycle price into the ring buffer, overwriting the oldest entry
+    pub fn record_price(&mut self, price: u64) {
+        let cursor = self.price_cursor as usize % Self::RECENT_PRICES_LEN;
+        self.recent_prices[cursor] = price;
+        self.price_cursor = ((cursor + 1) % Self::RECENT_PRICES_LEN) as u8;
+    }
+
+    /// Recorded prices in chronological order
+    pub fn recent_prices(&self) -> Vec<u64> {
+        let filled = (self.executed_cycles as usize).min(Self::RECENT_PRICES_LEN);
+        let cursor = self.price_cursor as usize % Self::RECENT_PRICES_LEN;
+        let start = (cursor + Self::RECENT_PRICES_LEN - filled) % Self::RECENT_PRICES_LEN;
+
+        (0..filled)
+            .map(|i| self.recent_prices[(start + i) % Self::RECENT_PRICES_LEN])
+            .collect()
+    }
+
+    /// Flag WSOL legs so deposits wrap lamports and withdrawals unwrap them
+    pub fn set_native_flags(&mut self) {
+        self.is_native_source = self.source_mint == native_mint::ID;
+        self.is_native_dest = self.dest_mint == native_mint::ID;
+    }
+
+    /// Whether the oracle `price` is at or below the stop-loss price
+    pub fn stop_loss_triggered(&self, price: &Price) -> Result<bool> {
+        Ok(self.stop_loss_price > 0
+            && scale_oracle_price(price.price, price.expo)? <= self.stop_loss_price)
+    }
+
+    /// Whether the oracle `price` is above the limit-buy price
+    pub fn above_max_buy_price(&self, price: &Price) -> Result<bool> {
+        Ok(self.max_buy_price > 0
+            && scale_oracle_price(price.price, price.expo)? > self.max_buy_price)
+    }
+
+    /// Whether the oracle `price` is at or above the take-profit price
+    pub fn take_profit_triggered(&self, price: &Price) -> Result<bool> {
+        Ok(self.take_profit_price > 0
+            && scale_oracle_price(price.price, price.expo)? >= self.take_profit_price)
+    }
+
+    pub fn take_profit_event(
+        &self,
+        vault: Pubkey,
+        price: &Price,
+        timestamp: i64,
+    ) -> Result<TakeProfitTriggeredEvent> {
+        Ok(TakeProfitTriggeredEvent {
+            vault,
+            price: scale_oracle_price(price.price, price.expo)?,
+            take_profit_price: self.take_profit_price,
+            liquidated: self.take_profit_liquidate,
+            timestamp,
+        })
+    }
+
+    /// Read a vault written with any earlier layout into the current one
+    /// Fields the old layout lacked get their defaults; the mint decimals
+    /// are only used for layouts that predate storing them
+    pub fn load_outdated(data: &[u8], source_decimals: u8, dest_decimals: u8) -> Result<Self> {
+        require!(
+            data.len() >= 8 && data[..8] == *Self::DISCRIMINATOR,
+            ErrorCode::NotLegacyVault
+        );
+        let len = data.len() - 8;
+        require!(len < Self::LEN, ErrorCode::VaultAlreadyMigrated);
+
+        if len <= LegacyVault::SPACE - 8 {
+            return Ok(LegacyVault::load(data)?.migrate(source_decimals, dest_decimals));
+        }
+
+        let unversioned_len = Self::UNVERSIONED_LENS[Self::UNVERSIONED_LENS.len() - 1];
+        let mut body = vec![0u8; Self::LEN];
+        if len > unversioned_len {
+            // Versioned layouts only ever append fields
+            body[..len].copy_from_slice(&data[8..]);
+        } else {
+            require!(Self::UNVERSIONED_LENS.contains(&len), ErrorCode::NotLegacyVault);
+            let fields = len - 2;
+            body[..fields].copy_from_slice(&data[8..8 + fields]);
+            body[unversioned_len - 2..unversioned_len].copy_from_slice(&data[8 + fields..]);
+        }
+        let mut vault = Self::deserialize(&mut &body[..])?;
+
+        // Fields whose default isn't zero, keyed by the layout that added them
+        if len < 184 {
+            vault.slippage_bps = Self::DEFAULT_SLIPPAGE_BPS;
+        }
+        if len < 220 {
+            vault.source_decimals = source_decimals;
+            vault.dest_decimals = dest_decimals;
+        }
+        if len < 236 {
+            // Vaults only supported fixed-size cycles back then
+            vault.cumulative_amount_in = vault
+                .amount_per_cycle
+                .saturating_mul(vault.executed_cycles as u64);
+        }
+        if len < 399 {
+            vault.set_native_flags();
+        }
+        if len < 623 {
+            vault.rent_payer = vault.owner;
+        }
+        if len < 739 {
+            // Nothing recorded which cycles the price sum covers, so it
+            // restarts together with `priced_cycles`
+            vault.cycles_executed_sum_price = 0;
+        }
+        vault.version = Self::VERSION;
+
+        Ok(vault)
+    }
+}
+
+// Keep LEN in sync with the field list above when adding fields
+const _: () = assert!(Vault::LEN == 739);
+
+/// Per-leg route for `execute_basket`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BasketRoute {
+    pub route_data: Vec<u8>,
+    pub swap_account_count: u8,
+}
+
+impl BasketRoute {
+    /// Accounts preceding the Jupiter route accounts in each leg's group
+    pub const FIXED_ACCOUNTS: usize = 5;
+}
+
+/// Derived vault progress returned by `get_summary`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct VaultSummary {
+    pub percent_complete_bps: u16,
+    pub remaining_cycles: u16,
+    pub average_price: u64,
+    pub estimated_completion_ts: i64,
+    pub best_cycle_out: u64,
+    pub worst_cycle_out: u64,
+    pub cycles_executed_sum_price: u128,
+    pub priced_cycles: u16,
+    pub average_cycle_price: u64,
+    pub total_paused_seconds: i64,
+}
+
+/// Vault layout before any fields were added (allocated with `space = 166`),
+/// read by `migrate_vault`
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyVault {
+    pub owner: Pubkey,
+    pub source_mint: Pubkey,
+    pub dest_mint: Pubkey,
+    pub amount_per_cycle: u64,
+    pub frequency_seconds: i64,
+    pub total_cycles: u16,
+    pub executed_cycles: u16,
+    pub total_deposited: u64,
+    pub total_received: u64,
+    pub last_execution: i64,
+    pub next_execution: i64,
+    pub status: u8,
+    pub bump: u8,
+}
+
+impl LegacyVault {
+    pub const LEN: usize = 32 // owner
+        + 32 // source_mint
+        + 32 // dest_mint
+        + 8 // amount_per_cycle
+        + 8 // frequency_seconds
+        + 2 // total_cycles
+        + 2 // executed_cycles
+        + 8 // total_deposited
+        + 8 // total_received
+        + 8 // last_execution
+        + 8 // next_execution
+        + 1 // status
+        + 1; // bump
+
+    /// Size legacy vaults were allocated with, padding included; vaults
+    /// created once space was derived from `LEN` have no padding
+    pub const SPACE: usize = 166;
+
+    /// Read a legacy vault from raw account data, which must be exactly one
+    /// of the old allocations and carry the `Vault` discriminator
+    pub fn load(data: &[u8]) -> Result<Self> {
+        require!(
+            (data.len() == Self::SPACE || data.len() == 8 + Self::LEN)
+                && data[..8] == *Vault::DISCRIMINATOR,
+            ErrorCode::NotLegacyVault
+        );
+        Ok(Self::deserialize(&mut &data[8..8 + Self::LEN])?)
+    }
+
+    /// Current-layout vault carrying over the legacy state, with every field
+    /// added since filled with its default
+    pub fn migrate(&self, source_decimals: u8, dest_decimals: u8) -> Vault {
+        let mut vault = Vault {
+            owner: self.owner,
+            creator: self.owner,
+            source_mint: self.source_mint,
+            dest_mint: self.dest_mint,
+            amount_per_cycle: self.amount_per_cycle,
+            frequency_seconds: self.frequency_seconds,
+            total_cycles: self.total_cycles,
+            executed_cycles: self.executed_cycles,
+            total_deposited: self.total_deposited,
+            total_received: self.total_received,
+            last_execution: self.last_execution,
+            next_execution: self.next_execution,
+            slippage_bps: Vault::DEFAULT_SLIPPAGE_BPS,
+            price_feed: Pubkey::default(),
+            max_price_deviation_bps: 0,
+            source_decimals,
+            dest_decimals,
+            keeper_reward: 0,
+            // Legacy vaults only supported fixed-size cycles
+            cumulative_amount_in: self
+                .amount_per_cycle
+                .saturating_mul(self.executed_cycles as u64),
+            recent_prices: [0; Vault::RECENT_PRICES_LEN],
+            price_cursor: 0,
+            stop_loss_price: 0,
+            mode: Vault::MODE_FIXED,
+            percent_bps: 0,
+            catch_up: false,
+            max_delay_seconds: 0,
+            locked: false,
+            require_session: false,
+            low_balance_threshold: 0,
+            consecutive_failures: 0,
+            max_failures: 0,
+            is_native_source: false,
+            is_native_dest: false,
+            label: [0; 32],
+            require_min_out: false,
+            compound: false,
+            compound_account: Pubkey::default(),
+            max_deposit: 0,
+            withdraw_cooldown_seconds: 0,
+            last_withdraw: 0,
+            skipped_cycles: 0,
+            max_buy_price: 0,
+            executor: Pubkey::default(),
+            swap_dust: false,
+            execution_window_seconds: 0,
+            is_basket: false,
+            referrer: Pubkey::default(),
+            referral_earned: 0,
+            resume_preserves_schedule: false,
+            take_profit_price: 0,
+            take_profit_liquidate: false,
+            rent_payer: self.owner,
+            expiry_timestamp: 0,
+            max_amount_out: 0,
+            pause_reason: Vault::PAUSE_REASON_NONE,
+            recovery_authority: Pubkey::default(),
+            recovery_delay_seconds: 0,
+            best_cycle_out: 0,
+            worst_cycle_out: 0,
+            cycles_executed_sum_price: 0,
+            min_initial_deposit: 0,
+            paused_at: 0,
+            total_paused_seconds: 0,
+            status: self.status,
+            bump: self.bump,
+            version: Vault::VERSION,
+            priced_cycles: 0,
+        };
+        vault.set_native_flags();
+        vault
+    }
+}
+
+const _: () = assert!(8 + LegacyVault::LEN <= LegacyVault::SPACE);
+
+/// Global protocol configuration
+#[account]
+pub struct Config {
+    pub admin: Pubkey,              // 32
+    pub treasury: Pubkey,           // 32
+    pub fee_bps: u16,               // 2
+    pub jupiter_program: Pubkey,    // 32 (only swap program executions may use)
+    pub paused: bool,               // 1 (halts all executions)
+    pub allowed_mints: [Pubkey; 16], // 32 * 16 (destination mint allowlist)
+    pub allowed_mints_count: u8,    // 1 (0 = any mint allowed)
+    pub referral_share_bps: u16,    // 2 (share of the protocol fee paid to referrers)
+    pub authorized_keepers: [Pubkey; 16], // 32 * 16 (shared keeper whitelist)
+    pub authorized_keepers_count: u8, // 1
+    pub require_whitelisted_keeper: bool, // 1 (executions must come from the whitelist)
+    pub max_total_cycles: u16,      // 2 (0 = only Vault::MAX_TOTAL_CYCLES applies)
+    pub min_frequency_seconds: i64, // 8 (0 = no protocol minimum)
+    pub bump: u8,                   // 1
+    pub version: u8,                // 1 (layout version; new fields go after it)
+}
+
+impl Config {
+    pub const LEN: usize = 32 // admin
+        + 32 // treasury
+        + 2 // fee_bps
+        + 32 // jupiter_program
+        + 1 // paused
+        + 32 * 16 // allowed_mints
+        + 1 // allowed_mints_count
+        + 2 // referral_share_bps
+        + 32 * 16 // authorized_keepers
+        + 1 // authorized_keepers_count
+        + 1 // require_whitelisted_keeper
+        + 2 // max_total_cycles
+        + 8 // min_frequency_seconds
+        + 1 // bump
+        + 1; // version
+
+    /// Hard cap on the protocol fee (1%)
+    pub const MAX_FEE_BPS: u16 = 100;
+
+    pub const MAX_ALLOWED_MINTS: usize = 16;
+
+    pub const MAX_AUTHORIZED_KEEPERS: usize = 16;
+
+    /// Current layout version; bump it when appending fields after `version`
+    pub const VERSION: u8 = 1;
+
+    /// `LEN` of every layout before `version` existed, oldest first. Fields
+    /// were inserted at different positions, so `load_outdated` reads each
+    /// one field by field
+    pub const UNVERSIONED_LENS: [usize; 9] = [67, 68, 581, 613, 615, 1129, 1145, 1155, 1139];
+
+    /// Read a config written with any earlier layout into the current one
+    /// Fields the old layout lacked get their defaults
+    pub fn load_outdated(data: &[u8]) -> Result<Self> {
+        require!(
+            data.len() >= 8 && data[..8] == *Self::DISCRIMINATOR,
+            ErrorCode::NotLegacyConfig
+        );
+        let len = data.len() - 8;
+        require!(len != Self::LEN, ErrorCode::ConfigAlreadyMigrated);
+        require!(Self::UNVERSIONED_LENS.contains(&len), ErrorCode::NotLegacyConfig);
+
+        let reader = &mut &data[8..];
+        let admin = Pubkey::deserialize(reader)?;
+        let treasury = Pubkey::deserialize(reader)?;
+        let fee_bps = u16::deserialize(reader)?;
+        let jupiter_program = if len >= 613 {
+            Pubkey::deserialize(reader)?
+        } else {
+            Pubkey::default()
+        };
+        let paused = if len >= 68 { bool::deserialize(reader)? } else { false };
+        let (allowed_mints, allowed_mints_count) = if len >= 581 {
+            (<[Pubkey; 16]>::deserialize(reader)?, u8::deserialize(reader)?)
+        } else {
+            ([Pubkey::default(); 16], 0)
+        };
+        let referral_share_bps = if len >= 615 { u16::deserialize(reader)? } else { 0 };
+        let (authorized_keepers, authorized_keepers_count, require_whitelisted_keeper) =
+            if len >= 1129 {
+                (
+                    <[Pubkey; 16]>::deserialize(reader)?,
+                    u8::deserialize(reader)?,
+                    bool::deserialize(reader)?,
+                )
+            } else {
+                ([Pubkey::default(); Self::MAX_AUTHORIZED_KEEPERS], 0, false)
+            };
+        if len == 1145 || len == 1155 {
+            // Protocol-wide fee totals, now kept per token account in a
+            // `FeeLedger`
+            <[u64; 2]>::deserialize(reader)?;
+        }
+        let (max_total_cycles, min_frequency_seconds) = if len == 1155 || len == 1139 {
+            (u16::deserialize(reader)?, i64::deserialize(reader)?)
+        } else {
+            (0, 0)
+        };
+        let bump = u8::deserialize(reader)?;
+
+        Ok(Self {
+            admin,
+            treasury,
+            fee_bps,
+            jupiter_program,
+            paused,
+            allowed_mints,
+            allowed_mints_count,
+            referral_share_bps,
+            authorized_keepers,
+            authorized_keepers_count,
+            require_whitelisted_keeper,
+            max_total_cycles,
+            min_frequency_seconds,
+            bump,
+            version: Self::VERSION,
+        })
+    }
+
+    /// Executions are halted while the admin circuit breaker is engaged
+    pub fn ensure_not_paused(&self) -> Result<()> {
+        require!(!self.paused, ErrorCode::ProtocolPaused);
+        Ok(())
+    }
+
+    /// Destination tokens owed to the treasury out of `amount_received`
+    pub fn protocol_fee(&self, amount_received: u64) -> Result<u64> {
+        let fee = (amount_received as u128)
+            .checked_mul(self.fee_bps as u128)
+            .ok_or(ErrorCode::ArithmeticOverflow)?
+            / Vault::MAX_BPS as u128;
+
+        Ok(fee as u64)
+    }
+
+    /// Whether `vault` has a referrer that executions must pay
+    pub fn pays_referral(&self, vault: &Vault) -> bool {
+        vault.referrer != Pubkey::default() && self.referral_share_bps > 0
+    }
+
+    /// Referrer's share of `protocol_fee`; the treasury keeps the remainder
+    pub fn referral_fee(&self, protocol_fee: u64) -> Result<u64> {
+        let share = (protocol_fee as u128)
+            .checked_mul(self.referral_share_bps as u128)
+            .ok_or(ErrorCode::ArithmeticOverflow)?
+            / Vault::MAX_BPS as u128;
+
+        Ok(share as u64)
+    }
+
+    /// Whether `mint` may be used as a vault's destination
+    pub fn is_mint_allowed(&self, mint: &Pubkey) -> bool {
+        let count = self.allowed_mints_count as usize;
+        count == 0 || self.allowed_mints[..count].contains(mint)
+    }
+
+    pub fn add_allowed_mint(&mut self, mint: Pubkey) -> Result<()> {
+        let count = self.allowed_mints_count as usize;
+
+        require!(
+            !self.allowed_mints[..count].contains(&mint),
+            ErrorCode::DuplicateMint
+        );
+        require!(
+            count < Self::MAX_ALLOWED_MINTS,
+            ErrorCode::TooManyMints
+        );
+
+        self.allowed_mints[count] = mint;
+        self.allowed_mints_count += 1;
+        Ok(())
+    }
+
+    pub fn remove_allowed_mint(&mut self, mint: Pubkey) -> Result<()> {
+        let count = self.allowed_mints_count as usize;
+
+        let index = self.allowed_mints[..count]
+            .iter()
+            .position(|allowed| *allowed == mint)
+            .ok_or(ErrorCode::MintNotAllowed)?;
+
+        // Move the last entry into the freed slot to keep the list packed
+        self.allowed_mints[index] = self.allowed_mints[count - 1];
+        self.allowed_mints[count - 1] = Pubkey::default();
+        self.allowed_mints_count -= 1;
+        Ok(())
+    }
+
+    /// New vaults must fit the admin's schedule limits on top of the hard caps
+    /// Existing vaults are only re-checked when their schedule changes
+    pub fn check_vault_limits(&self, frequency_seconds: i64, total_cycles: u16) -> Result<()> {
+        self.check_cycle_limit(total_cycles)?;
+        self.check_frequency_limit(frequency_seconds)
+    }
+
+    pub fn check_cycle_limit(&self, total_cycles: u16) -> Result<()> {
+        require!(
+            self.max_total_cycles == 0 || total_cycles <= self.max_total_cycles,
+            ErrorCode::InvalidCycleCount
+        );
+        Ok(())
+    }
+
+    pub fn check_frequency_limit(&self, frequency_seconds: i64) -> Result<()> {
+        require!(
+            frequency_seconds >= self.min_frequency_seconds,
+            ErrorCode::InvalidFrequency
+        );
+        Ok(())
+    }
+
+    /// Whether fees are paid to the program's `[b"treasury"]` PDA, whose
+    /// token accounts must then have their fees tallied in a `FeeLedger`
+    pub fn treasury_is_pda(&self) -> bool {
+        self.treasury == Pubkey::find_program_address(&[b"treasury"], &crate::ID).0
+    }
+
+    /// While the whitelist is required, only listed keepers may execute vaults
+    pub fn ensure_keeper_authorized(&self, keeper: &Pubkey) -> Result<()> {
+        let count = self.authorized_keepers_count as usize;
+        require!(
+            !self.require_whitelisted_keeper || self.authorized_keepers[..count].contains(keeper),
+            ErrorCode::KeeperNotAuthorized
+        );
+        Ok(())
+    }
+
+    pub fn add_keeper(&mut self, keeper: Pubkey) -> Result<()> {
+        let count = self.authorized_keepers_count as usize;
+
+        require!(
+            !self.authorized_keepers[..count].contains(&keeper),
+            ErrorCode::DuplicateKeeper
+        );
+        require!(
+            count < Self::MAX_AUTHORIZED_KEEPERS,
+            ErrorCode::TooManyKeepers
+        );
+
+        self.authorized_keepers[count] = keeper;
+        self.authorized_keepers_count += 1;
+        Ok(())
+    }
+
+    pub fn remove_keeper(&mut self, keeper: Pubkey) -> Result<()> {
+        let count = self.authorized_keepers_count as usize;
+
+        let index = self.authorized_keepers[..count]
+            .iter()
+            .position(|authorized| *authorized == keeper)
+            .ok_or(ErrorCode::KeeperNotAuthorized)?;
+
+        // Move the last entry into the freed slot to keep the list packed
+        self.authorized_keepers[index] = self.authorized_keepers[count - 1];
+        self.authorized_keepers[count - 1] = Pubkey::default();
+        self.authorized_keepers_count -= 1;
+        Ok(())
+    }
+}
+
+// Keep LEN in sync with the field list above when adding fields
+const _: () = assert!(Config::LEN == 1140);
+
+/// Protocol fees paid into one token account of the `[b"treasury"]` PDA
+#[account]
+pub struct FeeLedger {
+    pub token_account: Pubkey,      // 32
+    pub mint: Pubkey,               // 32
+    pub total_fees_collected: u64,  // 8
+    pub total_fees_withdrawn: u64,  // 8
+    pub bump: u8,                   // 1
+}
+
+impl FeeLedger {
+    pub const LEN: usize = 32 // token_account
+        + 32 // mint
+        + 8 // total_fees_collected
+        + 8 // total_fees_withdrawn
+        + 1; // bump
+
+    /// Tally a protocol fee paid into the ledger's token account
+    pub fn record_fees_collected(&mut self, amount: u64) -> Result<()> {
+        self.total_fees_collected = self
+            .total_fees_collected
+            .checked_add(amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    /// Tally a withdrawal, which can never exceed what this account collected
+    pub fn record_fees_withdrawn(&mut self, amount: u64) -> Result<()> {
+        let total_fees_withdrawn = self
+            .total_fees_withdrawn
+            .checked_add(amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        require!(
+            total_fees_withdrawn <= self.total_fees_collected,
+            ErrorCode::InsufficientTreasuryFees
+        );
+
+        self.total_fees_withdrawn = total_fees_withdrawn;
+        Ok(())
+    }
+}
+
+/// Per-owner index of vault addresses, so clients can find all of an
+/// owner's vaults without scanning program accounts
+/// Vaults keep their original registry entry after `transfer_ownership`
+#[account]
+pub struct VaultRegistry {
+    pub owner: Pubkey,              // 32
+    pub bump: u8,                   // 1
+    pub vaults: Vec<Pubkey>,        // 4 + 32 * n (grown on vault init)
+}
+
+impl VaultRegistry {
+    /// Serialized size holding `count` vault addresses
+    pub const fn space(count: usize) -> usize {
+        32 + 1 + 4 + 32 * count
+    }
+
+    /// Record a newly created vault
+    pub fn register(&mut self, vault: Pubkey) {
+        self.vaults.push(vault);
+    }
+
+    /// Forget a closed vault; a vault never registered here is a no-op
+    pub fn unregister(&mut self, vault: Pubkey) {
+        self.vaults.retain(|key| *key != vault);
+    }
+}
+
+/// Weighted destination legs for a basket vault
+#[account]
+pub struct DcaBasket {
+    pub vault: Pubkey,              // 32
+    pub legs: [BasketLeg; 4],       // 34 * 4 = 136
+    pub leg_count: u8,              // 1
     pub bump: u8,                   // 1
+    pub version: u8,                // 1 (layout version; new fields go after it)
+    pub price_feeds: [Pubkey; 4],   // 32 * 4 = 128 (per-leg Pyth feed, default = none)
+    pub leg_balances: [u64; 4],     // 8 * 4 = 32 (bought per leg, not yet withdrawn)
+}
+
+impl DcaBasket {
+    pub const LEN: usize = 32 // vault
+        + 34 * 4 // legs
+        + 1 // leg_count
+        + 1 // bump
+        + 1 // version
+        + 32 * 4 // price_feeds
+        + 8 * 4; // leg_balances
+
+    pub const MAX_LEGS: usize = 4;
+
+    /// Current layout version; bump it when appending fields after `version`
+    pub const VERSION: u8 = 1;
+
+    /// `LEN` of the layout before `version` existed
+    pub const UNVERSIONED_LEN: usize = 170;
+
+    /// Legs must be unique, non-zero, never the source mint and weigh
+    /// 10_000 bps in total
+    pub fn validate_legs(source_mint: Pubkey, legs: &[BasketLeg]) -> Result<()> {
+        require!(
+            !legs.is_empty() && legs.len() <= Self::MAX_LEGS,
+            ErrorCode::InvalidBasket
+        );
+
+        let mut total_weight: u32 = 0;
+        for (i, leg) in legs.iter().enumerate() {
+            require!(leg.weight_bps > 0, ErrorCode::InvalidBasket);
+            require_keys_neq!(leg.dest_mint, source_mint, ErrorCode::InvalidMint);
+            require!(
+                !legs[..i].iter().any(|other| other.dest_mint == leg.dest_mint),
+                ErrorCode::InvalidBasket
+            );
+            total_weight += leg.weight_bps as u32;
+        }
+        require!(total_weight == Vault::MAX_BPS as u32, ErrorCode::InvalidBasket);
+
+        Ok(())
+    }
+
+    pub fn active_legs(&self) -> &[BasketLeg] {
+        &self.legs[..self.leg_count as usize]
+    }
+
+    pub fn has_leg(&self, mint: Pubkey) -> bool {
+        self.active_legs().iter().any(|leg| leg.dest_mint == mint)
+    }
+
+    /// Feed that leg `index`'s output is checked against, if it has one
+    pub fn leg_price_feed(&self, index: usize) -> Option<Pubkey> {
+        Some(self.price_feeds[index]).filter(|feed| *feed != Pubkey::default())
+    }
+
+    /// Empty, or one feed per active leg in order
+    pub fn set_price_feeds(&mut self, price_feeds: &[Pubkey]) -> Result<()> {
+        require!(
+            price_feeds.is_empty() || price_feeds.len() == self.leg_count as usize,
+            ErrorCode::InvalidBasket
+        );
+        self.price_feeds = [Pubkey::default(); Self::MAX_LEGS];
+        self.price_feeds[..price_feeds.len()].copy_from_slice(price_feeds);
+        Ok(())
+    }
+
+    /// Book `amount` bought by leg `index`
+    pub fn record_leg_received(&mut self, index: usize, amount: u64) -> Result<()> {
+        self.leg_balances[index] = self.leg_balances[index]
+            .checked_add(amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    /// Book a withdrawal of `mint`; tokens sent to the vault directly can
+    /// take a leg past what it bought, which just empties it
+    pub fn record_leg_withdrawn(&mut self, mint: Pubkey, amount: u64) {
+        if let Some(index) = self.active_legs().iter().position(|leg| leg.dest_mint == mint) {
+            self.leg_balances[index] = self.leg_balances[index].saturating_sub(amount);
+        }
+    }
+
+    /// A basket vault only closes once every leg's purchases are withdrawn,
+    /// since `close_vault` sweeps just the source and destination accounts
+    pub fn ensure_legs_empty(&self) -> Result<()> {
+        require!(
+            self.leg_balances.iter().all(|&balance| balance == 0),
+            ErrorCode::BasketLegsNotEmpty
+        );
+        Ok(())
+    }
+
+    /// Read a basket written before `version` existed; new fields start empty
+    pub fn load_outdated(data: &[u8]) -> Result<Self> {
+        require!(
+            data.len() == 8 + Self::UNVERSIONED_LEN && data[..8] == *Self::DISCRIMINATOR,
+            ErrorCode::NotLegacyBasket
+        );
+        let mut body = vec![0u8; Self::LEN];
+        body[..Self::UNVERSIONED_LEN].copy_from_slice(&data[8..]);
+
+        let mut basket = Self::deserialize(&mut &body[..])?;
+        basket.version = Self::VERSION;
+        Ok(basket)
+    }
+}
+
+// Keep LEN in sync with the field list above when adding fields
+const _: () = assert!(DcaBasket::LEN == 331);
+
+/// One destination of a basket and its share of each cycle
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct BasketLeg {
+    pub dest_mint: Pubkey,          // 32
+    pub weight_bps: u16,            // 2
+}
+
+impl BasketLeg {
+    /// This leg's share of a cycle's source amount
+    pub fn leg_amount(&self, amount_per_cycle: u64) -> Result<u64> {
+        let amount = (amount_per_cycle as u128)
+            .checked_mul(self.weight_bps as u128)
+            .ok_or(ErrorCode::ArithmeticOverflow)?
+            / Vault::MAX_BPS as u128;
+
+        Ok(amount as u64)
+    }
+}
+
+// ============================================
+// Instruction Arguments
+// ============================================
+
+/// Creation parameters of a new vault, as documented on `initialize_vault`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct VaultParams {
+    pub amount_per_cycle: u64,
+    pub frequency_seconds: i64,
+    pub total_cycles: u16,
+    pub slippage_bps: u16,
+    pub keeper_reward: u64,
+    pub mode: u8,
+    pub percent_bps: u16,
+    pub catch_up: bool,
+    pub max_delay_seconds: i64,
+    pub low_balance_threshold: u64,
+    pub label: [u8; 32],
+    pub max_deposit: u64,
+    pub withdraw_cooldown_seconds: i64,
+    pub executor: Pubkey,
+    pub referrer: Pubkey,
+    pub jitter_seconds: i64,
+    pub expiry_timestamp: i64,
+    pub recovery_authority: Pubkey,
+    pub recovery_delay_seconds: i64,
+    pub min_initial_deposit: u64,
+}
+
+/// Per-vault arguments for `execute_dca_batch`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BatchExecution {
+    pub route_data: Vec<u8>,
+    pub min_amount_out: u64,
+    pub swap_account_count: u8,
+}
+
+impl BatchExecution {
+    /// Accounts preceding the Jupiter route accounts in each batch group
+    pub const FIXED_ACCOUNTS: usize = 9;
+}
+
+// ============================================
+// Events
+// ============================================
+
+/// Event emitted when a new vault is created
+#[event]
+pub struct VaultInitializedEvent {
+    pub vault: Pubkey,
+    pub owner: Pubkey,
+    pub source_mint: Pubkey,
+    pub dest_mint: Pubkey,
+    pub amount_per_cycle: u64,
+    pub frequency_seconds: i64,
+    pub total_cycles: u16,
+    pub label: [u8; 32],
+    pub timestamp: i64,
+}
+
+/// Event emitted when source tokens are deposited
+#[event]
+pub struct DepositEvent {
+    pub vault: Pubkey,
+    pub depositor: Pubkey,
+    pub amount: u64,
+    pub total_deposited: u64,
+    pub timestamp: i64,
+}
+
+/// Event emitted when a DCA cycle is executed
+#[event]
+pub struct DCAExecutedEvent {
+    pub vault: Pubkey,
+    pub cycle: u16,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub keeper_reward: u64,
+    pub protocol_fee: u64,
+    pub average_price: u64,
+    pub side: u8,
+    pub timestamp: i64,
+}
+
+/// Event emitted when a vault's source balance falls below its threshold
+#[event]
+pub struct LowBalanceEvent {
+    pub vault: Pubkey,
+    pub remaining_balance: u64,
+    pub timestamp: i64,
+}
+
+/// Event emitted after a batch execution
+#[event]
+pub struct BatchExecutedEvent {
+    pub executed_count: u16,
+    pub skipped_count: u16,
+    pub timestamp: i64,
+}
+
+/// Event emitted for each vault a batch execution skips
+#[event]
+pub struct VaultSkippedEvent {
+    pub vault: Pubkey,
+    pub error_code: u64,
+    pub timestamp: i64,
+}
+
+/// Event emitted when vault status changes
+#[event]
+pub struct VaultStatusChangedEvent {
+    pub vault: Pubkey,
+    pub old_status: u8,
+    pub new_status: u8,
+    pub pause_reason: u8, // Vault::PAUSE_REASON_* (0 unless new_status is paused)
+    pub timestamp: i64,
+}
+
+/// Event emitted when the oracle price reaches a vault's take-profit price
+#[event]
+pub struct TakeProfitTriggeredEvent {
+    pub vault: Pubkey,
+    pub price: u64,
+    pub take_profit_price: u64,
+    pub liquidated: bool,
+    pub timestamp: i64,
+}
+
+/// Event emitted when a vault's schedule changes
+#[event]
+pub struct ScheduleUpdatedEvent {
+    pub vault: Pubkey,
+    pub amount_per_cycle: u64,
+    pub frequency_seconds: i64,
+    pub total_cycles: u16,
+    pub next_execution: i64,
+    pub timestamp: i64,
+}
+
+/// Event emitted when vault ownership is transferred
+#[event]
+pub struct OwnershipTransferredEvent {
+    pub vault: Pubkey,
+    pub old_owner: Pubkey,
+    pub new_owner: Pubkey,
+}
+
+/// Event emitted when source tokens are withdrawn mid-DCA
+#[event]
+pub struct SourceWithdrawnEvent {
+    pub vault: Pubkey,
+    pub amount: u64,
+    pub remaining: u64,
+    pub timestamp: i64,
+}
+
+/// Event emitted when destination tokens are withdrawn
+#[event]
+pub struct DestWithdrawnEvent {
+    pub vault: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+/// Event emitted when a basket leg's tokens are withdrawn
+#[event]
+pub struct BasketLegWithdrawnEvent {
+    pub vault: Pubkey,
+    pub dest_mint: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+/// Event emitted after a basket cycle
+#[event]
+pub struct BasketExecutedEvent {
+    pub vault: Pubkey,
+    pub cycle: u16,
+    pub amount_in: u64,
+    pub amounts_out: Vec<u64>,
+    pub protocol_fees: Vec<u64>,
+    pub timestamp: i64,
+}
+
+/// Event emitted just before a swap reverts with `SlippageExceeded`
+#[event]
+pub struct SlippageFailureEvent {
+    pub vault: Pubkey,
+    pub amount_received: u64,
+    pub min_amount_out: u64,
+    pub timestamp: i64,
+}
+
+/// Event emitted when a cycle is skipped without swapping
+#[event]
+pub struct CycleSkippedEvent {
+    pub vault: Pubkey,
+    pub reason: u8,
+    pub skipped_cycles: u16,
+    pub next_execution: i64,
+    pub timestamp: i64,
+}
+
+/// Event emitted when the admin toggles the global pause
+#[event]
+pub struct GlobalPauseEvent {
+    pub admin: Pubkey,
+    pub paused: bool,
+    pub timestamp: i64,
+}
+
+// ============================================
+// Errors
+// ============================================
+
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Vault is not active")]
+    VaultNotActive,
+    
+    #[msg("Too early to execute DCA")]
+    TooEarlyToExecute,
+    
+    #[msg("All cycles have been completed")]
+    AllCyclesCompleted,
+    
+    #[msg("Insufficient balance in vault")]
+    InsufficientBalance,
+    
+    #[msg("Vault is not paused")]
+    VaultNotPaused,
+
+    #[msg("Slippage exceeded - received less than minimum")]
+    SlippageExceeded,
+
+    #[msg("Invalid token mint")]
+    InvalidMint,
+
+    #[msg("Unauthorized - not vault owner")]
+    Unauthorized,
+
+    #[msg("Amount must be greater than zero")]
+    InvalidAmount,
+
+    #[msg("Arithmetic overflow")]
+    ArithmeticOverflow,
+
+    #[msg("Swap spent more than amount per cycle")]
+    SwapInputExceeded,
+
+    #[msg("Withdrawal would leave less than one cycle in vault")]
+    WithdrawalLeavesInsufficientBalance,
+
+    #[msg("Slippage must not exceed 10000 bps")]
+    InvalidSlippage,
+
+    #[msg("Price feed account is required for this vault")]
+    MissingPriceFeed,
+
+    #[msg("Invalid price feed")]
+    InvalidPriceFeed,
+
+    #[msg("Price feed is stale")]
+    StalePriceFeed,
+
+    #[msg("Execution price deviates too far from oracle price")]
+    PriceDeviationExceeded,
+
+    #[msg("Frequency must be between 1 second and 1 year")]
+    InvalidFrequency,
+
+    #[msg("Cycle count must be between 1 and the maximum allowed")]
+    InvalidCycleCount,
+
+    #[msg("Vault is already completed or cancelled")]
+    VaultAlreadyFinalized,
+
+    #[msg("No DCA cycles have been executed yet")]
+    NoExecutionsYet,
+
+    #[msg("Invalid DCA mode")]
+    InvalidMode,
+
+    #[msg("Percent must be between 1 and 10000 bps")]
+    InvalidPercent,
+
+    #[msg("Protocol fee exceeds maximum")]
+    FeeTooHigh,
+
+    #[msg("Max delay must not be negative")]
+    InvalidMaxDelay,
+
+    #[msg("Vault must be completed or cancelled before closing")]
+    VaultStillActive,
+
+    #[msg("Vault is locked by an in-progress execution")]
+    VaultLocked,
+
+    #[msg("Batch remaining accounts do not match executions")]
+    InvalidBatchAccounts,
+
+    #[msg("Token account is not owned by the expected authority")]
+    InvalidTokenAccountOwner,
+
+    #[msg("Session key, session keys program and instructions sysvar must be supplied together")]
+    MissingSessionAccounts,
+
+    #[msg("Vault requires a keeper session key")]
+    SessionRequired,
+
+    #[msg("Session key is not owned by the vault owner")]
+    InvalidSessionKeyOwner,
+
+    #[msg("Protocol is globally paused")]
+    ProtocolPaused,
+
+    #[msg("Destination mint is not on the allowlist")]
+    MintNotAllowed,
+
+    #[msg("Too many allowed mints")]
+    TooManyMints,
+
+    #[msg("Mint is already on the allowlist")]
+    DuplicateMint,
+
+    #[msg("Label must be non-empty UTF-8")]
+    InvalidLabel,
+
+    #[msg("Vault already uses the current layout")]
+    VaultAlreadyMigrated,
+
+    #[msg("Account is not a legacy vault")]
+    InvalidVaultAccount,
+
+    #[msg("Account data does not match any earlier vault layout")]
+    NotLegacyVault,
+
+    #[msg("Config already uses the current layout")]
+    ConfigAlreadyMigrated,
+
+    #[msg("Account data does not match any earlier config layout")]
+    NotLegacyConfig,
+
+    #[msg("Vault requires a non-zero min_amount_out")]
+    MinOutRequired,
+
+    #[msg("Swap produced no output")]
+    ZeroSwapOutput,
+
+    #[msg("Compounding vault requires its compound token account")]
+    MissingCompoundAccount,
+
+    #[msg("Compound token account does not match the vault")]
+    InvalidCompoundAccount,
+
+    #[msg("Deposit would exceed the vault's deposit cap")]
+    DepositCapExceeded,
+
+    #[msg("Deposit cap cannot be below the amount already deposited")]
+    InvalidDepositCap,
+
+    #[msg("Withdrawal cooldown has not elapsed")]
+    WithdrawCooldown,
+
+    #[msg("Withdrawal cooldown must be between 0 and 30 days")]
+    InvalidWithdrawCooldown,
+
+    #[msg("Swap program does not match the configured Jupiter program")]
+    InvalidSwapProgram,
+
+    #[msg("Execution window must not be negative")]
+    InvalidExecutionWindow,
+
+    #[msg("Basket legs must be unique, non-zero and weigh 10000 bps in total")]
+    InvalidBasket,
+
+    #[msg("Basket routes or accounts do not match its legs")]
+    InvalidBasketAccounts,
+
+    #[msg("Basket vaults must be executed with execute_basket")]
+    BasketVault,
+
+    #[msg("Referral share cannot exceed 10000 bps")]
+    InvalidReferralShare,
+
+    #[msg("Referred vaults require the referrer token account")]
+    MissingReferrerAccount,
+
+    #[msg("Referrer token account is not owned by the vault's referrer")]
+    InvalidReferrerAccount,
+
+    #[msg("Jitter must be non-negative and shorter than the frequency")]
+    InvalidJitter,
+
+    #[msg("SlotHashes sysvar data is invalid")]
+    InvalidSlotHashes,
+
+    #[msg("Token account must be empty before it can be closed")]
+    TokenAccountNotEmpty,
+
+    #[msg("Vault expiry must be zero or in the future")]
+    InvalidVaultExpiry,
+
+    #[msg("Keeper is not in the authorized keeper list")]
+    KeeperNotAuthorized,
+
+    #[msg("Too many authorized keepers")]
+    TooManyKeepers,
+
+    #[msg("Keeper is already authorized")]
+    DuplicateKeeper,
+
+    #[msg("Swap output exceeds the vault's maximum amount out")]
+    SuspiciousOutput,
+
+    #[msg("Withdrawal exceeds the fees collected by the treasury")]
+    InsufficientTreasuryFees,
+
+    #[msg("Fee ledger is required when fees go to the treasury PDA")]
+    MissingFeeLedger,
+
+    #[msg("Fee ledger does not track the treasury token account")]
+    InvalidFeeLedger,
+
+    #[msg("Vault is not at the expected cycle")]
+    CycleMismatch,
+
+    #[msg("Recovery delay cannot be negative")]
+    InvalidRecoveryDelay,
+
+    #[msg("Recovery is not available until the vault has been inactive for the recovery delay")]
+    RecoveryNotYetAvailable,
+
+    #[msg("Destination token account already exists")]
+    DestAccountAlreadyExists,
+
+    #[msg("Vault has not received its minimum initial deposit")]
+    DepositTooSmall,
+
+    #[msg("Unknown frequency unit")]
+    InvalidFrequencyUnit,
+
+    #[msg("Token program does not own the mint")]
+    InvalidTokenProgram,
+
+    #[msg("Withdraw every basket leg before closing the vault")]
+    BasketLegsNotEmpty,
+
+    #[msg("Account data does not match the earlier basket layout")]
+    NotLegacyBasket,
+
+    #[msg("Native vaults withdraw through an unwrap account, others to the owner's token account")]
+    InvalidWithdrawAccounts,
+
+    #[msg("Schedule jitter needs the SlotHashes sysvar")]
+    MissingSlotHashes,
 }
 
-impl Vault {
-    pub const STATUS_ACTIVE: u8 = 0;
-    pub const STATUS_PAUSED: u8 = 1;
-    pub const STATUS_COMPLETED: u8 = 2;
-    pub const STATUS_CANCELLED: u8 = 3;
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::error::ErrorCode as AnchorErrorCode;
+    use anchor_lang::solana_program::bpf_loader_upgradeable;
+    use anchor_lang::solana_program::program_pack::Pack;
+    use anchor_lang::solana_program::program_stubs;
+    use anchor_lang::InstructionData;
+    use anchor_spl::token::{self, spl_token};
+    use spl_token_2022::extension::transfer_fee::{TransferFee, TransferFeeConfig};
+    use spl_token_2022::extension::{
+        BaseStateWithExtensionsMut, ExtensionType, StateWithExtensionsMut,
+    };
+    use pyth_sdk_solana::state as pyth_state;
+    use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+    use solana_sdk::signature::Signer as _;
+    use solana_sdk::transaction::Transaction;
+    use std::collections::BTreeSet;
+
+    fn zeroed_vault() -> Vault {
+        Vault::deserialize(&mut &[0u8; Vault::LEN][..]).unwrap()
+    }
+
+    /// Backing storage for an `AccountInfo` passed to `try_accounts`
+    struct TestAccount {
+        key: Pubkey,
+        owner: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+        is_signer: bool,
+        executable: bool,
+    }
+
+    impl TestAccount {
+        fn new(key: Pubkey, owner: Pubkey, data: Vec<u8>) -> Self {
+            Self {
+                key,
+                owner,
+                lamports: 1_000_000_000,
+                data,
+                is_signer: false,
+                executable: false,
+            }
+        }
+
+        fn program_owned<T: AccountSerialize>(key: Pubkey, account: &T) -> Self {
+            let mut data = Vec::new();
+            account.try_serialize(&mut data).unwrap();
+            Self::new(key, crate::ID, data)
+        }
+
+        fn token_account(mint: Pubkey, owner: Pubkey) -> Self {
+            let mut data = vec![0u8; spl_token::state::Account::LEN];
+            spl_token::state::Account {
+                mint,
+                owner,
+                state: spl_token::state::AccountState::Initialized,
+                ..Default::default()
+            }
+            .pack_into_slice(&mut data);
+            Self::new(Pubkey::new_unique(), token::ID, data)
+        }
+
+        fn mint(key: Pubkey) -> Self {
+            let mut data = vec![0u8; spl_token::state::Mint::LEN];
+            spl_token::state::Mint {
+                decimals: 6,
+                is_initialized: true,
+                ..Default::default()
+            }
+            .pack_into_slice(&mut data);
+            Self::new(key, token::ID, data)
+        }
+
+        /// Token-2022 mint charging `fee_bps` on every transfer
+        fn mint_2022(key: Pubkey, fee_bps: u16) -> Self {
+            let len = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[
+                ExtensionType::TransferFeeConfig,
+            ])
+            .unwrap();
+            let mut data = vec![0u8; len];
+            let mut state =
+                StateWithExtensionsMut::<spl_token_2022::state::Mint>::unpack_uninitialized(
+                    &mut data,
+                )
+                .unwrap();
+            let fee = TransferFee {
+                transfer_fee_basis_points: fee_bps.into(),
+                maximum_fee: u64::MAX.into(),
+                ..Default::default()
+            };
+            let config = state.init_extension::<TransferFeeConfig>(true).unwrap();
+            config.older_transfer_fee = fee;
+            config.newer_transfer_fee = fee;
+            state.base = spl_token_2022::state::Mint {
+                decimals: 6,
+                is_initialized: true,
+                ..Default::default()
+            };
+            state.pack_base();
+            state.init_account_type().unwrap();
+            Self::new(key, spl_token_2022::ID, data)
+        }
+
+        /// Token-2022 account for a transfer-fee mint
+        fn token_account_2022(mint: Pubkey, owner: Pubkey) -> Self {
+            let len = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Account>(
+                &[ExtensionType::TransferFeeAmount],
+            )
+            .unwrap();
+            let mut data = vec![0u8; len];
+            let mut state =
+                StateWithExtensionsMut::<spl_token_2022::state::Account>::unpack_uninitialized(
+                    &mut data,
+                )
+                .unwrap();
+            state.init_extension::<TransferFeeAmount>(true).unwrap();
+            state.base = spl_token_2022::state::Account {
+                mint,
+                owner,
+                state: spl_token_2022::state::AccountState::Initialized,
+                ..Default::default()
+            };
+            state.pack_base();
+            state.init_account_type().unwrap();
+            Self::new(Pubkey::new_unique(), spl_token_2022::ID, data)
+        }
+
+        fn signer(key: Pubkey) -> Self {
+            Self {
+                is_signer: true,
+                ..Self::new(key, system_program::ID, Vec::new())
+            }
+        }
+
+        fn program(key: Pubkey) -> Self {
+            Self {
+                executable: true,
+                ..Self::new(key, bpf_loader_upgradeable::ID, Vec::new())
+            }
+        }
+
+        /// Pyth price account publishing `price * 10^expo` at `publish_time`
+        fn price_feed(key: Pubkey, price: i64, expo: i32, publish_time: i64) -> Self {
+            Self::price_feed_with_conf(key, price, 0, expo, publish_time)
+        }
+
+        /// Pyth price feed whose price has confidence interval `conf`
+        fn price_feed_with_conf(
+            key: Pubkey,
+            price: i64,
+            conf: u64,
+            expo: i32,
+            publish_time: i64,
+        ) -> Self {
+            let account = SolanaPriceAccount {
+                magic: pyth_state::MAGIC,
+                ver: pyth_state::VERSION_2,
+                atype: pyth_state::AccountType::Price as u32,
+                expo,
+                timestamp: publish_time,
+                agg: pyth_state::PriceInfo {
+                    price,
+                    conf,
+                    status: pyth_state::PriceStatus::Trading,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            Self::new(key, Pubkey::new_unique(), bytemuck::bytes_of(&account).to_vec())
+        }
+
+        fn set_token_amount(&mut self, amount: u64) {
+            let mut token_account = spl_token::state::Account::unpack(&self.data).unwrap();
+            token_account.amount = amount;
+            token_account.pack_into_slice(&mut self.data);
+        }
+
+        /// Placeholder for an omitted optional account
+        fn none() -> Self {
+            Self::new(crate::ID, system_program::ID, Vec::new())
+        }
+
+        fn info(&mut self) -> AccountInfo<'_> {
+            AccountInfo::new(
+                &self.key,
+                self.is_signer,
+                true,
+                &mut self.lamports,
+                &mut self.data,
+                &self.owner,
+                self.executable,
+                0,
+            )
+        }
+    }
+
+    /// Run the account validation `T` performs for an instruction
+    fn validate_accounts<'info, T, B>(infos: &'info [AccountInfo<'info>]) -> Result<()>
+    where
+        T: Accounts<'info, B>,
+        B: Default,
+    {
+        let mut remaining = infos;
+        T::try_accounts(
+            &crate::ID,
+            &mut remaining,
+            &[],
+            &mut B::default(),
+            &mut BTreeSet::new(),
+        )
+        .map(|_| ())
+    }
+
+    fn test_vault() -> Vault {
+        let mut vault = zeroed_vault();
+        vault.owner = Pubkey::new_unique();
+        vault.creator = vault.owner;
+        vault.source_mint = Pubkey::new_unique();
+        vault.dest_mint = Pubkey::new_unique();
+        vault
+    }
+
+    fn vault_address(vault: &mut Vault) -> Pubkey {
+        let (address, bump) = Pubkey::find_program_address(
+            &[
+                b"vault",
+                vault.creator.as_ref(),
+                vault.source_mint.as_ref(),
+                vault.dest_mint.as_ref(),
+            ],
+            &crate::ID,
+        );
+        vault.bump = bump;
+        address
+    }
+
+    fn config_address() -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"config"], &crate::ID)
+    }
+
+    fn test_config() -> Config {
+        Config {
+            admin: Pubkey::new_unique(),
+            treasury: Pubkey::new_unique(),
+            fee_bps: 10,
+            jupiter_program: Pubkey::new_unique(),
+            paused: false,
+            allowed_mints: [Pubkey::default(); Config::MAX_ALLOWED_MINTS],
+            allowed_mints_count: 0,
+            referral_share_bps: 0,
+            authorized_keepers: [Pubkey::default(); Config::MAX_AUTHORIZED_KEEPERS],
+            authorized_keepers_count: 0,
+            require_whitelisted_keeper: false,
+            max_total_cycles: 0,
+            min_frequency_seconds: 0,
+            bump: config_address().1,
+            version: Config::VERSION,
+        }
+    }
+
+    /// Accounts for `execute_dca` in instruction order, with the config
+    /// stored at `config_key`
+    fn execute_dca_accounts(config_key: Pubkey) -> Vec<TestAccount> {
+        let mut vault = test_vault();
+        let vault_key = vault_address(&mut vault);
+        let config = test_config();
+
+        vec![
+            TestAccount::program_owned(vault_key, &vault),
+            TestAccount::signer(Pubkey::new_unique()),
+            TestAccount::token_account(vault.source_mint, vault_key),
+            TestAccount::token_account(vault.dest_mint, vault_key),
+            TestAccount::program(config.jupiter_program),
+            TestAccount::token_account(vault.source_mint, Pubkey::new_unique()),
+            TestAccount::none(),
+            TestAccount::program_owned(config_key, &config),
+            TestAccount::token_account(vault.dest_mint, config.treasury),
+            TestAccount::mint(vault.source_mint),
+            TestAccount::mint(vault.dest_mint),
+            TestAccount::none(),
+            TestAccount::none(),
+            TestAccount::program(token::ID),
+            TestAccount::program(token::ID),
+            TestAccount::none(),
+            TestAccount::none(),
+            TestAccount::none(),
+            TestAccount::none(),
+            TestAccount::new(event_authority(), system_program::ID, Vec::new()),
+            TestAccount::program(crate::ID),
+        ]
+    }
+
+    fn event_authority() -> Pubkey {
+        Pubkey::find_program_address(&[b"__event_authority"], &crate::ID).0
+    }
+
+    const EXECUTE_DCA_CONFIG_INDEX: usize = 7;
+    const EXECUTE_DCA_REFERRER_INDEX: usize = 12;
+    const EXECUTE_DCA_SESSION_KEY_INDEX: usize = 15;
+
+    fn validate_execute_dca(accounts: &mut [TestAccount]) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        validate_accounts::<ExecuteDCA, ExecuteDCABumps>(&infos)
+    }
+
+    #[test]
+    fn vault_len_matches_serialized_size() {
+        let mut data = Vec::new();
+        zeroed_vault().serialize(&mut data).unwrap();
+        assert_eq!(data.len(), Vault::LEN);
+    }
+
+    #[test]
+    fn capped_withdrawal_takes_requested_amount_within_balance() {
+        assert_eq!(capped_withdrawal(40, 100).unwrap(), 40);
+        assert_eq!(capped_withdrawal(100, 100).unwrap(), 100);
+    }
+
+    #[test]
+    fn capped_withdrawal_caps_at_balance() {
+        assert_eq!(capped_withdrawal(150, 100).unwrap(), 100);
+        assert_eq!(capped_withdrawal(u64::MAX, 100).unwrap(), 100);
+    }
+
+    #[test]
+    fn capped_withdrawal_rejects_empty_balance() {
+        assert_eq!(
+            capped_withdrawal(u64::MAX, 0),
+            Err(ErrorCode::InsufficientBalance.into())
+        );
+    }
+
+    #[test]
+    fn record_buy_accumulates_up_to_u64_max() {
+        let mut vault = zeroed_vault();
+        vault.total_received = u64::MAX - 10;
+
+        vault.record_buy(100, 10).unwrap();
+        assert_eq!(vault.total_received, u64::MAX);
+        assert_eq!(vault.cumulative_amount_in, 100);
+    }
+
+    #[test]
+    fn record_buy_rejects_total_received_overflow() {
+        let mut vault = zeroed_vault();
+        vault.total_received = u64::MAX;
+
+        assert_eq!(vault.record_buy(100, 1), Err(ErrorCode::ArithmeticOverflow.into()));
+        assert_eq!(vault.total_received, u64::MAX);
+    }
+
+    #[test]
+    fn offset_timestamp_rejects_overflow() {
+        assert_eq!(offset_timestamp(100, 60).unwrap(), 160);
+        assert_eq!(
+            offset_timestamp(i64::MAX - 10, 60),
+            Err(ErrorCode::ArithmeticOverflow.into())
+        );
+    }
+
+    #[test]
+    fn set_status_reports_pause_and_resume() {
+        let mut vault = zeroed_vault();
+        let key = Pubkey::new_unique();
+
+        let paused = vault.set_status(key, Vault::STATUS_PAUSED, 100);
+        assert_eq!(vault.status, Vault::STATUS_PAUSED);
+        assert_eq!(paused.vault, key);
+        assert_eq!(
+            (paused.old_status, paused.new_status, paused.timestamp),
+            (Vault::STATUS_ACTIVE, Vault::STATUS_PAUSED, 100)
+        );
+
+        let resumed = vault.set_status(key, Vault::STATUS_ACTIVE, 200);
+        assert_eq!(vault.status, Vault::STATUS_ACTIVE);
+        assert_eq!(
+            (resumed.old_status, resumed.new_status, resumed.timestamp),
+            (Vault::STATUS_PAUSED, Vault::STATUS_ACTIVE, 200)
+        );
+    }
+
+    #[test]
+    fn pause_and_resume_accumulate_paused_seconds() {
+        let mut vault = test_vault();
+        let key = Pubkey::new_unique();
+        vault.frequency_seconds = 3_600;
+
+        vault.pause(key, 1_000).unwrap();
+        assert_eq!(vault.paused_at, 1_000);
+        vault.resume(key, 1_600).unwrap();
+        assert_eq!((vault.paused_at, vault.total_paused_seconds), (0, 600));
+
+        // A second pause adds to the first; the open pause isn't counted yet
+        vault.pause(key, 5_000).unwrap();
+        assert_eq!(vault.summary().unwrap().total_paused_seconds, 600);
+        vault.resume(key, 5_250).unwrap();
+        assert_eq!(vault.summary().unwrap().total_paused_seconds, 850);
+
+        // Cancelling while paused closes the pause as well
+        vault.set_paused(key, Vault::PAUSE_REASON_AUTO_SLIPPAGE, 6_000);
+        vault.cancel(key, 6_100).unwrap();
+        assert_eq!(vault.total_paused_seconds, 950);
+    }
+
+    #[test]
+    fn set_status_reports_completion_and_cancellation() {
+        let mut vault = zeroed_vault();
+        let key = Pubkey::new_unique();
+
+        let completed = vault.set_status(key, Vault::STATUS_COMPLETED, 300);
+        assert_eq!(
+            (completed.old_status, completed.new_status),
+            (Vault::STATUS_ACTIVE, Vault::STATUS_COMPLETED)
+        );
+
+        let mut open_vault = zeroed_vault();
+        let cancelled = open_vault.set_status(key, Vault::STATUS_CANCELLED, 400);
+        assert_eq!(
+            (cancelled.old_status, cancelled.new_status),
+            (Vault::STATUS_ACTIVE, Vault::STATUS_CANCELLED)
+        );
+    }
+
+    #[test]
+    fn validate_new_vault_accepts_sane_schedule() {
+        let (source, dest) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        assert!(validate_new_vault(&source, &dest, 1_000, 86_400, 30).is_ok());
+        assert!(validate_new_vault(&source, &dest, 1, Vault::MAX_FREQUENCY_SECONDS, 1).is_ok());
+    }
+
+    #[test]
+    fn validate_new_vault_rejects_each_bad_parameter() {
+        let (source, dest) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        assert_eq!(
+            validate_new_vault(&source, &dest, 0, 86_400, 30),
+            Err(ErrorCode::InvalidAmount.into())
+        );
+        for frequency in [0, -1, Vault::MAX_FREQUENCY_SECONDS + 1] {
+            assert_eq!(
+                validate_new_vault(&source, &dest, 1_000, frequency, 30),
+                Err(ErrorCode::InvalidFrequency.into())
+            );
+        }
+        assert_eq!(
+            validate_new_vault(&source, &dest, 1_000, 86_400, 0),
+            Err(ErrorCode::InvalidCycleCount.into())
+        );
+        assert_eq!(
+            validate_new_vault(&source, &source, 1_000, 86_400, 30),
+            Err(ErrorCode::InvalidMint.into())
+        );
+    }
+
+    #[test]
+    fn pause_only_from_active() {
+        let key = Pubkey::new_unique();
+        let mut vault = zeroed_vault();
+
+        let paused = vault.pause(key, 100).unwrap();
+        assert_eq!(
+            (paused.old_status, paused.new_status),
+            (Vault::STATUS_ACTIVE, Vault::STATUS_PAUSED)
+        );
+        assert_eq!(paused.pause_reason, Vault::PAUSE_REASON_MANUAL);
+        assert_eq!(vault.pause_reason, Vault::PAUSE_REASON_MANUAL);
+        assert_eq!(vault.pause(key, 200).err(), Some(ErrorCode::VaultNotActive.into()));
+
+        // Resuming clears the reason
+        let resumed = vault.resume(key, 150).unwrap();
+        assert_eq!(resumed.pause_reason, Vault::PAUSE_REASON_NONE);
+        assert_eq!(vault.pause_reason, Vault::PAUSE_REASON_NONE);
+        vault.pause(key, 200).unwrap();
+
+        vault.status = Vault::STATUS_CANCELLED;
+        assert_eq!(vault.pause(key, 300).err(), Some(ErrorCode::VaultNotActive.into()));
+        assert_eq!(vault.status, Vault::STATUS_CANCELLED);
+    }
+
+    #[test]
+    fn cancel_from_active_or_paused_only() {
+        let key = Pubkey::new_unique();
+
+        for status in [Vault::STATUS_ACTIVE, Vault::STATUS_PAUSED] {
+            let mut vault = zeroed_vault();
+            vault.status = status;
+
+            let cancelled = vault.cancel(key, 100).unwrap();
+            assert_eq!(
+                (cancelled.old_status, cancelled.new_status),
+                (status, Vault::STATUS_CANCELLED)
+            );
+        }
+
+        for status in [Vault::STATUS_COMPLETED, Vault::STATUS_CANCELLED] {
+            let mut vault = zeroed_vault();
+            vault.status = status;
+
+            assert_eq!(
+                vault.cancel(key, 100).err(),
+                Some(ErrorCode::VaultAlreadyFinalized.into())
+            );
+            assert_eq!(vault.status, status);
+        }
+    }
+
+    #[test]
+    fn extend_cycles_reactivates_completed_vault() {
+        let key = Pubkey::new_unique();
+        let mut vault = zeroed_vault();
+        vault.amount_per_cycle = 100;
+        vault.frequency_seconds = 60;
+        vault.total_cycles = 2;
+
+        // Run both cycles to completion
+        vault.executed_cycles = 2;
+        vault.last_execution = 1_000;
+        vault.next_execution = 1_060;
+        vault.set_status(key, Vault::STATUS_COMPLETED, 1_000);
+        assert!(check_cycle_ready(&vault, 1_000, 0, 1_060).is_err());
+
+        let status_changed = vault.extend_cycles(key, 3, 2_000).unwrap().unwrap();
+        assert_eq!(
+            (status_changed.old_status, status_changed.new_status),
+            (Vault::STATUS_COMPLETED, Vault::STATUS_ACTIVE)
+        );
+        assert_eq!(vault.total_cycles, 5);
+        assert_eq!(vault.next_execution, 2_060);
+
+        assert_eq!(
+            check_cycle_ready(&vault, 1_000, 0, 2_059),
+            Err(ErrorCode::TooEarlyToExecute.into())
+        );
+        assert!(check_cycle_ready(&vault, 1_000, 0, 2_060).is_ok());
+    }
+
+    #[test]
+    fn extend_cycles_keeps_active_schedule() {
+        let key = Pubkey::new_unique();
+        let mut vault = zeroed_vault();
+        vault.total_cycles = 4;
+        vault.next_execution = 500;
+
+        assert!(vault.extend_cycles(key, 2, 100).unwrap().is_none());
+        assert_eq!(vault.total_cycles, 6);
+        assert_eq!(vault.next_execution, 500);
+    }
+
+    #[test]
+    fn extend_cycles_rejects_zero_cancelled_and_over_cap() {
+        let key = Pubkey::new_unique();
+        let mut vault = zeroed_vault();
+        vault.total_cycles = Vault::MAX_TOTAL_CYCLES;
+
+        assert_eq!(
+            vault.extend_cycles(key, 0, 0).err(),
+            Some(ErrorCode::InvalidCycleCount.into())
+        );
+        assert_eq!(
+            vault.extend_cycles(key, 1, 0).err(),
+            Some(ErrorCode::InvalidCycleCount.into())
+        );
+
+        vault.total_cycles = 2;
+        vault.status = Vault::STATUS_CANCELLED;
+        assert_eq!(
+            vault.extend_cycles(key, 1, 0).err(),
+            Some(ErrorCode::VaultAlreadyFinalized.into())
+        );
+    }
+
+    #[test]
+    fn recent_prices_before_buffer_fills() {
+        let mut vault = zeroed_vault();
+        for price in 1..=3 {
+            vault.record_price(price);
+            vault.executed_cycles += 1;
+        }
+
+        assert_eq!(vault.recent_prices(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn recent_prices_wrap_around_oldest_first() {
+        let mut vault = zeroed_vault();
+        let recorded = Vault::RECENT_PRICES_LEN as u64 + 4;
+        for price in 1..=recorded {
+            vault.record_price(price);
+            vault.executed_cycles += 1;
+        }
+
+        let expected: Vec<u64> = (5..=recorded).collect();
+        assert_eq!(vault.recent_prices(), expected);
+        assert_eq!(vault.price_cursor, 4);
+    }
+
+    fn oracle_price(price: i64, expo: i32) -> Price {
+        Price {
+            price,
+            conf: 0,
+            expo,
+            publish_time: 0,
+        }
+    }
+
+    #[test]
+    fn scale_oracle_price_to_fixed_point() {
+        assert_eq!(scale_oracle_price(150_000_000, -8), Ok(1_500_000));
+        assert_eq!(scale_oracle_price(15, -1), Ok(1_500_000));
+        assert_eq!(
+            scale_oracle_price(0, -8),
+            Err(ErrorCode::InvalidPriceFeed.into())
+        );
+    }
+
+    #[test]
+    fn oracle_prices_older_than_the_max_age_are_stale() {
+        let feed_key = Pubkey::new_unique();
+        let mut price_feed = TestAccount::price_feed(feed_key, 150_000_000, -8, 1_000);
+        let price_feed = price_feed.info();
+        let read = |now: i64| {
+            read_oracle_price(Some(&price_feed), feed_key, now).map(|price| price.price)
+        };
+
+        // Fresh up to the max age after the publish time
+        assert_eq!(read(1_000), Ok(150_000_000));
+        assert_eq!(read(1_000 + Vault::MAX_PRICE_AGE_SECONDS as i64), Ok(150_000_000));
+
+        assert_eq!(
+            read(1_001 + Vault::MAX_PRICE_AGE_SECONDS as i64),
+            Err(ErrorCode::StalePriceFeed.into())
+        );
+
+        // Only the vault's own feed is read
+        assert_eq!(
+            read_oracle_price(Some(&price_feed), Pubkey::new_unique(), 1_000).map(|_| ()),
+            Err(ErrorCode::InvalidPriceFeed.into())
+        );
+    }
+
+    #[test]
+    fn stop_loss_triggers_at_or_below_threshold() {
+        let mut vault = zeroed_vault();
+        let price = oracle_price(150_000_000, -8);
+
+        // Disabled
+        assert_eq!(vault.stop_loss_triggered(&price), Ok(false));
+
+        vault.stop_loss_price = 1_499_999;
+        assert_eq!(vault.stop_loss_triggered(&price), Ok(false));
+
+        vault.stop_loss_price = 1_500_000;
+        assert_eq!(vault.stop_loss_triggered(&price), Ok(true));
+    }
+
+    #[test]
+    fn stop_loss_ignored_above_threshold_or_when_disabled() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+        vault.price_feed = Pubkey::new_unique();
+        let mut price_feed = TestAccount::price_feed(vault.price_feed, 150_000_000, -8, 0);
+
+        for stop_loss_price in [0, 1_499_999] {
+            vault.stop_loss_price = stop_loss_price;
+            let plan = prepare_cycle(&vault, 1_000, 0, Some(&price_feed.info()), 1, 0).unwrap();
+            assert!(!plan.stop_loss_triggered);
+            assert_eq!(vault.status, Vault::STATUS_ACTIVE);
+        }
+    }
+
+    /// Run `execute_cycle` with `plan` on a copy of `vault` holding
+    /// `source_balance`
+    /// Returns the outcome with the vault and its source balance afterwards
+    fn run_cycle(
+        vault: &Vault,
+        source_balance: u64,
+        plan: CyclePlan,
+        now: i64,
+    ) -> (Result<CycleEvents>, Vault, u64) {
+        let mut vault = vault.clone();
+        let vault_key = vault_address(&mut vault);
+
+        let mut accounts = [
+            TestAccount::program_owned(vault_key, &vault),
+            TestAccount::token_account(vault.source_mint, vault_key),
+            TestAccount::token_account(vault.dest_mint, vault_key),
+            TestAccount::token_account(vault.source_mint, Pubkey::new_unique()),
+            TestAccount::token_account(vault.dest_mint, Pubkey::new_unique()),
+            TestAccount::program(Pubkey::new_unique()),
+            TestAccount::mint(vault.source_mint),
+            TestAccount::mint(vault.dest_mint),
+            TestAccount::program(token::ID),
+        ];
+        accounts[1].set_token_amount(source_balance);
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+
+        let mut vault = Account::<Vault>::try_from(&infos[0]).unwrap();
+        let mut vault_token_account =
+            InterfaceAccount::<TokenAccount>::try_from(&infos[1]).unwrap();
+        let mut vault_dest_token_account =
+            InterfaceAccount::<TokenAccount>::try_from(&infos[2]).unwrap();
+        let keeper_token_account = InterfaceAccount::<TokenAccount>::try_from(&infos[3]).unwrap();
+        let treasury_token_account = InterfaceAccount::<TokenAccount>::try_from(&infos[4]).unwrap();
+        let source_mint = InterfaceAccount::<Mint>::try_from(&infos[6]).unwrap();
+        let dest_mint = InterfaceAccount::<Mint>::try_from(&infos[7]).unwrap();
+
+        let result = execute_cycle(
+            CycleAccounts {
+                vault: &mut vault,
+                vault_token_account: &mut vault_token_account,
+                vault_dest_token_account: &mut vault_dest_token_account,
+                keeper_token_account: &keeper_token_account,
+                treasury_token_account: &treasury_token_account,
+                source_mint: &source_mint,
+                dest_mint: &dest_mint,
+                compound_token_account: None,
+                referrer_token_account: None,
+                swap_program: infos[5].clone(),
+                swap_accounts: &[],
+                source_token_program: infos[8].clone(),
+                dest_token_program: infos[8].clone(),
+                config: &test_config(),
+                fee_ledger: None,
+            },
+            plan,
+            Vec::new(),
+            &Clock {
+                unix_timestamp: now,
+                ..Clock::default()
+            },
+        );
+
+        (result, (*vault).clone(), vault_token_account.amount)
+    }
+
+    #[test]
+    fn stop_loss_cancels_vault_without_buying() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+        vault.price_feed = Pubkey::new_unique();
+        vault.stop_loss_price = 1_500_000;
+
+        let mut price_feed = TestAccount::price_feed(vault.price_feed, 150_000_000, -8, 0);
+        let plan = prepare_cycle(&vault, 1_000, 0, Some(&price_feed.info()), 1, 0).unwrap();
+        assert!(plan.stop_loss_triggered);
+
+        let (result, vault, source_balance) = run_cycle(&vault, 1_000, plan, 0);
+
+        let events = result.unwrap();
+        assert!(events.executed.is_none());
+        let status_changed = events
+            .status_changed
+            .expect("stop-loss at the threshold should trip");
+        assert_eq!(status_changed.old_status, Vault::STATUS_ACTIVE);
+        assert_eq!(status_changed.new_status, Vault::STATUS_CANCELLED);
+        assert_eq!(vault.status, Vault::STATUS_CANCELLED);
+        assert!(!vault.locked);
+        assert_eq!(vault.executed_cycles, 0);
+        assert_eq!(vault.total_received, 0);
+        assert_eq!(source_balance, 1_000);
+    }
+
+    #[test]
+    fn cycle_amount_fixed_and_percent_modes() {
+        let mut vault = zeroed_vault();
+        vault.amount_per_cycle = 100;
+        assert_eq!(vault.cycle_amount(1_000_000), Ok(100));
+
+        vault.mode = Vault::MODE_PERCENT;
+        vault.percent_bps = 1_000;
+        vault.source_decimals = 6;
+        assert_eq!(vault.cycle_amount(1_000_000), Ok(100_000));
+
+        // Below the dust floor the remaining balance is swept
+        assert_eq!(vault.cycle_amount(5_000), Ok(1_000));
+        assert_eq!(vault.cycle_amount(500), Ok(500));
+    }
+
+    #[test]
+    fn protocol_fee_is_bps_of_received() {
+        let mut config = test_config();
+        config.fee_bps = 30;
+        assert_eq!(config.protocol_fee(1_000_000), Ok(3_000));
+        assert_eq!(config.protocol_fee(10), Ok(0));
+
+        config.fee_bps = 0;
+        assert_eq!(config.protocol_fee(1_000_000), Ok(0));
+    }
+
+    #[test]
+    fn execute_dca_accepts_config_pda() {
+        let mut accounts = execute_dca_accounts(config_address().0);
+        assert_eq!(validate_execute_dca(&mut accounts), Ok(()));
+    }
+
+    #[test]
+    fn execute_dca_rejects_omitted_config() {
+        let mut accounts = execute_dca_accounts(config_address().0);
+        accounts.remove(EXECUTE_DCA_CONFIG_INDEX);
+
+        assert_eq!(
+            validate_execute_dca(&mut accounts),
+            Err(AnchorErrorCode::AccountOwnedByWrongProgram.into())
+        );
+    }
+
+    #[test]
+    fn execute_dca_rejects_mismatched_config() {
+        let mut accounts = execute_dca_accounts(Pubkey::new_unique());
+
+        assert_eq!(
+            validate_execute_dca(&mut accounts),
+            Err(AnchorErrorCode::ConstraintSeeds.into())
+        );
+    }
+
+    #[test]
+    fn force_cancel_from_any_status() {
+        let key = Pubkey::new_unique();
+        for status in [
+            Vault::STATUS_ACTIVE,
+            Vault::STATUS_PAUSED,
+            Vault::STATUS_COMPLETED,
+        ] {
+            let mut vault = zeroed_vault();
+            vault.status = status;
+
+            let status_changed = vault.force_cancel(key, 7).unwrap();
+            assert_eq!(status_changed.old_status, status);
+            assert_eq!(vault.status, Vault::STATUS_CANCELLED);
+        }
+
+        let mut vault = zeroed_vault();
+        vault.status = Vault::STATUS_CANCELLED;
+        assert!(vault.force_cancel(key, 7).is_none());
+    }
+
+    fn emergency_withdraw_accounts(vault: &mut Vault) -> Vec<TestAccount> {
+        let vault_key = vault_address(vault);
+
+        vec![
+            TestAccount::program_owned(vault_key, &*vault),
+            TestAccount::new(vault.owner, system_program::ID, Vec::new()),
+            TestAccount::signer(vault.owner),
+            TestAccount::token_account(vault.source_mint, vault_key),
+            TestAccount::token_account(vault.dest_mint, vault_key),
+            TestAccount::token_account(vault.source_mint, vault.owner),
+            TestAccount::token_account(vault.dest_mint, vault.owner),
+            TestAccount::mint(vault.source_mint),
+            TestAccount::mint(vault.dest_mint),
+            TestAccount::program(token::ID),
+            TestAccount::program(token::ID),
+            TestAccount::none(),
+            TestAccount::program(system_program::ID),
+            TestAccount::new(event_authority(), system_program::ID, Vec::new()),
+            TestAccount::program(crate::ID),
+        ]
+    }
+
+    fn validate_emergency_withdraw(accounts: &mut [TestAccount]) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        validate_accounts::<EmergencyWithdraw, EmergencyWithdrawBumps>(&infos)
+    }
+
+    #[test]
+    fn emergency_withdraw_sweeps_to_owner_accounts_only() {
+        let mut vault = test_vault();
+
+        let mut accounts = emergency_withdraw_accounts(&mut vault);
+        assert_eq!(validate_emergency_withdraw(&mut accounts), Ok(()));
+
+        // Destination tokens can't be routed into the owner's source account
+        let mut accounts = emergency_withdraw_accounts(&mut vault);
+        accounts.swap(5, 6);
+        assert_eq!(
+            validate_emergency_withdraw(&mut accounts),
+            Err(ErrorCode::InvalidMint.into())
+        );
+
+        // Only the vault's own token accounts can be drained
+        let mut accounts = emergency_withdraw_accounts(&mut vault);
+        accounts[3] = TestAccount::token_account(vault.source_mint, vault.owner);
+        assert_eq!(
+            validate_emergency_withdraw(&mut accounts),
+            Err(ErrorCode::InvalidTokenAccountOwner.into())
+        );
+
+        // A recovery authority signing can't redirect funds to itself
+        let recovery = Pubkey::new_unique();
+        let mut accounts = emergency_withdraw_accounts(&mut vault);
+        accounts[2] = TestAccount::signer(recovery);
+        accounts[5] = TestAccount::token_account(vault.source_mint, recovery);
+        assert_eq!(
+            validate_emergency_withdraw(&mut accounts),
+            Err(ErrorCode::InvalidTokenAccountOwner.into())
+        );
+    }
+
+    #[test]
+    fn recovery_authority_withdraws_only_after_the_inactivity_delay() {
+        let mut vault = test_vault();
+        let recovery = Pubkey::new_unique();
+        vault.last_execution = 1_000;
+        vault.recovery_delay_seconds = 500;
+
+        // The owner is never delayed; without a recovery authority nobody else may act
+        assert_eq!(vault.check_emergency_authority(vault.owner, 1_000), Ok(()));
+        assert_eq!(
+            vault.check_emergency_authority(recovery, 2_000),
+            Err(ErrorCode::Unauthorized.into())
+        );
+
+        vault.recovery_authority = recovery;
+        assert_eq!(
+            vault.check_emergency_authority(recovery, 1_500),
+            Err(ErrorCode::RecoveryNotYetAvailable.into())
+        );
+        assert_eq!(vault.check_emergency_authority(recovery, 1_501), Ok(()));
+        assert_eq!(
+            vault.check_emergency_authority(Pubkey::new_unique(), 1_501),
+            Err(ErrorCode::Unauthorized.into())
+        );
+    }
+
+    fn catch_up_vault() -> Vault {
+        let mut vault = zeroed_vault();
+        vault.catch_up = true;
+        vault.frequency_seconds = 3_600;
+        vault.max_delay_seconds = 600;
+        vault.next_execution = 10_000;
+        vault
+    }
+
+    #[test]
+    fn next_execution_on_time_keeper() {
+        let vault = catch_up_vault();
+        assert_eq!(vault.next_execution_after(10_000), Ok(13_600));
+    }
+
+    #[test]
+    fn next_execution_slightly_late_keeper_keeps_cadence() {
+        let vault = catch_up_vault();
+        assert_eq!(vault.next_execution_after(10_600), Ok(13_600));
+    }
+
+    #[test]
+    fn next_execution_very_late_keeper_resets_schedule() {
+        let vault = catch_up_vault();
+        assert_eq!(vault.next_execution_after(10_601), Ok(14_201));
+    }
+
+    #[test]
+    fn next_execution_without_catch_up_follows_keeper() {
+        let mut vault = catch_up_vault();
+        vault.catch_up = false;
+        assert_eq!(vault.next_execution_after(10_300), Ok(13_900));
+    }
+
+    #[test]
+    fn close_requires_completed_or_cancelled() {
+        let key = Pubkey::new_unique();
+        let mut vault = zeroed_vault();
+        assert_eq!(
+            vault.ensure_closable(),
+            Err(ErrorCode::VaultStillActive.into())
+        );
+
+        vault.pause(key, 0).unwrap();
+        assert_eq!(
+            vault.ensure_closable(),
+            Err(ErrorCode::VaultStillActive.into())
+        );
+
+        vault.cancel(key, 0).unwrap();
+        assert_eq!(vault.ensure_closable(), Ok(()));
+
+        vault.status = Vault::STATUS_COMPLETED;
+        assert_eq!(vault.ensure_closable(), Ok(()));
+    }
+
+    fn close_vault_accounts(vault: &mut Vault) -> Vec<TestAccount> {
+        let vault_key = vault_address(vault);
+
+        vec![
+            TestAccount::program_owned(vault_key, &*vault),
+            TestAccount::signer(vault.owner),
+            TestAccount::token_account(vault.source_mint, vault_key),
+            TestAccount::token_account(vault.source_mint, vault.owner),
+            TestAccount::token_account(vault.dest_mint, vault_key),
+            TestAccount::token_account(vault.dest_mint, vault.owner),
+            TestAccount::mint(vault.source_mint),
+            TestAccount::mint(vault.dest_mint),
+            TestAccount::program(crate::ID),
+            TestAccount::program(crate::ID),
+            TestAccount::program(token::ID),
+            TestAccount::program(token::ID),
+        ]
+    }
+
+    fn validate_close_vault(accounts: &mut [TestAccount]) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        validate_accounts::<CloseVault, CloseVaultBumps>(&infos)
+    }
+
+    #[test]
+    fn close_vault_sweeps_only_to_owner_accounts() {
+        let mut vault = test_vault();
+
+        let mut accounts = close_vault_accounts(&mut vault);
+        assert_eq!(validate_close_vault(&mut accounts), Ok(()));
+
+        // Receiving accounts must belong to the owner
+        let mut accounts = close_vault_accounts(&mut vault);
+        accounts[3] = TestAccount::token_account(vault.source_mint, Pubkey::new_unique());
+        assert_eq!(
+            validate_close_vault(&mut accounts),
+            Err(ErrorCode::InvalidTokenAccountOwner.into())
+        );
+
+        // Receiving accounts must hold the matching mint
+        let mut accounts = close_vault_accounts(&mut vault);
+        accounts[5] = TestAccount::token_account(vault.source_mint, vault.owner);
+        assert_eq!(
+            validate_close_vault(&mut accounts),
+            Err(ErrorCode::InvalidMint.into())
+        );
+
+        // The swept accounts must be the vault's own
+        let mut accounts = close_vault_accounts(&mut vault);
+        accounts[4] = TestAccount::token_account(vault.dest_mint, vault.owner);
+        assert_eq!(
+            validate_close_vault(&mut accounts),
+            Err(ErrorCode::InvalidTokenAccountOwner.into())
+        );
+    }
+
+    #[test]
+    fn frequency_units_convert_to_seconds() {
+        assert_eq!(frequency_to_seconds(90, Vault::FREQUENCY_UNIT_SECONDS), Ok(90));
+        assert_eq!(frequency_to_seconds(15, Vault::FREQUENCY_UNIT_MINUTES), Ok(900));
+        assert_eq!(frequency_to_seconds(6, Vault::FREQUENCY_UNIT_HOURS), Ok(21_600));
+        assert_eq!(frequency_to_seconds(7, Vault::FREQUENCY_UNIT_DAYS), Ok(604_800));
+
+        // The widest value still fits once scaled to seconds
+        assert_eq!(
+            frequency_to_seconds(u32::MAX, Vault::FREQUENCY_UNIT_DAYS),
+            Ok(u32::MAX as i64 * 86_400)
+        );
+
+        assert_eq!(
+            frequency_to_seconds(1, 4),
+            Err(ErrorCode::InvalidFrequencyUnit.into())
+        );
+    }
+
+    #[test]
+    fn execution_waits_for_the_minimum_initial_deposit() {
+        let mut vault = zeroed_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 4;
+        vault.min_initial_deposit = 400;
+
+        // Enough on hand for one cycle, but the vault isn't fully funded yet
+        vault.total_deposited = 100;
+        assert_eq!(
+            check_cycle_ready(&vault, 100, 0, 0),
+            Err(ErrorCode::DepositTooSmall.into())
+        );
+        assert!(!cycle_is_due(&vault, 100, 0, 0));
+
+        vault.total_deposited = 400;
+        assert_eq!(check_cycle_ready(&vault, 400, 0, 0), Ok(()));
+
+        // Later withdrawals don't re-arm the gate; the per-cycle balance check does
+        assert_eq!(check_cycle_ready(&vault, 100, 0, 0), Ok(()));
+    }
+
+    #[test]
+    fn nested_execution_rejected_while_locked() {
+        let mut vault = zeroed_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 2;
+
+        // Outer call passes its checks and locks the vault before the swap CPI
+        assert_eq!(check_cycle_ready(&vault, 100, 0, 0), Ok(()));
+        vault.locked = true;
+
+        // A re-entrant call from inside the CPI sees the lock
+        assert_eq!(
+            check_cycle_ready(&vault, 100, 0, 0),
+            Err(ErrorCode::VaultLocked.into())
+        );
+
+        vault.locked = false;
+        assert_eq!(check_cycle_ready(&vault, 100, 0, 0), Ok(()));
+    }
+
+    /// One `execute_dca_batch` account group for a fresh, due vault
+    fn batch_group(config: &Config) -> Vec<TestAccount> {
+        let mut vault = test_vault();
+        let vault_key = vault_address(&mut vault);
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+
+        let mut group = vec![
+            TestAccount::program_owned(vault_key, &vault),
+            TestAccount::token_account(vault.source_mint, vault_key),
+            TestAccount::token_account(vault.dest_mint, vault_key),
+            TestAccount::token_account(vault.source_mint, Pubkey::new_unique()),
+            TestAccount::token_account(vault.dest_mint, config.treasury),
+            TestAccount::mint(vault.source_mint),
+            TestAccount::mint(vault.dest_mint),
+            TestAccount::none(),
+            TestAccount::none(),
+        ];
+        group[1].set_token_amount(1_000);
+        group
+    }
+
+    #[test]
+    fn batch_skips_bad_vaults_without_blocking_the_rest() {
+        let config = test_config();
+
+        let mut malformed = batch_group(&config);
+        let stray_mint = Pubkey::new_unique();
+        malformed[3] = TestAccount::token_account(stray_mint, Pubkey::new_unique());
+
+        let mut not_due = batch_group(&config);
+        let mut vault = Vault::try_deserialize(&mut &not_due[0].data[..]).unwrap();
+        vault.next_execution = 100;
+        not_due[0] = TestAccount::program_owned(not_due[0].key, &vault);
+
+        let mut groups = [
+            batch_group(&config),
+            malformed,
+            not_due,
+            batch_group(&config),
+        ];
+        let infos: Vec<Vec<AccountInfo>> = groups
+            .iter_mut()
+            .map(|group| group.iter_mut().map(TestAccount::info).collect())
+            .collect();
+
+        let results: Vec<Result<()>> = infos
+            .iter()
+            .map(|group| prepare_batch_entry(group, &config, false, 1, 0).map(|_| ()))
+            .collect();
+
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Err(ErrorCode::InvalidMint.into()),
+                Err(ErrorCode::TooEarlyToExecute.into()),
+                Ok(()),
+            ]
+        );
+    }
+
+    #[test]
+    fn execute_dca_rejects_substituted_vault_token_accounts() {
+        let config_key = config_address().0;
+
+        // Source account owned by someone other than the vault
+        let mut accounts = execute_dca_accounts(config_key);
+        let source_mint = spl_token::state::Account::unpack(&accounts[2].data).unwrap().mint;
+        accounts[2] = TestAccount::token_account(source_mint, Pubkey::new_unique());
+        assert_eq!(
+            validate_execute_dca(&mut accounts),
+            Err(ErrorCode::InvalidTokenAccountOwner.into())
+        );
+
+        // Destination account for the wrong mint
+        let mut accounts = execute_dca_accounts(config_key);
+        let vault_key = accounts[0].key;
+        accounts[3] = TestAccount::token_account(Pubkey::new_unique(), vault_key);
+        assert_eq!(
+            validate_execute_dca(&mut accounts),
+            Err(ErrorCode::InvalidMint.into())
+        );
+    }
+
+    /// Active session for `session_pubkey` owned by `owner`, allowing `program`
+    /// to be called from any of this program's instructions
+    fn test_session(owner: Pubkey, session_pubkey: Pubkey, program: Pubkey) -> SessionKey {
+        let mut session = SessionKey::deserialize(&mut &[0u8; SessionKey::LEN][..]).unwrap();
+        session.owner = owner;
+        session.session_pubkey = session_pubkey;
+        session.max_amount_per_tx = 100;
+        session.max_total_amount = 150;
+        session.expiry_timestamp = 1_000;
+        session.allowed_programs[0] = program;
+        session.allowed_programs[1] = crate::ID;
+        session.allowed_programs_count = 2;
+        session.is_active = true;
+        session
+    }
+
+    /// This program's instruction `T` as the top-level instruction of a
+    /// session charge
+    fn top_level<T: Discriminator>() -> Option<(Pubkey, [u8; 8])> {
+        let mut discriminator = [0u8; 8];
+        discriminator.copy_from_slice(T::DISCRIMINATOR);
+        Some((crate::ID, discriminator))
+    }
+
+    fn execute_dca_instruction() -> Option<(Pubkey, [u8; 8])> {
+        top_level::<crate::instruction::ExecuteDca>()
+    }
+
+    fn test_plan(swap_amount: u64) -> CyclePlan {
+        CyclePlan {
+            swap_amount,
+            min_amount_out: 1,
+            derived_min: false,
+            reverse: false,
+            pay_keeper_reward: false,
+            oracle_price: None,
+            stop_loss_triggered: false,
+            above_max_buy_price: false,
+            take_profit_triggered: false,
+            window_missed: false,
+            oracle_uncertain: false,
+            expired: false,
+            output_cap: 0,
+        }
+    }
+
+    #[test]
+    fn session_gated_execution_charges_session() {
+        let vault = test_vault();
+        let vault_key = Pubkey::new_unique();
+        let (keeper, swap_program) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut session = test_session(vault.owner, keeper, swap_program);
+        session.spending_mint = vault.source_mint;
+
+        let charged = check_session_charge(
+            &vault,
+            vault_key,
+            Some(&session),
+            keeper,
+            execute_dca_instruction(),
+            swap_program,
+            &test_plan(100),
+            1,
+            0,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!((charged.spent_amount, charged.nonce), (100, 1));
+        assert_eq!(session.spent_amount, 0);
+
+        // The second cycle would exceed the session's total budget
+        assert!(check_session_charge(
+            &vault,
+            vault_key,
+            Some(&charged),
+            keeper,
+            execute_dca_instruction(),
+            swap_program,
+            &test_plan(100),
+            2,
+            0,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn session_owned_by_someone_else_rejected() {
+        let vault = test_vault();
+        let vault_key = Pubkey::new_unique();
+        let (keeper, swap_program) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let session = test_session(Pubkey::new_unique(), keeper, swap_program);
+
+        assert_eq!(
+            check_session_charge(
+                &vault,
+                vault_key,
+                Some(&session),
+                keeper,
+                execute_dca_instruction(),
+                swap_program,
+                &test_plan(1),
+                1,
+                0,
+            )
+            .err(),
+            Some(ErrorCode::InvalidSessionKeyOwner.into())
+        );
+    }
+
+    #[test]
+    fn opted_in_vault_requires_session() {
+        let mut vault = test_vault();
+        let vault_key = Pubkey::new_unique();
+        let (keeper, swap_program) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let plan = test_plan(1);
+
+        let charged =
+            check_session_charge(&vault, vault_key, None, keeper, None, swap_program, &plan, 0, 0);
+        assert!(charged.unwrap().is_none());
+
+        vault.require_session = true;
+        assert_eq!(
+            check_session_charge(&vault, vault_key, None, keeper, None, swap_program, &plan, 0, 0)
+                .err(),
+            Some(ErrorCode::SessionRequired.into())
+        );
+    }
+
+    #[test]
+    fn stop_loss_cycle_charges_no_session() {
+        let vault = test_vault();
+        let vault_key = Pubkey::new_unique();
+        let (keeper, swap_program) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let session = test_session(vault.owner, keeper, swap_program);
+        let mut plan = test_plan(100);
+        plan.stop_loss_triggered = true;
+
+        let session = Some(&session);
+        let charged = check_session_charge(
+            &vault,
+            vault_key,
+            session,
+            keeper,
+            execute_dca_instruction(),
+            swap_program,
+            &plan,
+            1,
+            0,
+        );
+        assert!(charged.unwrap().is_none());
+    }
+
+    #[test]
+    fn slippage_failure_charges_no_session() {
+        let vault = test_vault();
+        let vault_key = Pubkey::new_unique();
+        let (keeper, swap_program) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let session = test_session(vault.owner, keeper, swap_program);
+        let mut plan = test_plan(100);
+        plan.oracle_uncertain = true;
+
+        let session = Some(&session);
+        let charged = check_session_charge(
+            &vault,
+            vault_key,
+            session,
+            keeper,
+            execute_dca_instruction(),
+            swap_program,
+            &plan,
+            1,
+            0,
+        );
+        assert!(charged.unwrap().is_none());
+    }
+
+    #[test]
+    fn execute_dca_requires_session_key_of_vault_owner() {
+        let config_key = config_address().0;
+        let mut accounts = execute_dca_accounts(config_key);
+        let vault = Vault::try_deserialize(&mut &accounts[0].data[..]).unwrap();
+        let session_key = Pubkey::new_unique();
+
+        let stranger = test_session(Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::default());
+        let mut data = Vec::new();
+        stranger.try_serialize(&mut data).unwrap();
+        accounts[EXECUTE_DCA_SESSION_KEY_INDEX] =
+            TestAccount::new(session_key, session_keys::ID, data);
+        accounts[EXECUTE_DCA_SESSION_KEY_INDEX + 1] = TestAccount::program(session_keys::ID);
+        assert_eq!(
+            validate_execute_dca(&mut accounts),
+            Err(ErrorCode::InvalidSessionKeyOwner.into())
+        );
+
+        let owned = test_session(vault.owner, Pubkey::new_unique(), Pubkey::default());
+        let mut data = Vec::new();
+        owned.try_serialize(&mut data).unwrap();
+        accounts[EXECUTE_DCA_SESSION_KEY_INDEX] =
+            TestAccount::new(session_key, session_keys::ID, data);
+        assert_eq!(validate_execute_dca(&mut accounts), Ok(()));
+
+        // The instruction the session authorizes is read from the real sysvar
+        accounts[EXECUTE_DCA_SESSION_KEY_INDEX + 2] =
+            TestAccount::new(Pubkey::new_unique(), system_program::ID, Vec::new());
+        assert_eq!(
+            validate_execute_dca(&mut accounts),
+            Err(AnchorErrorCode::ConstraintAddress.into())
+        );
+        accounts[EXECUTE_DCA_SESSION_KEY_INDEX + 2] =
+            TestAccount::new(instructions_sysvar::ID, system_program::ID, Vec::new());
+        assert_eq!(validate_execute_dca(&mut accounts), Ok(()));
+    }
+
+    #[test]
+    fn batch_skips_vault_whose_session_charge_fails() {
+        let config = test_config();
+        let (keeper, swap_program) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        // The session belongs to the owner of the first and last vaults, which
+        // both spend the same source mint
+        let mut groups = [batch_group(&config), batch_group(&config), batch_group(&config)];
+        let first = Vault::try_deserialize(&mut &groups[0][0].data[..]).unwrap();
+        let (owner, source_mint) = (first.owner, first.source_mint);
+        let mut vault = Vault::try_deserialize(&mut &groups[2][0].data[..]).unwrap();
+        vault.owner = owner;
+        vault.source_mint = source_mint;
+        let vault_key = vault_address(&mut vault);
+        groups[2][0] = TestAccount::program_owned(vault_key, &vault);
+        groups[2][1] = TestAccount::token_account(source_mint, vault_key);
+        groups[2][1].set_token_amount(1_000);
+        groups[2][2] = TestAccount::token_account(vault.dest_mint, vault_key);
+        groups[2][3] = TestAccount::token_account(source_mint, Pubkey::new_unique());
+        groups[2][5] = TestAccount::mint(source_mint);
+
+        let infos: Vec<Vec<AccountInfo>> = groups
+            .iter_mut()
+            .map(|group| group.iter_mut().map(TestAccount::info).collect())
+            .collect();
+
+        let mut session = test_session(owner, keeper, swap_program);
+        session.max_total_amount = 1_000;
+        session.spending_mint = source_mint;
+        let mut session = Some(session);
+        let mut results = Vec::new();
+        for group in &infos {
+            // The skipped vault doesn't use up a nonce, so the last vault
+            // still presents the one the session expects
+            let result =
+                prepare_batch_entry(group, &config, false, 1, 0).and_then(|(entry, plan)| {
+                    check_session_charge(
+                        &entry.vault,
+                        entry.vault.key(),
+                        session.as_ref(),
+                        keeper,
+                        execute_dca_instruction(),
+                        swap_program,
+                        &plan,
+                        next_session_nonce(session.as_ref()),
+                        0,
+                    )
+                });
+            if let Ok(Some(charged)) = &result {
+                session = Some(charged.clone());
+            }
+            results.push(result.map(|_| ()));
+        }
+
+        assert_eq!(
+            results,
+            vec![Ok(()), Err(ErrorCode::InvalidSessionKeyOwner.into()), Ok(())]
+        );
+        assert_eq!(session.unwrap().nonce, 2);
+    }
+
+    #[test]
+    fn low_balance_event_fires_only_when_crossing_threshold() {
+        let mut vault = zeroed_vault();
+        vault.low_balance_threshold = 500;
+        let key = Pubkey::new_unique();
+
+        // Still at or above the threshold
+        assert!(vault.low_balance_event(key, 700, 500, 0).is_none());
+
+        let event = vault.low_balance_event(key, 500, 499, 10).unwrap();
+        assert_eq!(
+            (event.vault, event.remaining_balance, event.timestamp),
+            (key, 499, 10)
+        );
+
+        // Already below: no repeat warning
+        assert!(vault.low_balance_event(key, 499, 300, 20).is_none());
+
+        vault.low_balance_threshold = 0;
+        assert!(vault.low_balance_event(key, 100, 0, 30).is_none());
+    }
+
+    #[test]
+    fn oracle_band_is_checked_against_the_vaults_floors() {
+        let mut vault = test_vault();
+        vault.slippage_bps = 200;
+        vault.max_price_deviation_bps = 500;
+        let price = |conf| Price {
+            price: 150_000_000,
+            conf,
+            expo: -8,
+            publish_time: 0,
+        };
+        let expected_out = cycle_expected_out(&vault, false, 1_000_000, &price(0)).unwrap();
+        let derived_min = apply_slippage(expected_out, vault.slippage_bps).unwrap();
+
+        // A 1% band stays inside the 2% slippage floor
+        for conf in [0, 1_500_000] {
+            let band = price(conf);
+            assert_eq!(
+                oracle_band_misses_floors(&vault, false, 1_000_000, derived_min, true, &band),
+                Ok(false)
+            );
+        }
+
+        // A 3% band doesn't, but a keeper-supplied minimum is left to the swap
+        let wide = price(4_500_000);
+        assert_eq!(
+            oracle_band_misses_floors(&vault, false, 1_000_000, derived_min, true, &wide),
+            Ok(true)
+        );
+        assert_eq!(
+            oracle_band_misses_floors(&vault, false, 1_000_000, derived_min, false, &wide),
+            Ok(false)
+        );
+
+        // The deviation band applies either way, and to sell legs too
+        vault.max_price_deviation_bps = 100;
+        assert_eq!(
+            oracle_band_misses_floors(&vault, true, 1_000_000, 0, false, &price(3_000_000)),
+            Ok(true)
+        );
+        assert_eq!(
+            oracle_band_misses_floors(&vault, true, 1_000_000, 0, false, &price(150_000_000)),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn three_slippage_failures_auto_pause_vault() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 1_000_000;
+        vault.total_cycles = 3;
+        vault.frequency_seconds = 30;
+        vault.price_feed = Pubkey::new_unique();
+        vault.slippage_bps = 100;
+        vault.max_price_deviation_bps = 500;
+        vault.max_failures = 3;
+
+        // The oracle is 2% unsure of the price, wider than the 1% slippage floor
+        let mut price_feed =
+            TestAccount::price_feed_with_conf(vault.price_feed, 150_000_000, 3_000_000, -8, 0);
+
+        let attempts = [
+            (0, 1, Vault::STATUS_ACTIVE),
+            (30, 2, Vault::STATUS_ACTIVE),
+            (60, 3, Vault::STATUS_PAUSED),
+        ];
+        for (now, failures, status) in attempts {
+            let plan =
+                prepare_cycle(&vault, 10_000_000, 0, Some(&price_feed.info()), 0, now).unwrap();
+            assert!(plan.oracle_uncertain);
+            let (result, after, source_balance) = run_cycle(&vault, 10_000_000, plan, now);
+            vault = after;
+
+            assert_eq!(result.map(|events| events.executed.is_some()), Ok(false));
+            assert_eq!((vault.consecutive_failures, vault.status), (failures, status));
+            assert_eq!(source_balance, 10_000_000);
+        }
+        assert_eq!(vault.executed_cycles, 0);
+        assert_eq!(vault.pause_reason, Vault::PAUSE_REASON_AUTO_SLIPPAGE);
+    }
+
+    #[test]
+    fn execute_dca_accepts_token_2022_accounts() {
+        let config_key = config_address().0;
+        let mut accounts = execute_dca_accounts(config_key);
+
+        // Vault, keeper and treasury token accounts plus both mints
+        for index in [2, 3, 5, 8] {
+            let token_account = spl_token::state::Account::unpack(&accounts[index].data).unwrap();
+            accounts[index] =
+                TestAccount::token_account_2022(token_account.mint, token_account.owner);
+        }
+        for index in [9, 10] {
+            accounts[index] = TestAccount::mint_2022(accounts[index].key, 100);
+        }
+        accounts[13] = TestAccount::program(spl_token_2022::ID);
+        accounts[14] = TestAccount::program(spl_token_2022::ID);
+
+        assert_eq!(validate_execute_dca(&mut accounts), Ok(()));
+    }
+
+    #[test]
+    fn execute_dca_takes_each_mints_own_token_program() {
+        let config_key = config_address().0;
+        let mut accounts = execute_dca_accounts(config_key);
+
+        // SPL Token source, Token-2022 destination: vault and treasury
+        // destination accounts plus the destination mint
+        for index in [3, 8] {
+            let token_account = spl_token::state::Account::unpack(&accounts[index].data).unwrap();
+            accounts[index] =
+                TestAccount::token_account_2022(token_account.mint, token_account.owner);
+        }
+        accounts[10] = TestAccount::mint_2022(accounts[10].key, 100);
+        accounts[14] = TestAccount::program(spl_token_2022::ID);
+        assert_eq!(validate_execute_dca(&mut accounts), Ok(()));
+
+        // Either program swapped for the other doesn't own its mint
+        accounts[13] = TestAccount::program(spl_token_2022::ID);
+        assert_eq!(
+            validate_execute_dca(&mut accounts),
+            Err(ErrorCode::InvalidTokenProgram.into())
+        );
+
+        accounts[13] = TestAccount::program(token::ID);
+        accounts[14] = TestAccount::program(token::ID);
+        assert_eq!(
+            validate_execute_dca(&mut accounts),
+            Err(ErrorCode::InvalidTokenProgram.into())
+        );
+    }
+
+    /// Syscall stubs serving a default `Clock`, which Token-2022 reads to
+    /// pick the current epoch's transfer fee, and the default `Rent` that
+    /// account initialization reads
+    struct SysvarStubs;
+
+    impl program_stubs::SyscallStubs for SysvarStubs {
+        fn sol_get_clock_sysvar(&self, _var_addr: *mut u8) -> u64 {
+            0
+        }
+
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Rent) = Rent::default() };
+            0
+        }
+    }
+
+    #[test]
+    fn received_amount_is_net_of_transfer_fee() {
+        program_stubs::set_syscall_stubs(Box::new(SysvarStubs));
+
+        let mint_key = Pubkey::new_unique();
+        let depositor = Pubkey::new_unique();
+        let mut mint = TestAccount::mint_2022(mint_key, 100);
+        let mut depositor_token_account = TestAccount::token_account_2022(mint_key, depositor);
+        let mut vault_token_account =
+            TestAccount::token_account_2022(mint_key, Pubkey::new_unique());
+        let mut authority = TestAccount::signer(depositor);
+        {
+            let mut state = StateWithExtensionsMut::<spl_token_2022::state::Account>::unpack(
+                &mut depositor_token_account.data,
+            )
+            .unwrap();
+            state.base.amount = 1_000;
+            state.pack_base();
+        }
+        let infos = [
+            depositor_token_account.info(),
+            mint.info(),
+            vault_token_account.info(),
+            authority.info(),
+        ];
+
+        let mut token_account = InterfaceAccount::<TokenAccount>::try_from(&infos[2]).unwrap();
+        let balance_before = token_account.amount;
+
+        // Deposit 1_000 through Token-2022 itself, which applies the mint's 1% fee
+        let transfer = spl_token_2022::instruction::transfer_checked(
+            &spl_token_2022::ID,
+            infos[0].key,
+            infos[1].key,
+            infos[2].key,
+            infos[3].key,
+            &[],
+            1_000,
+            6,
+        )
+        .unwrap();
+        spl_token_2022::processor::Processor::process(&spl_token_2022::ID, &infos, &transfer.data)
+            .unwrap();
+
+        // Only the amount net of the withheld fee is credited
+        assert_eq!(received_amount(&mut token_account, balance_before), Ok(990));
+        let data = infos[2].try_borrow_data().unwrap();
+        let state = StateWithExtensions::<spl_token_2022::state::Account>::unpack(&data).unwrap();
+        let withheld = state.get_extension::<TransferFeeAmount>().unwrap().withheld_amount;
+        assert_eq!(u64::from(withheld), 10);
+    }
+
+    /// SOL -> USDC vault funded through its WSOL account
+    fn sol_to_usdc_vault() -> Vault {
+        let mut vault = test_vault();
+        vault.source_mint = native_mint::ID;
+        vault.set_native_flags();
+        vault
+    }
+
+    fn deposit_accounts(vault: &mut Vault) -> Vec<TestAccount> {
+        let vault_key = vault_address(vault);
+        let mut vault_token_account = TestAccount::token_account(vault.source_mint, vault_key);
+        vault_token_account.key = get_associated_token_address_with_program_id(
+            &vault_key,
+            &vault.source_mint,
+            &token::ID,
+        );
+
+        vec![
+            TestAccount::program_owned(vault_key, &*vault),
+            TestAccount::signer(vault.owner),
+            TestAccount::token_account(vault.source_mint, vault.owner),
+            vault_token_account,
+            TestAccount::mint(vault.source_mint),
+            TestAccount::program(token::ID),
+            TestAccount::program(system_program::ID),
+            TestAccount::new(event_authority(), system_program::ID, Vec::new()),
+            TestAccount::program(crate::ID),
+        ]
+    }
+
+    fn validate_deposit(accounts: &mut [TestAccount]) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        validate_accounts::<DepositToVault, DepositToVaultBumps>(&infos)
+    }
+
+    #[test]
+    fn native_flags_follow_wsol_mints() {
+        let vault = sol_to_usdc_vault();
+        assert!(vault.is_native_source);
+        assert!(!vault.is_native_dest);
+
+        let mut vault = test_vault();
+        vault.dest_mint = native_mint::ID;
+        vault.set_native_flags();
+        assert!(!vault.is_native_source);
+        assert!(vault.is_native_dest);
+
+        let mut vault = test_vault();
+        vault.set_native_flags();
+        assert!(!vault.is_native_source && !vault.is_native_dest);
+    }
+
+    #[test]
+    fn deposit_into_sol_to_usdc_vault_needs_system_program() {
+        let mut vault = sol_to_usdc_vault();
+
+        let mut accounts = deposit_accounts(&mut vault);
+        assert_eq!(validate_deposit(&mut accounts), Ok(()));
+
+        // Lamports are wrapped through the system program, so it must be the real one
+        let mut accounts = deposit_accounts(&mut vault);
+        accounts[6] = TestAccount::program(Pubkey::new_unique());
+        assert_eq!(
+            validate_deposit(&mut accounts),
+            Err(AnchorErrorCode::InvalidProgramId.into())
+        );
+
+        // The WSOL mint still has to match the vault's source leg
+        let mut accounts = deposit_accounts(&mut vault);
+        accounts[4] = TestAccount::mint(Pubkey::new_unique());
+        assert_eq!(
+            validate_deposit(&mut accounts),
+            Err(ErrorCode::InvalidMint.into())
+        );
+    }
+
+    /// The vault's temporary WSOL account for unwrapping withdrawals
+    fn unwrap_account(vault_key: Pubkey) -> TestAccount {
+        let mut account = TestAccount::token_account(native_mint::ID, vault_key);
+        account.key = Pubkey::find_program_address(&[b"unwrap", vault_key.as_ref()], &crate::ID).0;
+        account
+    }
+
+    fn withdraw_source_accounts(vault: &mut Vault) -> Vec<TestAccount> {
+        let vault_key = vault_address(vault);
+
+        vec![
+            TestAccount::program_owned(vault_key, &*vault),
+            TestAccount::signer(vault.owner),
+            TestAccount::token_account(vault.source_mint, vault_key),
+            TestAccount::token_account(vault.source_mint, vault.owner),
+            TestAccount::mint(vault.source_mint),
+            TestAccount::program(token::ID),
+            TestAccount::none(),
+            TestAccount::program(system_program::ID),
+            TestAccount::new(event_authority(), system_program::ID, Vec::new()),
+            TestAccount::program(crate::ID),
+        ]
+    }
+
+    fn validate_withdraw_source(accounts: &mut [TestAccount]) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        validate_accounts::<WithdrawSource, WithdrawSourceBumps>(&infos)
+    }
+
+    #[test]
+    fn native_withdrawals_unwrap_through_the_vaults_own_account() {
+        program_stubs::set_syscall_stubs(Box::new(SysvarStubs));
+
+        // SOL comes back through the vault's unwrap account instead of the
+        // owner's WSOL account, which may hold unrelated WSOL
+        let mut vault = sol_to_usdc_vault();
+        let vault_key = vault_address(&mut vault);
+        let mut accounts = withdraw_source_accounts(&mut vault);
+        accounts[3] = TestAccount::none();
+        accounts[6] = unwrap_account(vault_key);
+        assert_eq!(validate_withdraw_source(&mut accounts), Ok(()));
+
+        let mut accounts = withdraw_source_accounts(&mut vault);
+        accounts[3] = TestAccount::none();
+        accounts[6] = TestAccount::token_account(native_mint::ID, vault_key);
+        assert_eq!(
+            validate_withdraw_source(&mut accounts),
+            Err(AnchorErrorCode::ConstraintSeeds.into())
+        );
+
+        // Emergency withdrawals unwrap the same way
+        let mut accounts = emergency_withdraw_accounts(&mut vault);
+        accounts[5] = TestAccount::none();
+        accounts[11] = unwrap_account(vault_key);
+        assert_eq!(validate_emergency_withdraw(&mut accounts), Ok(()));
+
+        // Other vaults still pay out to the owner's token account
+        let mut vault = test_vault();
+        let mut accounts = withdraw_source_accounts(&mut vault);
+        assert_eq!(validate_withdraw_source(&mut accounts), Ok(()));
+    }
+
+    #[test]
+    fn withdrawals_need_the_target_account_for_their_source() {
+        let vault = sol_to_usdc_vault();
+        assert_eq!(vault.check_withdraw_accounts(false, true), Ok(()));
+        assert_eq!(vault.check_withdraw_accounts(true, true), Ok(()));
+        assert_eq!(
+            vault.check_withdraw_accounts(true, false),
+            Err(ErrorCode::InvalidWithdrawAccounts.into())
+        );
+
+        let vault = test_vault();
+        assert_eq!(vault.check_withdraw_accounts(true, false), Ok(()));
+        assert_eq!(
+            vault.check_withdraw_accounts(false, false),
+            Err(ErrorCode::InvalidWithdrawAccounts.into())
+        );
+        assert_eq!(
+            vault.check_withdraw_accounts(true, true),
+            Err(ErrorCode::InvalidWithdrawAccounts.into())
+        );
+    }
+
+    #[test]
+    fn global_pause_halts_executions_until_lifted() {
+        let mut config = test_config();
+        assert_eq!(config.ensure_not_paused(), Ok(()));
+
+        config.paused = true;
+        assert_eq!(
+            config.ensure_not_paused(),
+            Err(ErrorCode::ProtocolPaused.into())
+        );
+
+        config.paused = false;
+        assert_eq!(config.ensure_not_paused(), Ok(()));
+    }
+
+    #[test]
+    fn paused_config_still_validates_as_execute_dca_account() {
+        // The pause is enforced by the handler, so keepers get ProtocolPaused
+        // rather than an account error while the breaker is engaged
+        let config_key = config_address().0;
+        let mut accounts = execute_dca_accounts(config_key);
+        let mut config =
+            Config::try_deserialize(&mut &accounts[EXECUTE_DCA_CONFIG_INDEX].data[..]).unwrap();
+        config.paused = true;
+        accounts[EXECUTE_DCA_CONFIG_INDEX] = TestAccount::program_owned(config_key, &config);
+        assert_eq!(validate_execute_dca(&mut accounts), Ok(()));
+    }
+
+    #[test]
+    fn mint_allowlist_gates_destination_mints() {
+        let allowed = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let mut config = test_config();
+
+        // An empty list leaves destinations unrestricted
+        assert!(config.is_mint_allowed(&other));
+
+        assert_eq!(config.add_allowed_mint(allowed), Ok(()));
+        assert!(config.is_mint_allowed(&allowed));
+        assert!(!config.is_mint_allowed(&other));
+        assert_eq!(
+            config.add_allowed_mint(allowed),
+            Err(ErrorCode::DuplicateMint.into())
+        );
+
+        assert_eq!(
+            config.remove_allowed_mint(other),
+            Err(ErrorCode::MintNotAllowed.into())
+        );
+        assert_eq!(config.remove_allowed_mint(allowed), Ok(()));
+        assert_eq!(config.allowed_mints_count, 0);
+        assert!(config.is_mint_allowed(&other));
+    }
+
+    #[test]
+    fn mint_allowlist_is_capped_and_stays_packed() {
+        let mut config = test_config();
+        let mints: Vec<Pubkey> = (0..Config::MAX_ALLOWED_MINTS)
+            .map(|_| Pubkey::new_unique())
+            .collect();
+        for mint in &mints {
+            assert_eq!(config.add_allowed_mint(*mint), Ok(()));
+        }
+        assert_eq!(
+            config.add_allowed_mint(Pubkey::new_unique()),
+            Err(ErrorCode::TooManyMints.into())
+        );
+
+        assert_eq!(config.remove_allowed_mint(mints[0]), Ok(()));
+        assert_eq!(config.allowed_mints[0], mints[Config::MAX_ALLOWED_MINTS - 1]);
+        assert_eq!(config.allowed_mints[Config::MAX_ALLOWED_MINTS - 1], Pubkey::default());
+        assert!(mints[1..].iter().all(|mint| config.is_mint_allowed(mint)));
+    }
+
+    #[test]
+    fn keeper_whitelist_gates_executions_only_when_required() {
+        let keeper = Pubkey::new_unique();
+        let stranger = Pubkey::new_unique();
+        let mut config = test_config();
+
+        // Without the flag any keeper may execute, listed or not
+        assert_eq!(config.ensure_keeper_authorized(&stranger), Ok(()));
+
+        assert_eq!(config.add_keeper(keeper), Ok(()));
+        assert_eq!(
+            config.add_keeper(keeper),
+            Err(ErrorCode::DuplicateKeeper.into())
+        );
+
+        config.require_whitelisted_keeper = true;
+        assert_eq!(config.ensure_keeper_authorized(&keeper), Ok(()));
+        assert_eq!(
+            config.ensure_keeper_authorized(&stranger),
+            Err(ErrorCode::KeeperNotAuthorized.into())
+        );
+
+        assert_eq!(
+            config.remove_keeper(stranger),
+            Err(ErrorCode::KeeperNotAuthorized.into())
+        );
+        assert_eq!(config.remove_keeper(keeper), Ok(()));
+        assert_eq!(
+            config.ensure_keeper_authorized(&keeper),
+            Err(ErrorCode::KeeperNotAuthorized.into())
+        );
+    }
+
+    #[test]
+    fn keeper_whitelist_is_capped_and_stays_packed() {
+        let mut config = test_config();
+        let keepers: Vec<Pubkey> = (0..Config::MAX_AUTHORIZED_KEEPERS)
+            .map(|_| Pubkey::new_unique())
+            .collect();
+        for keeper in &keepers {
+            assert_eq!(config.add_keeper(*keeper), Ok(()));
+        }
+        assert_eq!(
+            config.add_keeper(Pubkey::new_unique()),
+            Err(ErrorCode::TooManyKeepers.into())
+        );
+
+        assert_eq!(config.remove_keeper(keepers[0]), Ok(()));
+        assert_eq!(
+            config.authorized_keepers[0],
+            keepers[Config::MAX_AUTHORIZED_KEEPERS - 1]
+        );
+        config.require_whitelisted_keeper = true;
+        assert!(keepers[1..]
+            .iter()
+            .all(|keeper| config.ensure_keeper_authorized(keeper).is_ok()));
+    }
+
+    fn update_config_accounts(config_key: Pubkey) -> Vec<TestAccount> {
+        let config = test_config();
+
+        vec![
+            TestAccount::program_owned(config_key, &config),
+            TestAccount::signer(config.admin),
+        ]
+    }
+
+    fn validate_update_config(accounts: &mut [TestAccount]) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        validate_accounts::<UpdateConfig, UpdateConfigBumps>(&infos)
+    }
+
+    #[test]
+    fn allowlist_updates_need_the_config_pda_and_its_admin() {
+        let mut accounts = update_config_accounts(config_address().0);
+        assert_eq!(validate_update_config(&mut accounts), Ok(()));
+
+        let mut accounts = update_config_accounts(config_address().0);
+        accounts[0] = TestAccount::none();
+        assert_eq!(
+            validate_update_config(&mut accounts),
+            Err(AnchorErrorCode::AccountOwnedByWrongProgram.into())
+        );
+
+        let mut accounts = update_config_accounts(Pubkey::new_unique());
+        assert_eq!(
+            validate_update_config(&mut accounts),
+            Err(AnchorErrorCode::ConstraintSeeds.into())
+        );
+
+        let mut accounts = update_config_accounts(config_address().0);
+        accounts[1] = TestAccount::signer(Pubkey::new_unique());
+        assert_eq!(
+            validate_update_config(&mut accounts),
+            Err(AnchorErrorCode::ConstraintHasOne.into())
+        );
+    }
+
+    #[test]
+    fn config_limits_bound_new_vaults_only() {
+        let mut config = test_config();
+
+        // Unset limits leave only the hard caps
+        assert_eq!(config.check_vault_limits(1, Vault::MAX_TOTAL_CYCLES), Ok(()));
+
+        config.max_total_cycles = 100;
+        config.min_frequency_seconds = 3_600;
+        assert_eq!(config.check_vault_limits(3_600, 100), Ok(()));
+        assert_eq!(
+            config.check_vault_limits(3_600, 101),
+            Err(ErrorCode::InvalidCycleCount.into())
+        );
+        assert_eq!(
+            config.check_vault_limits(3_599, 100),
+            Err(ErrorCode::InvalidFrequency.into())
+        );
+
+        // A vault created before the limits were tightened still executes
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.frequency_seconds = 60;
+        vault.total_cycles = 1_000;
+        vault.next_execution = 10_000;
+        assert!(prepare_cycle(&vault, 1_000, 0, None, 1, 10_000).is_ok());
+    }
+
+    #[test]
+    fn schedule_changes_are_held_to_the_config_limits() {
+        let mut config = test_config();
+        config.max_total_cycles = 100;
+        config.min_frequency_seconds = 3_600;
+
+        // A vault can't drop below the minimum frequency after creation
+        assert_eq!(config.check_frequency_limit(3_600), Ok(()));
+        assert_eq!(
+            config.check_frequency_limit(1),
+            Err(ErrorCode::InvalidFrequency.into())
+        );
+
+        // Nor extend past the cycle limit
+        let mut vault = test_vault();
+        vault.total_cycles = 90;
+        vault.extend_cycles(Pubkey::new_unique(), 10, 0).unwrap();
+        assert_eq!(config.check_cycle_limit(vault.total_cycles), Ok(()));
+        vault.extend_cycles(Pubkey::new_unique(), 1, 0).unwrap();
+        assert_eq!(
+            config.check_cycle_limit(vault.total_cycles),
+            Err(ErrorCode::InvalidCycleCount.into())
+        );
+
+        // The limits come from the protocol's own config
+        let mut vault = test_vault();
+        let vault_key = vault_address(&mut vault);
+        let mut accounts = vec![
+            TestAccount::program_owned(vault_key, &vault),
+            TestAccount::signer(vault.owner),
+            TestAccount::program_owned(config_address().0, &config),
+            TestAccount::new(event_authority(), system_program::ID, Vec::new()),
+            TestAccount::program(crate::ID),
+        ];
+        assert_eq!(validate_update_schedule(&mut accounts), Ok(()));
+
+        accounts[2] = TestAccount::program_owned(Pubkey::new_unique(), &test_config());
+        assert_eq!(
+            validate_update_schedule(&mut accounts),
+            Err(AnchorErrorCode::ConstraintSeeds.into())
+        );
+    }
+
+    fn validate_update_schedule(accounts: &mut [TestAccount]) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        validate_accounts::<UpdateSchedule, UpdateScheduleBumps>(&infos)
+    }
+
+    fn treasury_address() -> Pubkey {
+        Pubkey::find_program_address(&[b"treasury"], &crate::ID).0
+    }
+
+    /// The ledger of `token_account`, stored at its PDA
+    fn fee_ledger_account(token_account: Pubkey, mint: Pubkey) -> TestAccount {
+        let (key, bump) =
+            Pubkey::find_program_address(&[b"fee_ledger", token_account.as_ref()], &crate::ID);
+        let fee_ledger = FeeLedger {
+            token_account,
+            mint,
+            total_fees_collected: 0,
+            total_fees_withdrawn: 0,
+            bump,
+        };
+        TestAccount::program_owned(key, &fee_ledger)
+    }
+
+    fn withdraw_treasury_accounts(treasury: Pubkey) -> Vec<TestAccount> {
+        let config = test_config();
+        let mint = Pubkey::new_unique();
+        let treasury_token_account = TestAccount::token_account(mint, treasury);
+        let fee_ledger = fee_ledger_account(treasury_token_account.key, mint);
+
+        vec![
+            TestAccount::program_owned(config_address().0, &config),
+            TestAccount::signer(config.admin),
+            TestAccount::new(treasury_address(), system_program::ID, Vec::new()),
+            treasury_token_account,
+            fee_ledger,
+            TestAccount::token_account(mint, Pubkey::new_unique()),
+            TestAccount::mint(mint),
+            TestAccount::program(token::ID),
+        ]
+    }
+
+    fn validate_withdraw_treasury(accounts: &mut [TestAccount]) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        validate_accounts::<WithdrawTreasury, WithdrawTreasuryBumps>(&infos)
+    }
+
+    #[test]
+    fn only_the_admin_withdraws_from_the_treasury_pda() {
+        let mut accounts = withdraw_treasury_accounts(treasury_address());
+        assert_eq!(validate_withdraw_treasury(&mut accounts), Ok(()));
+
+        let mut accounts = withdraw_treasury_accounts(treasury_address());
+        accounts[1] = TestAccount::signer(Pubkey::new_unique());
+        assert_eq!(
+            validate_withdraw_treasury(&mut accounts),
+            Err(AnchorErrorCode::ConstraintHasOne.into())
+        );
+
+        // Fees in token accounts the PDA doesn't own are out of reach
+        let mut accounts = withdraw_treasury_accounts(Pubkey::new_unique());
+        assert_eq!(
+            validate_withdraw_treasury(&mut accounts),
+            Err(ErrorCode::InvalidTokenAccountOwner.into())
+        );
+
+        // Another token account's ledger can't fund the withdrawal
+        let mut accounts = withdraw_treasury_accounts(treasury_address());
+        accounts[4] = fee_ledger_account(Pubkey::new_unique(), Pubkey::new_unique());
+        assert_eq!(
+            validate_withdraw_treasury(&mut accounts),
+            Err(AnchorErrorCode::ConstraintSeeds.into())
+        );
+    }
+
+    #[test]
+    fn treasury_withdrawals_never_exceed_collected_fees() {
+        let mut fee_ledger = FeeLedger {
+            token_account: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            total_fees_collected: 0,
+            total_fees_withdrawn: 0,
+            bump: 255,
+        };
+        assert_eq!(
+            fee_ledger.record_fees_withdrawn(1),
+            Err(ErrorCode::InsufficientTreasuryFees.into())
+        );
+
+        fee_ledger.record_fees_collected(300).unwrap();
+        fee_ledger.record_fees_collected(200).unwrap();
+        assert_eq!(fee_ledger.record_fees_withdrawn(400), Ok(()));
+        assert_eq!(
+            fee_ledger.record_fees_withdrawn(101),
+            Err(ErrorCode::InsufficientTreasuryFees.into())
+        );
+        assert_eq!(fee_ledger.record_fees_withdrawn(100), Ok(()));
+        assert_eq!(
+            (fee_ledger.total_fees_collected, fee_ledger.total_fees_withdrawn),
+            (500, 500)
+        );
+    }
+
+    #[test]
+    fn batch_needs_the_fee_ledger_of_a_treasury_pda_account() {
+        let mut config = test_config();
+        assert!(!config.treasury_is_pda());
+        config.treasury = treasury_address();
+        assert!(config.treasury_is_pda());
+
+        let mut missing = batch_group(&config);
+
+        let mut foreign = batch_group(&config);
+        foreign[8] = fee_ledger_account(Pubkey::new_unique(), foreign[6].key);
+
+        let mut tracked = batch_group(&config);
+        tracked[8] = fee_ledger_account(tracked[4].key, tracked[6].key);
+
+        let results: Vec<Result<()>> = [&mut missing, &mut foreign, &mut tracked]
+            .into_iter()
+            .map(|group| {
+                let infos: Vec<AccountInfo> = group.iter_mut().map(TestAccount::info).collect();
+                prepare_batch_entry(&infos, &config, true, 1, 0).map(|_| ())
+            })
+            .collect();
+
+        assert_eq!(
+            results,
+            vec![
+                Err(AnchorErrorCode::AccountOwnedByWrongProgram.into()),
+                Err(ErrorCode::InvalidFeeLedger.into()),
+                Ok(()),
+            ]
+        );
+    }
+
+    fn padded_label(bytes: &[u8]) -> [u8; 32] {
+        let mut label = [0u8; 32];
+        label[..bytes.len()].copy_from_slice(bytes);
+        label
+    }
+
+    #[test]
+    fn validate_label_accepts_utf8_up_to_full_width() {
+        assert!(validate_label(&padded_label(b"Weekly SOL")).is_ok());
+        assert!(validate_label(&padded_label("Épargne ☀".as_bytes())).is_ok());
+        assert!(validate_label(&[b'a'; 32]).is_ok());
+    }
+
+    #[test]
+    fn validate_label_rejects_empty() {
+        assert_eq!(validate_label(&[0u8; 32]), Err(ErrorCode::InvalidLabel.into()));
+    }
+
+    #[test]
+    fn validate_label_rejects_over_length_truncated_mid_character() {
+        // A 33-byte label cut to 32 bytes splits its final two-byte character
+        let long = format!("{}é", "a".repeat(31));
+        assert_eq!(long.len(), 33);
+
+        let label = padded_label(&long.as_bytes()[..32]);
+        assert_eq!(validate_label(&label), Err(ErrorCode::InvalidLabel.into()));
+    }
+
+    #[test]
+    fn validate_label_rejects_non_utf8() {
+        assert_eq!(
+            validate_label(&padded_label(&[0x66, 0x6f, 0xff, 0x6f])),
+            Err(ErrorCode::InvalidLabel.into())
+        );
+    }
+
+    /// Split emitted event bytes into discriminator and payload, as log
+    /// parsers do, and decode the payload as `T`
+    fn decode_event<T: anchor_lang::Event + AnchorDeserialize>(event: &T) -> T {
+        let data = event.data();
+        assert_eq!(&data[..T::DISCRIMINATOR.len()], T::DISCRIMINATOR);
+        T::try_from_slice(&data[T::DISCRIMINATOR.len()..]).unwrap()
+    }
+
+    #[test]
+    fn vault_initialized_event_decodes() {
+        let key = Pubkey::new_unique();
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 250;
+        vault.frequency_seconds = 3_600;
+        vault.total_cycles = 12;
+        vault.label = padded_label(b"Weekly SOL");
+        vault.last_execution = 1_700_000_000;
+
+        let event = decode_event(&vault.initialized_event(key));
+        assert_eq!(event.vault, key);
+        assert_eq!(event.owner, vault.owner);
+        assert_eq!(event.source_mint, vault.source_mint);
+        assert_eq!(event.dest_mint, vault.dest_mint);
+        assert_eq!(event.amount_per_cycle, 250);
+        assert_eq!(event.frequency_seconds, 3_600);
+        assert_eq!(event.total_cycles, 12);
+        assert_eq!(event.label, padded_label(b"Weekly SOL"));
+        assert_eq!(event.timestamp, 1_700_000_000);
+    }
+
+    #[test]
+    fn deposit_event_decodes() {
+        let key = Pubkey::new_unique();
+        let mut vault = test_vault();
+        vault.total_deposited = 1_500;
+
+        let depositor = Pubkey::new_unique();
+        let event = decode_event(&vault.deposit_event(key, depositor, 500, 42));
+        assert_eq!(event.vault, key);
+        assert_eq!(event.depositor, depositor);
+        assert_eq!(event.amount, 500);
+        assert_eq!(event.total_deposited, 1_500);
+        assert_eq!(event.timestamp, 42);
+    }
+
+    fn legacy_vault() -> LegacyVault {
+        LegacyVault {
+            owner: Pubkey::new_unique(),
+            source_mint: Pubkey::new_unique(),
+            dest_mint: native_mint::ID,
+            amount_per_cycle: 100,
+            frequency_seconds: 86_400,
+            total_cycles: 10,
+            executed_cycles: 3,
+            total_deposited: 1_000,
+            total_received: 42,
+            last_execution: 1_700_000_000,
+            next_execution: 1_700_086_400,
+            status: Vault::STATUS_ACTIVE,
+            bump: 254,
+        }
+    }
+
+    /// Raw data of a vault allocated with the original `space = 166`
+    fn legacy_vault_data(legacy: &LegacyVault) -> Vec<u8> {
+        let mut data = Vault::DISCRIMINATOR.to_vec();
+        legacy.serialize(&mut data).unwrap();
+        data.resize(LegacyVault::SPACE, 0);
+        data
+    }
+
+    #[test]
+    fn migrate_legacy_vault_to_current_layout() {
+        let legacy = legacy_vault();
+        let loaded = LegacyVault::load(&legacy_vault_data(&legacy)).unwrap();
+
+        // Write into the grown account and read it back as a current vault
+        let mut data = vec![0u8; 8 + Vault::LEN];
+        let mut writer: &mut [u8] = &mut data[..];
+        loaded.migrate(6, 9).try_serialize(&mut writer).unwrap();
+        let vault = Vault::try_deserialize(&mut &data[..]).unwrap();
+
+        assert_eq!(vault.owner, legacy.owner);
+        assert_eq!(vault.creator, legacy.owner);
+        assert_eq!(vault.source_mint, legacy.source_mint);
+        assert_eq!(vault.executed_cycles, 3);
+        assert_eq!(vault.total_deposited, 1_000);
+        assert_eq!(vault.total_received, 42);
+        assert_eq!(vault.next_execution, 1_700_086_400);
+        assert_eq!(vault.cumulative_amount_in, 300);
+        assert_eq!((vault.source_decimals, vault.dest_decimals), (6, 9));
+        assert_eq!(vault.slippage_bps, Vault::DEFAULT_SLIPPAGE_BPS);
+        assert_eq!(vault.mode, Vault::MODE_FIXED);
+        assert!(!vault.is_native_source && vault.is_native_dest);
+        assert_eq!(vault.status, Vault::STATUS_ACTIVE);
+        assert_eq!(vault.bump, 254);
+    }
+
+    /// Raw data of `vault` as written by the unversioned layout of size `len`
+    fn unversioned_vault_data(vault: &Vault, len: usize) -> Vec<u8> {
+        let mut current = Vec::new();
+        vault.try_serialize(&mut current).unwrap();
+        let mut data = current[..8 + len - 2].to_vec();
+        data.extend_from_slice(&[vault.status, vault.bump]);
+        data
+    }
+
+    #[test]
+    fn migrate_every_unversioned_layout() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.executed_cycles = 2;
+        vault.slippage_bps = 75;
+        vault.source_decimals = 6;
+        vault.max_amount_out = 9_000;
+        vault.rent_payer = Pubkey::new_unique();
+        vault.total_paused_seconds = 600;
+        vault.status = Vault::STATUS_PAUSED;
+        vault.bump = 253;
+
+        for len in Vault::UNVERSIONED_LENS {
+            let data = unversioned_vault_data(&vault, len);
+            let migrated = Vault::load_outdated(&data, 9, 9).unwrap();
+            assert_eq!(migrated.owner, vault.owner);
+            assert_eq!(migrated.creator, vault.creator);
+            assert_eq!((migrated.status, migrated.bump), (Vault::STATUS_PAUSED, 253));
+            assert_eq!(migrated.version, Vault::VERSION);
+        }
+
+        // A layout from before slippage and decimals were stored
+        let migrated = Vault::load_outdated(&unversioned_vault_data(&vault, 182), 9, 8).unwrap();
+        assert_eq!(migrated.slippage_bps, Vault::DEFAULT_SLIPPAGE_BPS);
+        assert_eq!((migrated.source_decimals, migrated.dest_decimals), (9, 8));
+        assert_eq!(migrated.cumulative_amount_in, 200);
+        assert_eq!(migrated.rent_payer, vault.owner);
+        assert_eq!(migrated.max_amount_out, 0);
+
+        // A later one keeps everything it had and defaults only what it lacked
+        let migrated = Vault::load_outdated(&unversioned_vault_data(&vault, 640), 9, 8).unwrap();
+        assert_eq!(migrated.slippage_bps, 75);
+        assert_eq!((migrated.source_decimals, migrated.dest_decimals), (6, 0));
+        assert_eq!(migrated.rent_payer, vault.rent_payer);
+        assert_eq!(migrated.max_amount_out, 9_000);
+        assert_eq!(migrated.total_paused_seconds, 0);
+
+        // Version 1 predates `priced_cycles`, so its price sum restarts
+        vault.cycles_executed_sum_price = 8_250_000;
+        vault.version = 1;
+        let mut data = Vec::new();
+        vault.try_serialize(&mut data).unwrap();
+        data.truncate(8 + 737);
+        let migrated = Vault::load_outdated(&data, 9, 9).unwrap();
+        assert_eq!((migrated.total_paused_seconds, migrated.bump), (600, 253));
+        assert_eq!((migrated.cycles_executed_sum_price, migrated.priced_cycles), (0, 0));
+        assert_eq!(migrated.version, Vault::VERSION);
+
+        // Sizes that never existed and the current size are refused
+        assert_eq!(
+            Vault::load_outdated(&unversioned_vault_data(&vault, 500), 9, 9).err(),
+            Some(ErrorCode::NotLegacyVault.into())
+        );
+        let mut current = Vec::new();
+        vault.try_serialize(&mut current).unwrap();
+        assert_eq!(
+            Vault::load_outdated(&current, 9, 9).err(),
+            Some(ErrorCode::VaultAlreadyMigrated.into())
+        );
+    }
+
+    /// `config` as stored by the unversioned layout of size `len`
+    fn unversioned_config_data(config: &Config, len: usize) -> Vec<u8> {
+        let mut data = Config::DISCRIMINATOR.to_vec();
+        config.admin.serialize(&mut data).unwrap();
+        config.treasury.serialize(&mut data).unwrap();
+        config.fee_bps.serialize(&mut data).unwrap();
+        if len >= 613 {
+            config.jupiter_program.serialize(&mut data).unwrap();
+        }
+        if len >= 68 {
+            config.paused.serialize(&mut data).unwrap();
+        }
+        if len >= 581 {
+            config.allowed_mints.serialize(&mut data).unwrap();
+            config.allowed_mints_count.serialize(&mut data).unwrap();
+        }
+        if len >= 615 {
+            config.referral_share_bps.serialize(&mut data).unwrap();
+        }
+        if len >= 1129 {
+            config.authorized_keepers.serialize(&mut data).unwrap();
+            config.authorized_keepers_count.serialize(&mut data).unwrap();
+            config.require_whitelisted_keeper.serialize(&mut data).unwrap();
+        }
+        if len == 1145 || len == 1155 {
+            [500u64, 200u64].serialize(&mut data).unwrap();
+        }
+        if len == 1155 || len == 1139 {
+            config.max_total_cycles.serialize(&mut data).unwrap();
+            config.min_frequency_seconds.serialize(&mut data).unwrap();
+        }
+        config.bump.serialize(&mut data).unwrap();
+        assert_eq!(data.len(), 8 + len);
+        data
+    }
+
+    #[test]
+    fn migrate_every_unversioned_config_layout() {
+        let mut config = test_config();
+        config.paused = true;
+        config.add_allowed_mint(Pubkey::new_unique()).unwrap();
+        config.referral_share_bps = 2_000;
+        config.add_keeper(Pubkey::new_unique()).unwrap();
+        config.require_whitelisted_keeper = true;
+        config.max_total_cycles = 500;
+        config.min_frequency_seconds = 3_600;
+
+        for len in Config::UNVERSIONED_LENS {
+            let migrated = Config::load_outdated(&unversioned_config_data(&config, len)).unwrap();
+            assert_eq!(
+                (migrated.admin, migrated.treasury, migrated.fee_bps),
+                (config.admin, config.treasury, config.fee_bps)
+            );
+            assert_eq!((migrated.bump, migrated.version), (config.bump, Config::VERSION));
+        }
+
+        // The original layout only held the admin, treasury and fee
+        let migrated = Config::load_outdated(&unversioned_config_data(&config, 67)).unwrap();
+        assert_eq!(migrated.jupiter_program, Pubkey::default());
+        assert!(!migrated.paused);
+        assert_eq!((migrated.allowed_mints_count, migrated.authorized_keepers_count), (0, 0));
+
+        // `jupiter_program` was inserted ahead of the fields that existed then
+        let migrated = Config::load_outdated(&unversioned_config_data(&config, 613)).unwrap();
+        assert_eq!(migrated.jupiter_program, config.jupiter_program);
+        assert!(migrated.paused);
+        assert_eq!(migrated.allowed_mints, config.allowed_mints);
+        assert_eq!(migrated.referral_share_bps, 0);
+
+        // The protocol-wide fee totals are dropped, the fields around them kept
+        let migrated = Config::load_outdated(&unversioned_config_data(&config, 1155)).unwrap();
+        assert_eq!(migrated.authorized_keepers, config.authorized_keepers);
+        assert!(migrated.require_whitelisted_keeper);
+        assert_eq!(
+            (migrated.max_total_cycles, migrated.min_frequency_seconds),
+            (500, 3_600)
+        );
+
+        // Sizes that never existed and the current size are refused
+        let data = unversioned_config_data(&config, 581);
+        assert_eq!(
+            Config::load_outdated(&data[..data.len() - 1]).err(),
+            Some(ErrorCode::NotLegacyConfig.into())
+        );
+        let mut current = Vec::new();
+        config.try_serialize(&mut current).unwrap();
+        assert_eq!(
+            Config::load_outdated(&current).err(),
+            Some(ErrorCode::ConfigAlreadyMigrated.into())
+        );
+    }
+
+    #[test]
+    fn load_legacy_vault_checks_size_and_discriminator() {
+        let data = legacy_vault_data(&legacy_vault());
+
+        // Already migrated or otherwise resized accounts are not legacy vaults
+        let mut grown = data.clone();
+        grown.push(0);
+        assert_eq!(
+            LegacyVault::load(&grown).err(),
+            Some(ErrorCode::NotLegacyVault.into())
+        );
+        assert_eq!(
+            LegacyVault::load(&data[..data.len() - 1]).err(),
+            Some(ErrorCode::NotLegacyVault.into())
+        );
+
+        // Another account type of the same size
+        let mut foreign = data.clone();
+        foreign[..8].copy_from_slice(Config::DISCRIMINATOR);
+        assert_eq!(
+            LegacyVault::load(&foreign).err(),
+            Some(ErrorCode::NotLegacyVault.into())
+        );
+    }
+
+    #[test]
+    fn inactive_vault_reports_not_active_even_when_too_early() {
+        for status in [
+            Vault::STATUS_PAUSED,
+            Vault::STATUS_COMPLETED,
+            Vault::STATUS_CANCELLED,
+        ] {
+            let mut vault = zeroed_vault();
+            vault.amount_per_cycle = 100;
+            vault.total_cycles = 3;
+            vault.next_execution = 1_000;
+            vault.status = status;
+
+            // Before, at and after the due time alike
+            for now in [0, 1_000, 5_000] {
+                assert_eq!(
+                    check_cycle_ready(&vault, 1_000, 0, now),
+                    Err(ErrorCode::VaultNotActive.into())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn active_vault_checks_cycles_before_timing() {
+        let mut vault = zeroed_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+        vault.executed_cycles = 3;
+        vault.next_execution = 1_000;
+
+        assert_eq!(
+            check_cycle_ready(&vault, 1_000, 0, 0),
+            Err(ErrorCode::AllCyclesCompleted.into())
+        );
+
+        vault.executed_cycles = 2;
+        assert_eq!(
+            check_cycle_ready(&vault, 1_000, 0, 0),
+            Err(ErrorCode::TooEarlyToExecute.into())
+        );
+        assert_eq!(check_cycle_ready(&vault, 1_000, 0, 1_000), Ok(()));
+    }
+
+    #[test]
+    fn require_min_out_rejects_zero_minimum() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+        vault.price_feed = Pubkey::new_unique();
+        vault.require_min_out = true;
+        let mut feed = TestAccount::price_feed(vault.price_feed, 150_000_000, -8, 0);
+        let feed = feed.info();
+
+        // With the policy on, a zero minimum is refused rather than derived
+        // from the oracle
+        assert_eq!(
+            prepare_cycle(&vault, 1_000, 0, Some(&feed), 0, 0).err(),
+            Some(ErrorCode::MinOutRequired.into())
+        );
+        assert!(prepare_cycle(&vault, 1_000, 0, Some(&feed), 1, 0).is_ok());
+
+        vault.require_min_out = false;
+        assert!(prepare_cycle(&vault, 1_000, 0, Some(&feed), 0, 0).is_ok());
+    }
+
+    #[test]
+    fn zero_output_swap_is_rejected() {
+        let vault = Pubkey::new_unique();
+        assert_eq!(check_swap_output(vault, 5, 5, 0), Ok(()));
+        assert_eq!(
+            check_swap_output(vault, 4, 5, 0),
+            Err(ErrorCode::SlippageExceeded.into())
+        );
+
+        // Even a keeper passing no minimum can't take a swap that returned nothing
+        assert_eq!(
+            check_swap_output(vault, 0, 0, 0),
+            Err(ErrorCode::ZeroSwapOutput.into())
+        );
+        assert_eq!(check_swap_output(vault, 1, 0, 0), Ok(()));
+    }
+
+    fn alternate_vault() -> Vault {
+        let mut vault = test_vault();
+        vault.mode = Vault::MODE_ALTERNATE;
+        vault.amount_per_cycle = 100;
+        vault.percent_bps = 5_000;
+        vault.total_cycles = 4;
+        vault.source_decimals = 6;
+        vault.dest_decimals = 6;
+        vault
+    }
+
+    #[test]
+    fn alternate_mode_flips_direction_every_cycle() {
+        let mut vault = alternate_vault();
+        vault.keeper_reward = 10;
+
+        // Buy legs spend `amount_per_cycle` of source, sell legs half the
+        // destination balance; the keeper is paid from source either way
+        let expected = [(false, 100), (true, 200_000), (false, 100), (true, 200_000)];
+        for (cycle, (reverse, swap_amount)) in expected.into_iter().enumerate() {
+            assert_eq!(vault.executed_cycles as usize, cycle);
+            assert_eq!(vault.is_reverse_cycle(), reverse);
+
+            let plan = prepare_cycle(&vault, 1_000, 400_000, None, 1, 0).unwrap();
+            assert_eq!(plan.reverse, reverse);
+            assert_eq!(plan.swap_amount, swap_amount);
+            assert!(plan.pay_keeper_reward);
+
+            vault.executed_cycles += 1;
+        }
+    }
+
+    #[test]
+    fn alternate_mode_checks_the_balance_each_leg_sells() {
+        let mut vault = alternate_vault();
+
+        // Nothing bought yet, so the first sell leg has nothing to sell
+        assert!(prepare_cycle(&vault, 1_000, 0, None, 1, 0).is_ok());
+        vault.executed_cycles = 1;
+        assert_eq!(
+            check_cycle_ready(&vault, 1_000, 0, 0),
+            Err(ErrorCode::InsufficientBalance.into())
+        );
+        assert!(check_cycle_ready(&vault, 0, 400_000, 0).is_ok());
+
+        // A sell leg spends no source, so only the reward needs covering
+        vault.keeper_reward = 10;
+        let plan = prepare_cycle(&vault, 10, 400_000, None, 1, 0).unwrap();
+        assert!(plan.pay_keeper_reward);
+        let plan = prepare_cycle(&vault, 9, 400_000, None, 1, 0).unwrap();
+        assert!(!plan.pay_keeper_reward);
+    }
+
+    #[test]
+    fn sell_leg_oracle_floor_is_in_source_tokens() {
+        // One destination token costs 1.5 source tokens
+        assert_eq!(oracle_expected_out(150, 150_000_000, -8, 6, 6), Ok(100));
+        assert_eq!(oracle_expected_source_out(100, 150_000_000, -8, 6, 6), Ok(150));
+        assert_eq!(oracle_expected_source_out(100, 150_000_000, -8, 9, 6), Ok(150_000));
+
+        let mut vault = alternate_vault();
+        vault.price_feed = Pubkey::new_unique();
+        vault.slippage_bps = 100;
+        vault.executed_cycles = 1;
+        let mut feed = TestAccount::price_feed(vault.price_feed, 150_000_000, -8, 0);
+        let feed = feed.info();
+
+        // 200_000 destination units sold should fetch 300_000 source, less 1%
+        let plan = prepare_cycle(&vault, 0, 400_000, Some(&feed), 0, 0).unwrap();
+        assert!(plan.reverse && plan.derived_min);
+        assert_eq!(plan.min_amount_out, 297_000);
+    }
+
+    #[test]
+    fn compounding_vault_forwards_every_buy_leg_to_the_next_hop() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+        let next_hop_account = Pubkey::new_unique();
+
+        assert_eq!(
+            vault.set_compound(true, Pubkey::default()),
+            Err(ErrorCode::InvalidCompoundAccount.into())
+        );
+        assert_eq!(vault.set_compound(true, next_hop_account), Ok(()));
+
+        // Each cycle's output leaves for the next hop, but this vault still
+        // records it as received and the next hop never books it as a deposit
+        for cycle in 1..=3u64 {
+            let plan = prepare_cycle(&vault, 1_000, 0, None, 1, 0).unwrap();
+            assert_eq!(
+                vault.compound_target(plan.reverse, None),
+                Err(ErrorCode::MissingCompoundAccount.into())
+            );
+            assert_eq!(
+                vault.compound_target(plan.reverse, Some(Pubkey::new_unique())),
+                Err(ErrorCode::InvalidCompoundAccount.into())
+            );
+            assert_eq!(vault.compound_target(plan.reverse, Some(next_hop_account)), Ok(true));
+
+            vault.record_buy(plan.swap_amount, 50).unwrap();
+            vault.executed_cycles += 1;
+            assert_eq!(vault.total_received, 50 * cycle);
+            assert_eq!(vault.cumulative_amount_in, 100 * cycle);
+        }
+
+        // Sell legs and non-compounding vaults keep their output
+        assert_eq!(vault.compound_target(true, None), Ok(false));
+        assert_eq!(vault.set_compound(false, Pubkey::default()), Ok(()));
+        assert_eq!(vault.compound_target(false, None), Ok(false));
+    }
+
+    #[test]
+    fn deposits_fill_the_cap_but_never_exceed_it() {
+        let mut vault = test_vault();
+        vault.max_deposit = 1_000;
+
+        assert_eq!(vault.check_deposit_cap(600), Ok(()));
+        vault.total_deposited = 600;
+        assert_eq!(vault.check_deposit_cap(400), Ok(()));
+        vault.total_deposited = 1_000;
+        assert_eq!(
+            vault.check_deposit_cap(1),
+            Err(ErrorCode::DepositCapExceeded.into())
+        );
+
+        // The owner can raise the cap but not drop it below what's deposited
+        assert_eq!(
+            vault.set_deposit_cap(999),
+            Err(ErrorCode::InvalidDepositCap.into())
+        );
+        assert_eq!(vault.set_deposit_cap(1_500), Ok(()));
+        assert_eq!(vault.check_deposit_cap(500), Ok(()));
+
+        // Zero lifts the cap entirely
+        assert_eq!(vault.set_deposit_cap(0), Ok(()));
+        assert_eq!(vault.check_deposit_cap(u64::MAX - 1_000), Ok(()));
+    }
+
+    #[test]
+    fn deposit_accounts_must_belong_to_the_owner_and_vault() {
+        let mut vault = test_vault();
+
+        let mut accounts = deposit_accounts(&mut vault);
+        assert_eq!(validate_deposit(&mut accounts), Ok(()));
+
+        let mut accounts = deposit_accounts(&mut vault);
+        accounts[2] = TestAccount::token_account(vault.source_mint, Pubkey::new_unique());
+        assert_eq!(
+            validate_deposit(&mut accounts),
+            Err(ErrorCode::InvalidTokenAccountOwner.into())
+        );
+
+        let mut accounts = deposit_accounts(&mut vault);
+        accounts[2] = TestAccount::token_account(Pubkey::new_unique(), vault.owner);
+        assert_eq!(
+            validate_deposit(&mut accounts),
+            Err(ErrorCode::InvalidMint.into())
+        );
+
+        // Only the vault's associated token account can receive deposits
+        let mut accounts = deposit_accounts(&mut vault);
+        let vault_key = accounts[0].key;
+        accounts[3] = TestAccount::token_account(vault.source_mint, vault_key);
+        assert_eq!(
+            validate_deposit(&mut accounts),
+            Err(AnchorErrorCode::ConstraintAssociated.into())
+        );
+    }
+
+    #[test]
+    fn withdrawals_wait_out_the_cooldown() {
+        let mut vault = test_vault();
+        vault.withdraw_cooldown_seconds = 60;
+
+        assert_eq!(vault.record_withdrawal(1_000), Ok(()));
+        assert_eq!(
+            vault.record_withdrawal(1_059),
+            Err(ErrorCode::WithdrawCooldown.into())
+        );
+        assert_eq!(vault.last_withdraw, 1_000);
+
+        assert_eq!(vault.record_withdrawal(1_060), Ok(()));
+        assert_eq!(vault.last_withdraw, 1_060);
+
+        // No cooldown allows back-to-back withdrawals
+        vault.withdraw_cooldown_seconds = 0;
+        assert_eq!(vault.record_withdrawal(1_060), Ok(()));
+    }
+
+    #[test]
+    fn withdraw_cooldown_can_only_be_lowered_once_it_has_elapsed() {
+        let mut vault = test_vault();
+        vault.withdraw_cooldown_seconds = 60;
+        vault.record_withdrawal(1_000).unwrap();
+
+        // Raising it applies straight away
+        assert_eq!(vault.set_withdraw_cooldown(120, 1_030), Ok(()));
+        assert_eq!(
+            vault.check_withdraw_cooldown(1_060),
+            Err(ErrorCode::WithdrawCooldown.into())
+        );
+
+        // Lowering it can't cut the pending wait short
+        assert_eq!(
+            vault.set_withdraw_cooldown(0, 1_060),
+            Err(ErrorCode::WithdrawCooldown.into())
+        );
+        assert_eq!(vault.withdraw_cooldown_seconds, 120);
+
+        assert_eq!(vault.set_withdraw_cooldown(0, 1_120), Ok(()));
+        assert_eq!(vault.check_withdraw_cooldown(1_120), Ok(()));
+    }
+
+    #[test]
+    fn oversized_withdraw_cooldowns_are_rejected() {
+        let mut vault = test_vault();
+
+        // A cooldown that long would lock the vault's funds for good
+        for cooldown in [-1, Vault::MAX_WITHDRAW_COOLDOWN_SECONDS + 1, i64::MAX] {
+            assert_eq!(
+                Vault::validate_withdraw_cooldown(cooldown),
+                Err(ErrorCode::InvalidWithdrawCooldown.into())
+            );
+            assert_eq!(
+                vault.set_withdraw_cooldown(cooldown, 1_000),
+                Err(ErrorCode::InvalidWithdrawCooldown.into())
+            );
+        }
+        assert_eq!(vault.withdraw_cooldown_seconds, 0);
+
+        assert_eq!(
+            vault.set_withdraw_cooldown(Vault::MAX_WITHDRAW_COOLDOWN_SECONDS, 1_000),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn skip_cycle_pushes_the_schedule_back_one_period() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+        vault.frequency_seconds = 3_600;
+        vault.next_execution = 10_000;
+        vault.status = Vault::STATUS_ACTIVE;
+        let vault_key = Pubkey::new_unique();
+
+        let event = vault.skip_cycle(vault_key, 9_000).unwrap();
+        assert_eq!(vault.next_execution, 13_600);
+        assert_eq!((event.skipped_cycles, event.next_execution), (1, 13_600));
+        assert_eq!(vault.executed_cycles, 0);
+
+        // The skipped slot no longer runs a swap
+        assert_eq!(
+            check_cycle_ready(&vault, 1_000, 0, 10_000),
+            Err(ErrorCode::TooEarlyToExecute.into())
+        );
+        assert!(check_cycle_ready(&vault, 1_000, 0, 13_600).is_ok());
+
+        vault.status = Vault::STATUS_PAUSED;
+        assert_eq!(
+            vault.skip_cycle(vault_key, 9_000).err(),
+            Some(ErrorCode::VaultNotActive.into())
+        );
+        assert_eq!(vault.skipped_cycles, 1);
+    }
+
+    #[test]
+    fn limit_buy_only_buys_at_or_below_max_price() {
+        let mut vault = zeroed_vault();
+        let price = oracle_price(150_000_000, -8);
+
+        // Disabled, and a limit needs a feed to check it against
+        assert_eq!(vault.above_max_buy_price(&price), Ok(false));
+        assert_eq!(
+            vault.set_max_buy_price(1_400_000),
+            Err(ErrorCode::MissingPriceFeed.into())
+        );
+
+        vault.price_feed = Pubkey::new_unique();
+        assert_eq!(vault.set_max_buy_price(1_500_000), Ok(()));
+        assert_eq!(vault.above_max_buy_price(&price), Ok(false));
+
+        assert_eq!(vault.set_max_buy_price(1_499_999), Ok(()));
+        assert_eq!(vault.above_max_buy_price(&price), Ok(true));
+    }
+
+    #[test]
+    fn price_above_limit_skips_the_cycle_without_swapping() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+        vault.frequency_seconds = 3_600;
+        vault.price_feed = Pubkey::new_unique();
+
+        let mut price_feed = TestAccount::price_feed(vault.price_feed, 150_000_000, -8, 0);
+
+        // Below the limit the cycle goes ahead as usual
+        vault.max_buy_price = 1_600_000;
+        let plan = prepare_cycle(&vault, 1_000, 0, Some(&price_feed.info()), 1, 0).unwrap();
+        assert!(!plan.above_max_buy_price);
+
+        vault.max_buy_price = 1_400_000;
+        let plan = prepare_cycle(&vault, 1_000, 0, Some(&price_feed.info()), 1, 0).unwrap();
+        assert!(plan.above_max_buy_price);
+
+        let (result, vault, source_balance) = run_cycle(&vault, 1_000, plan, 0);
+
+        // The keeper's call succeeds and the vault simply waits a period
+        let events = result.unwrap();
+        assert_eq!((events.executed.is_some(), events.skipped.is_some()), (false, true));
+        assert_eq!(vault.status, Vault::STATUS_ACTIVE);
+        assert_eq!(vault.next_execution, 3_600);
+        assert_eq!((vault.executed_cycles, vault.skipped_cycles), (0, 1));
+        assert!(!vault.locked);
+        assert_eq!(source_balance, 1_000);
+    }
+
+    /// Instruction data `emit_cpi!` passes to the self-CPI for `event`
+    fn cpi_event_data<T: anchor_lang::Event>(event: &T) -> Vec<u8> {
+        [anchor_lang::event::EVENT_IX_TAG_LE, &event.data()].concat()
+    }
+
+    /// Decode the instruction data `emit_cpi!` passes to the self-CPI
+    fn decode_cpi_event<T: anchor_lang::Event + AnchorDeserialize>(ix_data: &[u8]) -> T {
+        let tag = anchor_lang::event::EVENT_IX_TAG_LE;
+        assert_eq!(&ix_data[..tag.len()], tag);
+
+        let data = &ix_data[tag.len()..];
+        assert_eq!(&data[..T::DISCRIMINATOR.len()], T::DISCRIMINATOR);
+        T::try_from_slice(&data[T::DISCRIMINATOR.len()..]).unwrap()
+    }
+
+    #[test]
+    fn cycle_events_decode_from_cpi_instruction_data() {
+        let vault_key = Pubkey::new_unique();
+        let mut vault = test_vault();
+        let status_changed = vault.set_status(vault_key, Vault::STATUS_COMPLETED, 42);
+
+        let decoded: VaultStatusChangedEvent = decode_cpi_event(&cpi_event_data(&status_changed));
+        assert_eq!(decoded.vault, vault_key);
+        assert_eq!(
+            (decoded.old_status, decoded.new_status, decoded.timestamp),
+            (Vault::STATUS_ACTIVE, Vault::STATUS_COMPLETED, 42)
+        );
+
+        let executed = DCAExecutedEvent {
+            vault: vault_key,
+            cycle: 3,
+            amount_in: 100,
+            amount_out: 95,
+            keeper_reward: 1,
+            protocol_fee: 2,
+            average_price: 950_000,
+            side: Vault::SIDE_BUY,
+            timestamp: 42,
+        };
+        let decoded: DCAExecutedEvent = decode_cpi_event(&cpi_event_data(&executed));
+        assert_eq!(
+            (decoded.cycle, decoded.amount_in, decoded.amount_out, decoded.average_price),
+            (3, 100, 95, 950_000)
+        );
+    }
+
+    #[test]
+    fn self_cpi_events_are_accepted_only_from_the_event_authority() {
+        let mut vault = test_vault();
+        let status_changed = vault.set_status(Pubkey::new_unique(), Vault::STATUS_PAUSED, 42);
+        let ix_data = cpi_event_data(&status_changed);
+
+        let dispatch = |key: Pubkey, is_signer: bool| {
+            let mut authority = TestAccount::new(key, system_program::ID, Vec::new());
+            let mut info = authority.info();
+            info.is_signer = is_signer;
+            crate::entry(&crate::ID, &[info], &ix_data)
+        };
+        let rejected =
+            |code: AnchorErrorCode| Err(ProgramError::from(anchor_lang::error::Error::from(code)));
+
+        // The program's own entrypoint takes the data `emit_cpi!` sends once
+        // the event authority PDA has signed it, as the self-CPI does
+        assert_eq!(dispatch(event_authority(), true), Ok(()));
+
+        // Nobody else can inject events for indexers to pick up
+        assert_eq!(
+            dispatch(event_authority(), false),
+            rejected(AnchorErrorCode::ConstraintSigner)
+        );
+        assert_eq!(
+            dispatch(Pubkey::new_unique(), true),
+            rejected(AnchorErrorCode::ConstraintSeeds)
+        );
+    }
+
+    #[test]
+    fn execute_dca_needs_the_event_authority_pda() {
+        let mut accounts = execute_dca_accounts(config_address().0);
+        let authority_index = accounts.len() - 2;
+        accounts[authority_index] =
+            TestAccount::new(Pubkey::new_unique(), system_program::ID, Vec::new());
+
+        assert_eq!(
+            validate_execute_dca(&mut accounts),
+            Err(AnchorErrorCode::ConstraintSeeds.into())
+        );
+    }
+
+    #[test]
+    fn is_due_reports_whether_a_cycle_can_run() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 2;
+        vault.next_execution = 1_000;
+
+        assert!(cycle_is_due(&vault, 100, 0, 1_000));
+
+        // Too early, underfunded, paused or finished vaults are not due
+        assert!(!cycle_is_due(&vault, 100, 0, 999));
+        assert!(!cycle_is_due(&vault, 99, 0, 1_000));
+        vault.status = Vault::STATUS_PAUSED;
+        assert!(!cycle_is_due(&vault, 100, 0, 1_000));
+        vault.status = Vault::STATUS_ACTIVE;
+        vault.executed_cycles = 2;
+        assert!(!cycle_is_due(&vault, 100, 0, 1_000));
+    }
+
+    #[test]
+    fn is_due_reads_only_the_vaults_own_token_accounts() {
+        let mut vault = test_vault();
+        let vault_key = vault_address(&mut vault);
+        let check_due = |accounts: &mut [TestAccount]| {
+            let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+            validate_accounts::<CheckDue, CheckDueBumps>(&infos)
+        };
+
+        let mut accounts = [
+            TestAccount::program_owned(vault_key, &vault),
+            TestAccount::token_account(vault.source_mint, vault_key),
+            TestAccount::token_account(vault.dest_mint, vault_key),
+        ];
+        assert_eq!(check_due(&mut accounts), Ok(()));
+
+        // A fuller account owned by someone else can't make the vault look funded
+        accounts[1] = TestAccount::token_account(vault.source_mint, Pubkey::new_unique());
+        assert_eq!(
+            check_due(&mut accounts),
+            Err(ErrorCode::InvalidTokenAccountOwner.into())
+        );
+    }
+
+    fn validate_deposit_from(accounts: &mut [TestAccount]) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        validate_accounts::<DepositFrom, DepositFromBumps>(&infos)
+    }
+
+    #[test]
+    fn anyone_can_fund_a_vault_from_their_own_tokens() {
+        let mut vault = test_vault();
+        let friend = Pubkey::new_unique();
+
+        // Same layout as `deposit`, signed by a non-owner paying from their account
+        let mut accounts = deposit_accounts(&mut vault);
+        accounts[1] = TestAccount::signer(friend);
+        accounts[2] = TestAccount::token_account(vault.source_mint, friend);
+        assert_eq!(validate_deposit_from(&mut accounts), Ok(()));
+
+        // `deposit` itself stays owner-only
+        assert_eq!(
+            validate_deposit(&mut accounts),
+            Err(AnchorErrorCode::ConstraintHasOne.into())
+        );
+
+        // The depositor can only spend their own tokens of the source mint
+        let mut accounts = deposit_accounts(&mut vault);
+        accounts[1] = TestAccount::signer(friend);
+        assert_eq!(
+            validate_deposit_from(&mut accounts),
+            Err(ErrorCode::InvalidTokenAccountOwner.into())
+        );
+
+        accounts[2] = TestAccount::token_account(Pubkey::new_unique(), friend);
+        assert_eq!(
+            validate_deposit_from(&mut accounts),
+            Err(ErrorCode::InvalidMint.into())
+        );
+
+        // And only into the vault's associated token account
+        let mut accounts = deposit_accounts(&mut vault);
+        let vault_key = accounts[0].key;
+        accounts[1] = TestAccount::signer(friend);
+        accounts[2] = TestAccount::token_account(vault.source_mint, friend);
+        accounts[3] = TestAccount::token_account(vault.source_mint, vault_key);
+        assert_eq!(
+            validate_deposit_from(&mut accounts),
+            Err(AnchorErrorCode::ConstraintAssociated.into())
+        );
+    }
+
+    #[test]
+    fn execute_dca_rejects_unconfigured_swap_program() {
+        let mut accounts = execute_dca_accounts(config_address().0);
+        accounts[4] = TestAccount::program(Pubkey::new_unique());
+
+        assert_eq!(
+            validate_execute_dca(&mut accounts),
+            Err(ErrorCode::InvalidSwapProgram.into())
+        );
+    }
+
+    #[test]
+    fn pinned_executor_is_the_only_keeper_allowed() {
+        let mut vault = test_vault();
+        let (keeper, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        // Unpinned vaults accept any keeper
+        assert_eq!(vault.check_executor(other), Ok(()));
+
+        vault.executor = keeper;
+        assert_eq!(vault.check_executor(keeper), Ok(()));
+        assert_eq!(
+            vault.check_executor(other),
+            Err(ErrorCode::Unauthorized.into())
+        );
+    }
+
+    #[test]
+    fn checked_execution_rejects_a_stale_expected_cycle() {
+        let mut vault = test_vault();
+        vault.executed_cycles = 4;
+
+        assert_eq!(vault.check_expected_cycle(4), Ok(()));
+
+        // A retry of the same keeper transaction after it already landed
+        vault.executed_cycles += 1;
+        assert_eq!(
+            vault.check_expected_cycle(4),
+            Err(ErrorCode::CycleMismatch.into())
+        );
+        assert_eq!(vault.check_expected_cycle(5), Ok(()));
+    }
+
+    #[test]
+    fn summary_reports_progress_and_estimated_completion() {
+        let mut vault = test_vault();
+
+        // No cycles configured or run yet
+        let summary = vault.summary().unwrap();
+        assert_eq!((summary.percent_complete_bps, summary.remaining_cycles), (0, 0));
+        assert_eq!(summary.average_price, 0);
+
+        vault.total_cycles = 8;
+        vault.executed_cycles = 3;
+        vault.frequency_seconds = 3_600;
+        vault.next_execution = 100_000;
+        vault.cumulative_amount_in = 300;
+        vault.total_received = 150;
+
+        let summary = vault.summary().unwrap();
+        assert_eq!(summary.percent_complete_bps, 3_750);
+        assert_eq!(summary.remaining_cycles, 5);
+        assert_eq!(summary.average_price, 2_000_000);
+        assert_eq!(summary.estimated_completion_ts, 100_000 + 5 * 3_600);
+
+        vault.executed_cycles = 8;
+        let summary = vault.summary().unwrap();
+        assert_eq!((summary.percent_complete_bps, summary.remaining_cycles), (10_000, 0));
+        assert_eq!(summary.estimated_completion_ts, 100_000);
+    }
+
+    #[test]
+    fn cycle_stats_track_best_worst_and_price_sum() {
+        let mut vault = test_vault();
+        let summary = vault.summary().unwrap();
+        assert_eq!((summary.best_cycle_out, summary.worst_cycle_out), (0, 0));
+
+        for (amount_out, price) in [(500, 2_000_000), (200, 5_000_000), (800, 1_250_000)] {
+            vault.record_cycle_stats(amount_out, price).unwrap();
+        }
+
+        let summary = vault.summary().unwrap();
+        assert_eq!(summary.best_cycle_out, 800);
+        assert_eq!(summary.worst_cycle_out, 200);
+        assert_eq!(summary.cycles_executed_sum_price, 8_250_000);
+        assert_eq!((summary.priced_cycles, summary.average_cycle_price), (3, 2_750_000));
+
+        // Unpriced sell legs of an alternate vault don't dilute the average
+        vault.mode = Vault::MODE_ALTERNATE;
+        vault.executed_cycles = 6;
+        assert_eq!(vault.summary().unwrap().average_cycle_price, 2_750_000);
+
+        // A basket cycle is priced on its summed output
+        assert_eq!(cycle_price(600 + 400, 1_000), Ok(1_000_000));
+        assert_eq!(cycle_price(1, 0), Err(ErrorCode::ArithmeticOverflow.into()));
+
+        // The u128 sum keeps accumulating past u64::MAX
+        vault.record_cycle_stats(100, u64::MAX).unwrap();
+        vault.record_cycle_stats(100, u64::MAX).unwrap();
+        assert_eq!(
+            vault.cycles_executed_sum_price,
+            8_250_000 + 2 * u64::MAX as u128
+        );
+        assert_eq!(vault.worst_cycle_out, 100);
+    }
+
+    #[test]
+    fn schedule_preview_steps_by_frequency_until_the_last_cycle() {
+        let mut vault = test_vault();
+        vault.total_cycles = 10;
+        vault.executed_cycles = 7;
+        vault.frequency_seconds = 3_600;
+        vault.next_execution = 100_000;
+
+        assert_eq!(vault.schedule_preview(2).unwrap(), vec![100_000, 103_600]);
+
+        // Only three cycles remain
+        assert_eq!(
+            vault.schedule_preview(5).unwrap(),
+            vec![100_000, 103_600, 107_200]
+        );
+
+        // The preview is capped, and stops before the vault expires
+        vault.total_cycles = 1_000;
+        assert_eq!(
+            vault.schedule_preview(u8::MAX).unwrap().len(),
+            Vault::MAX_SCHEDULE_PREVIEW as usize
+        );
+        vault.expiry_timestamp = 107_200;
+        assert_eq!(vault.schedule_preview(5).unwrap(), vec![100_000, 103_600]);
+
+        vault.status = Vault::STATUS_CANCELLED;
+        assert!(vault.schedule_preview(5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bps_slippage_matches_manual_minimum() {
+        for (expected_out, slippage_bps) in [(1_000_000u64, 50u16), (123_456_789, 75), (999, 1)] {
+            let manual = expected_out * (10_000 - slippage_bps as u64) / 10_000;
+            assert_eq!(apply_slippage(expected_out, slippage_bps).unwrap(), manual);
+        }
+    }
+
+    #[test]
+    fn apply_slippage_at_zero_bps_keeps_amount() {
+        assert_eq!(apply_slippage(1_000_000, 0).unwrap(), 1_000_000);
+    }
+
+    #[test]
+    fn apply_slippage_at_max_bps_floors_to_zero() {
+        assert_eq!(apply_slippage(1_000_000, Vault::MAX_BPS).unwrap(), 0);
+    }
+
+    #[test]
+    fn apply_slippage_handles_large_amounts() {
+        assert_eq!(apply_slippage(u64::MAX, 0).unwrap(), u64::MAX);
+        assert_eq!(apply_slippage(u64::MAX, 5_000).unwrap(), u64::MAX / 2);
+        assert_eq!(
+            apply_slippage(u64::MAX, 100).unwrap(),
+            (u64::MAX as u128 * 9_900 / 10_000) as u64
+        );
+    }
+
+    #[test]
+    fn registry_tracks_vaults_from_init_to_close() {
+        let mut registry = VaultRegistry {
+            owner: Pubkey::new_unique(),
+            bump: 255,
+            vaults: Vec::new(),
+        };
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        registry.register(first);
+        registry.register(second);
+        assert_eq!(registry.vaults, vec![first, second]);
+
+        let mut data = Vec::new();
+        registry.try_serialize(&mut data).unwrap();
+        assert_eq!(data.len(), 8 + VaultRegistry::space(2));
+
+        registry.unregister(first);
+        registry.unregister(Pubkey::new_unique());
+        assert_eq!(registry.vaults, vec![second]);
+    }
+
+    #[test]
+    fn pause_all_pauses_every_registered_vault() {
+        let owner = Pubkey::new_unique();
+        let mut vaults = [test_vault(), test_vault(), test_vault()];
+        for vault in vaults.iter_mut() {
+            vault.owner = owner;
+        }
+        vaults[2].status = Vault::STATUS_CANCELLED;
+
+        let mut accounts: Vec<TestAccount> = vaults
+            .iter()
+            .map(|vault| TestAccount::program_owned(Pubkey::new_unique(), vault))
+            .collect();
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+
+        let events = pause_owned_vaults(owner, &infos, 500).unwrap();
+
+        // Both active vaults are paused; the cancelled one is left alone
+        assert_eq!(events.len(), 2);
+        for (event, info) in events.iter().zip(infos.iter()) {
+            assert_eq!(event.vault, info.key());
+            assert_eq!(
+                (event.old_status, event.new_status),
+                (Vault::STATUS_ACTIVE, Vault::STATUS_PAUSED)
+            );
+            assert_eq!(event.timestamp, 500);
+        }
+
+        let statuses: Vec<u8> = infos
+            .iter()
+            .map(|info| Account::<Vault>::try_from(info).unwrap().status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![Vault::STATUS_PAUSED, Vault::STATUS_PAUSED, Vault::STATUS_CANCELLED]
+        );
+    }
+
+    #[test]
+    fn pause_all_rejects_vaults_of_another_owner() {
+        let vault = test_vault();
+        let mut accounts = [TestAccount::program_owned(Pubkey::new_unique(), &vault)];
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+
+        assert_eq!(
+            pause_owned_vaults(Pubkey::new_unique(), &infos, 0).err(),
+            Some(ErrorCode::Unauthorized.into())
+        );
+    }
+
+    #[test]
+    fn final_cycle_swaps_dust_only_when_enabled() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 1_000;
+        vault.total_cycles = 4;
+        vault.executed_cycles = 3;
+
+        // Slightly less than a full cycle is stuck without the flag
+        assert_eq!(
+            prepare_cycle(&vault, 990, 0, None, 1, 0).err(),
+            Some(ErrorCode::InsufficientBalance.into())
+        );
+
+        vault.swap_dust = true;
+        let plan = prepare_cycle(&vault, 990, 0, None, 1, 0).unwrap();
+        assert_eq!(plan.swap_amount, 990);
+
+        // A full balance still swaps exactly one cycle
+        assert_eq!(prepare_cycle(&vault, 5_000, 0, None, 1, 0).unwrap().swap_amount, 1_000);
+
+        // Earlier cycles never shrink to the dust
+        vault.executed_cycles = 2;
+        assert_eq!(
+            prepare_cycle(&vault, 990, 0, None, 1, 0).err(),
+            Some(ErrorCode::InsufficientBalance.into())
+        );
+    }
+
+    #[test]
+    fn execution_window_bounds_when_a_cycle_may_run() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+        vault.frequency_seconds = 3_600;
+        vault.next_execution = 10_000;
+        vault.execution_window_seconds = 600;
+
+        // Before the window the cycle is simply not due yet
+        assert_eq!(
+            prepare_cycle(&vault, 1_000, 0, None, 1, 9_999).err(),
+            Some(ErrorCode::TooEarlyToExecute.into())
+        );
+
+        // Anywhere inside the window, including its last second, runs normally
+        for now in [10_000, 10_300, 10_600] {
+            assert!(!prepare_cycle(&vault, 1_000, 0, None, 1, now).unwrap().window_missed);
+        }
+        assert!(prepare_cycle(&vault, 1_000, 0, None, 1, 10_601).unwrap().window_missed);
+
+        // Without a window a late keeper still executes
+        vault.execution_window_seconds = 0;
+        assert!(!prepare_cycle(&vault, 1_000, 0, None, 1, 1_000_000).unwrap().window_missed);
+    }
+
+    #[test]
+    fn late_keeper_rolls_the_schedule_forward_without_swapping() {
+        let mut vault = test_vault();
+        let vault_key = vault_address(&mut vault);
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+        vault.frequency_seconds = 3_600;
+        vault.next_execution = 10_000;
+        vault.execution_window_seconds = 600;
+
+        let plan = prepare_cycle(&vault, 1_000, 0, None, 1, 11_000).unwrap();
+        let (result, mut vault, source_balance) =
+            run_cycle(&vault, 1_000, plan, 11_000);
+
+        // The call succeeds, nothing is swapped and the next cycle is one
+        // interval later
+        let events = result.unwrap();
+        assert_eq!((events.executed.is_some(), events.skipped.is_some()), (false, true));
+        assert_eq!(vault.next_execution, 13_600);
+        assert_eq!((vault.executed_cycles, vault.skipped_cycles), (0, 1));
+        assert_eq!(source_balance, 1_000);
+
+        let skipped = vault.skip_missed_window(vault_key, 20_000).unwrap();
+        assert_eq!(skipped.reason, Vault::SKIP_REASON_WINDOW_MISSED);
+        assert_eq!(skipped.next_execution, 17_200);
+    }
+
+    fn basket_60_40(vault_key: Pubkey) -> DcaBasket {
+        let mut legs = [BasketLeg::default(); DcaBasket::MAX_LEGS];
+        legs[0] = BasketLeg {
+            dest_mint: Pubkey::new_unique(),
+            weight_bps: 6_000,
+        };
+        legs[1] = BasketLeg {
+            dest_mint: Pubkey::new_unique(),
+            weight_bps: 4_000,
+        };
+        let (_, bump) = Pubkey::find_program_address(&[b"basket", vault_key.as_ref()], &crate::ID);
+
+        DcaBasket {
+            vault: vault_key,
+            legs,
+            leg_count: 2,
+            bump,
+            version: DcaBasket::VERSION,
+            price_feeds: [Pubkey::default(); DcaBasket::MAX_LEGS],
+            leg_balances: [0; DcaBasket::MAX_LEGS],
+        }
+    }
+
+    fn basket_address(vault_key: Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"basket", vault_key.as_ref()], &crate::ID).0
+    }
+
+    #[test]
+    fn basket_60_40_splits_each_cycle_by_weight() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 1_000;
+        vault.total_cycles = 5;
+        vault.is_basket = true;
+        let basket = basket_60_40(Pubkey::new_unique());
+        let legs = basket.active_legs();
+
+        assert_eq!(DcaBasket::validate_legs(vault.source_mint, legs), Ok(()));
+        let amounts: Vec<u64> = legs
+            .iter()
+            .map(|leg| leg.leg_amount(vault.amount_per_cycle).unwrap())
+            .collect();
+        assert_eq!(amounts, vec![600, 400]);
+
+        // Rounding never spends more than the cycle amount
+        let amounts: Vec<u64> = legs.iter().map(|leg| leg.leg_amount(999).unwrap()).collect();
+        assert_eq!(amounts, vec![599, 399]);
+
+        // One route and one non-zero minimum per leg
+        let plan = prepare_basket_cycle(&vault, &basket, 2, &[60, 4], 1_000, None, 0).unwrap();
+        assert!(!plan.expired && !plan.stop_loss_triggered);
+        assert_eq!(plan.swap_amount, 1_000);
+        assert_eq!(
+            prepare_basket_cycle(&vault, &basket, 1, &[60, 4], 1_000, None, 0).err(),
+            Some(ErrorCode::InvalidBasketAccounts.into())
+        );
+        assert_eq!(
+            prepare_basket_cycle(&vault, &basket, 2, &[60], 1_000, None, 0).err(),
+            Some(ErrorCode::InvalidBasketAccounts.into())
+        );
+        assert_eq!(
+            prepare_basket_cycle(&vault, &basket, 2, &[60, 0], 1_000, None, 0).err(),
+            Some(ErrorCode::MinOutRequired.into())
+        );
+        assert_eq!(
+            prepare_basket_cycle(&vault, &basket, 2, &[60, 4], 999, None, 0).err(),
+            Some(ErrorCode::InsufficientBalance.into())
+        );
+
+        // The single-destination path refuses basket vaults
+        assert_eq!(
+            prepare_cycle(&vault, 1_000, 0, None, 1, 0).err(),
+            Some(ErrorCode::BasketVault.into())
+        );
+    }
+
+    #[test]
+    fn basket_cycles_run_the_single_destination_checks() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 1_000;
+        vault.total_cycles = 5;
+        vault.is_basket = true;
+        let mut basket = basket_60_40(Pubkey::new_unique());
+
+        // A leg with its own feed may leave the minimum to its oracle
+        let leg_feed = Pubkey::new_unique();
+        assert_eq!(
+            basket.set_price_feeds(&[leg_feed]),
+            Err(ErrorCode::InvalidBasket.into())
+        );
+        basket.set_price_feeds(&[Pubkey::default(), leg_feed]).unwrap();
+        assert_eq!(basket.leg_price_feed(1), Some(leg_feed));
+        assert!(prepare_basket_cycle(&vault, &basket, 2, &[60, 0], 1_000, None, 0).is_ok());
+        assert_eq!(
+            prepare_basket_cycle(&vault, &basket, 2, &[0, 4], 1_000, None, 0).err(),
+            Some(ErrorCode::MinOutRequired.into())
+        );
+        vault.require_min_out = true;
+        assert_eq!(
+            prepare_basket_cycle(&vault, &basket, 2, &[60, 0], 1_000, None, 0).err(),
+            Some(ErrorCode::MinOutRequired.into())
+        );
+        vault.require_min_out = false;
+
+        // The limit-buy price and execution window come from the vault
+        vault.price_feed = Pubkey::new_unique();
+        vault.max_buy_price = 1_400_000;
+        vault.execution_window_seconds = 60;
+        let mut price_feed = TestAccount::price_feed(vault.price_feed, 150_000_000, -8, 100);
+        let feed = price_feed.info();
+        let plan = prepare_basket_cycle(&vault, &basket, 2, &[60, 4], 1_000, Some(&feed), 100);
+        let plan = plan.unwrap();
+        assert!(plan.above_max_buy_price && plan.window_missed);
+
+        // The keeper's session pays for the whole cycle, and is required when
+        // the vault says so
+        vault.max_buy_price = 0;
+        let plan = prepare_basket_cycle(&vault, &basket, 2, &[60, 4], 1_000, Some(&feed), 0);
+        let plan = plan.unwrap();
+        let (keeper, swap_program) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut session = test_session(vault.owner, keeper, swap_program);
+        session.spending_mint = vault.source_mint;
+        session.max_amount_per_tx = 1_000;
+        session.max_total_amount = 1_000;
+        let vault_key = Pubkey::new_unique();
+        let charge = |session: Option<&SessionKey>, vault: &Vault| {
+            check_session_charge(
+                vault,
+                vault_key,
+                session,
+                keeper,
+                execute_dca_instruction(),
+                swap_program,
+                &plan,
+                1,
+                0,
+            )
+        };
+        assert_eq!(charge(Some(&session), &vault).unwrap().unwrap().spent_amount, 1_000);
+        vault.require_session = true;
+        assert_eq!(charge(None, &vault).err(), Some(ErrorCode::SessionRequired.into()));
+    }
+
+    #[tokio::test]
+    async fn basket_cycle_pays_the_protocol_fee_per_leg() {
+        let (mut program_test, swap_program, pool_authority) = program_test_with_swap();
+
+        let mut vault = test_vault();
+        let vault_key = vault_address(&mut vault);
+        vault.amount_per_cycle = 1_000;
+        vault.total_cycles = 2;
+        vault.frequency_seconds = 60;
+        vault.is_basket = true;
+        let basket = basket_60_40(vault_key);
+
+        let mut config = test_config();
+        config.jupiter_program = swap_program;
+
+        let mut source = TestAccount::token_account(vault.source_mint, vault_key);
+        source.set_token_amount(vault.amount_per_cycle);
+        let source_key = source.key;
+
+        // Each leg's route pays out 1_000_000 of its mint
+        let mut leg_metas = Vec::new();
+        let mut treasury_keys = Vec::new();
+        for leg in basket.active_legs() {
+            let leg_account = TestAccount::token_account(leg.dest_mint, vault_key);
+            let treasury = TestAccount::token_account(leg.dest_mint, config.treasury);
+            let mut pool = TestAccount::token_account(leg.dest_mint, pool_authority);
+            pool.set_token_amount(1_000_000);
+
+            leg_metas.extend([
+                AccountMeta::new(leg_account.key, false),
+                AccountMeta::new_readonly(leg.dest_mint, false),
+                AccountMeta::new_readonly(leg.dest_mint, false),
+                AccountMeta::new(treasury.key, false),
+                AccountMeta::new_readonly(leg.dest_mint, false),
+                AccountMeta::new(leg_account.key, false),
+                AccountMeta::new(pool.key, false),
+                AccountMeta::new_readonly(pool_authority, false),
+                AccountMeta::new_readonly(token::ID, false),
+            ]);
+            treasury_keys.push(treasury.key);
+
+            for account in [TestAccount::mint(leg.dest_mint), leg_account, treasury, pool] {
+                add_test_account(&mut program_test, account);
+            }
+        }
+
+        for account in [
+            TestAccount::program_owned(vault_key, &vault),
+            TestAccount::program_owned(basket_address(vault_key), &basket),
+            TestAccount::program_owned(config_address().0, &config),
+            source,
+        ] {
+            add_test_account(&mut program_test, account);
+        }
+
+        let mut context = program_test.start_with_context().await;
+
+        let mut metas = crate::accounts::ExecuteBasket {
+            vault: vault_key,
+            basket: basket_address(vault_key),
+            session_authority: context.payer.pubkey(),
+            vault_token_account: source_key,
+            price_feed: None,
+            swap_program_account: swap_program,
+            config: config_address().0,
+            token_program: token::ID,
+            session_key: None,
+            session_keys_program: None,
+            instructions: None,
+            event_authority: event_authority(),
+            program: crate::ID,
+        }
+        .to_account_metas(None);
+        metas.extend(leg_metas);
+
+        let route = BasketRoute {
+            route_data: 1_000_000u64.to_le_bytes().to_vec(),
+            swap_account_count: 4,
+        };
+        let instruction = Instruction {
+            program_id: crate::ID,
+            accounts: metas,
+            data: instruction::ExecuteBasket {
+                routes: vec![route.clone(), route],
+                min_amounts_out: vec![1, 1],
+                session_nonce: 0,
+            }
+            .data(),
+        };
+        let transaction = signed_transaction(&context, instruction);
+        context.banks_client.process_transaction(transaction).await.unwrap();
+
+        // 10 bps of each leg's output, which the legs no longer hold
+        for treasury_key in treasury_keys {
+            assert_eq!(token_amount(&mut context, treasury_key).await, 1_000);
+        }
+        let basket = context
+            .banks_client
+            .get_account(basket_address(vault_key))
+            .await
+            .unwrap()
+            .unwrap();
+        let basket = DcaBasket::try_deserialize(&mut &basket.data[..]).unwrap();
+        assert_eq!(basket.leg_balances[..2], [999_000, 999_000]);
+    }
+
+    #[test]
+    fn basket_vault_closes_only_once_every_leg_is_withdrawn() {
+        let mut basket = basket_60_40(Pubkey::new_unique());
+        let leg_mint = basket.legs[1].dest_mint;
+        assert_eq!(basket.ensure_legs_empty(), Ok(()));
+
+        basket.record_leg_received(1, 400).unwrap();
+        basket.record_leg_received(1, 300).unwrap();
+        assert_eq!(
+            basket.ensure_legs_empty(),
+            Err(ErrorCode::BasketLegsNotEmpty.into())
+        );
+
+        // Withdrawals of other mints leave the leg untouched
+        basket.record_leg_withdrawn(Pubkey::new_unique(), 700);
+        basket.record_leg_withdrawn(leg_mint, 500);
+        assert_eq!(basket.leg_balances[1], 200);
+
+        // Tokens sent to the vault directly can be withdrawn too
+        basket.record_leg_withdrawn(leg_mint, 1_000);
+        assert_eq!(basket.ensure_legs_empty(), Ok(()));
+    }
+
+    #[test]
+    fn migrate_unversioned_basket() {
+        let basket = basket_60_40(Pubkey::new_unique());
+        let mut data = Vec::new();
+        basket.try_serialize(&mut data).unwrap();
+        data.truncate(8 + DcaBasket::UNVERSIONED_LEN);
+
+        let migrated = DcaBasket::load_outdated(&data).unwrap();
+        assert_eq!(migrated.vault, basket.vault);
+        assert_eq!(migrated.active_legs().len(), 2);
+        assert_eq!((migrated.bump, migrated.version), (basket.bump, DcaBasket::VERSION));
+        assert_eq!(migrated.leg_price_feed(0), None);
+        assert_eq!(migrated.ensure_legs_empty(), Ok(()));
+
+        // A current basket is refused
+        let mut current = Vec::new();
+        migrated.try_serialize(&mut current).unwrap();
+        assert_eq!(
+            DcaBasket::load_outdated(&current).err(),
+            Some(ErrorCode::NotLegacyBasket.into())
+        );
+    }
+
+    #[test]
+    fn basket_legs_must_weigh_exactly_10000_bps() {
+        let source_mint = Pubkey::new_unique();
+        let leg = |weight_bps| BasketLeg {
+            dest_mint: Pubkey::new_unique(),
+            weight_bps,
+        };
+
+        let invalid = [
+            vec![],
+            vec![leg(6_000), leg(3_000)],
+            vec![leg(6_000), leg(4_000), leg(0)],
+            vec![leg(2_000); 5],
+        ];
+        for legs in invalid.iter() {
+            assert_eq!(
+                DcaBasket::validate_legs(source_mint, legs),
+                Err(ErrorCode::InvalidBasket.into())
+            );
+        }
+
+        // Duplicate legs and the source mint itself are rejected
+        let duplicate = leg(5_000);
+        assert_eq!(
+            DcaBasket::validate_legs(source_mint, &[duplicate, duplicate]),
+            Err(ErrorCode::InvalidBasket.into())
+        );
+        let mut to_source = leg(4_000);
+        to_source.dest_mint = source_mint;
+        assert_eq!(
+            DcaBasket::validate_legs(source_mint, &[leg(6_000), to_source]),
+            Err(ErrorCode::InvalidMint.into())
+        );
+    }
+
+    fn withdraw_basket_leg_accounts(vault: &mut Vault, leg_mint: Pubkey) -> Vec<TestAccount> {
+        let vault_key = vault_address(vault);
+        let mut basket = basket_60_40(vault_key);
+        basket.legs[1].dest_mint = leg_mint;
+
+        vec![
+            TestAccount::program_owned(vault_key, &*vault),
+            TestAccount::program_owned(basket_address(vault_key), &basket),
+            TestAccount::signer(vault.owner),
+            TestAccount::token_account(leg_mint, vault_key),
+            TestAccount::token_account(leg_mint, vault.owner),
+            TestAccount::mint(leg_mint),
+            TestAccount::program(token::ID),
+            TestAccount::new(event_authority(), system_program::ID, Vec::new()),
+            TestAccount::program(crate::ID),
+        ]
+    }
+
+    fn validate_withdraw_basket_leg(accounts: &mut [TestAccount]) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        validate_accounts::<WithdrawBasketLeg, WithdrawBasketLegBumps>(&infos)
+    }
+
+    #[test]
+    fn basket_legs_are_withdrawn_only_to_the_owner() {
+        let mut vault = test_vault();
+        vault.is_basket = true;
+        let leg_mint = Pubkey::new_unique();
+
+        let mut accounts = withdraw_basket_leg_accounts(&mut vault, leg_mint);
+        assert_eq!(validate_withdraw_basket_leg(&mut accounts), Ok(()));
+
+        // Only mints bought by the basket can be withdrawn through it
+        let other_mint = Pubkey::new_unique();
+        let mut accounts = withdraw_basket_leg_accounts(&mut vault, leg_mint);
+        accounts[3] = TestAccount::token_account(other_mint, accounts[0].key);
+        accounts[4] = TestAccount::token_account(other_mint, vault.owner);
+        accounts[5] = TestAccount::mint(other_mint);
+        assert_eq!(
+            validate_withdraw_basket_leg(&mut accounts),
+            Err(ErrorCode::InvalidMint.into())
+        );
+
+        let mut accounts = withdraw_basket_leg_accounts(&mut vault, leg_mint);
+        accounts[4] = TestAccount::token_account(leg_mint, Pubkey::new_unique());
+        assert_eq!(
+            validate_withdraw_basket_leg(&mut accounts),
+            Err(ErrorCode::InvalidTokenAccountOwner.into())
+        );
+    }
+
+    #[test]
+    fn close_vault_closes_the_vaults_own_basket() {
+        let mut vault = test_vault();
+        vault.status = Vault::STATUS_COMPLETED;
+        vault.is_basket = true;
+
+        let mut accounts = close_vault_accounts(&mut vault);
+        let vault_key = accounts[0].key;
+        let basket = basket_60_40(vault_key);
+        accounts[9] = TestAccount::program_owned(basket_address(vault_key), &basket);
+        assert_eq!(validate_close_vault(&mut accounts), Ok(()));
+
+        // Another vault's basket can't be closed in its place
+        let other_vault = Pubkey::new_unique();
+        accounts[9] =
+            TestAccount::program_owned(basket_address(other_vault), &basket_60_40(other_vault));
+        assert_eq!(
+            validate_close_vault(&mut accounts),
+            Err(AnchorErrorCode::ConstraintSeeds.into())
+        );
+    }
+
+    #[test]
+    fn session_instruction_restriction_is_checked_against_the_top_level_instruction() {
+        let vault = test_vault();
+        let vault_key = Pubkey::new_unique();
+        let (keeper, swap_program) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut session = test_session(vault.owner, keeper, swap_program);
+        session.spending_mint = vault.source_mint;
+
+        let charge = |session: &SessionKey, instruction: Option<(Pubkey, [u8; 8])>| {
+            check_session_charge(
+                &vault,
+                vault_key,
+                Some(session),
+                keeper,
+                instruction,
+                swap_program,
+                &test_plan(10),
+                1,
+                0,
+            )
+            .map(|charged| charged.is_some())
+        };
+        let batch = top_level::<crate::instruction::ExecuteDcaBatch>();
+
+        // Sessions limited to execute_dca may run it, but not the other
+        // execution paths
+        let (_, execute_dca) = execute_dca_instruction().unwrap();
+        session.set_allowed_instruction(crate::ID, execute_dca).unwrap();
+        assert_eq!(charge(&session, execute_dca_instruction()), Ok(true));
+        assert_eq!(
+            charge(&session, batch),
+            Err(session_keys::ErrorCode::InstructionNotAllowed.into())
+        );
+
+        // The swap program's own restriction is not what gates execution
+        session.set_allowed_instruction(crate::ID, [0; 8]).unwrap();
+        let (_, close_only) = top_level::<crate::instruction::CloseVault>().unwrap();
+        session.set_allowed_instruction(swap_program, close_only).unwrap();
+        assert_eq!(charge(&session, batch), Ok(true));
+
+        // A program wrapping this one must be allowed itself
+        let wrapper = Some((Pubkey::new_unique(), execute_dca));
+        assert_eq!(
+            charge(&session, wrapper),
+            Err(session_keys::ErrorCode::ProgramNotAllowed.into())
+        );
+
+        // Without the instructions sysvar there is nothing to check against
+        assert_eq!(
+            charge(&session, None),
+            Err(ErrorCode::MissingSessionAccounts.into())
+        );
+    }
+
+    #[test]
+    fn session_charge_requires_cycle_input_mint() {
+        let vault = test_vault();
+        let vault_key = Pubkey::new_unique();
+        let (keeper, swap_program) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut session = test_session(vault.owner, keeper, swap_program);
+        let mismatch = Err(session_keys::ErrorCode::MintMismatch.into());
 
-// ============================================
-// Events
-// ============================================
+        let charge = |session: &SessionKey, plan: &CyclePlan| {
+            check_session_charge(
+                &vault,
+                vault_key,
+                Some(session),
+                keeper,
+                execute_dca_instruction(),
+                swap_program,
+                plan,
+                1,
+                0,
+            )
+            .map(|charged| charged.is_some())
+        };
 
-/// Event emitted when a DCA cycle is executed
-#[event]
-pub struct DCAExecutedEvent {
-    pub vault: Pubkey,
-    pub cycle: u16,
-    pub amount_in: u64,
-    pub amount_out: u64,
-    pub timestamp: i64,
-}
+        // A session denominated in another token cannot fund the cycle
+        session.spending_mint = Pubkey::new_unique();
+        assert_eq!(charge(&session, &test_plan(10)), mismatch);
 
-/// Event emitted when vault status changes
-#[event]
-pub struct VaultStatusChangedEvent {
-    pub vault: Pubkey,
-    pub old_status: u8,
-    pub new_status: u8,
-    pub timestamp: i64,
-}
+        session.spending_mint = vault.source_mint;
+        assert_eq!(charge(&session, &test_plan(10)), Ok(true));
 
-// ============================================
-// Errors
-// ============================================
+        // Reverse cycles spend the destination mint instead
+        let mut reverse = test_plan(10);
+        reverse.reverse = true;
+        assert_eq!(charge(&session, &reverse), mismatch);
 
-#[error_code]
-pub enum ErrorCode {
-    #[msg("Vault is not active")]
-    VaultNotActive,
-    
-    #[msg("Too early to execute DCA")]
-    TooEarlyToExecute,
-    
-    #[msg("All cycles have been completed")]
-    AllCyclesCompleted,
-    
-    #[msg("Insufficient balance in vault")]
-    InsufficientBalance,
-    
-    #[msg("Vault is not paused")]
-    VaultNotPaused,
+        session.spending_mint = vault.dest_mint;
+        assert_eq!(charge(&session, &reverse), Ok(true));
+    }
 
-    #[msg("Slippage exceeded - received less than minimum")]
-    SlippageExceeded,
+    #[test]
+    fn referral_fee_splits_protocol_fee() {
+        let mut config = test_config();
+        config.fee_bps = 30;
+        config.referral_share_bps = 2_500;
 
-    #[msg("Invalid token mint")]
-    InvalidMint,
+        // A quarter of the 3_000 fee goes to the referrer
+        let protocol_fee = config.protocol_fee(1_000_000).unwrap();
+        let referral_fee = config.referral_fee(protocol_fee).unwrap();
+        assert_eq!((referral_fee, protocol_fee - referral_fee), (750, 2_250));
 
-    #[msg("Unauthorized - not vault owner")]
-    Unauthorized,
-}
+        // Rounding favours the treasury
+        assert_eq!(config.referral_fee(3), Ok(0));
+        assert_eq!(config.referral_fee(5), Ok(1));
+
+        config.referral_share_bps = Vault::MAX_BPS;
+        assert_eq!(config.referral_fee(protocol_fee), Ok(protocol_fee));
+    }
+
+    #[test]
+    fn zero_referrer_disables_referral_split() {
+        let mut config = test_config();
+        config.referral_share_bps = 5_000;
+        let mut vault = test_vault();
+
+        assert!(!config.pays_referral(&vault));
+
+        vault.referrer = Pubkey::new_unique();
+        assert!(config.pays_referral(&vault));
+
+        // Nothing is split while the protocol pays no share
+        config.referral_share_bps = 0;
+        assert!(!config.pays_referral(&vault));
+    }
+
+    #[test]
+    fn batch_skips_referred_vaults() {
+        let mut config = test_config();
+        config.referral_share_bps = 5_000;
+
+        let mut group = batch_group(&config);
+        let mut vault = Vault::try_deserialize(&mut &group[0].data[..]).unwrap();
+        vault.referrer = Pubkey::new_unique();
+        group[0] = TestAccount::program_owned(group[0].key, &vault);
+
+        let infos: Vec<AccountInfo> = group.iter_mut().map(TestAccount::info).collect();
+        assert_eq!(
+            prepare_batch_entry(&infos, &config, false, 1, 0).map(|_| ()),
+            Err(ErrorCode::MissingReferrerAccount.into())
+        );
+    }
+
+    #[test]
+    fn execute_dca_checks_referrer_token_account() {
+        let mut accounts = execute_dca_accounts(config_address().0);
+        let mut vault = Vault::try_deserialize(&mut &accounts[0].data[..]).unwrap();
+        vault.referrer = Pubkey::new_unique();
+        accounts[0] = TestAccount::program_owned(accounts[0].key, &vault);
+
+        let mut with_referrer_account = |mint: Pubkey, owner: Pubkey| {
+            accounts[EXECUTE_DCA_REFERRER_INDEX] = TestAccount::token_account(mint, owner);
+            validate_execute_dca(&mut accounts)
+        };
+
+        assert_eq!(
+            with_referrer_account(vault.dest_mint, Pubkey::new_unique()),
+            Err(ErrorCode::InvalidReferrerAccount.into())
+        );
+        assert_eq!(
+            with_referrer_account(vault.source_mint, vault.referrer),
+            Err(ErrorCode::InvalidMint.into())
+        );
+        assert_eq!(with_referrer_account(vault.dest_mint, vault.referrer), Ok(()));
+    }
+
+    #[test]
+    fn resume_restarts_a_full_interval_by_default() {
+        let mut vault = test_vault();
+        vault.frequency_seconds = 3_600;
+        vault.next_execution = 1_000;
+        let vault_key = Pubkey::new_unique();
+
+        vault.pause(vault_key, 900).unwrap();
+        let resumed = vault.resume(vault_key, 950).unwrap();
+
+        assert_eq!(vault.status, Vault::STATUS_ACTIVE);
+        assert_eq!(vault.next_execution, 950 + 3_600);
+        assert_eq!(
+            (resumed.old_status, resumed.new_status),
+            (Vault::STATUS_PAUSED, Vault::STATUS_ACTIVE)
+        );
+
+        // Only paused vaults resume
+        assert_eq!(
+            vault.resume(vault_key, 960).err(),
+            Some(ErrorCode::VaultNotPaused.into())
+        );
+    }
+
+    #[test]
+    fn resume_can_preserve_the_schedule() {
+        let mut vault = test_vault();
+        vault.frequency_seconds = 3_600;
+        vault.next_execution = 1_000;
+        vault.resume_preserves_schedule = true;
+        let vault_key = Pubkey::new_unique();
+
+        // A brief pause keeps the pending slot
+        vault.pause(vault_key, 900).unwrap();
+        vault.resume(vault_key, 950).unwrap();
+        assert_eq!(vault.next_execution, 1_000);
+
+        // A slot that passed while paused runs as soon as possible
+        vault.pause(vault_key, 960).unwrap();
+        vault.resume(vault_key, 5_000).unwrap();
+        assert_eq!(vault.next_execution, 5_000);
+    }
+
+    #[test]
+    fn jitter_spreads_vaults_created_in_one_slot() {
+        // One SlotHashes entry: count, slot, hash
+        let mut data = 1u64.to_le_bytes().to_vec();
+        data.extend_from_slice(&42u64.to_le_bytes());
+        data.extend_from_slice(&[7u8; 32]);
+        let mut slot_hashes = TestAccount::new(slot_hashes::ID, system_program::ID, data);
+        let slot_hashes = slot_hashes.info();
+        let slot_hashes = Some(&slot_hashes);
+
+        let offsets: Vec<i64> = (0..8)
+            .map(|_| schedule_jitter(slot_hashes, &Pubkey::new_unique(), 3_600).unwrap())
+            .collect();
+        assert!(offsets.iter().all(|offset| (0..=3_600).contains(offset)));
+        assert!(offsets.iter().any(|offset| *offset != offsets[0]));
+
+        // The same vault in the same slot always lands on the same offset
+        let vault_key = Pubkey::new_unique();
+        assert_eq!(
+            schedule_jitter(slot_hashes, &vault_key, 3_600),
+            schedule_jitter(slot_hashes, &vault_key, 3_600)
+        );
+
+        // Without jitter every vault keeps the plain schedule, and the
+        // sysvar can be left out
+        assert_eq!(schedule_jitter(slot_hashes, &vault_key, 0), Ok(0));
+        assert_eq!(schedule_jitter(None, &vault_key, 0), Ok(0));
+        assert_eq!(
+            schedule_jitter(None, &vault_key, 3_600),
+            Err(ErrorCode::MissingSlotHashes.into())
+        );
+    }
+
+    #[test]
+    fn jitter_rejects_truncated_slot_hashes() {
+        let mut slot_hashes = TestAccount::new(slot_hashes::ID, system_program::ID, vec![0; 40]);
+        let slot_hashes = slot_hashes.info();
+
+        assert_eq!(
+            schedule_jitter(Some(&slot_hashes), &Pubkey::new_unique(), 60),
+            Err(ErrorCode::InvalidSlotHashes.into())
+        );
+        assert_eq!(schedule_jitter(Some(&slot_hashes), &Pubkey::new_unique(), 0), Ok(0));
+    }
+
+    #[test]
+    fn take_profit_completes_vault_without_swapping() {
+        let mut vault = test_vault();
+        let vault_key = vault_address(&mut vault);
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+        vault.price_feed = Pubkey::new_unique();
+        vault.set_take_profit(1_500_000, false).unwrap();
+
+        let mut price_feed = TestAccount::price_feed(vault.price_feed, 150_000_000, -8, 0);
+        let plan = prepare_cycle(&vault, 1_000, 0, Some(&price_feed.info()), 1, 0).unwrap();
+        assert!(plan.take_profit_triggered);
+        assert_eq!(plan.swap_amount, 100);
+
+        // No swap, so no session budget is spent
+        let keeper = Pubkey::new_unique();
+        let swap_program = Pubkey::new_unique();
+        let session = test_session(vault.owner, keeper, swap_program);
+        assert!(check_session_charge(
+            &vault,
+            vault_key,
+            Some(&session),
+            keeper,
+            execute_dca_instruction(),
+            swap_program,
+            &plan,
+            1,
+            0
+        )
+        .unwrap()
+        .is_none());
+
+        let (result, vault, source_balance) = run_cycle(&vault, 1_000, plan, 0);
+        let events = result.unwrap();
+
+        assert!(events.executed.is_none());
+        let status_changed = events.status_changed.unwrap();
+        assert_eq!(status_changed.new_status, Vault::STATUS_COMPLETED);
+        assert_eq!(vault.status, Vault::STATUS_COMPLETED);
+        assert!(!vault.locked);
+        assert_eq!(vault.executed_cycles, 0);
+        assert_eq!(source_balance, 1_000);
+    }
+
+    #[test]
+    fn take_profit_liquidation_swaps_all_remaining_source() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+        vault.keeper_reward = 10;
+        vault.max_buy_price = 1_400_000;
+        vault.price_feed = Pubkey::new_unique();
+        vault.set_take_profit(1_500_000, true).unwrap();
+
+        let mut at_target = TestAccount::price_feed(vault.price_feed, 150_000_000, -8, 0);
+        let mut below_target = TestAccount::price_feed(vault.price_feed, 149_999_999, -8, 0);
+
+        // Everything but the keeper reward is swapped in this final cycle, even
+        // though the price is also above the limit-buy price
+        let plan = prepare_cycle(&vault, 1_000, 0, Some(&at_target.info()), 1, 0).unwrap();
+        assert!(plan.take_profit_triggered && !plan.above_max_buy_price);
+        assert!(plan.pay_keeper_reward);
+        assert_eq!(plan.swap_amount, 990);
+
+        // An output cap set for 100-unit cycles scales to the liquidation
+        vault.max_amount_out = 200;
+        let plan = prepare_cycle(&vault, 1_000, 0, Some(&at_target.info()), 1, 0).unwrap();
+        assert_eq!(plan.output_cap, 1_980);
+
+        // The liquidation is a real swap, so the session is charged for it
+        let (keeper, swap_program) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut session = test_session(vault.owner, keeper, swap_program);
+        session.spending_mint = vault.source_mint;
+        session.max_amount_per_tx = 1_000;
+        session.max_total_amount = 1_000;
+        let charged = check_session_charge(
+            &vault,
+            Pubkey::new_unique(),
+            Some(&session),
+            keeper,
+            execute_dca_instruction(),
+            swap_program,
+            &plan,
+            1,
+            0,
+        );
+        assert_eq!(charged.unwrap().unwrap().spent_amount, 990);
+
+        // Below the target it is an ordinary cycle
+        vault.max_buy_price = 0;
+        let plan = prepare_cycle(&vault, 1_000, 0, Some(&below_target.info()), 1, 0).unwrap();
+        assert!(!plan.take_profit_triggered);
+        assert_eq!(plan.swap_amount, 100);
+
+        // Take-profit needs a price feed to check
+        vault.price_feed = Pubkey::default();
+        assert_eq!(
+            vault.set_take_profit(1_500_000, true),
+            Err(ErrorCode::MissingPriceFeed.into())
+        );
+        assert_eq!(vault.set_take_profit(0, false), Ok(()));
+    }
+
+    #[test]
+    fn health_check_combines_status_flags() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 2;
+        vault.next_execution = 1_000;
+        vault.low_balance_threshold = 150;
+
+        // Due and comfortably funded
+        assert_eq!(vault_health(&vault, 500, 0, 1_000), Ok(Vault::HEALTH_DUE));
+
+        // Not yet due, with the balance below the owner's threshold
+        assert_eq!(vault_health(&vault, 120, 0, 999), Ok(Vault::HEALTH_LOW_BALANCE));
+
+        // Too little for the next cycle
+        assert_eq!(
+            vault_health(&vault, 50, 0, 1_000),
+            Ok(Vault::HEALTH_INSUFFICIENT_BALANCE | Vault::HEALTH_LOW_BALANCE)
+        );
+
+        // Paused vaults are never due
+        vault.status = Vault::STATUS_PAUSED;
+        assert_eq!(vault_health(&vault, 500, 0, 1_000), Ok(Vault::HEALTH_PAUSED));
+
+        vault.status = Vault::STATUS_COMPLETED;
+        vault.executed_cycles = 2;
+        assert_eq!(
+            vault_health(&vault, 0, 0, 1_000),
+            Ok(Vault::HEALTH_COMPLETED
+                | Vault::HEALTH_INSUFFICIENT_BALANCE
+                | Vault::HEALTH_LOW_BALANCE)
+        );
+    }
+
+    #[test]
+    fn sell_mode_reports_average_sale_price() {
+        let mut vault = zeroed_vault();
+        vault.mode = Vault::MODE_SELL;
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 2;
+        assert_eq!(vault.average_price(), Ok(0));
+
+        // Sizing matches fixed mode: the asset is sold 100 at a time
+        assert_eq!(vault.cycle_amount(1_000), Ok(100));
+        assert_eq!(vault.cycle_side(), Vault::SIDE_SELL);
+
+        // Sold 200 of the asset for 400 proceeds: 2.0 per token
+        vault.record_buy(100, 250).unwrap();
+        vault.record_buy(100, 150).unwrap();
+        assert_eq!(vault.average_price(), Ok(2_000_000));
+
+        // The same fills in a buy vault are a 0.5 average cost
+        vault.mode = Vault::MODE_FIXED;
+        assert_eq!(vault.average_price(), Ok(500_000));
+        assert_eq!(vault.cycle_side(), Vault::SIDE_BUY);
+
+        // Alternate-mode sell legs are labelled as sells too
+        vault.mode = Vault::MODE_ALTERNATE;
+        vault.executed_cycles = 1;
+        assert_eq!(vault.cycle_side(), Vault::SIDE_SELL);
+    }
+
+    fn validate_close_vault_to_payer(accounts: &mut [TestAccount]) -> Result<()> {
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        validate_accounts::<CloseVaultToPayer, CloseVaultToPayerBumps>(&infos)
+    }
+
+    #[test]
+    fn close_vault_to_payer_refunds_rent_payer_and_sweeps_to_owner() {
+        let mut vault = test_vault();
+        vault.status = Vault::STATUS_CANCELLED;
+        vault.rent_payer = Pubkey::new_unique();
+        let (owner, rent_payer) = (vault.owner, vault.rent_payer);
+
+        let with_payer = |vault: &mut Vault, rent_payer: Pubkey| {
+            let mut accounts = close_vault_accounts(vault);
+            accounts.insert(2, TestAccount::new(rent_payer, system_program::ID, Vec::new()));
+            accounts
+        };
+
+        let mut accounts = with_payer(&mut vault, rent_payer);
+        assert_eq!(validate_close_vault_to_payer(&mut accounts), Ok(()));
+
+        // Rent only goes to the payer recorded at init, never the owner
+        let mut accounts = with_payer(&mut vault, owner);
+        assert_eq!(
+            validate_close_vault_to_payer(&mut accounts),
+            Err(AnchorErrorCode::ConstraintHasOne.into())
+        );
+
+        // Token balances still go to the owner rather than the rent payer
+        let mut accounts = with_payer(&mut vault, rent_payer);
+        accounts[4] = TestAccount::token_account(vault.source_mint, rent_payer);
+        assert_eq!(
+            validate_close_vault_to_payer(&mut accounts),
+            Err(ErrorCode::InvalidTokenAccountOwner.into())
+        );
+
+        let mut accounts = with_payer(&mut vault, rent_payer);
+        accounts[6] = TestAccount::token_account(vault.dest_mint, rent_payer);
+        assert_eq!(
+            validate_close_vault_to_payer(&mut accounts),
+            Err(ErrorCode::InvalidTokenAccountOwner.into())
+        );
+    }
+
+    /// Account key laid out as in the runtime's input buffer, where
+    /// `AccountInfo::resize` reads the original data length just before it
+    #[repr(C)]
+    struct SerializedKey {
+        original_data_len: u32,
+        key: Pubkey,
+    }
+
+    #[test]
+    fn close_vault_to_payer_moves_the_vault_rent_to_the_payer() {
+        program_stubs::set_syscall_stubs(Box::new(SysvarStubs));
+
+        let mut vault = test_vault();
+        vault.status = Vault::STATUS_CANCELLED;
+        vault.rent_payer = Pubkey::new_unique();
+        let mut accounts = close_vault_accounts(&mut vault);
+        accounts.insert(2, TestAccount::new(vault.rent_payer, system_program::ID, Vec::new()));
+
+        // Closing resizes the vault's data, which also writes the length word
+        // in the 8 bytes in front of it, and reassigns its owner, so that
+        // can't point at the `crate::ID` constant
+        let vault_key = SerializedKey {
+            original_data_len: accounts[0].data.len() as u32,
+            key: accounts[0].key,
+        };
+        let mut vault_data = [vec![0; 8], accounts[0].data.clone()].concat();
+        let (mut vault_lamports, vault_owner) = (accounts[0].lamports, crate::ID);
+        let (rent, owner_lamports, payer_lamports) =
+            (vault_lamports, accounts[1].lamports, accounts[2].lamports);
+
+        let mut infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        infos[0] = AccountInfo::new(
+            &vault_key.key,
+            false,
+            true,
+            &mut vault_lamports,
+            &mut vault_data[8..],
+            &vault_owner,
+            false,
+            0,
+        );
+
+        assert_eq!(
+            crate::entry(&crate::ID, &infos, &instruction::CloseVaultToPayer.data()),
+            Ok(())
+        );
+
+        // The vault's rent goes to the payer, not the owner who signed
+        assert_eq!(infos[0].lamports(), 0);
+        assert_eq!(infos[2].lamports(), payer_lamports + rent);
+        assert_eq!(infos[1].lamports(), owner_lamports);
+        assert!(infos[0].data_is_empty());
+        assert_eq!(*infos[0].owner, system_program::ID);
+    }
+
+    #[test]
+    fn close_reclaims_token_account_rent_only_once_swept() {
+        // Swept accounts (and WSOL ones, which the close unwraps) are closed
+        assert_eq!(ensure_token_account_closable(false, 0), Ok(()));
+        assert_eq!(ensure_token_account_closable(true, 0), Ok(()));
+        assert_eq!(ensure_token_account_closable(true, 5_000), Ok(()));
+
+        assert_eq!(
+            ensure_token_account_closable(false, 1),
+            Err(ErrorCode::TokenAccountNotEmpty.into())
+        );
+    }
+
+    #[test]
+    fn withheld_transfer_fees_are_harvested_so_the_close_refunds_rent() {
+        program_stubs::set_syscall_stubs(Box::new(SysvarStubs));
+
+        let (mint_key, vault_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut mint = TestAccount::mint_2022(mint_key, 100);
+        let mut token_account = TestAccount::token_account_2022(mint_key, vault_key);
+        let mut owner = TestAccount::new(Pubkey::new_unique(), system_program::ID, Vec::new());
+        let mut vault = TestAccount::signer(vault_key);
+        {
+            let mut state = StateWithExtensionsMut::<spl_token_2022::state::Account>::unpack(
+                &mut token_account.data,
+            )
+            .unwrap();
+            state.get_extension_mut::<TransferFeeAmount>().unwrap().withheld_amount = 10.into();
+        }
+        let (rent, owner_lamports) = (token_account.lamports, owner.lamports);
+        let infos = [mint.info(), token_account.info(), owner.info(), vault.info()];
+
+        // SPL Token accounts never hold withheld fees
+        let mut spl_account = TestAccount::token_account(mint_key, vault_key);
+        assert_eq!(withheld_transfer_fees(&spl_account.info()), Ok(0));
+        assert_eq!(withheld_transfer_fees(&infos[1]), Ok(10));
+
+        let close = spl_token_2022::instruction::close_account(
+            &spl_token_2022::ID,
+            infos[1].key,
+            infos[2].key,
+            infos[3].key,
+            &[],
+        )
+        .unwrap();
+        let close_infos = [infos[1].clone(), infos[2].clone(), infos[3].clone()];
+        let process = |accounts: &[AccountInfo], data: &[u8]| {
+            spl_token_2022::processor::Processor::process(&spl_token_2022::ID, accounts, data)
+        };
+
+        // Token-2022 refuses to close the account while it holds the fees
+        assert!(process(&close_infos, &close.data).is_err());
+
+        let harvest =
+            spl_token_2022::extension::transfer_fee::instruction::harvest_withheld_tokens_to_mint(
+                &spl_token_2022::ID,
+                infos[0].key,
+                &[infos[1].key],
+            )
+            .unwrap();
+        process(&infos[..2], &harvest.data).unwrap();
+        assert_eq!(withheld_transfer_fees(&infos[1]), Ok(0));
+
+        process(&close_infos, &close.data).unwrap();
+        assert_eq!(infos[1].lamports(), 0);
+        assert_eq!(infos[2].lamports(), owner_lamports + rent);
+    }
+
+    #[test]
+    fn expired_vault_is_cancelled_instead_of_executing() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+        vault.frequency_seconds = 3_600;
+        vault.next_execution = 10_000;
+        vault.expiry_timestamp = 11_000;
+
+        // Before the deadline cycles run as usual
+        assert!(!prepare_cycle(&vault, 1_000, 0, None, 1, 10_999).unwrap().expired);
+
+        // The keeper's minimum is irrelevant once the vault has expired
+        vault.require_min_out = true;
+        let plan = prepare_cycle(&vault, 1_000, 0, None, 0, 11_000).unwrap();
+        assert!(plan.expired);
+
+        let (result, vault, source_balance) = run_cycle(&vault, 1_000, plan, 11_000);
+        let events = result.unwrap();
+
+        assert!(events.executed.is_none());
+        let status_changed = events.status_changed.unwrap();
+        assert_eq!(
+            (status_changed.old_status, status_changed.new_status),
+            (Vault::STATUS_ACTIVE, Vault::STATUS_CANCELLED)
+        );
+        assert_eq!(vault.executed_cycles, 0);
+        assert_eq!(source_balance, 1_000);
+
+        // Later keepers find the vault finished
+        assert_eq!(
+            prepare_cycle(&vault, 1_000, 0, None, 1, 20_000).err(),
+            Some(ErrorCode::VaultNotActive.into())
+        );
+    }
+
+    #[test]
+    fn expiry_is_checked_before_cycle_readiness() {
+        let mut vault = test_vault();
+        vault.amount_per_cycle = 100;
+        vault.total_cycles = 3;
+        vault.next_execution = 20_000;
+        vault.expiry_timestamp = 11_000;
+        vault.total_deposited = 50;
+        vault.min_initial_deposit = 100;
+
+        // Early, underfunded and below the minimum deposit: not ready to run
+        assert_eq!(
+            check_cycle_ready(&vault, 50, 0, 11_000),
+            Err(ErrorCode::DepositTooSmall.into())
+        );
+
+        // Yet it has expired, so it is still due and gets cancelled
+        assert!(cycle_is_due(&vault, 50, 0, 11_000));
+        assert!(prepare_cycle(&vault, 50, 0, None, 0, 11_000).unwrap().expired);
+
+        // Only the status checks still apply
+        vault.locked = true;
+        assert!(!cycle_is_due(&vault, 50, 0, 11_000));
+        assert_eq!(
+            prepare_cycle(&vault, 50, 0, None, 0, 11_000).err(),
+            Some(ErrorCode::VaultLocked.into())
+        );
+        vault.locked = false;
+        vault.status = Vault::STATUS_PAUSED;
+        assert_eq!(
+            prepare_cycle(&vault, 50, 0, None, 0, 11_000).err(),
+            Some(ErrorCode::VaultNotActive.into())
+        );
+
+        // The basket path cancels expired vaults the same way, whatever the
+        // keeper's minimums
+        vault.status = Vault::STATUS_ACTIVE;
+        vault.is_basket = true;
+        let basket = basket_60_40(Pubkey::new_unique());
+        let plan = prepare_basket_cycle(&vault, &basket, 2, &[0, 0], 50, None, 11_000).unwrap();
+        assert!(plan.expired);
+        assert_eq!(
+            prepare_basket_cycle(&vault, &basket, 2, &[0, 0], 50, None, 10_999).err(),
+            Some(ErrorCode::MinOutRequired.into())
+        );
+    }
+
+    #[test]
+    fn vault_expiry_must_be_unset_or_in_the_future() {
+        assert_eq!(Vault::validate_expiry(0, 1_000), Ok(()));
+        assert_eq!(Vault::validate_expiry(1_001, 1_000), Ok(()));
+        assert_eq!(
+            Vault::validate_expiry(1_000, 1_000),
+            Err(ErrorCode::InvalidVaultExpiry.into())
+        );
+
+        // Health flags warn once expiry is within one interval
+        let mut vault = test_vault();
+        vault.frequency_seconds = 3_600;
+        vault.expiry_timestamp = 10_000;
+        let near_expiry = |vault: &Vault, now| {
+            vault_health(vault, 0, 0, now).unwrap() & Vault::HEALTH_NEAR_EXPIRY != 0
+        };
+        assert!(!near_expiry(&vault, 6_399));
+        assert!(near_expiry(&vault, 6_400));
+
+        vault.expiry_timestamp = 0;
+        assert!(!near_expiry(&vault, 6_400));
+    }
+
+    #[test]
+    fn slippage_failure_event_reports_received_and_minimum() {
+        let vault = Pubkey::new_unique();
+        assert!(slippage_failure(vault, 1_000, 1_000, 50).is_none());
+
+        let failure = slippage_failure(vault, 940, 1_000, 50).unwrap();
+        assert_eq!(failure.vault, vault);
+        assert_eq!((failure.amount_received, failure.min_amount_out), (940, 1_000));
+        assert_eq!(failure.timestamp, 50);
+    }
+
+    #[test]
+    fn output_above_cap_is_rejected_as_suspicious() {
+        let mut vault = alternate_vault();
+
+        // No cap by default
+        assert_eq!(vault.output_cap(false, 100, 100), 0);
+        assert_eq!(check_output_cap(0, u64::MAX), Ok(()));
+
+        vault.max_amount_out = 5_000;
+        assert_eq!(check_output_cap(5_000, 5_000), Ok(()));
+        assert_eq!(
+            check_output_cap(5_000, 5_001),
+            Err(ErrorCode::SuspiciousOutput.into())
+        );
+
+        // Buy legs are capped, sell legs deliver source tokens and are not
+        let plan = prepare_cycle(&vault, 1_000, 400_000, None, 1, 0).unwrap();
+        assert_eq!((plan.reverse, plan.output_cap), (false, 5_000));
+        vault.executed_cycles = 1;
+        let plan = prepare_cycle(&vault, 1_000, 400_000, None, 1, 0).unwrap();
+        assert_eq!((plan.reverse, plan.output_cap), (true, 0));
+
+        // Swapping ten cycles' worth at once allows ten times the output
+        assert_eq!(vault.output_cap(false, 1_000, 100), 50_000);
+        assert_eq!(vault.output_cap(false, 50, 100), 5_000);
+    }
+
+    #[test]
+    fn initialize_and_deposit_creates_and_funds_the_vault() {
+        let owner = Pubkey::new_unique();
+        let payer = Pubkey::new_unique();
+        let mut new_vault = zeroed_vault();
+        new_vault.creator = owner;
+        new_vault.source_mint = Pubkey::new_unique();
+        new_vault.dest_mint = Pubkey::new_unique();
+        let vault_key = vault_address(&mut new_vault);
+        let bump = new_vault.bump;
+
+        let mut accounts = [
+            TestAccount::program_owned(vault_key, &zeroed_vault()),
+            TestAccount::mint(new_vault.source_mint),
+            TestAccount::mint(new_vault.dest_mint),
+            TestAccount::signer(owner),
+            TestAccount::token_account(new_vault.source_mint, owner),
+            TestAccount::token_account(new_vault.source_mint, vault_key),
+            TestAccount::program(token::ID),
+            TestAccount::program(system_program::ID),
+        ];
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+        let mut vault = Account::<Vault>::try_from(&infos[0]).unwrap();
+        let source_mint = InterfaceAccount::<Mint>::try_from(&infos[1]).unwrap();
+        let dest_mint = InterfaceAccount::<Mint>::try_from(&infos[2]).unwrap();
+        let mut vault_token_account =
+            InterfaceAccount::<TokenAccount>::try_from(&infos[5]).unwrap();
+        let config = test_config();
+
+        process_initialize_vault(
+            NewVaultAccounts {
+                vault: &mut vault,
+                owner,
+                payer,
+                source_mint: &source_mint,
+                dest_mint: &dest_mint,
+                config: &config,
+                registry: None,
+                slot_hashes: None,
+                bump,
+            },
+            VaultParams {
+                amount_per_cycle: 100,
+                frequency_seconds: 3_600,
+                total_cycles: 10,
+                slippage_bps: 50,
+                keeper_reward: 0,
+                mode: Vault::MODE_FIXED,
+                percent_bps: 0,
+                catch_up: false,
+                max_delay_seconds: 0,
+                low_balance_threshold: 0,
+                label: padded_label(b"Weekly SOL"),
+                max_deposit: 1_000,
+                withdraw_cooldown_seconds: 0,
+                executor: Pubkey::default(),
+                referrer: Pubkey::default(),
+                jitter_seconds: 0,
+                expiry_timestamp: 0,
+                recovery_authority: Pubkey::default(),
+                recovery_delay_seconds: 0,
+                min_initial_deposit: 0,
+            },
+            5_000,
+        )
+        .unwrap();
+
+        assert_eq!(vault.status, Vault::STATUS_ACTIVE);
+        assert_eq!((vault.owner, vault.rent_payer), (owner, payer));
+        assert_eq!(vault.total_deposited, 0);
+        assert_eq!(vault.next_execution, 8_600);
+
+        // The same instruction then funds the vault it just created, held to
+        // the cap set at init
+        program_stubs::set_syscall_stubs(Box::new(SysvarStubs));
+        let mut deposit = |amount: u64| {
+            process_deposit(
+                DepositAccounts {
+                    vault: &mut vault,
+                    depositor: infos[3].clone(),
+                    depositor_token_account: infos[4].clone(),
+                    vault_token_account: &mut vault_token_account,
+                    source_mint: &source_mint,
+                    token_program: infos[6].clone(),
+                    system_program: infos[7].clone(),
+                },
+                amount,
+            )
+        };
+        assert_eq!(
+            deposit(1_001).map(|event| event.amount),
+            Err(ErrorCode::DepositCapExceeded.into())
+        );
+
+        // The token transfer is a no-op off-chain, so nothing is credited, but
+        // the deposit is accepted and reported for the new vault
+        let deposited = deposit(1_000).unwrap();
+        assert_eq!((deposited.vault, deposited.depositor), (vault_key, owner));
+        assert_eq!((deposited.amount, deposited.total_deposited), (0, 0));
+    }
+
+    #[test]
+    fn ensure_dest_account_targets_only_a_missing_vault_ata() {
+        let vault = test_vault();
+        let vault_key = vault_address(&vault);
+        let dest_ata =
+            get_associated_token_address_with_program_id(&vault_key, &vault.dest_mint, &token::ID);
+        let validate = |accounts: &mut [TestAccount]| {
+            let infos: Vec<AccountInfo> = accounts.iter_mut().map(TestAccount::info).collect();
+            validate_accounts::<EnsureDestAccount, EnsureDestAccountBumps>(&infos)
+        };
+        let accounts_with = |dest: TestAccount| {
+            vec![
+                TestAccount::program_owned(vault_key, &vault),
+                TestAccount::signer(Pubkey::new_unique()),
+                dest,
+                TestAccount::mint(vault.dest_mint),
+                TestAccount::program(token::ID),
+                TestAccount::program(associated_token::ID),
+                TestAccount::program(system_program::ID),
+            ]
+        };
+
+        // The dest ATA doesn't exist yet, so any payer may create it
+        let missing = TestAccount::new(dest_ata, system_program::ID, Vec::new());
+        let mut accounts = accounts_with(missing);
+        assert_eq!(validate(&mut accounts), Ok(()));
+
+        // An already-created account is left alone
+        let mut existing = TestAccount::token_account(vault.dest_mint, vault_key);
+        existing.key = dest_ata;
+        let mut accounts = accounts_with(existing);
+        assert_eq!(
+            validate(&mut accounts),
+            Err(ErrorCode::DestAccountAlreadyExists.into())
+        );
+
+        // Only the vault's canonical ATA for the dest mint can be created
+        let mut accounts = accounts_with(TestAccount::new(
+            Pubkey::new_unique(),
+            system_program::ID,
+            Vec::new(),
+        ));
+        assert_eq!(
+            validate(&mut accounts),
+            Err(AnchorErrorCode::ConstraintAddress.into())
+        );
+    }
+
+    /// Stand-in for Jupiter: pays the route's little-endian `u64` amount out
+    /// of a pool owned by its `b"pool"` PDA
+    fn mock_swap(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        data: &[u8],
+    ) -> anchor_lang::solana_program::entrypoint::ProgramResult {
+        let [destination, pool, pool_authority, token_program] = accounts else {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        };
+        let amount = data
+            .try_into()
+            .map(u64::from_le_bytes)
+            .map_err(|_| ProgramError::InvalidInstructionData)?;
+        let (_, bump) = Pubkey::find_program_address(&[b"pool"], program_id);
 
+        let transfer = spl_token::instruction::transfer(
+            token_program.key,
+            pool.key,
+            destination.key,
+            pool_authority.key,
+            &[],
+            amount,
+        )?;
+        invoke_signed(&transfer, accounts, &[&[b"pool", &[bump]]])
+    }
+
+    /// The program built by `anchor build`, with `mock_swap` standing in for
+    /// the configured Jupiter program; returns the swap program and its pool
+    /// authority
+    fn program_test_with_swap() -> (ProgramTest, Pubkey, Pubkey) {
+        let swap_program = Pubkey::new_unique();
+        let pool_authority = Pubkey::find_program_address(&[b"pool"], &swap_program).0;
+
+        let mut program_test = ProgramTest::new("dca_vault", crate::ID, None);
+        program_test.prefer_bpf(true);
+        program_test.add_program("mock_swap", swap_program, processor!(mock_swap));
+
+        (program_test, swap_program, pool_authority)
+    }
+
+    fn add_test_account(program_test: &mut ProgramTest, account: TestAccount) {
+        program_test.add_account(
+            account.key,
+            solana_sdk::account::Account {
+                lamports: account.lamports,
+                data: account.data,
+                owner: account.owner,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+    }
+
+    fn signed_transaction(context: &ProgramTestContext, instruction: Instruction) -> Transaction {
+        Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&context.payer.pubkey()),
+            &[&context.payer],
+            context.last_blockhash,
+        )
+    }
+
+    async fn token_amount(context: &mut ProgramTestContext, key: Pubkey) -> u64 {
+        let account = context.banks_client.get_account(key).await.unwrap().unwrap();
+        spl_token::state::Account::unpack(&account.data).unwrap().amount
+    }
+
+    /// Compute units `execute_dca` spends on one buy cycle of a vault with no
+    /// optional policy enabled, under a config charging `fee_bps`
+    async fn cycle_compute_units(fee_bps: u16) -> u64 {
+        let (mut program_test, swap_program, pool_authority) = program_test_with_swap();
+
+        let mut vault = test_vault();
+        let vault_key = vault_address(&mut vault);
+        vault.total_cycles = 2;
+        vault.amount_per_cycle = 1_000_000;
+        vault.frequency_seconds = 60;
+
+        let mut config = test_config();
+        config.fee_bps = fee_bps;
+        config.jupiter_program = swap_program;
+
+        let mut source = TestAccount::token_account(vault.source_mint, vault_key);
+        source.set_token_amount(vault.amount_per_cycle);
+        let dest = TestAccount::token_account(vault.dest_mint, vault_key);
+        let keeper = TestAccount::token_account(vault.source_mint, Pubkey::new_unique());
+        let treasury = TestAccount::token_account(vault.dest_mint, config.treasury);
+        let mut pool = TestAccount::token_account(vault.dest_mint, pool_authority);
+        pool.set_token_amount(1_000_000_000);
+        let (source_key, dest_key, keeper_key, treasury_key, pool_key) =
+            (source.key, dest.key, keeper.key, treasury.key, pool.key);
+
+        for account in [
+            TestAccount::program_owned(vault_key, &vault),
+            TestAccount::program_owned(config_address().0, &config),
+            TestAccount::mint(vault.source_mint),
+            TestAccount::mint(vault.dest_mint),
+            source,
+            dest,
+            keeper,
+            treasury,
+            pool,
+        ] {
+            add_test_account(&mut program_test, account);
+        }
+
+        let mut context = program_test.start_with_context().await;
+
+        let mut metas = crate::accounts::ExecuteDCA {
+            vault: vault_key,
+            session_authority: context.payer.pubkey(),
+            vault_token_account: source_key,
+            vault_dest_token_account: dest_key,
+            swap_program_account: swap_program,
+            keeper_token_account: keeper_key,
+            price_feed: None,
+            config: config_address().0,
+            treasury_token_account: treasury_key,
+            source_mint: vault.source_mint,
+            dest_mint: vault.dest_mint,
+            compound_token_account: None,
+            referrer_token_account: None,
+            source_token_program: token::ID,
+            dest_token_program: token::ID,
+            session_key: None,
+            session_keys_program: None,
+            instructions: None,
+            fee_ledger: None,
+            event_authority: event_authority(),
+            program: crate::ID,
+        }
+        .to_account_metas(None);
+        metas.extend([
+            AccountMeta::new(dest_key, false),
+            AccountMeta::new(pool_key, false),
+            AccountMeta::new_readonly(pool_authority, false),
+            AccountMeta::new_readonly(token::ID, false),
+        ]);
+
+        let instruction = Instruction {
+            program_id: crate::ID,
+            accounts: metas,
+            data: instruction::ExecuteDca {
+                route_data: 2_000_000u64.to_le_bytes().to_vec(),
+                min_amount_out: 0,
+                session_nonce: 0,
+            }
+            .data(),
+        };
+
+        let simulation = context
+            .banks_client
+            .simulate_transaction(signed_transaction(&context, instruction))
+            .await
+            .unwrap();
+        assert_eq!(simulation.result, Some(Ok(())));
+        simulation.simulation_details.unwrap().units_consumed
+    }
+
+    /// Ceiling on a plain vault's cycle, the swap CPI included
+    const PLAIN_CYCLE_COMPUTE_BUDGET: u64 = 60_000;
+
+    #[tokio::test]
+    async fn plain_cycle_fits_the_compute_budget() {
+        let plain = cycle_compute_units(0).await;
+        assert!(
+            plain <= PLAIN_CYCLE_COMPUTE_BUDGET,
+            "plain cycle used {plain} compute units"
+        );
+
+        // A zero fee skips the treasury transfer rather than sending nothing
+        assert!(plain < cycle_compute_units(10).await);
+    }
+}
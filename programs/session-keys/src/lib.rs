@@ -1,4 +1,5 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::instructions as instructions_sysvar;
 
 declare_id!("SessioNXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX");
 
@@ -7,6 +8,13 @@ pub mod session_keys {
     use super::*;
 
     /// Create a new session key with spending limits
+    /// `window_limit` caps spending per `window_seconds` window, restarted by the
+    /// first spend after it elapses (0 = no window)
+    /// `max_uses` caps the number of validations (0 = unlimited)
+    /// `min_interval_seconds` is the minimum gap between validations (0 = none)
+    /// All amount limits are denominated in `spending_mint`
+    /// The owner's `SessionRegistry` must exist; creation past its cap fails
+    #[allow(clippy::too_many_arguments)]
     pub fn create_session_key(
         ctx: Context<CreateSessionKey>,
         session_pubkey: Pubkey,
@@ -14,7 +22,27 @@ pub mod session_keys {
         max_total_amount: u64,
         expiry_timestamp: i64,
         allowed_programs: Vec<Pubkey>,
+        window_seconds: i64,
+        window_limit: u64,
+        max_uses: u32,
+        min_interval_seconds: i64,
+        spending_mint: Pubkey,
     ) -> Result<()> {
+        SessionKey::validate_window(window_seconds, window_limit)?;
+        require!(min_interval_seconds >= 0, ErrorCode::InvalidMinInterval);
+
+        let clock = Clock::get()?;
+
+        SessionKey::validate_new(
+            max_amount_per_tx,
+            max_total_amount,
+            expiry_timestamp,
+            &allowed_programs,
+            clock.unix_timestamp,
+        )?;
+
+        ctx.accounts.registry.add_session()?;
+
         let session_key = &mut ctx.accounts.session_key;
 
         session_key.owner = ctx.accounts.owner.key();
@@ -22,107 +50,389 @@ pub mod session_keys {
         session_key.max_amount_per_tx = max_amount_per_tx;
         session_key.max_total_amount = max_total_amount;
         session_key.spent_amount = 0;
-        session_key.created_at = Clock::get()?.unix_timestamp;
+        session_key.created_at = clock.unix_timestamp;
         session_key.expiry_timestamp = expiry_timestamp;
-        session_key.allowed_programs_count = allowed_programs.len() as u8;
-        
-        // Copy allowed programs into fixed array
-        for (i, program) in allowed_programs.iter().enumerate() {
-            if i >= 10 {
-                break;
-            }
-            session_key.allowed_programs[i] = *program;
-        }
-        
+        session_key.window_seconds = window_seconds;
+        session_key.window_limit = window_limit;
+        session_key.window_spent = 0;
+        session_key.window_start = session_key.created_at;
+        session_key.nonce = 0;
+        session_key.max_uses = max_uses;
+        session_key.use_count = 0;
+        session_key.min_interval_seconds = min_interval_seconds;
+        session_key.spending_mint = spending_mint;
+        session_key.rent_payer = ctx.accounts.payer.key();
+        session_key.manager = Pubkey::default();
+        session_key.last_used = 0;
+        session_key.set_allowed_programs(&allowed_programs)?;
+        session_key.allowed_accounts = [Pubkey::default(); SessionKey::MAX_ALLOWED_ACCOUNTS];
+        session_key.allowed_accounts_count = 0;
+
         session_key.is_active = true;
+        session_key.is_revoked = false;
         session_key.bump = ctx.bumps.session_key;
 
         msg!("Session key created: {}", session_pubkey);
         msg!("Max per tx: {}", max_amount_per_tx);
         msg!("Max total: {}", max_total_amount);
         msg!("Expires at: {}", expiry_timestamp);
+        msg!("Window: {} per {} seconds", window_limit, window_seconds);
+        msg!("Max uses: {}", max_uses);
+        msg!("Min interval: {} seconds", min_interval_seconds);
+        msg!("Spending mint: {}", spending_mint);
+
+        emit!(session_key.created_event(session_key.key()));
 
         Ok(())
     }
 
     /// Validate session key for a transaction
+    /// `nonce` must be exactly one more than the last validated nonce
+    /// `target_account` must be in the allowed accounts list when it is non-empty
+    /// The transaction's top-level instruction, read from the instructions
+    /// sysvar, must belong to an allowed program and match that program's
+    /// allowed instruction when one is set
+    /// `mint` is the token being spent and must match the session's spending mint
+    #[allow(clippy::too_many_arguments)]
     pub fn validate_session(
         ctx: Context<ValidateSession>,
         program_id: Pubkey,
+        target_account: Pubkey,
         amount: u64,
+        nonce: u64,
+        mint: Pubkey,
     ) -> Result<()> {
+        let (instruction_program, instruction_discriminator) =
+            SessionKey::current_instruction(&ctx.accounts.instructions)?;
         let session_key = &mut ctx.accounts.session_key;
         let clock = Clock::get()?;
 
-        // Check if active
-        require!(session_key.is_active, ErrorCode::SessionKeyNotActive);
+        session_key.check_mint(mint)?;
+        session_key.check_instruction(instruction_program, instruction_discriminator)?;
+        session_key.validate_use(
+            ctx.accounts.session_authority.key(),
+            program_id,
+            target_account,
+            amount,
+            nonce,
+            clock.unix_timestamp,
+        )?;
 
-        // Check expiry
-        require!(
-            clock.unix_timestamp < session_key.expiry_timestamp,
-            ErrorCode::SessionKeyExpired
-        );
+        msg!("Session validated - Amount: {}", amount);
+        msg!("Total spent: {}", session_key.spent_amount);
 
-        // Check per-transaction limit
-        require!(
-            amount <= session_key.max_amount_per_tx,
-            ErrorCode::AmountExceedsPerTxLimit
-        );
+        emit!(session_key.validated_event(program_id, amount, clock.unix_timestamp));
 
-        // Check total limit
-        require!(
-            session_key.spent_amount + amount <= session_key.max_total_amount,
-            ErrorCode::AmountExceedsTotalLimit
-        );
+        Ok(())
+    }
 
-        // Check allowed programs
-        let mut found = false;
-        for i in 0..session_key.allowed_programs_count as usize {
-            if session_key.allowed_programs[i] == program_id {
-                found = true;
-                break;
-            }
+    /// Revoke session key (permanent, cannot be resumed)
+    /// `reason` is one of the `SessionKey::REASON_*` values and is echoed in the event
+    pub fn revoke_session_key(ctx: Context<UpdateSessionKey>, reason: u8) -> Result<()> {
+        require!(reason <= SessionKey::REASON_EXPIRED, ErrorCode::InvalidRevokeReason);
+
+        let session_key = &mut ctx.accounts.session_key;
+        session_key.revoke()?;
+
+        msg!("Session key revoked (reason {})", reason);
+
+        emit!(session_key.revoked_event(
+            session_key.key(),
+            reason,
+            Clock::get()?.unix_timestamp
+        ));
+
+        Ok(())
+    }
+
+    /// Revoke every session key passed in `remaining_accounts` (lost device)
+    /// Keys that are already inactive (paused or revoked) are left as-is
+    pub fn revoke_all<'info>(ctx: Context<'_, '_, 'info, 'info, RevokeAll<'info>>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let events =
+            SessionKey::revoke_owned(ctx.accounts.owner.key(), ctx.remaining_accounts, now)?;
+
+        let revoked_count = events.len();
+
+        for event in events {
+            emit!(event);
         }
-        require!(found, ErrorCode::ProgramNotAllowed);
 
-        // Update spent amount
-        session_key.spent_amount += amount;
+        msg!("Revoked {} session keys", revoked_count);
+        Ok(())
+    }
 
-        msg!("Session validated - Amount: {}", amount);
-        msg!("Total spent: {}", session_key.spent_amount);
+    /// Extend the expiry of a session key that has not yet expired
+    pub fn extend_expiry(ctx: Context<UpdateSessionKey>, new_expiry: i64) -> Result<()> {
+        let session_key = &mut ctx.accounts.session_key;
+        let clock = Clock::get()?;
+
+        session_key.extend_expiry(new_expiry, clock.unix_timestamp)?;
+
+        msg!("Session key expiry extended to {}", new_expiry);
+
+        emit!(SessionKeyUpdatedEvent {
+            session_key: session_key.key(),
+            expiry_timestamp: session_key.expiry_timestamp,
+            is_active: session_key.is_active,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Temporarily disable a session key without losing its state
+    pub fn pause_session(ctx: Context<UpdateSessionKey>) -> Result<()> {
+        let session_key = &mut ctx.accounts.session_key;
+
+        session_key.pause()?;
+
+        msg!("Session key paused");
+
+        emit!(SessionKeyUpdatedEvent {
+            session_key: session_key.key(),
+            expiry_timestamp: session_key.expiry_timestamp,
+            is_active: session_key.is_active,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
 
         Ok(())
     }
 
-    /// Revoke session key
-    pub fn revoke_session_key(ctx: Context<UpdateSessionKey>) -> Result<()> {
+    /// Re-enable a paused session key
+    pub fn resume_session(ctx: Context<UpdateSessionKey>) -> Result<()> {
         let session_key = &mut ctx.accounts.session_key;
-        session_key.is_active = false;
 
-        msg!("Session key revoked");
+        session_key.resume()?;
+
+        msg!("Session key resumed");
+
+        emit!(SessionKeyUpdatedEvent {
+            session_key: session_key.key(),
+            expiry_timestamp: session_key.expiry_timestamp,
+            is_active: session_key.is_active,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
         Ok(())
     }
 
-    /// Update session key limits
+    /// Update session key limits, including the spending window (0/0 disables it)
     pub fn update_limits(
         ctx: Context<UpdateSessionKey>,
         max_amount_per_tx: u64,
         max_total_amount: u64,
+        window_seconds: i64,
+        window_limit: u64,
     ) -> Result<()> {
+        SessionKey::validate_window(window_seconds, window_limit)?;
+
         let session_key = &mut ctx.accounts.session_key;
 
         session_key.max_amount_per_tx = max_amount_per_tx;
         session_key.max_total_amount = max_total_amount;
+        session_key.window_seconds = window_seconds;
+        session_key.window_limit = window_limit;
 
         msg!("Limits updated - Per tx: {}, Total: {}", max_amount_per_tx, max_total_amount);
+        msg!("Window: {} per {} seconds", window_limit, window_seconds);
+
+        emit!(session_key.limits_updated_event(session_key.key(), Clock::get()?.unix_timestamp));
+
+        Ok(())
+    }
+
+    /// Delegate limit tightening to `manager` (`Pubkey::default()` removes it)
+    pub fn set_manager(ctx: Context<UpdateSessionKey>, manager: Pubkey) -> Result<()> {
+        ctx.accounts.session_key.manager = manager;
+
+        msg!("Session manager updated: {}", manager);
+        Ok(())
+    }
+
+    /// Let the session's manager lower its spending limits or bring its
+    /// expiry forward; raising either is rejected with `CannotEscalate`
+    pub fn update_limits_by_manager(
+        ctx: Context<ManageSessionKey>,
+        max_amount_per_tx: u64,
+        max_total_amount: u64,
+        expiry_timestamp: i64,
+    ) -> Result<()> {
+        let session_key = &mut ctx.accounts.session_key;
+        let now = Clock::get()?.unix_timestamp;
+
+        session_key.tighten_limits(max_amount_per_tx, max_total_amount, expiry_timestamp)?;
+
+        msg!(
+            "Limits tightened by manager - Per tx: {}, Total: {}",
+            max_amount_per_tx,
+            max_total_amount
+        );
+        msg!("Expires at: {}", expiry_timestamp);
+
+        emit!(session_key.limits_updated_event(session_key.key(), now));
+
+        Ok(())
+    }
+
+    /// Update the minimum gap between validations (0 disables the cooldown)
+    pub fn set_min_interval(
+        ctx: Context<UpdateSessionKey>,
+        min_interval_seconds: i64,
+    ) -> Result<()> {
+        require!(min_interval_seconds >= 0, ErrorCode::InvalidMinInterval);
+
+        ctx.accounts.session_key.min_interval_seconds = min_interval_seconds;
+
+        msg!("Min interval updated: {} seconds", min_interval_seconds);
+        Ok(())
+    }
+
+    /// Restrict an allowed program to a single instruction discriminator
+    /// An all-zero discriminator lifts the restriction
+    pub fn set_allowed_instruction(
+        ctx: Context<UpdateSessionKey>,
+        program: Pubkey,
+        discriminator: [u8; 8],
+    ) -> Result<()> {
+        let session_key = &mut ctx.accounts.session_key;
+        session_key.set_allowed_instruction(program, discriminator)?;
+
+        msg!("Allowed instruction set for program: {}", program);
+        Ok(())
+    }
+
+    /// Add a program to the session key's allowed list
+    pub fn add_allowed_program(ctx: Context<UpdateSessionKey>, program: Pubkey) -> Result<()> {
+        let session_key = &mut ctx.accounts.session_key;
+        session_key.add_allowed_program(program)?;
+
+        msg!("Allowed program added: {}", program);
+        Ok(())
+    }
+
+    /// Remove a program from the session key's allowed list
+    pub fn remove_allowed_program(ctx: Context<UpdateSessionKey>, program: Pubkey) -> Result<()> {
+        let session_key = &mut ctx.accounts.session_key;
+        session_key.remove_allowed_program(program)?;
+
+        msg!("Allowed program removed: {}", program);
+        Ok(())
+    }
+
+    /// Restore session budget after a validated action failed or was reverted
+    /// Over-refunding clamps the counters at zero
+    pub fn refund_spent(ctx: Context<UpdateSessionKey>, amount: u64) -> Result<()> {
+        let session_key = &mut ctx.accounts.session_key;
+        session_key.refund(amount);
+
+        msg!("Session refunded - Amount: {}", amount);
+        msg!("Total spent: {}", session_key.spent_amount);
+
+        emit!(session_key.refund_event(session_key.key(), amount, Clock::get()?.unix_timestamp));
+
+        Ok(())
+    }
+
+    /// Restrict the session key to an additional target account (e.g. a vault PDA)
+    pub fn add_allowed_account(ctx: Context<UpdateSessionKey>, account: Pubkey) -> Result<()> {
+        let session_key = &mut ctx.accounts.session_key;
+        session_key.add_allowed_account(account)?;
+
+        msg!("Allowed account added: {}", account);
+        Ok(())
+    }
+
+    /// Remove an account from the session key's allowed list
+    pub fn remove_allowed_account(ctx: Context<UpdateSessionKey>, account: Pubkey) -> Result<()> {
+        let session_key = &mut ctx.accounts.session_key;
+        session_key.remove_allowed_account(account)?;
+
+        msg!("Allowed account removed: {}", account);
+        Ok(())
+    }
+
+    /// Close an expired session key and refund its rent to the owner
+    /// Permissionless, so anyone can crank stale keys once they expire
+    pub fn crank_expired(ctx: Context<CrankExpired>) -> Result<()> {
+        let session_key = &mut ctx.accounts.session_key;
+        let clock = Clock::get()?;
+
+        session_key.expire(clock.unix_timestamp)?;
+        session_key.release_slot(&ctx.accounts.registry)?;
+
+        msg!("Expired session key closed: {}", session_key.session_pubkey);
+
+        emit!(session_key.revoked_event(
+            session_key.key(),
+            SessionKey::REASON_EXPIRED,
+            clock.unix_timestamp
+        ));
+
         Ok(())
     }
 
+    /// Allowed programs for permission UIs, meant to be read via `simulateTransaction`
+    pub fn get_allowed_programs(ctx: Context<ViewSessionKey>) -> Result<Vec<Pubkey>> {
+        Ok(ctx.accounts.session_key.allowed_programs().to_vec())
+    }
+
+    /// Budget left before the total spending limit is reached
+    pub fn get_remaining_budget(ctx: Context<ViewSessionKey>) -> Result<u64> {
+        Ok(ctx.accounts.session_key.remaining_budget())
+    }
+
     /// Close session key account
-    pub fn close_session_key(_ctx: Context<CloseSessionKey>) -> Result<()> {
+    pub fn close_session_key(ctx: Context<CloseSessionKey>) -> Result<()> {
+        ctx.accounts.session_key.release_slot(&ctx.accounts.registry)?;
+
         msg!("Session key closed");
         Ok(())
     }
+
+    /// Close a session key like `close_session_key`, but refund its rent to
+    /// the `rent_payer` recorded at creation
+    pub fn close_session_key_to_payer(ctx: Context<CloseSessionKeyToPayer>) -> Result<()> {
+        ctx.accounts.session_key.release_slot(&ctx.accounts.registry)?;
+
+        // `close` can only name a fixed account, so the refund target read
+        // from the key is paid out by hand
+        ctx.accounts
+            .session_key
+            .close(ctx.accounts.rent_payer.to_account_info())?;
+
+        msg!("Session key closed - rent refunded to {}", ctx.accounts.rent_payer.key());
+        Ok(())
+    }
+
+    /// Create the owner's session registry, capping how many keys they can hold
+    /// Keys can only be created once it exists; keys from before registries
+    /// hold no slot, so closing them frees none
+    pub fn initialize_session_registry(
+        ctx: Context<InitializeSessionRegistry>,
+        max_sessions: u16,
+    ) -> Result<()> {
+        require!(max_sessions > 0, ErrorCode::InvalidMaxSessions);
+
+        let registry = &mut ctx.accounts.registry;
+        registry.owner = ctx.accounts.owner.key();
+        registry.count = 0;
+        registry.max_sessions = max_sessions;
+        registry.bump = ctx.bumps.registry;
+
+        msg!("Session registry initialized - max {} sessions", max_sessions);
+        Ok(())
+    }
+
+    /// Change the owner's session cap (existing keys above the cap are kept)
+    pub fn set_max_sessions(ctx: Context<UpdateSessionRegistry>, max_sessions: u16) -> Result<()> {
+        require!(max_sessions > 0, ErrorCode::InvalidMaxSessions);
+
+        ctx.accounts.registry.max_sessions = max_sessions;
+
+        msg!("Max sessions updated: {}", max_sessions);
+        Ok(())
+    }
 }
 
 // ============================================
@@ -134,7 +444,7 @@ pub mod session_keys {
 pub struct CreateSessionKey<'info> {
     #[account(
         init,
-        payer = owner,
+        payer = payer,
         space = 8 + SessionKey::LEN,
         seeds = [
             b"session",
@@ -145,9 +455,22 @@ pub struct CreateSessionKey<'info> {
     )]
     pub session_key: Account<'info, SessionKey>,
 
+    /// Owner's session registry, whose cap the new key counts against
+    #[account(
+        mut,
+        seeds = [b"session_registry", owner.key().as_ref()],
+        bump = registry.bump,
+        has_one = owner,
+    )]
+    pub registry: Account<'info, SessionRegistry>,
+
     #[account(mut)]
     pub owner: Signer<'info>,
 
+    /// Pays the session key's rent; may differ from the owner for custodial setups
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
     pub system_program: Program<'info, System>,
 }
 
@@ -166,6 +489,24 @@ pub struct ValidateSession<'info> {
 
     /// The session authority must sign
     pub session_authority: Signer<'info>,
+
+    /// CHECK: address-constrained to the instructions sysvar, which is read
+    /// for the top-level instruction being authorized
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ViewSessionKey<'info> {
+    #[account(
+        seeds = [
+            b"session",
+            session_key.owner.as_ref(),
+            session_key.session_pubkey.as_ref(),
+        ],
+        bump = session_key.bump,
+    )]
+    pub session_key: Account<'info, SessionKey>,
 }
 
 #[derive(Accounts)]
@@ -185,6 +526,28 @@ pub struct UpdateSessionKey<'info> {
     pub owner: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct ManageSessionKey<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"session",
+            session_key.owner.as_ref(),
+            session_key.session_pubkey.as_ref(),
+        ],
+        bump = session_key.bump,
+        has_one = manager @ ErrorCode::Unauthorized,
+    )]
+    pub session_key: Account<'info, SessionKey>,
+
+    pub manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeAll<'info> {
+    pub owner: Signer<'info>,
+}
+
 #[derive(Accounts)]
 pub struct CloseSessionKey<'info> {
     #[account(
@@ -200,51 +563,1847 @@ pub struct CloseSessionKey<'info> {
     )]
     pub session_key: Account<'info, SessionKey>,
 
+    /// CHECK: owner's `SessionRegistry` PDA, freeing the key's slot if it holds one
+    #[account(
+        mut,
+        seeds = [b"session_registry", owner.key().as_ref()],
+        bump,
+    )]
+    pub registry: UncheckedAccount<'info>,
+
     #[account(mut)]
     pub owner: Signer<'info>,
 }
 
-// ============================================
-// State
-// ============================================
+#[derive(Accounts)]
+pub struct CloseSessionKeyToPayer<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"session",
+            session_key.owner.as_ref(),
+            session_key.session_pubkey.as_ref(),
+        ],
+        bump = session_key.bump,
+        has_one = owner,
+        has_one = rent_payer,
+    )]
+    pub session_key: Account<'info, SessionKey>,
 
-#[account]
-pub struct SessionKey {
-    pub owner: Pubkey,                      // 32
-    pub session_pubkey: Pubkey,             // 32
-    pub max_amount_per_tx: u64,             // 8
-    pub max_total_amount: u64,              // 8
-    pub spent_amount: u64,                  // 8
-    pub created_at: i64,                    // 8
-    pub expiry_timestamp: i64,              // 8
-    pub allowed_programs: [Pubkey; 10],     // 32 * 10 = 320
-    pub allowed_programs_count: u8,         // 1
-    pub is_active: bool,                    // 1
-    pub bump: u8,                           // 1
-}
+    /// CHECK: owner's `SessionRegistry` PDA, freeing the key's slot if it holds one
+    #[account(
+        mut,
+        seeds = [b"session_registry", owner.key().as_ref()],
+        bump,
+    )]
+    pub registry: UncheckedAccount<'info>,
 
-impl SessionKey {
-    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 8 + 8 + (32 * 10) + 1 + 1 + 1;
-}
+    pub owner: Signer<'info>,
 
-// ============================================
-// Errors
-// ============================================
+    /// Account that paid the session key's rent, refunded on close
+    #[account(mut)]
+    pub rent_payer: SystemAccount<'info>,
+}
 
-#[error_code]
-pub enum ErrorCode {
-    #[msg("Session key is not active")]
-    SessionKeyNotActive,
+#[derive(Accounts)]
+pub struct InitializeSessionRegistry<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + SessionRegistry::LEN,
+        seeds = [b"session_registry", owner.key().as_ref()],
+        bump
+    )]
+    pub registry: Account<'info, SessionRegistry>,
 
-    #[msg("Session key has expired")]
-    SessionKeyExpired,
+    #[account(mut)]
+    pub owner: Signer<'info>,
 
-    #[msg("Amount exceeds per-transaction limit")]
-    AmountExceedsPerTxLimit,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateSessionRegistry<'info> {
+    #[account(
+        mut,
+        seeds = [b"session_registry", owner.key().as_ref()],
+        bump = registry.bump,
+        has_one = owner,
+    )]
+    pub registry: Account<'info, SessionRegistry>,
+
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CrankExpired<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"session",
+            session_key.owner.as_ref(),
+            session_key.session_pubkey.as_ref(),
+        ],
+        bump = session_key.bump,
+        has_one = owner,
+        close = owner
+    )]
+    pub session_key: Account<'info, SessionKey>,
+
+    /// CHECK: owner's `SessionRegistry` PDA, freeing the key's slot if it holds one
+    #[account(
+        mut,
+        seeds = [b"session_registry", owner.key().as_ref()],
+        bump,
+    )]
+    pub registry: UncheckedAccount<'info>,
+
+    /// Session key owner receiving the rent refund
+    #[account(mut)]
+    pub owner: SystemAccount<'info>,
+}
+
+// ============================================
+// State
+// ============================================
+
+#[account]
+pub struct SessionKey {
+    pub owner: Pubkey,                      // 32
+    pub session_pubkey: Pubkey,             // 32
+    pub max_amount_per_tx: u64,             // 8
+    pub max_total_amount: u64,              // 8
+    pub spent_amount: u64,                  // 8
+    pub created_at: i64,                    // 8
+    pub expiry_timestamp: i64,              // 8
+    pub allowed_programs: [Pubkey; 10],     // 32 * 10 = 320
+    pub allowed_programs_count: u8,         // 1
+    pub is_active: bool,                    // 1
+    pub bump: u8,                           // 1
+    pub window_seconds: i64,                // 8 (0 = no spending window)
+    pub window_limit: u64,                  // 8
+    pub window_spent: u64,                  // 8
+    pub window_start: i64,                  // 8
+    pub nonce: u64,                         // 8 (last validated nonce)
+    pub max_uses: u32,                      // 4 (0 = unlimited)
+    pub use_count: u32,                     // 4
+    pub is_revoked: bool,                   // 1
+    pub allowed_accounts: [Pubkey; 8],      // 32 * 8 = 256 (empty = any account)
+    pub allowed_accounts_count: u8,         // 1
+    pub min_interval_seconds: i64,          // 8 (0 = no cooldown)
+    pub last_used: i64,                     // 8
+    pub allowed_instructions: [[u8; 8]; 10], // 8 * 10 = 80 (paired with allowed_programs)
+    pub spending_mint: Pubkey,              // 32 (denomination of every amount limit)
+    pub rent_payer: Pubkey,                 // 32 (refunded by close_session_key_to_payer)
+    pub manager: Pubkey,                    // 32 (may only tighten limits, default = none)
+}
+
+impl SessionKey {
+    pub const MAX_ALLOWED_PROGRAMS: usize = 10;
+
+    pub const MAX_ALLOWED_ACCOUNTS: usize = 8;
+
+    /// `SessionRevokedEvent` reasons; `revoke_all` reports a compromised key
+    /// and `crank_expired` an expired one
+    pub const REASON_MANUAL: u8 = 0;
+    pub const REASON_COMPROMISED: u8 = 1;
+    pub const REASON_EXPIRED: u8 = 2;
+
+    pub const LEN: usize = 32 // owner
+        + 32 // session_pubkey
+        + 8 // max_amount_per_tx
+        + 8 // max_total_amount
+        + 8 // spent_amount
+        + 8 // created_at
+        + 8 // expiry_timestamp
+        + 32 * 10 // allowed_programs
+        + 1 // allowed_programs_count
+        + 1 // is_active
+        + 1 // bump
+        + 8 // window_seconds
+        + 8 // window_limit
+        + 8 // window_spent
+        + 8 // window_start
+        + 8 // nonce
+        + 4 // max_uses
+        + 4 // use_count
+        + 1 // is_revoked
+        + 32 * 8 // allowed_accounts
+        + 1 // allowed_accounts_count
+        + 8 // min_interval_seconds
+        + 8 // last_used
+        + 8 * 10 // allowed_instructions
+        + 32 // spending_mint
+        + 32 // rent_payer
+        + 32; // manager
+
+    /// A spending window is either disabled (both zero) or has a positive
+    /// length and limit
+    pub fn validate_window(window_seconds: i64, window_limit: u64) -> Result<()> {
+        require!(window_seconds >= 0, ErrorCode::InvalidWindow);
+        require!(
+            (window_seconds == 0) == (window_limit == 0),
+            ErrorCode::InvalidWindow
+        );
+        Ok(())
+    }
+
+    /// Reject limits, expiry or program lists a new key could never use
+    /// Every program must be a distinct, non-default pubkey
+    pub fn validate_new(
+        max_amount_per_tx: u64,
+        max_total_amount: u64,
+        expiry_timestamp: i64,
+        allowed_programs: &[Pubkey],
+        now: i64,
+    ) -> Result<()> {
+        require!(
+            max_amount_per_tx <= max_total_amount,
+            ErrorCode::InvalidLimits
+        );
+        require!(expiry_timestamp > now, ErrorCode::ExpiryInPast);
+        require!(!allowed_programs.is_empty(), ErrorCode::NoProgramsSpecified);
+
+        for (i, program) in allowed_programs.iter().enumerate() {
+            require_keys_neq!(*program, Pubkey::default(), ErrorCode::InvalidProgram);
+            require!(
+                !allowed_programs[..i].contains(program),
+                ErrorCode::DuplicateProgram
+            );
+        }
+        Ok(())
+    }
+
+    /// Replace the allowed list, rejecting more than `MAX_ALLOWED_PROGRAMS`
+    pub fn set_allowed_programs(&mut self, programs: &[Pubkey]) -> Result<()> {
+        require!(
+            programs.len() <= Self::MAX_ALLOWED_PROGRAMS,
+            ErrorCode::TooManyPrograms
+        );
+
+        self.allowed_programs = [Pubkey::default(); Self::MAX_ALLOWED_PROGRAMS];
+        self.allowed_programs[..programs.len()].copy_from_slice(programs);
+        self.allowed_programs_count = programs.len() as u8;
+        self.allowed_instructions = [[0; 8]; Self::MAX_ALLOWED_PROGRAMS];
+        Ok(())
+    }
+
+    /// The first `allowed_programs_count` entries of `allowed_programs`
+    pub fn allowed_programs(&self) -> &[Pubkey] {
+        &self.allowed_programs[..self.allowed_programs_count as usize]
+    }
+
+    /// Amount still spendable before `max_total_amount` is reached
+    pub fn remaining_budget(&self) -> u64 {
+        self.max_total_amount.saturating_sub(self.spent_amount)
+    }
+
+    /// Append `program` to the allowed list, rejecting duplicates
+    pub fn add_allowed_program(&mut self, program: Pubkey) -> Result<()> {
+        let count = self.allowed_programs_count as usize;
+
+        require_keys_neq!(program, Pubkey::default(), ErrorCode::InvalidProgram);
+        require!(
+            !self.allowed_programs[..count].contains(&program),
+            ErrorCode::DuplicateProgram
+        );
+        require!(
+            count < Self::MAX_ALLOWED_PROGRAMS,
+            ErrorCode::TooManyPrograms
+        );
+
+        self.allowed_programs[count] = program;
+        self.allowed_instructions[count] = [0; 8];
+        self.allowed_programs_count += 1;
+        Ok(())
+    }
+
+    /// Remove `program` from the allowed list
+    pub fn remove_allowed_program(&mut self, program: Pubkey) -> Result<()> {
+        let count = self.allowed_programs_count as usize;
+
+        let index = self.allowed_programs[..count]
+            .iter()
+            .position(|allowed| *allowed == program)
+            .ok_or(ErrorCode::ProgramNotAllowed)?;
+
+        // Move the last entry into the freed slot to keep the list packed
+        self.allowed_programs[index] = self.allowed_programs[count - 1];
+        self.allowed_programs[count - 1] = Pubkey::default();
+        self.allowed_instructions[index] = self.allowed_instructions[count - 1];
+        self.allowed_instructions[count - 1] = [0; 8];
+        self.allowed_programs_count -= 1;
+        Ok(())
+    }
+
+    /// Pair an allowed program with the only instruction it may be used for
+    pub fn set_allowed_instruction(
+        &mut self,
+        program: Pubkey,
+        discriminator: [u8; 8],
+    ) -> Result<()> {
+        let count = self.allowed_programs_count as usize;
+
+        let index = self.allowed_programs[..count]
+            .iter()
+            .position(|allowed| *allowed == program)
+            .ok_or(ErrorCode::ProgramNotAllowed)?;
+
+        self.allowed_instructions[index] = discriminator;
+        Ok(())
+    }
+
+    /// Check that `mint` is the token this session's limits are denominated in
+    pub fn check_mint(&self, mint: Pubkey) -> Result<()> {
+        require_keys_eq!(mint, self.spending_mint, ErrorCode::MintMismatch);
+        Ok(())
+    }
+
+    /// Check that `program` is allowed and `discriminator` is the instruction
+    /// it is restricted to; unrestricted programs allow any instruction
+    pub fn check_instruction(&self, program: Pubkey, discriminator: [u8; 8]) -> Result<()> {
+        let count = self.allowed_programs_count as usize;
+
+        let index = self.allowed_programs[..count]
+            .iter()
+            .position(|allowed| *allowed == program)
+            .ok_or(ErrorCode::ProgramNotAllowed)?;
+
+        let instruction = self.allowed_instructions[index];
+        require!(
+            instruction == [0; 8] || instruction == discriminator,
+            ErrorCode::InstructionNotAllowed
+        );
+        Ok(())
+    }
+
+    /// Program id and Anchor discriminator of the transaction's currently
+    /// executing top-level instruction, read from the instructions sysvar
+    /// CPIs never appear there, so a calling program can't choose what it is
+    /// authorized as; instructions too short for a discriminator are rejected
+    pub fn current_instruction(instructions: &AccountInfo) -> Result<(Pubkey, [u8; 8])> {
+        let index = instructions_sysvar::load_current_index_checked(instructions)?;
+        let instruction =
+            instructions_sysvar::load_instruction_at_checked(index as usize, instructions)?;
+
+        let discriminator = instruction
+            .data
+            .get(..8)
+            .and_then(|data| <[u8; 8]>::try_from(data).ok())
+            .ok_or(ErrorCode::InstructionNotAllowed)?;
+        Ok((instruction.program_id, discriminator))
+    }
+
+    /// Append `account` to the allowed target accounts, rejecting duplicates
+    pub fn add_allowed_account(&mut self, account: Pubkey) -> Result<()> {
+        let count = self.allowed_accounts_count as usize;
+
+        require!(
+            !self.allowed_accounts[..count].contains(&account),
+            ErrorCode::DuplicateAccount
+        );
+        require!(
+            count < Self::MAX_ALLOWED_ACCOUNTS,
+            ErrorCode::TooManyAccounts
+        );
+
+        self.allowed_accounts[count] = account;
+        self.allowed_accounts_count += 1;
+        Ok(())
+    }
+
+    /// Remove `account` from the allowed target accounts
+    pub fn remove_allowed_account(&mut self, account: Pubkey) -> Result<()> {
+        let count = self.allowed_accounts_count as usize;
+
+        let index = self.allowed_accounts[..count]
+            .iter()
+            .position(|allowed| *allowed == account)
+            .ok_or(ErrorCode::AccountNotAllowed)?;
+
+        // Move the last entry into the freed slot to keep the list packed
+        self.allowed_accounts[index] = self.allowed_accounts[count - 1];
+        self.allowed_accounts[count - 1] = Pubkey::default();
+        self.allowed_accounts_count -= 1;
+        Ok(())
+    }
+
+    /// Move the expiry of a live, unexpired key later to `new_expiry`
+    pub fn extend_expiry(&mut self, new_expiry: i64, now: i64) -> Result<()> {
+        require!(!self.is_revoked, ErrorCode::SessionKeyRevoked);
+        require!(now < self.expiry_timestamp, ErrorCode::SessionKeyExpired);
+        require!(
+            new_expiry > now && new_expiry > self.expiry_timestamp,
+            ErrorCode::InvalidExpiry
+        );
+
+        self.expiry_timestamp = new_expiry;
+        Ok(())
+    }
+
+    /// Lower the spending limits and/or bring the expiry forward; nothing
+    /// may be raised, so a manager can never grant more than the owner did
+    pub fn tighten_limits(
+        &mut self,
+        max_amount_per_tx: u64,
+        max_total_amount: u64,
+        expiry_timestamp: i64,
+    ) -> Result<()> {
+        require!(!self.is_revoked, ErrorCode::SessionKeyRevoked);
+        require!(
+            max_amount_per_tx <= self.max_amount_per_tx
+                && max_total_amount <= self.max_total_amount
+                && expiry_timestamp <= self.expiry_timestamp,
+            ErrorCode::CannotEscalate
+        );
+        require!(
+            max_amount_per_tx <= max_total_amount,
+            ErrorCode::InvalidLimits
+        );
+
+        self.max_amount_per_tx = max_amount_per_tx;
+        self.max_total_amount = max_total_amount;
+        self.expiry_timestamp = expiry_timestamp;
+        Ok(())
+    }
+
+    /// Temporarily disable an active key
+    pub fn pause(&mut self) -> Result<()> {
+        require!(self.is_active, ErrorCode::SessionKeyNotActive);
+
+        self.is_active = false;
+        Ok(())
+    }
+
+    /// Re-enable a paused key; revoked keys stay disabled
+    pub fn resume(&mut self) -> Result<()> {
+        require!(!self.is_revoked, ErrorCode::SessionKeyRevoked);
+        require!(!self.is_active, ErrorCode::SessionKeyNotPaused);
+
+        self.is_active = true;
+        Ok(())
+    }
+
+    /// Permanently disable a key; a key is only ever revoked once
+    pub fn revoke(&mut self) -> Result<()> {
+        require!(!self.is_revoked, ErrorCode::SessionKeyRevoked);
+
+        self.is_active = false;
+        self.is_revoked = true;
+        Ok(())
+    }
+
+    /// Deactivate a key whose expiry has strictly passed at `now`
+    pub fn expire(&mut self, now: i64) -> Result<()> {
+        require!(self.expiry_timestamp < now, ErrorCode::SessionKeyNotExpired);
+
+        self.is_active = false;
+        Ok(())
+    }
+
+    /// Free this key's slot in the owner's registry stored in `registry`
+    /// Every caller closes the key afterwards, so a slot is freed only once
+    pub fn release_slot(&self, registry: &AccountInfo) -> Result<()> {
+        SessionRegistry::update_if_exists(registry, SessionRegistry::remove_session)
+    }
+
+    /// Check that `session_authority` may spend `amount` through `program_id`
+    /// on `target_account` at `now` with the next `nonce`, then add the amount
+    /// to the spent totals
+    #[allow(clippy::too_many_arguments)]
+    pub fn validate_use(
+        &mut self,
+        session_authority: Pubkey,
+        program_id: Pubkey,
+        target_account: Pubkey,
+        amount: u64,
+        nonce: u64,
+        now: i64,
+    ) -> Result<()> {
+        // Check the signer is the delegated session key
+        require!(
+            session_authority == self.session_pubkey,
+            ErrorCode::InvalidSessionAuthority
+        );
+
+        // Check if active
+        require!(self.is_active, ErrorCode::SessionKeyNotActive);
+
+        // Check replay protection
+        require!(
+            self.nonce.checked_add(1) == Some(nonce),
+            ErrorCode::InvalidNonce
+        );
+
+        // Check usage count
+        require!(
+            self.max_uses == 0 || self.use_count < self.max_uses,
+            ErrorCode::MaxUsesExceeded
+        );
+
+        // Check cooldown since the last validation
+        if self.min_interval_seconds > 0 {
+            let next_allowed = self
+                .last_used
+                .checked_add(self.min_interval_seconds)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+            require!(now >= next_allowed, ErrorCode::SessionRateLimited);
+        }
+
+        // Check expiry
+        require!(
+            now < self.expiry_timestamp,
+            ErrorCode::SessionKeyExpired
+        );
+
+        // Check allowed programs
+        let mut found = false;
+        for i in 0..self.allowed_programs_count as usize {
+            if self.allowed_programs[i] == program_id {
+                found = true;
+                break;
+            }
+        }
+        require!(found, ErrorCode::ProgramNotAllowed);
+
+        // Check allowed accounts (empty list allows any account)
+        let account_count = self.allowed_accounts_count as usize;
+        require!(
+            account_count == 0 || self.allowed_accounts[..account_count].contains(&target_account),
+            ErrorCode::AccountNotAllowed
+        );
+
+        // Check per-transaction, total and window limits
+        self.record_spend(amount, now)?;
+
+        self.nonce = nonce;
+        self.use_count = self
+            .use_count
+            .checked_add(1)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        self.last_used = now;
+        Ok(())
+    }
+
+    /// Give `amount` back to the total and window budgets, clamping at zero
+    pub fn refund(&mut self, amount: u64) {
+        self.spent_amount = self.spent_amount.saturating_sub(amount);
+        self.window_spent = self.window_spent.saturating_sub(amount);
+    }
+
+    /// Check `amount` against the per-transaction, total and window limits at
+    /// `now`, then add it to the spent totals
+    /// The window is fixed rather than rolling: the first spend after it has
+    /// elapsed starts a fresh one with nothing spent
+    pub fn record_spend(&mut self, amount: u64, now: i64) -> Result<()> {
+        require!(
+            amount <= self.max_amount_per_tx,
+            ErrorCode::AmountExceedsPerTxLimit
+        );
+
+        let new_spent = self
+            .spent_amount
+            .checked_add(amount)
+            .ok_or(ErrorCode::ArithmeticOverflow)?;
+        require!(
+            new_spent <= self.max_total_amount,
+            ErrorCode::AmountExceedsTotalLimit
+        );
+
+        if self.window_seconds > 0 {
+            let window_end = self
+                .window_start
+                .checked_add(self.window_seconds)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+            if now >= window_end {
+                self.window_spent = 0;
+                self.window_start = now;
+            }
+
+            let new_window_spent = self
+                .window_spent
+                .checked_add(amount)
+                .ok_or(ErrorCode::ArithmeticOverflow)?;
+            require!(
+                new_window_spent <= self.window_limit,
+                ErrorCode::AmountExceedsWindowLimit
+            );
+        }
+
+        self.spent_amount = new_spent;
+        self.window_spent = self.window_spent.saturating_add(amount);
+
+        Ok(())
+    }
+
+    /// Event payloads for the lifecycle handlers, built from current state
+    pub fn created_event(&self, session_key: Pubkey) -> SessionKeyCreatedEvent {
+        SessionKeyCreatedEvent {
+            session_key,
+            owner: self.owner,
+            session_pubkey: self.session_pubkey,
+            max_amount_per_tx: self.max_amount_per_tx,
+            max_total_amount: self.max_total_amount,
+            expiry_timestamp: self.expiry_timestamp,
+            timestamp: self.created_at,
+        }
+    }
+
+    pub fn validated_event(
+        &self,
+        program_id: Pubkey,
+        amount: u64,
+        now: i64,
+    ) -> SessionValidatedEvent {
+        SessionValidatedEvent {
+            session_pubkey: self.session_pubkey,
+            program_id,
+            amount,
+            total_spent: self.spent_amount,
+            timestamp: now,
+        }
+    }
+
+    pub fn refund_event(&self, session_key: Pubkey, amount: u64, now: i64) -> SessionRefundEvent {
+        SessionRefundEvent {
+            session_key,
+            amount,
+            total_spent: self.spent_amount,
+            timestamp: now,
+        }
+    }
+
+    /// Revoke each active key in `keys`, all of which must belong to `owner`
+    /// Inactive keys are skipped; returns one event per revoked key
+    pub fn revoke_owned<'info>(
+        owner: Pubkey,
+        keys: &'info [AccountInfo<'info>],
+        now: i64,
+    ) -> Result<Vec<SessionRevokedEvent>> {
+        let mut events = Vec::with_capacity(keys.len());
+
+        for account in keys.iter() {
+            let mut session_key = Account::<SessionKey>::try_from(account)?;
+            require_keys_eq!(session_key.owner, owner, ErrorCode::Unauthorized);
+
+            if !session_key.is_active {
+                continue;
+            }
+
+            session_key.revoke()?;
+            events.push(session_key.revoked_event(account.key(), Self::REASON_COMPROMISED, now));
+
+            // Remaining accounts are not persisted by Anchor
+            session_key.exit(&crate::ID)?;
+        }
+
+        Ok(events)
+    }
+
+    pub fn revoked_event(&self, session_key: Pubkey, reason: u8, now: i64) -> SessionRevokedEvent {
+        SessionRevokedEvent {
+            session_key,
+            session_pubkey: self.session_pubkey,
+            reason,
+            timestamp: now,
+        }
+    }
+
+    pub fn limits_updated_event(&self, session_key: Pubkey, now: i64) -> SessionLimitsUpdatedEvent {
+        SessionLimitsUpdatedEvent {
+            session_key,
+            max_amount_per_tx: self.max_amount_per_tx,
+            max_total_amount: self.max_total_amount,
+            timestamp: now,
+        }
+    }
+}
+
+// Keep LEN in sync with the field list above when adding fields
+const _: () = assert!(SessionKey::LEN == 925);
+
+/// Per-owner count of open session keys
+#[account]
+pub struct SessionRegistry {
+    pub owner: Pubkey,                      // 32
+    pub count: u16,                         // 2
+    pub max_sessions: u16,                  // 2
+    pub bump: u8,                           // 1
+}
+
+impl SessionRegistry {
+    pub const LEN: usize = 32 // owner
+        + 2 // count
+        + 2 // max_sessions
+        + 1; // bump
+
+    /// Apply `update` to the registry stored in `info`, if the owner has
+    /// created one
+    pub fn update_if_exists(
+        info: &AccountInfo,
+        update: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        if info.owner != &crate::ID || info.data_is_empty() {
+            return Ok(());
+        }
+
+        let mut data = info.try_borrow_mut_data()?;
+        let mut registry = Self::try_deserialize(&mut &data[..])?;
+        update(&mut registry)?;
+        registry.try_serialize(&mut &mut data[..])?;
+        Ok(())
+    }
+
+    /// Count a newly created key against the cap
+    pub fn add_session(&mut self) -> Result<()> {
+        require!(self.count < self.max_sessions, ErrorCode::TooManySessions);
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Free the slot of a closed key
+    pub fn remove_session(&mut self) -> Result<()> {
+        self.count = self.count.saturating_sub(1);
+        Ok(())
+    }
+}
+
+// ============================================
+// Events
+// ============================================
+
+/// Event emitted when a session key is created
+#[event]
+pub struct SessionKeyCreatedEvent {
+    pub session_key: Pubkey,
+    pub owner: Pubkey,
+    pub session_pubkey: Pubkey,
+    pub max_amount_per_tx: u64,
+    pub max_total_amount: u64,
+    pub expiry_timestamp: i64,
+    pub timestamp: i64,
+}
+
+/// Event emitted when a session key passes validation
+#[event]
+pub struct SessionValidatedEvent {
+    pub session_pubkey: Pubkey,
+    pub program_id: Pubkey,
+    pub amount: u64,
+    pub total_spent: u64,
+    pub timestamp: i64,
+}
+
+/// Event emitted when spent budget is refunded to a session key
+#[event]
+pub struct SessionRefundEvent {
+    pub session_key: Pubkey,
+    pub amount: u64,
+    pub total_spent: u64,
+    pub timestamp: i64,
+}
+
+/// Event emitted when a session key is revoked
+#[event]
+pub struct SessionRevokedEvent {
+    pub session_key: Pubkey,
+    pub session_pubkey: Pubkey,
+    pub reason: u8,
+    pub timestamp: i64,
+}
+
+/// Event emitted when session key limits change
+#[event]
+pub struct SessionLimitsUpdatedEvent {
+    pub session_key: Pubkey,
+    pub max_amount_per_tx: u64,
+    pub max_total_amount: u64,
+    pub timestamp: i64,
+}
+
+/// Event emitted when a session key's expiry or active flag changes
+#[event]
+pub struct SessionKeyUpdatedEvent {
+    pub session_key: Pubkey,
+    pub expiry_timestamp: i64,
+    pub is_active: bool,
+    pub timestamp: i64,
+}
+
+// ============================================
+// Errors
+// ============================================
+
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Session key is not active")]
+    SessionKeyNotActive,
+
+    #[msg("Session key has expired")]
+    SessionKeyExpired,
+
+    #[msg("Amount exceeds per-transaction limit")]
+    AmountExceedsPerTxLimit,
 
     #[msg("Amount exceeds total spending limit")]
     AmountExceedsTotalLimit,
 
     #[msg("Program is not in allowed list")]
     ProgramNotAllowed,
+
+    #[msg("Signer is not the session key")]
+    InvalidSessionAuthority,
+
+    #[msg("Amount exceeds the spending window limit")]
+    AmountExceedsWindowLimit,
+
+    #[msg("Window must be non-negative and set together with a positive window limit")]
+    InvalidWindow,
+
+    #[msg("Nonce must be exactly one more than the last used nonce")]
+    InvalidNonce,
+
+    #[msg("Session key has reached its maximum number of uses")]
+    MaxUsesExceeded,
+
+    #[msg("Arithmetic overflow")]
+    ArithmeticOverflow,
+
+    #[msg("Session key has been revoked")]
+    SessionKeyRevoked,
+
+    #[msg("Session key is not paused")]
+    SessionKeyNotPaused,
+
+    #[msg("New expiry must be in the future and later than the current expiry")]
+    InvalidExpiry,
+
+    #[msg("Too many allowed programs")]
+    TooManyPrograms,
+
+    #[msg("Program is already in allowed list")]
+    DuplicateProgram,
+
+    #[msg("Account is not in allowed list")]
+    AccountNotAllowed,
+
+    #[msg("Too many allowed accounts")]
+    TooManyAccounts,
+
+    #[msg("Account is already in allowed list")]
+    DuplicateAccount,
+
+    #[msg("Per-transaction limit exceeds total limit")]
+    InvalidLimits,
+
+    #[msg("Expiry timestamp is in the past")]
+    ExpiryInPast,
+
+    #[msg("At least one allowed program is required")]
+    NoProgramsSpecified,
+
+    #[msg("Session key has not expired yet")]
+    SessionKeyNotExpired,
+
+    #[msg("Owner has reached the maximum number of session keys")]
+    TooManySessions,
+
+    #[msg("Max sessions must be greater than zero")]
+    InvalidMaxSessions,
+
+    #[msg("Session key was used too recently")]
+    SessionRateLimited,
+
+    #[msg("Min interval cannot be negative")]
+    InvalidMinInterval,
+
+    #[msg("Mint does not match the session's spending mint")]
+    MintMismatch,
+
+    #[msg("Instruction is not allowed for this program")]
+    InstructionNotAllowed,
+
+    #[msg("Session key is not owned by the signer")]
+    Unauthorized,
+
+    #[msg("Program cannot be the default pubkey")]
+    InvalidProgram,
+
+    #[msg("Managers may only lower limits or shorten expiry")]
+    CannotEscalate,
+
+    #[msg("Unknown revocation reason")]
+    InvalidRevokeReason,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::InstructionData;
+
+    #[test]
+    fn session_key_len_matches_serialized_size() {
+        let key = SessionKey::deserialize(&mut &[0u8; SessionKey::LEN][..]).unwrap();
+        let mut data = Vec::new();
+        key.serialize(&mut data).unwrap();
+        assert_eq!(data.len(), SessionKey::LEN);
+    }
+
+    /// Target for sessions without an account restriction
+    const ANY_TARGET: Pubkey = Pubkey::new_from_array([7; 32]);
+
+    /// Active session for `session_pubkey` allowing `program` until t=100
+    fn test_session(session_pubkey: Pubkey, program: Pubkey) -> SessionKey {
+        let mut key = SessionKey::deserialize(&mut &[0u8; SessionKey::LEN][..]).unwrap();
+        key.session_pubkey = session_pubkey;
+        key.max_amount_per_tx = 100;
+        key.max_total_amount = 1_000;
+        key.expiry_timestamp = 100;
+        key.allowed_programs[0] = program;
+        key.allowed_programs_count = 1;
+        key.is_active = true;
+        key
+    }
+
+    #[test]
+    fn validate_rejects_wrong_signer() {
+        let program = Pubkey::new_unique();
+        let mut key = test_session(Pubkey::new_unique(), program);
+
+        assert_eq!(
+            key.validate_use(Pubkey::new_unique(), program, ANY_TARGET, 10, 1, 0),
+            Err(ErrorCode::InvalidSessionAuthority.into())
+        );
+        assert_eq!(key.spent_amount, 0);
+    }
+
+    #[test]
+    fn validate_accepts_session_signer() {
+        let session_pubkey = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut key = test_session(session_pubkey, program);
+
+        assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 10, 1, 0), Ok(()));
+        assert_eq!(key.spent_amount, 10);
+        assert_eq!(key.nonce, 1);
+    }
+
+    #[test]
+    fn window_limit_resets_after_window_elapses() {
+        let session_pubkey = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut key = test_session(session_pubkey, program);
+        key.window_seconds = 10;
+        key.window_limit = 150;
+
+        assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 100, 1, 0), Ok(()));
+        assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 50, 2, 5), Ok(()));
+
+        // Window exhausted until it elapses
+        assert_eq!(
+            key.validate_use(session_pubkey, program, ANY_TARGET, 1, 3, 9),
+            Err(ErrorCode::AmountExceedsWindowLimit.into())
+        );
+
+        assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 100, 3, 10), Ok(()));
+        assert_eq!(key.window_start, 10);
+        assert_eq!(key.window_spent, 100);
+        assert_eq!(key.spent_amount, 250);
+    }
+
+    #[test]
+    fn window_must_be_disabled_or_fully_set() {
+        assert_eq!(SessionKey::validate_window(0, 0), Ok(()));
+        assert_eq!(SessionKey::validate_window(60, 10), Ok(()));
+        assert_eq!(
+            SessionKey::validate_window(60, 0),
+            Err(ErrorCode::InvalidWindow.into())
+        );
+        assert_eq!(
+            SessionKey::validate_window(-1, 10),
+            Err(ErrorCode::InvalidWindow.into())
+        );
+    }
+
+    #[test]
+    fn stale_or_skipped_nonce_rejected() {
+        let session_pubkey = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut key = test_session(session_pubkey, program);
+
+        assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 10, 1, 0), Ok(()));
+
+        // Replaying the same nonce fails and spends nothing
+        assert_eq!(
+            key.validate_use(session_pubkey, program, ANY_TARGET, 10, 1, 0),
+            Err(ErrorCode::InvalidNonce.into())
+        );
+        assert_eq!(
+            key.validate_use(session_pubkey, program, ANY_TARGET, 10, 3, 0),
+            Err(ErrorCode::InvalidNonce.into())
+        );
+        assert_eq!(key.spent_amount, 10);
+
+        assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 10, 2, 0), Ok(()));
+        assert_eq!(key.nonce, 2);
+    }
+
+    #[test]
+    fn key_unusable_after_max_uses() {
+        let session_pubkey = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut key = test_session(session_pubkey, program);
+        key.max_uses = 2;
+
+        assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 10, 1, 0), Ok(()));
+        assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 10, 2, 0), Ok(()));
+        assert_eq!(
+            key.validate_use(session_pubkey, program, ANY_TARGET, 10, 3, 0),
+            Err(ErrorCode::MaxUsesExceeded.into())
+        );
+        assert_eq!(key.use_count, 2);
+        assert_eq!(key.spent_amount, 20);
+    }
+
+    #[test]
+    fn zero_max_uses_is_unlimited() {
+        let session_pubkey = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut key = test_session(session_pubkey, program);
+
+        for nonce in 1..=20 {
+            assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 1, nonce, 0), Ok(()));
+        }
+        assert_eq!(key.use_count, 20);
+    }
+
+    #[test]
+    fn extend_expiry_rejects_expired_key() {
+        let mut key = test_session(Pubkey::new_unique(), Pubkey::new_unique());
+
+        assert_eq!(
+            key.extend_expiry(500, 100),
+            Err(ErrorCode::SessionKeyExpired.into())
+        );
+        assert_eq!(
+            key.extend_expiry(90, 50),
+            Err(ErrorCode::InvalidExpiry.into())
+        );
+        assert_eq!(key.extend_expiry(500, 50), Ok(()));
+        assert_eq!(key.expiry_timestamp, 500);
+    }
+
+    #[test]
+    fn pause_then_resume_keeps_state() {
+        let session_pubkey = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut key = test_session(session_pubkey, program);
+        assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 10, 1, 0), Ok(()));
+
+        assert_eq!(key.pause(), Ok(()));
+        assert_eq!(
+            key.validate_use(session_pubkey, program, ANY_TARGET, 10, 2, 0),
+            Err(ErrorCode::SessionKeyNotActive.into())
+        );
+
+        assert_eq!(key.resume(), Ok(()));
+        assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 10, 2, 0), Ok(()));
+        assert_eq!(key.spent_amount, 20);
+        assert_eq!(key.nonce, 2);
+    }
+
+    #[test]
+    fn revoked_key_cannot_be_resumed() {
+        let mut key = test_session(Pubkey::new_unique(), Pubkey::new_unique());
+        key.is_active = false;
+        key.is_revoked = true;
+
+        assert_eq!(key.resume(), Err(ErrorCode::SessionKeyRevoked.into()));
+    }
+
+    #[test]
+    fn key_is_revoked_only_once() {
+        let mut key = test_session(Pubkey::new_unique(), Pubkey::new_unique());
+
+        // Paused keys can still be revoked
+        assert_eq!(key.pause(), Ok(()));
+        assert_eq!(key.revoke(), Ok(()));
+        assert!(!key.is_active && key.is_revoked);
+
+        // A second revocation would report the key revoked again
+        assert_eq!(key.revoke(), Err(ErrorCode::SessionKeyRevoked.into()));
+    }
+
+    #[test]
+    fn oversized_program_list_rejected() {
+        let mut key = test_session(Pubkey::new_unique(), Pubkey::new_unique());
+        let programs: Vec<Pubkey> = (0..11).map(|_| Pubkey::new_unique()).collect();
+
+        assert_eq!(
+            key.set_allowed_programs(&programs),
+            Err(ErrorCode::TooManyPrograms.into())
+        );
+        assert_eq!(key.set_allowed_programs(&programs[..10]), Ok(()));
+        assert_eq!(key.allowed_programs_count, 10);
+        assert_eq!(
+            key.add_allowed_program(programs[10]),
+            Err(ErrorCode::TooManyPrograms.into())
+        );
+    }
+
+    #[test]
+    fn add_rejects_duplicates_and_remove_keeps_list_packed() {
+        let first = Pubkey::new_unique();
+        let second = Pubkey::new_unique();
+        let mut key = test_session(Pubkey::new_unique(), first);
+
+        assert_eq!(
+            key.add_allowed_program(first),
+            Err(ErrorCode::DuplicateProgram.into())
+        );
+        assert_eq!(key.add_allowed_program(second), Ok(()));
+
+        assert_eq!(key.remove_allowed_program(first), Ok(()));
+        assert_eq!(key.allowed_programs_count, 1);
+        assert_eq!(key.allowed_programs[0], second);
+        assert_eq!(
+            key.remove_allowed_program(first),
+            Err(ErrorCode::ProgramNotAllowed.into())
+        );
+    }
+
+    #[test]
+    fn lifecycle_events_carry_session_state() {
+        let address = Pubkey::new_unique();
+        let session_pubkey = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut key = test_session(session_pubkey, program);
+        key.owner = Pubkey::new_unique();
+        key.created_at = 5;
+
+        let created = key.created_event(address);
+        assert_eq!(created.session_key, address);
+        assert_eq!(created.owner, key.owner);
+        assert_eq!(created.session_pubkey, session_pubkey);
+        assert_eq!(created.max_amount_per_tx, 100);
+        assert_eq!(created.max_total_amount, 1_000);
+        assert_eq!(created.expiry_timestamp, 100);
+        assert_eq!(created.timestamp, 5);
+
+        assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 30, 1, 7), Ok(()));
+        let validated = key.validated_event(program, 30, 7);
+        assert_eq!(validated.session_pubkey, session_pubkey);
+        assert_eq!(validated.program_id, program);
+        assert_eq!(validated.amount, 30);
+        assert_eq!(validated.total_spent, 30);
+        assert_eq!(validated.timestamp, 7);
+
+        key.max_amount_per_tx = 50;
+        let updated = key.limits_updated_event(address, 8);
+        assert_eq!(updated.session_key, address);
+        assert_eq!(updated.max_amount_per_tx, 50);
+        assert_eq!(updated.max_total_amount, 1_000);
+        assert_eq!(updated.timestamp, 8);
+
+        let revoked = key.revoked_event(address, SessionKey::REASON_COMPROMISED, 9);
+        assert_eq!(revoked.session_key, address);
+        assert_eq!(revoked.session_pubkey, session_pubkey);
+        assert_eq!(revoked.reason, SessionKey::REASON_COMPROMISED);
+        assert_eq!(revoked.timestamp, 9);
+    }
+
+    #[test]
+    fn session_restricted_to_one_vault() {
+        let session_pubkey = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let (vault, other_vault) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut key = test_session(session_pubkey, program);
+        assert_eq!(key.add_allowed_account(vault), Ok(()));
+
+        assert_eq!(
+            key.validate_use(session_pubkey, program, other_vault, 10, 1, 0),
+            Err(ErrorCode::AccountNotAllowed.into())
+        );
+        assert_eq!(key.validate_use(session_pubkey, program, vault, 10, 1, 0), Ok(()));
+
+        // Lifting the restriction opens the key to any account again
+        assert_eq!(key.remove_allowed_account(vault), Ok(()));
+        assert_eq!(key.validate_use(session_pubkey, program, other_vault, 10, 2, 0), Ok(()));
+    }
+
+    #[test]
+    fn allowed_accounts_reject_duplicates_and_overflow() {
+        let mut key = test_session(Pubkey::new_unique(), Pubkey::new_unique());
+        let accounts: Vec<Pubkey> = (0..SessionKey::MAX_ALLOWED_ACCOUNTS)
+            .map(|_| Pubkey::new_unique())
+            .collect();
+
+        for account in &accounts {
+            assert_eq!(key.add_allowed_account(*account), Ok(()));
+        }
+        assert_eq!(
+            key.add_allowed_account(accounts[0]),
+            Err(ErrorCode::DuplicateAccount.into())
+        );
+        assert_eq!(
+            key.add_allowed_account(Pubkey::new_unique()),
+            Err(ErrorCode::TooManyAccounts.into())
+        );
+
+        assert_eq!(key.remove_allowed_account(accounts[0]), Ok(()));
+        assert_eq!(key.allowed_accounts[0], accounts[SessionKey::MAX_ALLOWED_ACCOUNTS - 1]);
+        assert_eq!(
+            key.remove_allowed_account(accounts[0]),
+            Err(ErrorCode::AccountNotAllowed.into())
+        );
+    }
+
+    #[test]
+    fn refund_restores_budget_and_clamps_at_zero() {
+        let session_pubkey = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut key = test_session(session_pubkey, program);
+        key.window_seconds = 60;
+        key.window_limit = 100;
+        assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 80, 1, 0), Ok(()));
+
+        key.refund(30);
+        assert_eq!((key.spent_amount, key.window_spent), (50, 50));
+
+        // Over-refunding can't underflow into a huge budget
+        key.refund(u64::MAX);
+        assert_eq!((key.spent_amount, key.window_spent), (0, 0));
+
+        // The refunded window budget is usable again
+        assert_eq!(key.validate_use(session_pubkey, program, ANY_TARGET, 100, 2, 1), Ok(()));
+
+        let event = key.refund_event(Pubkey::new_unique(), 5, 9);
+        assert_eq!((event.amount, event.total_spent, event.timestamp), (5, 100, 9));
+    }
+
+    #[test]
+    fn new_session_rejects_unusable_parameters() {
+        let program = Pubkey::new_unique();
+
+        assert_eq!(
+            SessionKey::validate_new(101, 100, 50, &[program], 10).err(),
+            Some(ErrorCode::InvalidLimits.into())
+        );
+        assert_eq!(
+            SessionKey::validate_new(10, 100, 10, &[program], 10).err(),
+            Some(ErrorCode::ExpiryInPast.into())
+        );
+        assert_eq!(
+            SessionKey::validate_new(10, 100, 50, &[], 10).err(),
+            Some(ErrorCode::NoProgramsSpecified.into())
+        );
+        assert_eq!(SessionKey::validate_new(100, 100, 11, &[program], 10), Ok(()));
+    }
+
+    #[test]
+    fn new_session_rejects_duplicate_and_default_programs() {
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        assert_eq!(
+            SessionKey::validate_new(10, 100, 50, &[first, second, first], 0),
+            Err(ErrorCode::DuplicateProgram.into())
+        );
+        assert_eq!(
+            SessionKey::validate_new(10, 100, 50, &[first, Pubkey::default()], 0),
+            Err(ErrorCode::InvalidProgram.into())
+        );
+        assert_eq!(SessionKey::validate_new(10, 100, 50, &[first, second], 0), Ok(()));
+
+        // Adding to an existing list applies the same rules
+        let mut key = test_session(Pubkey::new_unique(), first);
+        assert_eq!(
+            key.add_allowed_program(Pubkey::default()),
+            Err(ErrorCode::InvalidProgram.into())
+        );
+        assert_eq!(key.add_allowed_program(first), Err(ErrorCode::DuplicateProgram.into()));
+        assert_eq!(key.add_allowed_program(second), Ok(()));
+    }
+
+    #[test]
+    fn spend_near_u64_max_cannot_wrap_past_limits() {
+        let session_pubkey = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut key = test_session(session_pubkey, program);
+        key.max_amount_per_tx = u64::MAX;
+        key.max_total_amount = u64::MAX;
+        key.spent_amount = u64::MAX - 10;
+
+        assert_eq!(
+            key.record_spend(u64::MAX - 5, 0),
+            Err(ErrorCode::ArithmeticOverflow.into())
+        );
+        assert_eq!(key.spent_amount, u64::MAX - 10);
+
+        key.spent_amount = 0;
+        key.window_seconds = 10;
+        key.window_limit = u64::MAX;
+        key.window_spent = u64::MAX - 10;
+        assert_eq!(
+            key.record_spend(u64::MAX - 5, 0),
+            Err(ErrorCode::ArithmeticOverflow.into())
+        );
+        assert_eq!(key.spent_amount, 0);
+
+        // A window ending past the end of time can't be compared against
+        key.window_spent = 0;
+        key.window_start = i64::MAX - 5;
+        assert_eq!(key.record_spend(1, 0), Err(ErrorCode::ArithmeticOverflow.into()));
+        assert_eq!((key.spent_amount, key.window_spent), (0, 0));
+    }
+
+    #[test]
+    fn crank_only_expires_keys_past_their_expiry() {
+        let mut key = test_session(Pubkey::new_unique(), Pubkey::new_unique());
+
+        // Still live at and before the expiry timestamp
+        assert_eq!(key.expire(99), Err(ErrorCode::SessionKeyNotExpired.into()));
+        assert_eq!(key.expire(100), Err(ErrorCode::SessionKeyNotExpired.into()));
+        assert!(key.is_active);
+
+        assert_eq!(key.expire(101), Ok(()));
+        assert!(!key.is_active);
+    }
+
+    fn registry_data(count: u16, max_sessions: u16) -> Vec<u8> {
+        let registry = SessionRegistry {
+            owner: Pubkey::new_unique(),
+            count,
+            max_sessions,
+            bump: 255,
+        };
+        let mut data = Vec::new();
+        registry.try_serialize(&mut data).unwrap();
+        assert_eq!(data.len(), 8 + SessionRegistry::LEN);
+        data
+    }
+
+    #[test]
+    fn session_cap_rejects_the_n_plus_first_key_until_one_closes() {
+        let key = Pubkey::new_unique();
+        let mut lamports = 1_000_000;
+        let mut data = registry_data(0, 3);
+        let info = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &ID, false, 0);
+
+        for _ in 0..3 {
+            SessionRegistry::update_if_exists(&info, SessionRegistry::add_session).unwrap();
+        }
+        assert_eq!(
+            SessionRegistry::update_if_exists(&info, SessionRegistry::add_session),
+            Err(ErrorCode::TooManySessions.into())
+        );
+
+        // Closing (or cranking) a key frees its slot for a new one
+        SessionRegistry::update_if_exists(&info, SessionRegistry::remove_session).unwrap();
+        SessionRegistry::update_if_exists(&info, SessionRegistry::add_session).unwrap();
+
+        let registry = SessionRegistry::try_deserialize(&mut &info.data.borrow()[..]).unwrap();
+        assert_eq!((registry.count, registry.max_sessions), (3, 3));
+    }
+
+    #[test]
+    fn releasing_a_key_frees_its_slot() {
+        let registry = Pubkey::new_unique();
+        let mut lamports = 1_000_000;
+        let mut data = registry_data(3, 3);
+        let info =
+            AccountInfo::new(&registry, false, true, &mut lamports, &mut data, &ID, false, 0);
+        let count = || {
+            SessionRegistry::try_deserialize(&mut &info.data.borrow()[..])
+                .unwrap()
+                .count
+        };
+
+        let key = test_session(Pubkey::new_unique(), Pubkey::new_unique());
+        key.release_slot(&info).unwrap();
+        assert_eq!(count(), 2);
+    }
+
+    #[test]
+    fn session_keys_cannot_be_created_without_a_registry() {
+        let (owner, session_pubkey) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (address, _) = Pubkey::find_program_address(
+            &[b"session", owner.as_ref(), session_pubkey.as_ref()],
+            &ID,
+        );
+        let (registry, _) =
+            Pubkey::find_program_address(&[b"session_registry", owner.as_ref()], &ID);
+
+        // The registry was never initialized, so it's an empty system account
+        let mut lamports = [0u64, 0, 1_000_000, 1];
+        let (mut key_data, mut registry_data, mut owner_data, mut program_data) =
+            (vec![], vec![], vec![], vec![]);
+        let [key_lamports, registry_lamports, owner_lamports, program_lamports] = &mut lamports;
+        let system = system_program::ID;
+
+        let key_info =
+            AccountInfo::new(&address, false, true, key_lamports, &mut key_data, &system, false, 0);
+        let registry_info = AccountInfo::new(
+            &registry,
+            false,
+            true,
+            registry_lamports,
+            &mut registry_data,
+            &system,
+            false,
+            0,
+        );
+        let owner_info = AccountInfo::new(
+            &owner,
+            true,
+            true,
+            owner_lamports,
+            &mut owner_data,
+            &system,
+            false,
+            0,
+        );
+        let program_info = AccountInfo::new(
+            &system,
+            false,
+            false,
+            program_lamports,
+            &mut program_data,
+            &system,
+            true,
+            0,
+        );
+        // The owner pays the key's rent themselves
+        let infos = [key_info, registry_info, owner_info.clone(), owner_info, program_info];
+
+        let result = CreateSessionKey::try_accounts(
+            &ID,
+            &mut &infos[..],
+            session_pubkey.as_ref(),
+            &mut CreateSessionKeyBumps::default(),
+            &mut std::collections::BTreeSet::new(),
+        );
+        assert_eq!(
+            result.map(|_| ()).err(),
+            Some(anchor_lang::error::ErrorCode::AccountNotInitialized.into())
+        );
+    }
+
+    #[test]
+    fn validations_within_min_interval_are_rate_limited() {
+        let signer = Pubkey::new_unique();
+        let program = Pubkey::new_unique();
+        let mut key = test_session(signer, program);
+        key.min_interval_seconds = 30;
+        key.expiry_timestamp = 1_000;
+
+        key.validate_use(signer, program, ANY_TARGET, 10, 1, 100).unwrap();
+        assert_eq!(key.last_used, 100);
+
+        // A second validation inside the interval is rejected and changes nothing
+        assert_eq!(
+            key.validate_use(signer, program, ANY_TARGET, 10, 2, 129),
+            Err(ErrorCode::SessionRateLimited.into())
+        );
+        assert_eq!((key.nonce, key.spent_amount), (1, 10));
+
+        // Once the clock passes the interval the key works again
+        key.validate_use(signer, program, ANY_TARGET, 10, 2, 130).unwrap();
+        assert_eq!((key.last_used, key.spent_amount), (130, 20));
+
+        // No interval means back-to-back validations are fine
+        key.min_interval_seconds = 0;
+        key.validate_use(signer, program, ANY_TARGET, 10, 3, 130).unwrap();
+    }
+
+    #[test]
+    fn session_restricted_to_a_single_instruction() {
+        let signer = Pubkey::new_unique();
+        let (program, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut key = test_session(signer, program);
+        key.add_allowed_program(other).unwrap();
+        let (allowed, blocked) = ([1, 2, 3, 4, 5, 6, 7, 8], [8, 7, 6, 5, 4, 3, 2, 1]);
+
+        // No restriction allows any instruction
+        assert_eq!(key.check_instruction(program, blocked), Ok(()));
+
+        key.set_allowed_instruction(program, allowed).unwrap();
+        assert_eq!(key.check_instruction(program, allowed), Ok(()));
+        assert_eq!(
+            key.check_instruction(program, blocked),
+            Err(ErrorCode::InstructionNotAllowed.into())
+        );
+
+        // Restrictions are per program, and programs outside the list are
+        // never allowed
+        assert_eq!(key.check_instruction(other, blocked), Ok(()));
+        assert_eq!(
+            key.check_instruction(Pubkey::new_unique(), allowed),
+            Err(ErrorCode::ProgramNotAllowed.into())
+        );
+        assert_eq!(
+            key.set_allowed_instruction(Pubkey::new_unique(), allowed),
+            Err(ErrorCode::ProgramNotAllowed.into())
+        );
+
+        // Removing a program keeps the remaining pairs together
+        key.set_allowed_instruction(other, blocked).unwrap();
+        key.remove_allowed_program(program).unwrap();
+        assert_eq!(key.allowed_programs[0], other);
+        assert_eq!(key.allowed_instructions[0], blocked);
+        assert_eq!(key.allowed_instructions[1], [0; 8]);
+
+        // An all-zero discriminator lifts the restriction again
+        key.set_allowed_instruction(other, [0; 8]).unwrap();
+        assert_eq!(key.check_instruction(other, allowed), Ok(()));
+    }
+
+    /// Instructions sysvar data for a transaction of `instructions` that is
+    /// executing the one at `current`
+    fn instructions_data(instructions: &[(Pubkey, Vec<u8>)], current: u16) -> Vec<u8> {
+        let borrowed: Vec<_> = instructions
+            .iter()
+            .map(|(program_id, data)| instructions_sysvar::BorrowedInstruction {
+                program_id,
+                accounts: vec![],
+                data,
+            })
+            .collect();
+        let mut data = instructions_sysvar::construct_instructions_data(&borrowed);
+        instructions_sysvar::store_current_index_checked(&mut data, current).unwrap();
+        data
+    }
+
+    #[test]
+    fn authorized_instruction_is_the_current_top_level_one() {
+        let (program, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let discriminator = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut execute = discriminator.to_vec();
+        execute.extend_from_slice(&42u64.to_le_bytes());
+
+        let mut lamports = 1;
+        let mut data = instructions_data(
+            &[(other, vec![9; 8]), (program, execute), (other, vec![1])],
+            1,
+        );
+        let sysvar = instructions_sysvar::ID;
+        let info =
+            AccountInfo::new(&sysvar, false, false, &mut lamports, &mut data, &sysvar, false, 0);
+        assert_eq!(SessionKey::current_instruction(&info), Ok((program, discriminator)));
+
+        // Instructions without a discriminator authorize nothing
+        instructions_sysvar::store_current_index_checked(&mut info.data.borrow_mut(), 2).unwrap();
+        assert_eq!(
+            SessionKey::current_instruction(&info),
+            Err(ErrorCode::InstructionNotAllowed.into())
+        );
+
+        // Nor does any account other than the sysvar
+        let fake = Pubkey::new_unique();
+        let mut fake_lamports = 1;
+        let mut fake_data = instructions_data(&[(program, discriminator.to_vec())], 0);
+        let fake_info = AccountInfo::new(
+            &fake,
+            false,
+            false,
+            &mut fake_lamports,
+            &mut fake_data,
+            &sysvar,
+            false,
+            0,
+        );
+        assert_eq!(
+            SessionKey::current_instruction(&fake_info),
+            Err(ProgramError::UnsupportedSysvar.into())
+        );
+    }
+
+    #[test]
+    fn validation_requires_spending_mint() {
+        let program = Pubkey::new_unique();
+        let mut key = test_session(Pubkey::new_unique(), program);
+        key.spending_mint = Pubkey::new_unique();
+
+        assert_eq!(
+            key.check_mint(Pubkey::new_unique()),
+            Err(ErrorCode::MintMismatch.into())
+        );
+        assert_eq!(key.check_mint(Pubkey::default()), Err(ErrorCode::MintMismatch.into()));
+        assert_eq!(key.check_mint(key.spending_mint), Ok(()));
+    }
+
+    #[test]
+    fn allowed_programs_view_matches_creation() {
+        let programs = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+        SessionKey::validate_new(10, 100, 50, &programs, 0).unwrap();
+
+        let mut key = test_session(Pubkey::new_unique(), Pubkey::new_unique());
+        key.set_allowed_programs(&programs).unwrap();
+        assert_eq!(key.allowed_programs(), &programs[..]);
+
+        // Removal keeps the list packed, with no default padding returned
+        key.remove_allowed_program(programs[0]).unwrap();
+        assert_eq!(key.allowed_programs(), &[programs[2], programs[1]][..]);
+    }
+
+    #[test]
+    fn remaining_budget_saturates_at_zero() {
+        let program = Pubkey::new_unique();
+        let session_pubkey = Pubkey::new_unique();
+        let mut key = test_session(session_pubkey, program);
+        assert_eq!(key.remaining_budget(), 1_000);
+
+        key.validate_use(session_pubkey, program, ANY_TARGET, 100, 1, 0).unwrap();
+        assert_eq!(key.remaining_budget(), 900);
+
+        // Lowering the limit below what was already spent leaves nothing
+        key.max_total_amount = 50;
+        assert_eq!(key.remaining_budget(), 0);
+    }
+
+    #[test]
+    fn revoke_all_revokes_active_keys_and_skips_inactive_ones() {
+        let owner = Pubkey::new_unique();
+
+        // Three active keys, one paused and one already revoked
+        let mut keys: Vec<SessionKey> = (0..5)
+            .map(|_| {
+                let mut key = test_session(Pubkey::new_unique(), Pubkey::new_unique());
+                key.owner = owner;
+                key
+            })
+            .collect();
+        keys[3].pause().unwrap();
+        keys[4].is_active = false;
+        keys[4].is_revoked = true;
+
+        let addresses: Vec<Pubkey> = keys.iter().map(|_| Pubkey::new_unique()).collect();
+        let mut lamports = vec![1_000_000u64; keys.len()];
+        let mut data: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|key| {
+                let mut data = Vec::new();
+                key.try_serialize(&mut data).unwrap();
+                data
+            })
+            .collect();
+        let infos: Vec<AccountInfo> = addresses
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|((key, lamports), data)| {
+                AccountInfo::new(key, false, true, lamports, data, &ID, false, 0)
+            })
+            .collect();
+
+        assert_eq!(
+            SessionKey::revoke_owned(Pubkey::new_unique(), &infos, 10).err(),
+            Some(ErrorCode::Unauthorized.into())
+        );
+
+        let events = SessionKey::revoke_owned(owner, &infos, 10).unwrap();
+        let revoked: Vec<Pubkey> = events.iter().map(|event| event.session_key).collect();
+        assert_eq!(revoked, addresses[..3].to_vec());
+        assert!(events
+            .iter()
+            .all(|event| event.timestamp == 10 && event.reason == SessionKey::REASON_COMPROMISED));
+
+        let stored: Vec<(bool, bool)> = infos
+            .iter()
+            .map(|info| {
+                let key = SessionKey::try_deserialize(&mut &info.data.borrow()[..]).unwrap();
+                (key.is_active, key.is_revoked)
+            })
+            .collect();
+        assert_eq!(
+            stored,
+            vec![(false, true), (false, true), (false, true), (false, false), (false, true)]
+        );
+    }
+
+    /// Account key laid out as in the runtime's input buffer, where
+    /// `AccountInfo::resize` reads the original data length just before it
+    #[repr(C)]
+    struct SerializedKey {
+        original_data_len: u32,
+        key: Pubkey,
+    }
+
+    /// Run `close_session_key_to_payer` for `key` with `rent_payer` supplied as
+    /// the refund account, returning the result and the lamports the key and
+    /// `rent_payer` hold afterwards
+    fn close_to_payer(
+        key: &SessionKey,
+        rent_payer: Pubkey,
+    ) -> (std::result::Result<(), ProgramError>, u64, u64) {
+        let (address, _) = Pubkey::find_program_address(
+            &[b"session", key.owner.as_ref(), key.session_pubkey.as_ref()],
+            &ID,
+        );
+        let (registry, _) =
+            Pubkey::find_program_address(&[b"session_registry", key.owner.as_ref()], &ID);
+
+        // Closing resizes the key's data, which also writes the length word
+        // in the 8 bytes in front of it, and reassigns its owner, so that
+        // can't point at the `ID` constant
+        let mut key_data = vec![0u8; 8];
+        key.try_serialize(&mut key_data).unwrap();
+        let address = SerializedKey {
+            original_data_len: (key_data.len() - 8) as u32,
+            key: address,
+        };
+
+        let mut lamports = [1_000_000u64; 4];
+        let (mut registry_data, mut owner_data, mut payer_data) = (vec![], vec![], vec![]);
+        let [key_lamports, registry_lamports, owner_lamports, payer_lamports] = &mut lamports;
+        let key_owner = ID;
+
+        let infos = [
+            AccountInfo::new(
+                &address.key,
+                false,
+                true,
+                key_lamports,
+                &mut key_data[8..],
+                &key_owner,
+                false,
+                0,
+            ),
+            AccountInfo::new(
+                &registry,
+                false,
+                true,
+                registry_lamports,
+                &mut registry_data,
+                &system_program::ID,
+                false,
+                0,
+            ),
+            AccountInfo::new(
+                &key.owner,
+                true,
+                false,
+                owner_lamports,
+                &mut owner_data,
+                &system_program::ID,
+                false,
+                0,
+            ),
+            AccountInfo::new(
+                &rent_payer,
+                false,
+                true,
+                payer_lamports,
+                &mut payer_data,
+                &system_program::ID,
+                false,
+                0,
+            ),
+        ];
+
+        let result = crate::entry(&ID, &infos, &instruction::CloseSessionKeyToPayer.data());
+        (result, infos[0].lamports(), infos[3].lamports())
+    }
+
+    #[test]
+    fn close_to_payer_refunds_only_the_recorded_rent_payer() {
+        let mut key = test_session(Pubkey::new_unique(), Pubkey::new_unique());
+        key.owner = Pubkey::new_unique();
+        key.rent_payer = Pubkey::new_unique();
+        key.bump = Pubkey::find_program_address(
+            &[b"session", key.owner.as_ref(), key.session_pubkey.as_ref()],
+            &ID,
+        )
+        .1;
+
+        // The key's rent moves to the payer recorded at creation
+        assert_eq!(close_to_payer(&key, key.rent_payer), (Ok(()), 0, 2_000_000));
+
+        // Neither the owner nor any other account can collect the payer's rent
+        let has_one = Err(ProgramError::from(anchor_lang::error::Error::from(
+            anchor_lang::error::ErrorCode::ConstraintHasOne,
+        )));
+        assert_eq!(close_to_payer(&key, key.owner), (has_one.clone(), 1_000_000, 1_000_000));
+        assert_eq!(
+            close_to_payer(&key, Pubkey::new_unique()),
+            (has_one, 1_000_000, 1_000_000)
+        );
+    }
+
+    #[test]
+    fn manager_can_tighten_but_not_escalate() {
+        let mut key = test_session(Pubkey::new_unique(), Pubkey::new_unique());
+
+        assert_eq!(key.tighten_limits(50, 500, 80), Ok(()));
+        assert_eq!(
+            (key.max_amount_per_tx, key.max_total_amount, key.expiry_timestamp),
+            (50, 500, 80)
+        );
+
+        for (per_tx, total, expiry) in [(51, 500, 80), (50, 501, 80), (50, 500, 81)] {
+            assert_eq!(
+                key.tighten_limits(per_tx, total, expiry),
+                Err(ErrorCode::CannotEscalate.into())
+            );
+        }
+        assert_eq!(
+            key.tighten_limits(50, 40, 80),
+            Err(ErrorCode::InvalidLimits.into())
+        );
+        assert_eq!(key.max_total_amount, 500);
+    }
+
+    /// Run `ManageSessionKey` validation for `key` signed by `manager`
+    fn validate_manage(key: &SessionKey, manager: Pubkey) -> Result<()> {
+        let (address, _) = Pubkey::find_program_address(
+            &[b"session", key.owner.as_ref(), key.session_pubkey.as_ref()],
+            &ID,
+        );
+
+        let mut lamports = [1_000_000u64; 2];
+        let mut key_data = Vec::new();
+        key.try_serialize(&mut key_data).unwrap();
+        let mut manager_data = vec![];
+        let [key_lamports, manager_lamports] = &mut lamports;
+
+        let infos = [
+            AccountInfo::new(&address, false, true, key_lamports, &mut key_data, &ID, false, 0),
+            AccountInfo::new(
+                &manager,
+                true,
+                false,
+                manager_lamports,
+                &mut manager_data,
+                &system_program::ID,
+                false,
+                0,
+            ),
+        ];
+
+        ManageSessionKey::try_accounts(
+            &ID,
+            &mut &infos[..],
+            &[],
+            &mut ManageSessionKeyBumps::default(),
+            &mut std::collections::BTreeSet::new(),
+        )
+        .map(|_| ())
+    }
+
+    #[test]
+    fn only_the_recorded_manager_may_manage_limits() {
+        let mut key = test_session(Pubkey::new_unique(), Pubkey::new_unique());
+        key.owner = Pubkey::new_unique();
+        key.manager = Pubkey::new_unique();
+        key.bump = Pubkey::find_program_address(
+            &[b"session", key.owner.as_ref(), key.session_pubkey.as_ref()],
+            &ID,
+        )
+        .1;
+
+        assert!(validate_manage(&key, key.manager).is_ok());
+        assert_eq!(
+            validate_manage(&key, key.owner),
+            Err(ErrorCode::Unauthorized.into())
+        );
+    }
 }